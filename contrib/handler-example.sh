@@ -4,20 +4,49 @@
 # Legacy mode — receives the event name as $1:
 #   device-arrived, device-left,
 #   paper-in, paper-out,
-#   button-down, button-up
+#   button-down, button-up,
+#   permission-denied (scanner attached but its device node isn't accessible)
+#   usb-degraded, usb-recovered (USB errors crossed / fell below [usb_health])
 #
 # Config mode — receives:
 #   scan <profile>   (gesture completed)
+#   lid-open <minutes> (lid_reminder_minutes elapsed)
+#   idle <minutes>     (no button or paper activity for idle_minutes)
 #   device-arrived, device-left, paper-in, paper-out
+#   maintenance-entered|maintenance-exited <gesture|command>
+#   batch-start <id>, batch-page <id> <page>, batch-end <id> <pages>
+#   permission-denied
+#   usb-degraded <errors>, usb-recovered
+#   scan-started <profile> <dir>, scan-finished <profile> <pages> <dir>
+#                    (the daemon scanned a [profiles.<profile>] itself)
+#   <name>-on, <name>-off (a [watch] status bit changed)
+#   handler-failed <event> <reason>
+#                    (a run for <event> failed: exit-N, signal-N, timeout or
+#                    error; with on_handler_failure = "event")
+
+# Every run also gets a unique $S1500D_JOB_ID, which s1500d logs alongside
+# the command — include it in your own logs to correlate the two.
+# $S1500D_SEQ is the sequence number of the event that triggered this run.
+# On device-left, $S1500D_LID_OPEN_SECONDS is how long the lid was open.
+# $S1500D_SERIAL is the USB serial number of the scanner, to tell several apart.
+# $S1500D_EVENT, $S1500D_PROFILE, $S1500D_PRESS_COUNT, $S1500D_PAPER (1/0),
+# $S1500D_DEVICE_VENDOR, $S1500D_DEVICE_MODEL,
+# $S1500D_DEVICE_FIRMWARE and $S1500D_TIMESTAMP describe the event; those that
+# don't apply are unset. With handler_json = true, stdin carries the event as
+# one line of JSON.
+# Write "snooze EVENT SECONDS" lines to fd $S1500D_CONTROL_FD to have the
+# daemon skip dispatching EVENT for that long once this run exits.
 
 EVENT="$1"
 PROFILE="${2:-}"
 
 case "$EVENT" in
     scan)
-        logger -t s1500d "Scan gesture: profile=$PROFILE"
+        logger -t s1500d "Scan gesture: profile=$PROFILE job=$S1500D_JOB_ID"
         # Your scan logic here — scanimage is safe to call,
         # s1500d has released the USB device.
+        # Don't dispatch the paper-out caused by feeding these pages.
+        echo "snooze paper-out 10" >&"$S1500D_CONTROL_FD"
         ;;
     paper-in)
         logger -t s1500d "Paper detected"
@@ -29,7 +58,46 @@ case "$EVENT" in
         logger -t s1500d "Scanner lid opened"
         ;;
     device-left)
-        logger -t s1500d "Scanner lid closed"
+        logger -t s1500d "Scanner lid closed after ${S1500D_LID_OPEN_SECONDS:-?}s"
+        ;;
+    lid-open)
+        logger -t s1500d "Scanner lid open for $2 minutes — close it to power down"
+        ;;
+    idle)
+        logger -t s1500d "Scanner unused for $2 minutes — close the lid to save the lamp"
+        ;;
+    maintenance-entered)
+        logger -t s1500d "Maintenance mode on ($2) — scanner events ignored"
+        ;;
+    maintenance-exited)
+        logger -t s1500d "Maintenance mode off ($2)"
+        ;;
+    batch-start)
+        logger -t s1500d "Batch $2 started"
+        ;;
+    batch-page)
+        logger -t s1500d "Batch $2: page $3 loaded"
+        ;;
+    batch-end)
+        logger -t s1500d "Batch $2 finished with $3 pages"
+        ;;
+    usb-degraded)
+        logger -t s1500d "USB connection flaky ($2 recent errors) — check the cable, run s1500d doctor"
+        ;;
+    usb-recovered)
+        logger -t s1500d "USB connection healthy again"
+        ;;
+    scan-started)
+        logger -t s1500d "Scanning $2 into $3"
+        ;;
+    scan-finished)
+        logger -t s1500d "Scanned $3 pages of $2 into $4"
+        ;;
+    handler-failed)
+        logger -t s1500d "Handler for $2 failed ($3)"
+        ;;
+    permission-denied)
+        logger -t s1500d "Scanner not accessible — install 99-scansnap.rules"
         ;;
     *)
         logger -t s1500d "Event: $EVENT"
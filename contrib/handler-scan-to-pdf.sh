@@ -39,6 +39,9 @@ case "$EVENT" in
             exit 1
         fi
 
+        # The feeder emptying is not a new event worth handling.
+        echo "snooze paper-out 10" >&"$S1500D_CONTROL_FD"
+
         img2pdf "${PAGES[@]}" -o "$OUTFILE"
         logger -t s1500d "Saved $OUTFILE (${#PAGES[@]} pages)"
         ;;
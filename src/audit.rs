@@ -0,0 +1,207 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use jiff::Timestamp;
+
+use crate::cgroup::Usage;
+
+/// One handler invocation, as recorded in the audit log.
+pub struct Record<'a> {
+    pub job: &'a str,
+    pub event: &'a str,
+    pub argv: &'a [&'a str],
+    /// Variables the daemon added to the handler's environment.
+    pub env: &'a [(&'a str, String)],
+    pub uid: u32,
+    pub status: &'a io::Result<ExitStatus>,
+    pub duration: Duration,
+    pub usage: Option<Usage>,
+}
+
+/// Append a record to the audit log at `path`.
+///
+/// Each record is a single `logfmt` line written with one `write` call on an
+/// `O_APPEND` file, so entries are never interleaved or partially rewritten.
+/// It is always stamped in RFC 3339 UTC: `log_timestamp` and
+/// `log_timezone` only shape the daemon's own log.
+pub fn append(path: &Path, record: &Record) -> io::Result<()> {
+    let line = format_record(Timestamp::now(), record);
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+fn format_record(ts: Timestamp, r: &Record) -> String {
+    let argv: Vec<String> = r.argv.iter().map(|a| shell_quote(a)).collect();
+    let env: Vec<String> = r.env.iter().map(|(k, v)| format!("{k}={v}")).collect();
+    let status = match r.status {
+        Ok(s) => match (s.code(), s.signal()) {
+            (Some(code), _) => format!("exit:{code}"),
+            (None, Some(sig)) => format!("signal:{sig}"),
+            (None, None) => "unknown".into(),
+        },
+        Err(e) => format!("spawn-error:{e}"),
+    };
+    let mut line = format!(
+        "ts={} job={} event={} argv={} env={} uid={} status={} duration_ms={}",
+        ts.strftime("%Y-%m-%dT%H:%M:%SZ"),
+        r.job,
+        logfmt_value(r.event),
+        logfmt_value(&argv.join(" ")),
+        logfmt_value(&env.join(" ")),
+        r.uid,
+        logfmt_value(&status),
+        r.duration.as_millis()
+    );
+    if let Some(u) = r.usage {
+        line.push_str(&format!(
+            " cpu_user_ms={} cpu_system_ms={}",
+            u.user_us / 1000,
+            u.system_us / 1000
+        ));
+        if let Some(peak) = u.memory_peak {
+            line.push_str(&format!(" memory_peak_bytes={peak}"));
+        }
+    }
+    line.push('\n');
+    line
+}
+
+/// Quote a logfmt value if it contains spaces, quotes, `=` or is empty.
+fn logfmt_value(s: &str) -> String {
+    if !s.is_empty() && !s.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        return s.to_string();
+    }
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+/// Quote one argv element the way a POSIX shell would need it.
+pub(crate) fn shell_quote(s: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts() -> Timestamp {
+        "2026-01-02T03:04:05Z".parse().unwrap()
+    }
+
+    #[test]
+    fn stamped_in_utc_whatever_the_log_uses() {
+        // Whatever log_timestamp and log_timezone say, the audit log keeps
+        // RFC 3339 UTC.
+        let status = Ok(ExitStatus::from_raw(0));
+        let record = Record {
+            job: "j",
+            event: "scan",
+            argv: &["/bin/h.sh", "scan"],
+            env: &[],
+            uid: 0,
+            status: &status,
+            duration: Duration::ZERO,
+            usage: None,
+        };
+        assert!(format_record(ts(), &record).starts_with("ts=2026-01-02T03:04:05Z job=j "));
+
+        let path = std::env::temp_dir().join(format!("s1500d-{}-audit-utc", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        append(&path, &record).unwrap();
+        let line = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let stamp = line.strip_prefix("ts=").unwrap().split(' ').next().unwrap();
+        assert!(
+            stamp.ends_with('Z') && stamp.parse::<Timestamp>().is_ok(),
+            "{line}"
+        );
+    }
+
+    #[test]
+    fn record_line() {
+        let status = Ok(ExitStatus::from_raw(0));
+        let env = [
+            ("S1500D_JOB_ID", "abc".to_string()),
+            ("S1500D_SEQ", "7".to_string()),
+        ];
+        let record = Record {
+            job: "abc",
+            event: "scan",
+            argv: &["/bin/h.sh", "scan", "legal size"],
+            env: &env,
+            uid: 1000,
+            status: &status,
+            duration: Duration::from_millis(1500),
+            usage: None,
+        };
+        assert_eq!(
+            format_record(ts(), &record),
+            "ts=2026-01-02T03:04:05Z job=abc event=scan \
+             argv=\"/bin/h.sh scan 'legal size'\" \
+             env=\"S1500D_JOB_ID=abc S1500D_SEQ=7\" uid=1000 status=exit:0 \
+             duration_ms=1500\n"
+        );
+    }
+
+    #[test]
+    fn record_signal_and_usage() {
+        // Raw wait status 9 = killed by SIGKILL.
+        let status = Ok(ExitStatus::from_raw(9));
+        let record = Record {
+            job: "j",
+            event: "paper-in",
+            argv: &["/bin/h.sh", "paper-in"],
+            env: &[],
+            uid: 0,
+            status: &status,
+            duration: Duration::from_millis(5),
+            usage: Some(Usage {
+                user_us: 2_000,
+                system_us: 1_000,
+                memory_peak: Some(4096),
+            }),
+        };
+        let line = format_record(ts(), &record);
+        assert!(line.contains("status=signal:9"));
+        assert!(line.contains("env=\"\""));
+        assert!(line.ends_with("cpu_user_ms=2 cpu_system_ms=1 memory_peak_bytes=4096\n"));
+    }
+
+    #[test]
+    fn spawn_error_recorded() {
+        let status = Err(io::Error::from(io::ErrorKind::NotFound));
+        let record = Record {
+            job: "j",
+            event: "",
+            argv: &["/missing"],
+            env: &[],
+            uid: 0,
+            status: &status,
+            duration: Duration::ZERO,
+            usage: None,
+        };
+        let line = format_record(ts(), &record);
+        assert!(line.starts_with("ts=2026-01-02T03:04:05Z job=j event=\"\""));
+        assert!(line.contains("status=\"spawn-error:"));
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(shell_quote("scan"), "scan");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(logfmt_value("a b"), "\"a b\"");
+        assert_eq!(logfmt_value("say \"hi\""), "\"say \\\"hi\\\"\"");
+    }
+}
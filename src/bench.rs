@@ -0,0 +1,291 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use clap::builder::RangedU64ValueParser;
+use clap::Args;
+
+use crate::error::{self, UsbError};
+use crate::{access_denied_help, open_device, poll_status, usb_config};
+
+const DEFAULT_POLLS: usize = 1000;
+const DEFAULT_PRESSES: usize = 5;
+const PRESS_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, PartialEq, Eq, Args)]
+pub struct Options {
+    /// GET_HW_STATUS round trips to time.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_POLLS,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub polls: usize,
+    /// Button presses to time interactively; 0 skips that phase.
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_PRESSES)]
+    pub presses: usize,
+}
+
+/// Latency distribution summary.
+#[derive(Debug, PartialEq, Eq)]
+struct Summary {
+    min: Duration,
+    p50: Duration,
+    p90: Duration,
+    p99: Duration,
+    max: Duration,
+    mean: Duration,
+}
+
+fn summarize(samples: &[Duration]) -> Option<Summary> {
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    // Nearest-rank percentile.
+    let pct = |p: usize| sorted[((sorted.len() * p + 99) / 100).max(1) - 1];
+    Some(Summary {
+        min: *sorted.first()?,
+        p50: pct(50),
+        p90: pct(90),
+        p99: pct(99),
+        max: *sorted.last()?,
+        mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+    })
+}
+
+fn print_summary(label: &str, s: &Summary) {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    println!(
+        "{label}: min {:.2}ms  p50 {:.2}ms  p90 {:.2}ms  p99 {:.2}ms  max {:.2}ms  mean {:.2}ms",
+        ms(s.min),
+        ms(s.p50),
+        ms(s.p90),
+        ms(s.p99),
+        ms(s.max),
+        ms(s.mean)
+    );
+}
+
+/// One back-to-back poll: start and end relative to the start of the
+/// measurement, and whether the button was down.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    start: Duration,
+    end: Duration,
+    button: bool,
+}
+
+/// Estimate how long after the press the daemon would have reported it.
+///
+/// The samples come from polling back to back, so the press is pinned to
+/// between the last "up" sample and the first "down" one (taken as the
+/// midpoint). The daemon instead polls once per `interval` after each
+/// round trip; replaying that schedule over the same samples gives the
+/// first daemon poll that would have seen the button down.
+fn detection_latency(samples: &[Sample], interval: Duration) -> Option<Duration> {
+    let edge = samples.iter().position(|s| s.button)?;
+    let pressed_at = match edge {
+        0 => samples[0].start,
+        i => (samples[i - 1].end + samples[i].end) / 2,
+    };
+    let mut next_tick = Duration::ZERO;
+    for s in samples {
+        if s.start < next_tick {
+            continue;
+        }
+        if s.button && s.end >= pressed_at {
+            return Some(s.end - pressed_at);
+        }
+        next_tick = s.end + interval;
+    }
+    None
+}
+
+/// Poll back to back until the button goes down and, if so, record the
+/// run of samples leading up to it, plus two daemon `interval`s after.
+fn record_press(
+    handle: &rusb::DeviceHandle<rusb::Context>,
+    interval: Duration,
+) -> Option<Vec<Sample>> {
+    let origin = Instant::now();
+    let mut samples = Vec::new();
+    loop {
+        let start = origin.elapsed();
+        let button = poll_status(handle).ok()?.button;
+        samples.push(Sample {
+            start,
+            end: origin.elapsed(),
+            button,
+        });
+        if button {
+            // Keep sampling for one more daemon interval so the replayed
+            // schedule has a poll to land on.
+            let until = origin.elapsed() + interval * 2;
+            while origin.elapsed() < until {
+                let start = origin.elapsed();
+                let button = poll_status(handle).ok()?.button;
+                samples.push(Sample {
+                    start,
+                    end: origin.elapsed(),
+                    button,
+                });
+            }
+            return Some(samples);
+        }
+        if origin.elapsed() > PRESS_TIMEOUT {
+            return None;
+        }
+    }
+}
+
+/// Wait (polling) for the button to be released.
+fn wait_release(handle: &rusb::DeviceHandle<rusb::Context>) {
+    let start = Instant::now();
+    while start.elapsed() < PRESS_TIMEOUT {
+        if poll_status(handle).is_ok_and(|s| !s.button) {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+pub fn bench(opts: &Options) {
+    let ctx = match rusb::Context::new() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("s1500d: cannot initialize libusb: {e}");
+            std::process::exit(error::EXIT_USB);
+        }
+    };
+    let handle = match open_device(&ctx) {
+        Ok(h) => h,
+        Err(UsbError::AccessDenied) => {
+            eprintln!("s1500d: {}", access_denied_help(&ctx));
+            std::process::exit(error::EXIT_ACCESS);
+        }
+        Err(UsbError::NotFound) => {
+            eprintln!("s1500d: scanner not found (04c5:11a2) — is the ADF lid open?");
+            std::process::exit(error::EXIT_NOT_FOUND);
+        }
+        Err(e) => {
+            eprintln!("s1500d: cannot open the scanner: {e}");
+            std::process::exit(error::EXIT_FAILURE);
+        }
+    };
+
+    // ── Round-trip latency ───────────────────────────────────────
+    print!("Timing {} GET_HW_STATUS round trips... ", opts.polls);
+    let _ = io::stdout().flush();
+    let mut rtts = Vec::with_capacity(opts.polls);
+    let mut failures = 0usize;
+    for _ in 0..opts.polls {
+        let start = Instant::now();
+        match poll_status(&handle) {
+            Ok(_) => rtts.push(start.elapsed()),
+            Err(_) => failures += 1,
+        }
+    }
+    println!("done");
+    match summarize(&rtts) {
+        Some(s) => print_summary("round trip", &s),
+        None => println!("round trip: no successful polls"),
+    }
+    if failures > 0 {
+        println!("failed polls: {failures}/{}", opts.polls);
+    }
+
+    if opts.presses == 0 {
+        return;
+    }
+
+    // ── Press-to-detection latency ───────────────────────────────
+    println!(
+        "\nPress and release the scan button {} times, pausing between presses.",
+        opts.presses
+    );
+    // Presses come after a pause, so time them against the idle rate.
+    let usb = usb_config();
+    let (interval, setting) = match usb.slow_poll_interval {
+        Some(slow) => (slow, "slow_poll_interval_ms"),
+        None => (usb.poll_interval, "poll_interval_ms"),
+    };
+    println!(
+        "Each press is timed against the daemon's {}ms poll interval ({setting}).",
+        interval.as_millis()
+    );
+    let mut detections = Vec::new();
+    for n in 1..=opts.presses {
+        print!("  press {n}/{}: ", opts.presses);
+        let _ = io::stdout().flush();
+        wait_release(&handle);
+        let latency = record_press(&handle, interval)
+            .as_deref()
+            .and_then(|s| detection_latency(s, interval));
+        match latency {
+            Some(d) => {
+                println!("detected after {:.1}ms", d.as_secs_f64() * 1000.0);
+                detections.push(d);
+            }
+            None => println!("no press seen — skipped"),
+        }
+        wait_release(&handle);
+    }
+    if let Some(s) = summarize(&detections) {
+        print_summary("\npress to detection", &s);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(v: u64) -> Duration {
+        Duration::from_millis(v)
+    }
+
+    #[test]
+    fn percentiles() {
+        let samples: Vec<_> = (1..=100).rev().map(ms).collect();
+        let s = summarize(&samples).unwrap();
+        assert_eq!(s.min, ms(1));
+        assert_eq!(s.p50, ms(50));
+        assert_eq!(s.p90, ms(90));
+        assert_eq!(s.p99, ms(99));
+        assert_eq!(s.max, ms(100));
+        assert_eq!(s.mean, Duration::from_micros(50_500));
+        assert!(summarize(&[]).is_none());
+        assert_eq!(summarize(&[ms(7)]).unwrap().p99, ms(7));
+    }
+
+    /// Back-to-back 2ms polls; the button goes down in the poll ending at
+    /// `press_end`.
+    fn samples(press_end: u64, total: u64) -> Vec<Sample> {
+        (0..total / 2)
+            .map(|i| Sample {
+                start: ms(i * 2),
+                end: ms(i * 2 + 2),
+                button: i * 2 + 2 >= press_end,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detection_waits_for_the_next_daemon_poll() {
+        // Daemon polls end at 2, 104, 206, … ; press pinned at 41ms.
+        let d = detection_latency(&samples(42, 300), ms(100)).unwrap();
+        assert_eq!(d, ms(104 - 41));
+    }
+
+    #[test]
+    fn detection_right_after_a_daemon_poll() {
+        // Press lands just before a daemon poll completes.
+        let d = detection_latency(&samples(104, 300), ms(100)).unwrap();
+        assert_eq!(d, ms(1));
+    }
+
+    #[test]
+    fn detection_needs_a_press() {
+        let mut s = samples(42, 300);
+        s.iter_mut().for_each(|s| s.button = false);
+        assert!(detection_latency(&s, ms(100)).is_none());
+    }
+}
@@ -0,0 +1,98 @@
+//! s1500ctl — send commands to a running s1500d over its control socket.
+//!
+//! ```sh
+//! s1500ctl pause            # stop running handlers (events still logged)
+//! s1500ctl resume
+//! s1500ctl status
+//! s1500ctl maintenance on   # ignore all events, e.g. while cleaning rollers
+//! s1500ctl debug on         # verbose logging until `debug off`
+//! s1500ctl dump             # internal state as JSON
+//! s1500ctl inhibit          # let go of the scanner for scanimage & co.
+//! s1500ctl -s /path/to.sock status
+//! ```
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Where s1500d listens when configured with the documented default.
+const DEFAULT_SOCKET: &str = "/run/s1500d/control.sock";
+
+/// The daemon answers within a poll cycle, but may be busy running a
+/// handler synchronously.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn print_usage() {
+    eprintln!(
+        "s1500ctl — control a running s1500d\n\
+         \n\
+         Usage: s1500ctl [-s SOCKET] COMMAND\n\
+         \n\
+         Commands:\n\
+         \x20 pause    Keep monitoring the scanner but stop running handlers\n\
+         \x20 resume   Run handlers again\n\
+         \x20 maintenance on|off\n\
+         \x20          Ignore all events until maintenance is turned off\n\
+         \x20 status   Show whether dispatch is running, paused or in maintenance\n\
+         \x20 debug on|off\n\
+         \x20          Switch debug logging on, or back to the configured level\n\
+         \x20 dump     Print the daemon's internal state as JSON\n\
+         \x20 inhibit  Release the scanner and stop polling, for other tools\n\
+         \x20 uninhibit\n\
+         \x20          Take the scanner back, without reporting what changed\n\
+         \n\
+         SOCKET defaults to $S1500D_SOCKET, then {DEFAULT_SOCKET}\n\
+         (set control_socket in config.toml to enable it)."
+    );
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut socket = std::env::var("S1500D_SOCKET").unwrap_or_else(|_| DEFAULT_SOCKET.into());
+    if args.first().map(String::as_str) == Some("-s") {
+        if args.len() < 2 {
+            eprintln!("s1500ctl: -s requires a socket path");
+            return ExitCode::from(2);
+        }
+        socket = args.remove(1);
+        args.remove(0);
+    }
+    let command = match args.as_slice() {
+        [cmd] if cmd != "--help" && cmd != "-h" => cmd.clone(),
+        [cmd, arg] => format!("{cmd} {arg}"),
+        _ => {
+            print_usage();
+            return ExitCode::from(2);
+        }
+    };
+
+    match send(&socket, &command) {
+        Ok(reply) => {
+            let reply = reply.trim_end();
+            match reply.strip_prefix("ok") {
+                Some(rest) => {
+                    println!("{}", rest.trim_start());
+                    ExitCode::SUCCESS
+                }
+                None => {
+                    eprintln!("s1500ctl: {}", reply.trim_start_matches("error: "));
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("s1500ctl: {socket}: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn send(socket: &str, command: &str) -> std::io::Result<String> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    writeln!(stream, "{command}")?;
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    Ok(reply)
+}
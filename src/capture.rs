@@ -0,0 +1,536 @@
+//! `analyze-capture`: decode ScanSnap traffic from a USB capture.
+//!
+//! Reads classic pcap or pcapng files recorded with Linux usbmon
+//! (`tcpdump -i usbmon1`, Wireshark) or USBPcap on Windows, picks out the
+//! bulk transfers, and prints each Fujitsu command envelope, its data and
+//! status phases, and which GET_HW_STATUS bits changed between polls.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// usbmon with the 48-byte header.
+const LINKTYPE_USB_LINUX: u32 = 189;
+/// usbmon with the 64-byte (mmapped) header.
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+/// USBPcap (Windows).
+const LINKTYPE_USBPCAP: u32 = 249;
+
+const XFER_BULK: u8 = 3;
+
+/// A captured packet: timestamp (seconds), link type and bytes.
+#[derive(Debug, Clone, PartialEq)]
+struct Packet {
+    ts: f64,
+    linktype: u32,
+    data: Vec<u8>,
+}
+
+/// Payload of one bulk transfer, in the direction it carried data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Transfer {
+    endpoint: u8,
+    data: Vec<u8>,
+}
+
+impl Transfer {
+    fn is_in(&self) -> bool {
+        self.endpoint & 0x80 != 0
+    }
+}
+
+/// Little cursor over a byte slice with switchable endianness.
+struct Reader<'a> {
+    buf: &'a [u8],
+    le: bool,
+}
+
+impl Reader<'_> {
+    fn u16(&self, at: usize) -> Option<u16> {
+        let b: [u8; 2] = self.buf.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.le {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let b: [u8; 4] = self.buf.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+}
+
+// ── File formats ─────────────────────────────────────────────────────
+
+fn read_packets(buf: &[u8]) -> Result<Vec<Packet>, String> {
+    match buf.get(..4) {
+        Some([0x0A, 0x0D, 0x0D, 0x0A]) => read_pcapng(buf),
+        Some([0xD4, 0xC3, 0xB2, 0xA1] | [0x4D, 0x3C, 0xB2, 0xA1]) => read_pcap(buf, true),
+        Some([0xA1, 0xB2, 0xC3, 0xD4] | [0xA1, 0xB2, 0x3C, 0x4D]) => read_pcap(buf, false),
+        _ => Err("not a pcap or pcapng file".into()),
+    }
+}
+
+fn read_pcap(buf: &[u8], le: bool) -> Result<Vec<Packet>, String> {
+    let r = Reader { buf, le };
+    let nanos = matches!(
+        buf[..4],
+        [0x4D, 0x3C, 0xB2, 0xA1] | [0xA1, 0xB2, 0x3C, 0x4D]
+    );
+    let linktype = r.u32(20).ok_or("truncated pcap header")? & 0x0FFF_FFFF;
+    let mut packets = Vec::new();
+    let mut at = 24;
+    while at + 16 <= buf.len() {
+        let (sec, frac, caplen) = (r.u32(at), r.u32(at + 4), r.u32(at + 8));
+        let (Some(sec), Some(frac), Some(caplen)) = (sec, frac, caplen) else {
+            break;
+        };
+        let start = at + 16;
+        let Some(data) = buf.get(start..start + caplen as usize) else {
+            break; // truncated final record
+        };
+        let scale = if nanos { 1e-9 } else { 1e-6 };
+        packets.push(Packet {
+            ts: f64::from(sec) + f64::from(frac) * scale,
+            linktype,
+            data: data.to_vec(),
+        });
+        at = start + caplen as usize;
+    }
+    Ok(packets)
+}
+
+fn read_pcapng(buf: &[u8]) -> Result<Vec<Packet>, String> {
+    // (link type, timestamp units per second) per interface, per section.
+    let mut interfaces: Vec<(u32, f64)> = Vec::new();
+    let mut packets = Vec::new();
+    let mut le = true;
+    let mut at = 0;
+    while at + 12 <= buf.len() {
+        if buf[at..at + 4] == [0x0A, 0x0D, 0x0D, 0x0A] {
+            le = match buf.get(at + 8..at + 12) {
+                Some([0x4D, 0x3C, 0x2B, 0x1A]) => true,
+                Some([0x1A, 0x2B, 0x3C, 0x4D]) => false,
+                _ => return Err("bad pcapng byte-order magic".into()),
+            };
+            interfaces.clear();
+        }
+        let r = Reader { buf, le };
+        let kind = r.u32(at).ok_or("truncated block")?;
+        let len = r.u32(at + 4).ok_or("truncated block")? as usize;
+        if len < 12 || at + len > buf.len() {
+            break; // truncated final block
+        }
+        let body = Reader {
+            buf: &buf[at + 8..at + len - 4],
+            le,
+        };
+        match kind {
+            // Interface Description Block
+            1 => {
+                let linktype = u32::from(body.u16(0).ok_or("truncated IDB")?);
+                interfaces.push((linktype, if_tsresol(&body)));
+            }
+            // Enhanced Packet Block
+            6 => {
+                let iface = body.u32(0).ok_or("truncated EPB")? as usize;
+                let &(linktype, units) = interfaces
+                    .get(iface)
+                    .ok_or("packet on undeclared interface")?;
+                let ts = (u64::from(body.u32(4).unwrap_or(0)) << 32)
+                    | u64::from(body.u32(8).unwrap_or(0));
+                let caplen = body.u32(12).ok_or("truncated EPB")? as usize;
+                let data = body.buf.get(20..20 + caplen).ok_or("truncated EPB")?;
+                packets.push(Packet {
+                    ts: ts as f64 / units,
+                    linktype,
+                    data: data.to_vec(),
+                });
+            }
+            // Simple Packet Block (interface 0, no timestamp)
+            3 => {
+                let &(linktype, _) = interfaces.first().ok_or("packet before IDB")?;
+                packets.push(Packet {
+                    ts: 0.0,
+                    linktype,
+                    data: body.buf.get(4..).unwrap_or_default().to_vec(),
+                });
+            }
+            _ => {}
+        }
+        at += len;
+    }
+    Ok(packets)
+}
+
+/// Timestamp resolution of an interface (units per second), from its
+/// `if_tsresol` option; microseconds if absent.
+fn if_tsresol(idb: &Reader) -> f64 {
+    let mut at = 8;
+    while let (Some(code), Some(len)) = (idb.u16(at), idb.u16(at + 2)) {
+        if code == 0 {
+            break;
+        }
+        if code == 9 && len == 1 {
+            if let Some(&v) = idb.buf.get(at + 4) {
+                return if v & 0x80 == 0 {
+                    10f64.powi(i32::from(v))
+                } else {
+                    2f64.powi(i32::from(v & 0x7F))
+                };
+            }
+        }
+        at += 4 + (usize::from(len) + 3) / 4 * 4;
+    }
+    1e6
+}
+
+// ── USB link layers ──────────────────────────────────────────────────
+
+/// Extract the payload of a bulk transfer: OUT data from the submission,
+/// IN data from the completion. Everything else yields `None`.
+fn bulk_transfer(packet: &Packet) -> Option<Transfer> {
+    let d = &packet.data;
+    let (endpoint, payload, is_data_phase) = match packet.linktype {
+        LINKTYPE_USB_LINUX | LINKTYPE_USB_LINUX_MMAPPED => {
+            let header = if packet.linktype == LINKTYPE_USB_LINUX {
+                48
+            } else {
+                64
+            };
+            if *d.get(9)? != XFER_BULK {
+                return None;
+            }
+            let endpoint = *d.get(10)?;
+            let event = *d.get(8)?;
+            let in_dir = endpoint & 0x80 != 0;
+            (endpoint, d.get(header..)?, (event == b'C') == in_dir)
+        }
+        LINKTYPE_USBPCAP => {
+            let header = usize::from(u16::from_le_bytes(d.get(0..2)?.try_into().ok()?));
+            if *d.get(22)? != XFER_BULK {
+                return None;
+            }
+            let endpoint = *d.get(21)?;
+            // info bit 0: 1 = completion (device → host).
+            let completion = d.get(16)? & 1 == 1;
+            let in_dir = endpoint & 0x80 != 0;
+            (endpoint, d.get(header..)?, completion == in_dir)
+        }
+        _ => return None,
+    };
+    (is_data_phase && !payload.is_empty()).then(|| Transfer {
+        endpoint,
+        data: payload.to_vec(),
+    })
+}
+
+// ── Fujitsu protocol ─────────────────────────────────────────────────
+
+const ENVELOPE_LEN: usize = 31;
+const CDB_OFFSET: usize = 19;
+const STATUS_LEN: usize = 13;
+
+/// SCSI opcodes used by the SANE `fujitsu` backend.
+fn opcode_name(op: u8) -> &'static str {
+    match op {
+        0x00 => "TEST_UNIT_READY",
+        0x03 => "REQUEST_SENSE",
+        0x12 => "INQUIRY",
+        0x15 => "MODE_SELECT",
+        0x16 => "RESERVE_UNIT",
+        0x17 => "RELEASE_UNIT",
+        0x1A => "MODE_SENSE",
+        0x1B => "SCAN",
+        0x1D => "SEND_DIAGNOSTIC",
+        0x24 => "SET_WINDOW",
+        0x28 => "READ",
+        0x2A => "SEND",
+        0x31 => "OBJECT_POSITION",
+        0xC1 => "ENDORSER",
+        0xC2 => "GET_HW_STATUS",
+        0xF1 => "SCANNER_CONTROL",
+        _ => "unknown",
+    }
+}
+
+/// Known GET_HW_STATUS bits on the S1500: (byte, mask, meaning).
+const HW_STATUS_BITS: &[(usize, u8, &str)] = &[
+    (3, 0x80, "hopper empty"),
+    (4, 0x20, "button held"),
+    (4, 0x01, "button tap"),
+    (4, 0x80, "virgin (no press since power-on)"),
+];
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Describe every bit that differs between two GET_HW_STATUS responses.
+fn status_changes(prev: &[u8], curr: &[u8]) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (i, (&a, &b)) in prev.iter().zip(curr).enumerate() {
+        for bit in (0..8).rev() {
+            let mask = 1u8 << bit;
+            if (a ^ b) & mask == 0 {
+                continue;
+            }
+            let name = HW_STATUS_BITS
+                .iter()
+                .find(|&&(byte, m, _)| byte == i && m == mask)
+                .map_or("unknown", |&(_, _, name)| name);
+            let (from, to) = (u8::from(a & mask != 0), u8::from(b & mask != 0));
+            changes.push(format!("byte {i} bit {bit} ({name}) {from}→{to}"));
+        }
+    }
+    changes
+}
+
+/// Decode a sequence of bulk transfers into a human-readable report.
+fn analyze(transfers: &[(f64, Transfer)]) -> String {
+    let mut out = String::new();
+    let mut counts: BTreeMap<u8, usize> = BTreeMap::new();
+    let mut last_op: Option<u8> = None;
+    let mut last_status: Option<Vec<u8>> = None;
+    let t0 = transfers.first().map_or(0.0, |(ts, _)| *ts);
+
+    for (ts, t) in transfers {
+        let ts = ts - t0;
+        let d = &t.data;
+        if !t.is_in() && d.len() == ENVELOPE_LEN && d[0] == 0x43 {
+            let op = d[CDB_OFFSET];
+            *counts.entry(op).or_default() += 1;
+            last_op = Some(op);
+            if op != 0xC2 {
+                let _ = writeln!(
+                    out,
+                    "{ts:10.6}  cmd   {op:02x} {:<16} cdb {}",
+                    opcode_name(op),
+                    hex(&d[CDB_OFFSET..])
+                );
+            }
+        } else if t.is_in() && d.len() == STATUS_LEN && d[0] == 0x53 {
+            if last_op != Some(0xC2) {
+                let _ = writeln!(out, "{ts:10.6}  stat  {}", hex(d));
+            }
+            last_op = None;
+        } else if t.is_in() && last_op == Some(0xC2) {
+            // Only report polls whose answer changed.
+            match &last_status {
+                Some(prev) if prev == d => {}
+                Some(prev) => {
+                    let _ = writeln!(out, "{ts:10.6}  hw    {}", hex(d));
+                    for change in status_changes(prev, d) {
+                        let _ = writeln!(out, "{:10}        {change}", "");
+                    }
+                }
+                None => {
+                    let _ = writeln!(out, "{ts:10.6}  hw    {} (first poll)", hex(d));
+                }
+            }
+            last_status = Some(d.clone());
+        } else {
+            let dir = if t.is_in() { "in" } else { "out" };
+            let shown = &d[..d.len().min(16)];
+            let more = if d.len() > 16 { " …" } else { "" };
+            let _ = writeln!(
+                out,
+                "{ts:10.6}  {dir:<5} {} bytes: {}{more}",
+                d.len(),
+                hex(shown)
+            );
+        }
+    }
+
+    let _ = writeln!(out, "\nCommands seen:");
+    for (op, n) in &counts {
+        let _ = writeln!(out, "  {op:02x} {:<16} {n:>6}", opcode_name(*op));
+    }
+    out
+}
+
+/// Entry point for `s1500d analyze-capture FILE`.
+pub fn analyze_capture(path: &str) -> Result<String, String> {
+    let buf = std::fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    let packets = read_packets(&buf).map_err(|e| format!("{path}: {e}"))?;
+    let transfers: Vec<(f64, Transfer)> = packets
+        .iter()
+        .filter_map(|p| bulk_transfer(p).map(|t| (p.ts, t)))
+        .collect();
+    if transfers.is_empty() {
+        return Err(format!(
+            "{path}: no USB bulk transfers found ({} packets; expected a usbmon or USBPcap capture)",
+            packets.len()
+        ));
+    }
+    Ok(analyze(&transfers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A usbmon (48-byte header) record.
+    fn usbmon(event: u8, endpoint: u8, xfer: u8, payload: &[u8]) -> Vec<u8> {
+        let mut d = vec![0u8; 48];
+        d[8] = event;
+        d[9] = xfer;
+        d[10] = endpoint;
+        d.extend_from_slice(payload);
+        d
+    }
+
+    fn envelope(op: u8) -> Vec<u8> {
+        let mut e = vec![0u8; ENVELOPE_LEN];
+        e[0] = 0x43;
+        e[CDB_OFFSET] = op;
+        e
+    }
+
+    fn pcap(linktype: u32, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut f = Vec::new();
+        f.extend_from_slice(&0xA1B2_C3D4u32.to_le_bytes());
+        f.extend_from_slice(&[2, 0, 4, 0]);
+        f.extend_from_slice(&[0; 8]);
+        f.extend_from_slice(&65535u32.to_le_bytes());
+        f.extend_from_slice(&linktype.to_le_bytes());
+        for (i, r) in records.iter().enumerate() {
+            f.extend_from_slice(&(i as u32).to_le_bytes());
+            f.extend_from_slice(&0u32.to_le_bytes());
+            f.extend_from_slice(&(r.len() as u32).to_le_bytes());
+            f.extend_from_slice(&(r.len() as u32).to_le_bytes());
+            f.extend_from_slice(r);
+        }
+        f
+    }
+
+    fn pcapng(linktype: u16, records: &[Vec<u8>]) -> Vec<u8> {
+        fn block(f: &mut Vec<u8>, kind: u32, body: &[u8]) {
+            let len = 12 + (body.len() + 3) / 4 * 4;
+            f.extend_from_slice(&kind.to_le_bytes());
+            f.extend_from_slice(&(len as u32).to_le_bytes());
+            f.extend_from_slice(body);
+            f.resize(f.len() + (len - 12 - body.len()), 0);
+            f.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        let mut f = Vec::new();
+        let mut shb = 0x1A2B_3C4Du32.to_le_bytes().to_vec();
+        shb.extend_from_slice(&[1, 0, 0, 0]);
+        shb.extend_from_slice(&u64::MAX.to_le_bytes());
+        block(&mut f, 0x0A0D_0D0A, &shb);
+        let mut idb = linktype.to_le_bytes().to_vec();
+        idb.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        // if_tsresol = 9 (nanoseconds), then opt_endofopt.
+        idb.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+        block(&mut f, 1, &idb);
+        for (i, r) in records.iter().enumerate() {
+            let ts = i as u64 * 500_000_000; // 0.5s apart
+            let mut epb = 0u32.to_le_bytes().to_vec();
+            epb.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+            epb.extend_from_slice(&(ts as u32).to_le_bytes());
+            epb.extend_from_slice(&(r.len() as u32).to_le_bytes());
+            epb.extend_from_slice(&(r.len() as u32).to_le_bytes());
+            epb.extend_from_slice(r);
+            block(&mut f, 6, &epb);
+        }
+        f
+    }
+
+    fn poll(status: [u8; 12]) -> Vec<Vec<u8>> {
+        vec![
+            usbmon(b'S', 0x02, XFER_BULK, &envelope(0xC2)),
+            usbmon(b'C', 0x02, XFER_BULK, &[]),
+            usbmon(b'S', 0x81, XFER_BULK, &[]),
+            usbmon(b'C', 0x81, XFER_BULK, &status),
+            usbmon(b'C', 0x81, XFER_BULK, &[0x53; STATUS_LEN]),
+        ]
+    }
+
+    const IDLE: [u8; 12] = [0, 0, 0, 0x80, 0x80, 1, 0x80, 0, 0, 0, 0, 0];
+    const HELD: [u8; 12] = [0, 0, 0, 0x80, 0x20, 1, 0x80, 0, 0, 0, 0, 0];
+
+    #[test]
+    fn usbmon_direction_filtering() {
+        let p = |d: Vec<u8>| Packet {
+            ts: 0.0,
+            linktype: LINKTYPE_USB_LINUX,
+            data: d,
+        };
+        // OUT data travels with the submission, IN data with the completion.
+        assert!(bulk_transfer(&p(usbmon(b'S', 0x02, XFER_BULK, &[1]))).is_some());
+        assert!(bulk_transfer(&p(usbmon(b'C', 0x02, XFER_BULK, &[1]))).is_none());
+        assert!(bulk_transfer(&p(usbmon(b'C', 0x81, XFER_BULK, &[1]))).is_some());
+        assert!(bulk_transfer(&p(usbmon(b'S', 0x81, XFER_BULK, &[1]))).is_none());
+        // Control transfers are ignored.
+        assert!(bulk_transfer(&p(usbmon(b'S', 0x00, 2, &[1]))).is_none());
+    }
+
+    #[test]
+    fn usbpcap_transfer() {
+        let mut d = vec![0u8; 27];
+        d[0] = 27;
+        d[16] = 1; // completion
+        d[21] = 0x81;
+        d[22] = XFER_BULK;
+        d.extend_from_slice(&[0xAA, 0xBB]);
+        let t = bulk_transfer(&Packet {
+            ts: 0.0,
+            linktype: LINKTYPE_USBPCAP,
+            data: d,
+        })
+        .unwrap();
+        assert_eq!(t.endpoint, 0x81);
+        assert_eq!(t.data, vec![0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn pcap_and_pcapng_agree() {
+        let records: Vec<_> = [poll(IDLE), poll(HELD)].concat();
+        let a = read_packets(&pcap(LINKTYPE_USB_LINUX, &records)).unwrap();
+        let b = read_packets(&pcapng(LINKTYPE_USB_LINUX as u16, &records)).unwrap();
+        assert_eq!(a.len(), records.len());
+        assert_eq!(b.len(), records.len());
+        assert!(a.iter().zip(&b).all(|(x, y)| x.data == y.data));
+        // Nanosecond if_tsresol honoured.
+        assert!((b[1].ts - 0.5).abs() < 1e-9);
+        assert!(read_packets(b"garbage!").is_err());
+    }
+
+    #[test]
+    fn status_bit_changes() {
+        assert_eq!(
+            status_changes(&IDLE, &HELD),
+            vec![
+                "byte 4 bit 7 (virgin (no press since power-on)) 1→0",
+                "byte 4 bit 5 (button held) 0→1",
+            ]
+        );
+        assert!(status_changes(&IDLE, &IDLE).is_empty());
+    }
+
+    #[test]
+    fn report_shows_changes_and_commands() {
+        let mut records: Vec<_> = [poll(IDLE), poll(IDLE), poll(HELD)].concat();
+        records.push(usbmon(b'S', 0x02, XFER_BULK, &envelope(0x12)));
+        records.push(usbmon(b'C', 0x81, XFER_BULK, &[0x06, 0x00, 0x02]));
+        records.push(usbmon(b'C', 0x81, XFER_BULK, &[0x53; STATUS_LEN]));
+        let path = std::env::temp_dir().join(format!("s1500d-{}-cap.pcap", std::process::id()));
+        std::fs::write(&path, pcap(LINKTYPE_USB_LINUX, &records)).unwrap();
+        let report = analyze_capture(path.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(report.matches("  hw  ").count(), 2, "{report}");
+        assert!(report.contains("(first poll)"));
+        assert!(report.contains("(button held) 0→1"));
+        assert!(report.contains("cmd   12 INQUIRY"));
+        assert!(report.contains("in    3 bytes: 06 00 02"));
+        assert!(report.contains("c2 GET_HW_STATUS         3"));
+    }
+}
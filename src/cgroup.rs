@@ -0,0 +1,203 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+
+use log::{info, warn};
+use serde::Deserialize;
+
+/// `[handler_cgroup]` settings: run each handler in its own cgroup (v2).
+///
+/// `path` is a parent cgroup the daemon can write to — e.g. one delegated
+/// by systemd with `Delegate=yes`. Each handler run gets a child cgroup
+/// `job-<id>` under it, which is removed when the handler exits.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CgroupConfig {
+    pub path: PathBuf,
+    /// Hard memory limit per handler run, in MiB.
+    #[serde(default)]
+    pub memory_max_mb: Option<u64>,
+    /// CPU bandwidth limit per handler run, in percent of one CPU.
+    #[serde(default)]
+    pub cpu_max_percent: Option<u32>,
+}
+
+/// cgroup v2 CPU bandwidth period, in microseconds.
+const CPU_PERIOD_US: u64 = 100_000;
+
+impl CgroupConfig {
+    fn cpu_max(&self) -> Option<String> {
+        self.cpu_max_percent
+            .map(|p| format!("{} {CPU_PERIOD_US}", u64::from(p) * CPU_PERIOD_US / 100))
+    }
+}
+
+/// Resource usage of one handler run, read back from its cgroup.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub user_us: u64,
+    pub system_us: u64,
+    pub memory_peak: Option<u64>,
+}
+
+/// A per-job cgroup, created before the handler is spawned.
+pub struct JobCgroup {
+    dir: PathBuf,
+    /// `cgroup.procs`, opened in the parent so the child can join the
+    /// cgroup between fork and exec without allocating.
+    procs: File,
+}
+
+impl JobCgroup {
+    pub fn create(config: &CgroupConfig, job: &str) -> io::Result<Self> {
+        if config.memory_max_mb.is_some() || config.cpu_max_percent.is_some() {
+            // Controllers must be enabled on the parent for limits to apply.
+            // This fails harmlessly if they already are or can't be.
+            let _ = fs::write(config.path.join("cgroup.subtree_control"), "+memory +cpu");
+        }
+        let dir = config.path.join(format!("job-{job}"));
+        fs::create_dir(&dir)?;
+        if let Some(mb) = config.memory_max_mb {
+            fs::write(dir.join("memory.max"), (mb * 1024 * 1024).to_string())?;
+        }
+        if let Some(cpu_max) = config.cpu_max() {
+            fs::write(dir.join("cpu.max"), cpu_max)?;
+        }
+        let procs = OpenOptions::new()
+            .write(true)
+            .open(dir.join("cgroup.procs"))?;
+        Ok(Self { dir, procs })
+    }
+
+    pub fn procs_fd(&self) -> RawFd {
+        self.procs.as_raw_fd()
+    }
+
+    /// Collect usage, kill anything the handler left behind, and remove
+    /// the cgroup.
+    pub fn finish(self, job: &str) -> Usage {
+        let usage = Usage {
+            memory_peak: fs::read_to_string(self.dir.join("memory.peak"))
+                .ok()
+                .and_then(|s| s.trim().parse().ok()),
+            ..fs::read_to_string(self.dir.join("cpu.stat"))
+                .map(|s| parse_cpu_stat(&s))
+                .unwrap_or_default()
+        };
+        info!("job {job}: {}", format_usage(&usage));
+
+        if fs::remove_dir(&self.dir).is_err() {
+            warn!("job {job}: handler left processes behind, killing them");
+            let _ = fs::write(self.dir.join("cgroup.kill"), "1");
+            for _ in 0..10 {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                if fs::remove_dir(&self.dir).is_ok() {
+                    return usage;
+                }
+            }
+            warn!("job {job}: cannot remove {}", self.dir.display());
+        }
+        usage
+    }
+}
+
+/// Move the calling process into the cgroup whose `cgroup.procs` is `fd`.
+///
+/// Called between fork and exec: writing `0` migrates the writer itself.
+pub fn join(fd: RawFd) -> io::Result<()> {
+    // SAFETY: fd is a valid open file owned by the parent's JobCgroup, and
+    // the buffer is a static byte string.
+    if unsafe { libc::write(fd, b"0".as_ptr().cast(), 1) } != 1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn parse_cpu_stat(text: &str) -> Usage {
+    let mut usage = Usage::default();
+    for line in text.lines() {
+        let mut parts = line.split_whitespace();
+        let (Some(key), Some(value)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let value = value.parse().unwrap_or(0);
+        match key {
+            "user_usec" => usage.user_us = value,
+            "system_usec" => usage.system_us = value,
+            _ => {}
+        }
+    }
+    usage
+}
+
+fn format_usage(usage: &Usage) -> String {
+    let cpu = format!(
+        "cpu {:.2}s user, {:.2}s system",
+        usage.user_us as f64 / 1e6,
+        usage.system_us as f64 / 1e6
+    );
+    match usage.memory_peak {
+        Some(bytes) => format!("{cpu}, peak memory {:.1} MiB", bytes as f64 / 1048576.0),
+        None => cpu,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_max_from_percent() {
+        let config = CgroupConfig {
+            path: "/sys/fs/cgroup/s1500d".into(),
+            memory_max_mb: None,
+            cpu_max_percent: Some(50),
+        };
+        assert_eq!(config.cpu_max().as_deref(), Some("50000 100000"));
+
+        let config = CgroupConfig {
+            cpu_max_percent: Some(200),
+            ..config
+        };
+        assert_eq!(config.cpu_max().as_deref(), Some("200000 100000"));
+    }
+
+    #[test]
+    fn cpu_stat_parsing() {
+        let text = "usage_usec 1500000\nuser_usec 1200000\nsystem_usec 300000\nnr_periods 0\n";
+        let usage = parse_cpu_stat(text);
+        assert_eq!(usage.user_us, 1_200_000);
+        assert_eq!(usage.system_us, 300_000);
+    }
+
+    #[test]
+    fn usage_formatting() {
+        let usage = Usage {
+            user_us: 1_250_000,
+            system_us: 500_000,
+            memory_peak: Some(64 * 1024 * 1024),
+        };
+        assert_eq!(
+            format_usage(&usage),
+            "cpu 1.25s user, 0.50s system, peak memory 64.0 MiB"
+        );
+        let usage = Usage {
+            memory_peak: None,
+            ..usage
+        };
+        assert_eq!(format_usage(&usage), "cpu 1.25s user, 0.50s system");
+    }
+
+    #[test]
+    fn config_deserializes() {
+        let config: CgroupConfig = toml::from_str(
+            r#"
+            path = "/sys/fs/cgroup/s1500d"
+            memory_max_mb = 512
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.memory_max_mb, Some(512));
+        assert!(config.cpu_max_percent.is_none());
+    }
+}
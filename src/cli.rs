@@ -0,0 +1,330 @@
+use std::path::Path;
+
+use clap::{Args, Parser, Subcommand};
+use s1500d::protocol::Model;
+
+use crate::config::Config;
+use crate::logging::LogFormat;
+use crate::{bench, doctor, status, wait};
+
+/// Shown after `--help`: what handlers receive, and what exit codes mean.
+const AFTER_HELP: &str = "\
+Handler mode (s1500d HANDLER) — handler receives the event name as $1:
+  device-arrived   Scanner lid opened (USB device appeared)
+  device-left      Scanner lid closed (USB device removed)
+  paper-in         Paper inserted into feeder
+  paper-out        Paper removed from feeder
+  button-down      Scan button pressed
+  button-up        Scan button released
+  permission-denied
+                   Scanner present but not accessible (udev rule)
+  usb-degraded     Too many recent USB errors; usb-recovered once clear
+
+Config mode (s1500d -c CONFIG.toml) — handler receives:
+  scan <profile>   Gesture completed (press count mapped to profile)
+  paper-in         Paper inserted (no second arg)
+  paper-out        Paper removed (no second arg)
+  device-arrived   Scanner appeared (no second arg)
+  device-left      Scanner removed (no second arg; $S1500D_LID_OPEN_SECONDS
+                   is how long the lid was open)
+  lid-open <m>     Lid still open after <m> minutes (lid_reminder_minutes)
+  idle <m>         No button or paper activity for <m> minutes (idle_minutes)
+  maintenance-entered|maintenance-exited <gesture|command>
+  batch-start <id>, batch-page <id> <n>, batch-end <id> <pages>
+                   Batch session ([batch] gesture)
+  permission-denied
+                   Scanner present but not accessible (udev rule)
+  usb-degraded <n>, usb-recovered
+                   USB errors crossed / fell back below [usb_health] limits
+  scan-started <profile> <dir>, scan-finished <profile> <pages> <dir>
+                   The daemon scanned a [profiles.<profile>] itself
+  handler-failed <event> <reason>
+                   A handler run failed (on_handler_failure = \"event\")
+
+Exit status: 1 a check or command failed, 2 bad arguments, 3 bad config,
+4 no libusb, 5 scanner not found, 6 permission denied, 7 cannot open
+the journal, control socket, --record file or terminal, 8 wait timed out.
+
+Set log_level = \"debug\" in config.toml (or pass --log-level debug) for
+verbose output.";
+
+/// Event daemon for the Fujitsu ScanSnap S1500.
+///
+/// Without a subcommand, runs the daemon as `run` does.
+#[derive(Debug, Parser)]
+#[command(
+    name = "s1500d",
+    version,
+    args_conflicts_with_subcommands = true,
+    after_help = AFTER_HELP
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: Global,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+/// Options every subcommand takes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Args)]
+pub struct Global {
+    /// Config file: gestures, profiles, [device], [usb] and the rest.
+    #[arg(short = 'c', long, value_name = "CONFIG.toml", global = true)]
+    pub config: Option<String>,
+    /// Log level, over RUST_LOG and the config's log_level.
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        global = true,
+        value_parser = ["off", "error", "warn", "info", "debug", "trace"]
+    )]
+    pub log_level: Option<String>,
+    /// Scanner model, over the config's [device] model.
+    #[arg(long, value_name = "MODEL", global = true, value_parser = parse_model)]
+    pub device: Option<Model>,
+    /// Log one JSON object per line, over the config's log_format.
+    #[arg(long, global = true)]
+    pub log_json: bool,
+}
+
+impl Global {
+    /// Put `--device`, `--log-level` and `--log-json` over what a config
+    /// file says, so a reload doesn't see them as changes.
+    pub fn apply(&self, config: &mut Config) {
+        if self.log_json {
+            config.log_format = LogFormat::Json;
+        }
+        if let Some(model) = self.device {
+            config.device = model;
+        }
+        if let Some(level) = &self.log_level {
+            config.log_level.clone_from(level);
+        }
+    }
+}
+
+fn parse_model(name: &str) -> Result<Model, String> {
+    Model::by_name(name).ok_or_else(|| {
+        let names: Vec<&str> = Model::ALL.iter().map(|m| m.name).collect();
+        format!("unknown model (expected one of {})", names.join(", "))
+    })
+}
+
+/// Whether a legacy `HANDLER` names a program: a file, directly or on
+/// `PATH`. A bare word that doesn't is more likely a mistyped subcommand.
+pub fn handler_exists(handler: &str) -> bool {
+    if Path::new(handler).exists() {
+        return true;
+    }
+    !handler.contains('/')
+        && std::env::var_os("PATH")
+            .is_some_and(|dirs| std::env::split_paths(&dirs).any(|dir| dir.join(handler).is_file()))
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the daemon: log events, or with -c detect gestures and run
+    /// profiles, or run HANDLER on each raw event.
+    Run(RunArgs),
+    /// Interactive hardware verification, then optional gesture-window
+    /// calibration.
+    #[command(long_flag = "doctor")]
+    Doctor(doctor::Options),
+    /// Read the scanner once: lid, paper, button. Exits 0 if attached, 5
+    /// if not.
+    Status(status::Options),
+    /// Block until the scanner does one of EVENT, print it and exit 0;
+    /// exit 8 on timeout.
+    Wait(wait::Options),
+    /// Measure status round-trip latency, then press-to-detection latency.
+    Bench(bench::Options),
+    /// Load the -c config, print any problems and warnings and exit: 0 if
+    /// it is usable, 3 if it doesn't parse or its handler can't be run.
+    CheckConfig {
+        /// Fail on warnings too.
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Decode ScanSnap traffic in a usbmon/USBPcap capture (pcap or
+    /// pcapng).
+    AnalyzeCapture {
+        #[arg(value_name = "FILE")]
+        file: String,
+    },
+}
+
+/// How to run the daemon.
+#[derive(Debug, Default, PartialEq, Eq, Args)]
+pub struct RunArgs {
+    /// Run HANDLER on each raw event (legacy mode, instead of -c).
+    #[arg(value_name = "HANDLER", conflicts_with = "config")]
+    pub handler: Option<String>,
+    /// Show a live terminal dashboard instead of logging to stderr (needs
+    /// the tui feature).
+    #[arg(long)]
+    pub tui: bool,
+    /// Play SCRIPT (or stdin) in place of the scanner: sleep MS,
+    /// paper-in, paper-out, button-down, button-up — one per line.
+    #[arg(
+        long,
+        value_name = "SCRIPT",
+        num_args = 0..=1,
+        default_missing_value = "-"
+    )]
+    pub simulate: Option<String>,
+    /// Play a --record FILE in place of the scanner.
+    #[arg(long, value_name = "FILE", conflicts_with = "simulate")]
+    pub replay: Option<String>,
+    /// Run as usual, appending every status response to FILE.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["simulate", "replay"])]
+    pub record: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+    use clap::CommandFactory;
+    use s1500d::protocol::Event;
+    use std::time::Duration;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("s1500d").chain(args.iter().copied()))
+    }
+
+    fn kind(args: &[&str]) -> ErrorKind {
+        parse(args).unwrap_err().kind()
+    }
+
+    #[test]
+    fn cli_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn runs_the_daemon_by_default() {
+        let cli = parse(&["-c", "s.toml"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.global.config.as_deref(), Some("s.toml"));
+
+        let cli = parse(&["handler.sh", "--tui"]).unwrap();
+        assert_eq!(cli.run.handler.as_deref(), Some("handler.sh"));
+        assert!(cli.run.tui);
+
+        let Some(Command::Run(run)) = parse(&["run", "-c", "s.toml", "--tui"]).unwrap().command
+        else {
+            panic!("expected run");
+        };
+        assert!(run.tui);
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        assert_eq!(kind(&["--doctro"]), ErrorKind::UnknownArgument);
+        assert_eq!(kind(&["status", "--paper"]), ErrorKind::UnknownArgument);
+        assert_eq!(
+            kind(&["handler.sh", "-c", "s.toml"]),
+            ErrorKind::ArgumentConflict
+        );
+    }
+
+    #[test]
+    fn global_flags_combine() {
+        let cli = parse(&["-c", "s.toml", "--log-level", "debug", "--device", "ix500"]).unwrap();
+        assert_eq!(cli.global.log_level.as_deref(), Some("debug"));
+        assert_eq!(cli.global.device, Some(Model::IX500));
+
+        let cli = parse(&["status", "--json", "-c", "s.toml", "--device", "s1500"]).unwrap();
+        assert_eq!(cli.global.config.as_deref(), Some("s.toml"));
+        assert!(matches!(cli.command, Some(Command::Status(s)) if s.json));
+
+        assert_eq!(kind(&["--log-level", "loud"]), ErrorKind::InvalidValue);
+        assert_eq!(kind(&["--device", "fi-7160"]), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn applies_global_flags_to_config() {
+        let mut config =
+            crate::config::parse_config("handler = \"/bin/h.sh\"\nlog_level = \"warn\"").unwrap();
+        let global = Global {
+            config: None,
+            log_level: Some("debug".into()),
+            device: Some(Model::IX500),
+            log_json: true,
+        };
+        global.apply(&mut config);
+        assert_eq!(config.log_level, "debug");
+        assert_eq!(config.device, Model::IX500);
+        assert_eq!(config.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn handlers_are_files() {
+        assert!(handler_exists("sh"));
+        assert!(handler_exists("/bin/sh"));
+        assert!(!handler_exists("doctro"));
+        assert!(!handler_exists("/nonexistent/handler.sh"));
+    }
+
+    #[test]
+    fn doctor_takes_a_flag_or_subcommand() {
+        for args in [&["--doctor", "--json"][..], &["doctor", "--json"]] {
+            let Some(Command::Doctor(opts)) = parse(args).unwrap().command else {
+                panic!("expected doctor for {args:?}");
+            };
+            assert!(opts.json);
+        }
+        let cli = parse(&["doctor", "-c", "s.toml", "--non-interactive"]).unwrap();
+        assert_eq!(cli.global.config.as_deref(), Some("s.toml"));
+    }
+
+    #[test]
+    fn wait_takes_events() {
+        let cli = parse(&["wait", "button-down", "--timeout", "60", "paper-in"]).unwrap();
+        let Some(Command::Wait(opts)) = cli.command else {
+            panic!("expected wait");
+        };
+        assert_eq!(opts.events, [Event::ButtonDown, Event::PaperIn]);
+        assert_eq!(opts.timeout, Some(Duration::from_secs(60)));
+
+        assert_eq!(kind(&["wait"]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind(&["wait", "scan"]), ErrorKind::ValueValidation);
+        assert_eq!(kind(&["wait", "idle"]), ErrorKind::ValueValidation);
+        assert_eq!(
+            kind(&["wait", "paper-in", "--timeout", "1m"]),
+            ErrorKind::ValueValidation
+        );
+    }
+
+    #[test]
+    fn bench_needs_a_poll() {
+        let Some(Command::Bench(opts)) = parse(&["bench", "--presses", "0"]).unwrap().command
+        else {
+            panic!("expected bench");
+        };
+        assert_eq!((opts.polls, opts.presses), (1000, 0));
+        assert_eq!(kind(&["bench", "--polls", "0"]), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn simulate_takes_an_optional_script() {
+        let cli = parse(&["-c", "s.toml", "--simulate", "presses.txt"]).unwrap();
+        assert_eq!(cli.run.simulate.as_deref(), Some("presses.txt"));
+        let cli = parse(&["--simulate", "-c", "s.toml"]).unwrap();
+        assert_eq!(cli.run.simulate.as_deref(), Some("-"));
+
+        let cli = parse(&["-c", "s.toml", "--record", "poll.log"]).unwrap();
+        assert_eq!(cli.run.record.as_deref(), Some("poll.log"));
+        assert_eq!(kind(&["--record"]), ErrorKind::InvalidValue);
+        assert_eq!(
+            kind(&["--record", "a.log", "--replay", "b.log"]),
+            ErrorKind::ArgumentConflict
+        );
+        assert_eq!(
+            kind(&["--simulate", "--replay", "b.log"]),
+            ErrorKind::ArgumentConflict
+        );
+    }
+}
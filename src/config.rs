@@ -1,16 +1,301 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use log::LevelFilter;
+use s1500d::Model;
 use serde::Deserialize;
 
+use crate::cgroup::CgroupConfig;
+use crate::debounce::Settle;
+use crate::handler::{IoPriority, OnFailure, RunAs};
+use crate::logging::{LogFormat, TimestampFormat, TimestampStyle};
+use crate::queue::{self, Backpressure};
+use crate::schedule::TimeWindow;
+use crate::statefile::OnStart;
+use crate::watch;
+
 #[derive(Debug, Deserialize)]
 struct RawConfig {
-    handler: String,
+    #[serde(default)]
+    handler: Option<RawHandler>,
     #[serde(default = "default_gesture_timeout_ms")]
     gesture_timeout_ms: u64,
+    #[serde(default = "default_long_press_ms")]
+    long_press_ms: u64,
+    #[serde(default)]
+    paper_debounce_ms: u64,
+    #[serde(default)]
+    button_debounce_ms: u64,
+    #[serde(default)]
+    post_scan_cooldown_ms: u64,
     #[serde(default = "default_log_level")]
     log_level: String,
     #[serde(default)]
+    log_timestamp: TimestampFormat,
+    #[serde(default)]
+    log_timezone: Option<String>,
+    #[serde(default)]
+    log_format: LogFormat,
+    #[serde(default)]
+    event_log_levels: HashMap<String, String>,
+    #[serde(default)]
+    ignore_events: Vec<String>,
+    #[serde(default)]
+    lid_reminder_minutes: Option<u64>,
+    #[serde(default)]
+    idle_minutes: Option<u64>,
+    #[serde(default)]
+    journal: Option<String>,
+    #[serde(default)]
+    on_start: OnStart,
+    #[serde(default)]
+    state_file: Option<String>,
+    #[serde(default)]
+    audit_log: Option<String>,
+    #[serde(default)]
+    event_log: Option<String>,
+    #[serde(default = "default_event_log_max_mb")]
+    event_log_max_mb: u64,
+    #[serde(default)]
+    event_log_max_days: Option<u64>,
+    #[serde(default = "default_event_log_keep")]
+    event_log_keep: u32,
+    #[serde(default)]
+    control_socket: Option<String>,
+    #[serde(default)]
+    inhibit_file: Option<String>,
+    #[serde(default)]
+    state_dump: Option<String>,
+    #[serde(default)]
+    maintenance_presses: Option<u32>,
+    #[serde(default)]
+    batch: Option<RawBatch>,
+    #[serde(default)]
+    device: RawDevice,
+    #[serde(default)]
+    usb: RawUsb,
+    #[serde(default)]
+    usb_health: RawUsbHealth,
+    #[serde(default)]
+    rate_limit: RawRateLimit,
+    #[serde(default)]
+    quiet_hours: Option<RawQuietHours>,
+    #[serde(default)]
+    dbus: Option<DbusConfig>,
+    #[serde(default)]
+    mqtt: Option<RawMqtt>,
+    #[serde(default)]
+    webhook_url: Option<String>,
+    #[serde(default)]
+    webhook_headers: HashMap<String, String>,
+    #[serde(default = "default_webhook_retries")]
+    webhook_retries: u32,
+    #[serde(default)]
+    webhook_spool: Option<String>,
+    #[serde(default = "default_backlog")]
+    webhook_backlog: usize,
+    #[serde(default)]
+    backpressure: Backpressure,
+    #[serde(default = "default_queue_size")]
+    queue_size: usize,
+    #[serde(default)]
+    handler_json: bool,
+    #[serde(default)]
+    handler_timeout_s: Option<u64>,
+    #[serde(default = "default_handler_retries")]
+    handler_retries: u32,
+    #[serde(default)]
+    on_handler_failure: OnFailure,
+    #[serde(default)]
+    handler_nice: Option<i32>,
+    #[serde(default)]
+    handler_ionice: Option<String>,
+    #[serde(default)]
+    handler_user: Option<String>,
+    #[serde(default)]
+    handler_group: Option<String>,
+    #[serde(default)]
+    handler_clear_env: bool,
+    #[serde(default)]
+    handler_path: Option<String>,
+    #[serde(default)]
+    handler_cwd: Option<String>,
+    #[serde(default)]
+    handler_cgroup: Option<CgroupConfig>,
+    /// Gesture keys map to profile names; a table is a scan profile.
+    #[serde(default)]
+    profiles: HashMap<String, toml::Value>,
+    /// Gesture → profile while the feeder is empty.
+    #[serde(default)]
+    profiles_no_paper: HashMap<String, String>,
+    /// Hold duration in milliseconds → profile, for single-press holds.
+    #[serde(default)]
+    long_press_profiles: HashMap<String, String>,
+    #[serde(default)]
+    schedule: Vec<RawScheduleEntry>,
+    /// Event name → status bit to watch.
+    #[serde(default)]
+    watch: HashMap<String, RawWatch>,
+}
+
+/// `handler = "/path"` or `handler = ["/usr/bin/python3", "/opt/h.py", …]`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawCommand {
+    Path(String),
+    Argv(Vec<String>),
+}
+
+/// A local command, or `handler = { ssh = "host", command = … }` to run it
+/// on another machine.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawHandler {
+    Local(RawCommand),
+    Remote { ssh: String, command: RawCommand },
+}
+
+#[derive(Debug, Deserialize)]
+struct RawBatch {
+    gesture: String,
+    #[serde(default = "default_batch_idle_seconds")]
+    idle_seconds: u64,
+}
+
+/// The scanner model, with optional overrides for models not in the table.
+#[derive(Debug, Default, Deserialize)]
+struct RawDevice {
+    model: Option<String>,
+    vid: Option<u16>,
+    pid: Option<u16>,
+    /// `[byte, mask]` of the hopper-empty flag.
+    hopper_empty: Option<(usize, u8)>,
+    /// `[byte, mask]` of the button bits.
+    button: Option<(usize, u8)>,
+    /// `[device."SERIAL"]` sections, by USB serial number.
+    #[serde(flatten)]
+    units: HashMap<String, RawUnit>,
+}
+
+/// `[device."SERIAL"]`: settings for one unit, over the top-level ones.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawUnit {
+    handler: Option<RawHandler>,
+    gesture_timeout_ms: Option<u64>,
+    #[serde(default)]
+    profiles: HashMap<String, String>,
+}
+
+/// Transport tuning; unset fields take the local or `remote` default.
+#[derive(Debug, Default, Deserialize)]
+struct RawUsb {
+    #[serde(default)]
+    remote: bool,
+    timeout_ms: Option<u64>,
+    poll_interval_ms: Option<u64>,
+    slow_poll_interval_ms: Option<u64>,
+    slow_after_seconds: Option<u64>,
+    reconnect_seconds: Option<u64>,
+    max_poll_failures: Option<u32>,
+    serial: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRateLimit {
+    max_per_minute: Option<u32>,
+    min_interval_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawUsbHealth {
+    #[serde(default = "default_usb_max_errors")]
+    max_errors: usize,
+    #[serde(default = "default_usb_window_seconds")]
+    window_seconds: u64,
+}
+
+impl Default for RawUsbHealth {
+    fn default() -> Self {
+        Self {
+            max_errors: default_usb_max_errors(),
+            window_seconds: default_usb_window_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RawQuietHours {
+    from: String,
+    to: String,
+    #[serde(default, rename = "override")]
+    override_gesture: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawMqtt {
+    broker: String,
+    #[serde(default = "default_mqtt_topic_prefix")]
+    topic_prefix: String,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    node_id: Option<String>,
+    #[serde(default = "default_true")]
+    discovery: bool,
+    #[serde(default = "default_mqtt_discovery_prefix")]
+    discovery_prefix: String,
+    #[serde(default)]
+    spool: Option<String>,
+    #[serde(default = "default_backlog")]
+    backlog: usize,
+}
+
+/// `[profiles.NAME]`: scan settings for a profile the daemon scans itself.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawScanProfile {
+    output_dir: String,
+    #[serde(default = "default_scan_resolution")]
+    resolution: u32,
+    #[serde(default = "default_scan_mode")]
+    mode: String,
+    #[serde(default)]
+    duplex: Option<bool>,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default = "default_scan_format")]
+    format: String,
+}
+
+/// `[profiles]` entry with handler arguments: `2 = { name = "legal", args
+/// = ["scan", "--size", "legal"] }`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfileArgs {
+    name: String,
+    args: Vec<String>,
+}
+
+/// `[watch]` entry: `name = { byte = 5, bit = 2, invert = false }`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawWatch {
+    byte: usize,
+    bit: u8,
+    #[serde(default)]
+    invert: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawScheduleEntry {
+    from: String,
+    to: String,
     profiles: HashMap<String, String>,
 }
 
@@ -18,119 +303,3056 @@ fn default_gesture_timeout_ms() -> u64 {
     600
 }
 
+fn default_long_press_ms() -> u64 {
+    1000
+}
+
+fn default_batch_idle_seconds() -> u64 {
+    300
+}
+
+fn default_usb_max_errors() -> usize {
+    10
+}
+
+fn default_usb_window_seconds() -> u64 {
+    300
+}
+
+fn default_dbus_name() -> String {
+    "io.s1500d".into()
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "s1500d".into()
+}
+
+fn default_mqtt_discovery_prefix() -> String {
+    "homeassistant".into()
+}
+
+fn default_webhook_retries() -> u32 {
+    5
+}
+
+fn default_backlog() -> usize {
+    1000
+}
+
+fn default_event_log_max_mb() -> u64 {
+    10
+}
+
+fn default_event_log_keep() -> u32 {
+    5
+}
+
+fn default_scan_resolution() -> u32 {
+    300
+}
+
+fn default_scan_mode() -> String {
+    "Color".into()
+}
+
+fn default_scan_format() -> String {
+    "tiff".into()
+}
+
+fn default_true() -> bool {
+    true
+}
+
 fn default_log_level() -> String {
     "info".into()
 }
 
+fn default_handler_retries() -> u32 {
+    3
+}
+
+fn default_queue_size() -> usize {
+    queue::DEFAULT_CAPACITY
+}
+
+/// A button gesture that a profile can be mapped to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Gesture {
+    /// `n` presses, each released promptly.
+    Presses(u32),
+    /// `n` presses, the last held for at least `long_press_ms`.
+    Hold(u32),
+}
+
+impl Gesture {
+    /// Parse a `[profiles]` key: a press count (`2`) or a name (`double`).
+    pub fn parse(key: &str) -> Result<Self, String> {
+        match key {
+            "single" => Ok(Self::Presses(1)),
+            "double" => Ok(Self::Presses(2)),
+            "triple" => Ok(Self::Presses(3)),
+            "long" => Ok(Self::Hold(1)),
+            "double-hold" => Ok(Self::Hold(2)),
+            _ => key.parse().map(Self::Presses).map_err(|_| {
+                format!(
+                    "profile key {key:?} is not a press count or gesture name \
+                     (single, double, triple, long, double-hold)"
+                )
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Gesture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Presses(n) => write!(f, "{n}x press"),
+            Self::Hold(1) => write!(f, "long press"),
+            Self::Hold(n) => write!(f, "{n}x press, last held"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Config {
-    pub handler: String,
+    /// Handler program followed by any fixed arguments; event arguments are
+    /// appended when it runs. Empty if there is none (only `webhook_url` or
+    /// scan profiles).
+    pub handler: Vec<String>,
+    /// Run the handler on this host over SSH instead of locally.
+    pub handler_ssh: Option<String>,
     pub gesture_timeout_ms: u64,
+    /// How long the last press of a gesture must be held to count as a hold.
+    pub long_press_ms: u64,
+    /// How long a paper or button change must last before it is an event
+    /// (0 = at once).
+    pub paper_debounce_ms: u64,
+    pub button_debounce_ms: u64,
+    /// How long paper and button changes are ignored after a scan.
+    pub post_scan_cooldown_ms: u64,
     pub log_level: String,
-    pub profiles: HashMap<u32, String>,
+    /// Timestamp format and zone for the log and audit log.
+    pub log_timestamps: TimestampStyle,
+    /// Free-form text lines, or one JSON object per line.
+    pub log_format: LogFormat,
+    /// Log level per event name (`paper-in`, `scan`, …); unlisted events
+    /// are logged at `info`.
+    pub event_log_levels: HashMap<String, LevelFilter>,
+    /// Events never dispatched to the handler, only logged (at `debug`
+    /// unless `event_log_levels` says otherwise).
+    pub ignore_events: Vec<String>,
+    pub lid_reminder_minutes: Option<u64>,
+    /// Minutes without button or paper activity before the `idle` event.
+    pub idle_minutes: Option<u64>,
+    pub journal: Option<String>,
+    /// What the first poll reports when there is no saved state.
+    pub on_start: OnStart,
+    /// Last known scanner state, kept across restarts.
+    pub state_file: Option<String>,
+    pub audit_log: Option<String>,
+    /// Append-only NDJSON record of every event and handler outcome.
+    pub event_log: Option<EventLogConfig>,
+    pub control_socket: Option<String>,
+    /// While this file exists, the scanner is left to other tools.
+    pub inhibit_file: Option<String>,
+    /// Press count that toggles maintenance mode instead of scanning.
+    pub maintenance_presses: Option<u32>,
+    pub batch: Option<BatchConfig>,
+    /// The scanner model (`[device]`): USB IDs and status bit layout.
+    pub device: Model,
+    /// Per-unit settings (`[device."SERIAL"]`), by USB serial number.
+    pub units: HashMap<String, UnitConfig>,
+    /// The serial of the unit whose settings are applied, if any.
+    pub unit: Option<String>,
+    /// The top-level settings a unit's replaced, to go back to when
+    /// another unit arrives.
+    pub unit_defaults: Option<UnitDefaults>,
+    pub usb: UsbConfig,
+    pub usb_health: UsbHealthConfig,
+    pub rate_limit: RateLimit,
+    pub quiet_hours: Option<QuietHours>,
+    /// Publish events on D-Bus (`[dbus]`, `dbus` feature).
+    pub dbus: Option<DbusConfig>,
+    /// Publish events and scanner state to an MQTT broker (`[mqtt]`,
+    /// `mqtt` feature).
+    pub mqtt: Option<MqttConfig>,
+    /// POST every event as JSON here (`webhook` feature).
+    pub webhook_url: Option<WebhookUrl>,
+    /// Extra request headers, sorted by name.
+    pub webhook_headers: Vec<(String, String)>,
+    /// How often to retry a request the server failed before giving up.
+    pub webhook_retries: u32,
+    /// Keep events held for the server in this file too.
+    pub webhook_spool: Option<String>,
+    /// Events held while the server is unreachable; past this the oldest go.
+    pub webhook_backlog: usize,
+    pub backpressure: Backpressure,
+    pub queue_size: usize,
+    /// Write each event to the handler's stdin as a JSON object.
+    pub handler_json: bool,
+    /// Kill a handler still running after this many seconds.
+    pub handler_timeout_s: Option<u64>,
+    /// How often to run a failed handler again, with `on_handler_failure =
+    /// "retry"`.
+    pub handler_retries: u32,
+    pub on_handler_failure: OnFailure,
+    pub handler_nice: Option<i32>,
+    pub handler_ionice: Option<IoPriority>,
+    /// Run handlers as this user (`handler_user`, `handler_group`).
+    pub handler_user: Option<RunAs>,
+    /// Give handlers only `PATH`, `HOME`, `USER`, `LOGNAME` and the
+    /// `S1500D_` variables.
+    pub handler_clear_env: bool,
+    pub handler_path: Option<String>,
+    pub handler_cwd: Option<String>,
+    pub handler_cgroup: Option<CgroupConfig>,
+    pub profiles: HashMap<Gesture, String>,
+    /// Overrides for when no paper is in the feeder (`[profiles_no_paper]`),
+    /// e.g. a cleaning routine on the gesture that otherwise scans.
+    pub profiles_no_paper: HashMap<Gesture, String>,
+    /// Single-press holds by duration (`[long_press_profiles]`): the
+    /// longest tier a hold reaches, in milliseconds, picks the profile.
+    pub long_press_profiles: BTreeMap<u64, String>,
+    /// Profiles the daemon scans itself (`[profiles.NAME]`), by name.
+    pub scan_profiles: HashMap<String, ScanProfile>,
+    /// Handler arguments for profiles mapped as `N = { name, args }`, by
+    /// profile name.
+    pub profile_args: HashMap<String, ArgsTemplate>,
+    pub schedule: Vec<ScheduleEntry>,
+    /// Status bits that raise `<name>-on`/`<name>-off` events (`[watch]`),
+    /// sorted by name.
+    pub watches: Vec<Watch>,
+    /// Where state dumps are written; `None` logs them instead.
+    pub state_dump: Option<String>,
+    /// FNV-1a hash of the config text, to tell which version was loaded.
+    pub digest: u64,
 }
 
-impl Config {
-    pub fn gesture_timeout(&self) -> Duration {
-        Duration::from_millis(self.gesture_timeout_ms)
+/// Settings from `[device."SERIAL"]` that apply while that unit is the one
+/// attached; unset ones keep the top-level value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnitConfig {
+    /// Handler argv, and the host to run it on over SSH.
+    pub handler: Option<(Vec<String>, Option<String>)>,
+    pub gesture_timeout_ms: Option<u64>,
+    /// Gesture mappings, on top of the top-level `[profiles]`.
+    pub profiles: HashMap<Gesture, String>,
+}
+
+/// What [`Config::select_unit`] replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitDefaults {
+    handler: Vec<String>,
+    handler_ssh: Option<String>,
+    gesture_timeout_ms: u64,
+    profiles: HashMap<Gesture, String>,
+}
+
+/// Batch sessions: `gesture` opens a batch and closes it again; it also
+/// closes after `idle` without a new page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchConfig {
+    pub gesture: Gesture,
+    pub idle: Duration,
+}
+
+/// A daily window in which handlers aren't run. The `override_gesture`
+/// lifts it until the lid is closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub window: TimeWindow,
+    pub override_gesture: Option<Gesture>,
+}
+
+/// Which scanner to use, and USB timing. The timing defaults suit a scanner
+/// on a local port; `remote` ones allow for the latency and hiccups of
+/// USB/IP and similar transports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbConfig {
+    pub remote: bool,
+    /// Timeout for each bulk transfer of a status poll.
+    pub timeout: Duration,
+    /// Time between status polls; each poll also keeps the link alive.
+    pub poll_interval: Duration,
+    /// Time between status polls once nothing has happened for
+    /// `slow_after`; `None` always polls at `poll_interval`.
+    pub slow_poll_interval: Option<Duration>,
+    /// How long without a change, press or pending gesture counts as idle.
+    pub slow_after: Duration,
+    /// Time between attempts to find the scanner while it is absent.
+    pub reconnect_interval: Duration,
+    /// Failed polls in a row before the device is reset and re-enumerated.
+    pub max_poll_failures: u32,
+    /// Only use the scanner with this USB serial number.
+    pub serial: Option<String>,
+}
+
+impl UsbConfig {
+    pub const LOCAL: Self = Self {
+        remote: false,
+        timeout: s1500d::device::DEFAULT_TIMEOUT,
+        poll_interval: Duration::from_millis(100),
+        slow_poll_interval: None,
+        slow_after: Duration::from_secs(10),
+        reconnect_interval: Duration::from_secs(2),
+        max_poll_failures: 3,
+        serial: None,
+    };
+
+    pub const REMOTE: Self = Self {
+        remote: true,
+        timeout: Duration::from_millis(5000),
+        poll_interval: Duration::from_millis(250),
+        slow_poll_interval: None,
+        slow_after: Duration::from_secs(10),
+        reconnect_interval: Duration::from_secs(5),
+        max_poll_failures: 10,
+        serial: None,
+    };
+}
+
+impl Default for UsbConfig {
+    fn default() -> Self {
+        Self::LOCAL
     }
 }
 
-fn parse_config(text: &str) -> Result<Config, String> {
-    let raw: RawConfig = toml::from_str(text).map_err(|e| format!("invalid config: {e}"))?;
-    let mut profiles = HashMap::new();
-    for (k, v) in raw.profiles {
-        let n: u32 = k
-            .parse()
-            .map_err(|_| format!("profile key {k:?} is not a valid press count"))?;
-        profiles.insert(n, v);
+/// Which message bus `[dbus]` connects to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bus {
+    #[default]
+    System,
+    Session,
+}
+
+/// Where to publish events on D-Bus, and under which well-known name.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DbusConfig {
+    #[serde(default)]
+    pub bus: Bus,
+    #[serde(default = "default_dbus_name")]
+    pub name: String,
+}
+
+/// The MQTT broker to publish to, and where (`[mqtt]`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub topic_prefix: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// This scanner's topic level and Home Assistant ID; `None` for the
+    /// host's instance ID.
+    pub node_id: Option<String>,
+    /// Publish Home Assistant discovery configs under `discovery_prefix`.
+    pub discovery: bool,
+    pub discovery_prefix: String,
+    /// Keep events held for the broker in this file too.
+    pub spool: Option<String>,
+    /// Events held while the broker is unreachable; past this the oldest go.
+    pub backlog: usize,
+}
+
+/// Where the event log goes and when it is rotated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventLogConfig {
+    pub path: String,
+    /// Rotate before the file would grow past this (`None` = never).
+    pub max_bytes: Option<u64>,
+    /// Rotate once the file is this old (`None` = never).
+    pub max_age: Option<Duration>,
+    /// Rotated files kept as `path.1` (newest) … `path.N`.
+    pub keep: u32,
+}
+
+/// A parsed `webhook_url`. Only plain `http://` is supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookUrl {
+    pub url: String,
+    /// `host[:port]` as written, for the `Host` header.
+    pub authority: String,
+    pub host: String,
+    pub port: u16,
+    /// Path and query, at least `/`.
+    pub path: String,
+}
+
+/// `[rate_limit]`: how often the handler may run. Both are off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Runs of each event name in any minute.
+    pub max_per_minute: Option<u32>,
+    /// Least time between two runs with the same arguments.
+    pub min_interval: Option<Duration>,
+}
+
+/// When USB errors count as a degraded connection: `max_errors` within
+/// `window` (0 = never).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbHealthConfig {
+    pub max_errors: usize,
+    pub window: Duration,
+}
+
+impl Default for UsbHealthConfig {
+    fn default() -> Self {
+        Self {
+            max_errors: default_usb_max_errors(),
+            window: Duration::from_secs(default_usb_window_seconds()),
+        }
     }
-    Ok(Config {
-        handler: raw.handler,
-        gesture_timeout_ms: raw.gesture_timeout_ms,
-        log_level: raw.log_level,
-        profiles,
-    })
 }
 
-pub fn load_config(path: &str) -> Config {
-    let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
-        eprintln!("s1500d: cannot read config {path}: {e}");
-        std::process::exit(1);
-    });
-    let config = parse_config(&text).unwrap_or_else(|e| {
-        eprintln!("s1500d: {e}");
-        std::process::exit(1);
-    });
-    if !std::path::Path::new(&config.handler).exists() {
-        eprintln!("s1500d: warning: handler not found: {}", config.handler);
+/// A GET_HW_STATUS bit to raise events for, beyond paper and button.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    /// Events are `<name>-on` and `<name>-off`.
+    pub name: String,
+    /// Byte of the response, 0-11.
+    pub byte: usize,
+    /// Bit of that byte, 0 (least significant) to 7.
+    pub bit: u8,
+    /// Count the bit as on while it is clear.
+    pub invert: bool,
+}
+
+/// How the daemon scans a profile with `scanimage` instead of running the
+/// handler for it. `mode` and `source` are the fujitsu backend's names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanProfile {
+    /// Each scan writes its pages to a new directory in here.
+    pub output_dir: String,
+    /// Dots per inch.
+    pub resolution: u32,
+    /// `Color`, `Gray`, `Halftone` or `Lineart`.
+    pub mode: String,
+    /// `ADF Duplex`, `ADF Front` or `ADF Back`.
+    pub source: String,
+    /// scanimage's `--format`: `tiff`, `png`, `jpeg` or `pnm`.
+    pub format: String,
+}
+
+/// The handler's arguments for a profile, in place of `scan <profile>`.
+/// Placeholders are filled in when the handler runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgsTemplate(Vec<String>);
+
+impl ArgsTemplate {
+    pub const PLACEHOLDERS: [&'static str; 4] = ["profile", "press_count", "event", "serial"];
+
+    fn parse(args: Vec<String>) -> Result<Self, String> {
+        if args.is_empty() {
+            return Err("args must not be empty".into());
+        }
+        for arg in &args {
+            let mut rest = arg.as_str();
+            while let Some(start) = rest.find('{') {
+                let Some(len) = rest[start..].find('}') else {
+                    break;
+                };
+                let name = &rest[start + 1..start + len];
+                let word =
+                    !name.is_empty() && name.bytes().all(|b| b.is_ascii_lowercase() || b == b'_');
+                if word && !Self::PLACEHOLDERS.contains(&name) {
+                    return Err(format!(
+                        "unknown placeholder {{{name}}} (expected one of {})",
+                        Self::PLACEHOLDERS.map(|p| format!("{{{p}}}")).join(", ")
+                    ));
+                }
+                rest = &rest[start + len + 1..];
+            }
+        }
+        Ok(Self(args))
+    }
+
+    /// The arguments for a run of `profile`; a press count or serial that
+    /// isn't known expands to nothing.
+    pub fn expand(
+        &self,
+        profile: &str,
+        press_count: Option<u32>,
+        serial: Option<&str>,
+    ) -> Vec<String> {
+        let count = press_count.map(|n| n.to_string()).unwrap_or_default();
+        self.0
+            .iter()
+            .map(|arg| {
+                arg.replace("{profile}", profile)
+                    .replace("{press_count}", &count)
+                    .replace("{event}", "scan")
+                    .replace("{serial}", serial.unwrap_or(""))
+            })
+            .collect()
     }
-    config
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Profile overrides that apply during a daily time window.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ScheduleEntry {
+    pub window: TimeWindow,
+    pub profiles: HashMap<Gesture, String>,
+}
 
-    #[test]
-    fn parse_full_config() {
-        let toml = r#"
-            handler = "/usr/bin/scan.sh"
-            gesture_timeout_ms = 500
-            log_level = "debug"
+impl Config {
+    /// Apply the `[device."SERIAL"]` section of the unit with `serial`,
+    /// or go back to the top-level settings if it has none. Returns
+    /// whether anything changed.
+    pub fn select_unit(&mut self, serial: Option<&str>) -> bool {
+        let serial = serial.filter(|s| self.units.contains_key(*s));
+        if serial == self.unit.as_deref() {
+            return false;
+        }
+        if let Some(defaults) = self.unit_defaults.take() {
+            self.handler = defaults.handler;
+            self.handler_ssh = defaults.handler_ssh;
+            self.gesture_timeout_ms = defaults.gesture_timeout_ms;
+            self.profiles = defaults.profiles;
+        }
+        self.unit = serial.map(str::to_string);
+        let Some(unit) = serial.map(|s| self.units[s].clone()) else {
+            return true;
+        };
+        self.unit_defaults = Some(UnitDefaults {
+            handler: self.handler.clone(),
+            handler_ssh: self.handler_ssh.clone(),
+            gesture_timeout_ms: self.gesture_timeout_ms,
+            profiles: self.profiles.clone(),
+        });
+        if let Some((handler, handler_ssh)) = unit.handler {
+            self.handler = handler;
+            self.handler_ssh = handler_ssh;
+        }
+        if let Some(ms) = unit.gesture_timeout_ms {
+            self.gesture_timeout_ms = ms;
+        }
+        self.profiles.extend(unit.profiles);
+        true
+    }
 
-            [profiles]
-            1 = "standard"
-            2 = "legal"
-            3 = "photo"
-        "#;
-        let config = parse_config(toml).unwrap();
-        assert_eq!(config.handler, "/usr/bin/scan.sh");
-        assert_eq!(config.gesture_timeout_ms, 500);
-        assert_eq!(config.log_level, "debug");
-        assert_eq!(config.profiles.len(), 3);
-        assert_eq!(config.profiles[&1], "standard");
-        assert_eq!(config.profiles[&2], "legal");
-        assert_eq!(config.profiles[&3], "photo");
+    pub fn gesture_timeout(&self) -> Duration {
+        Duration::from_millis(self.gesture_timeout_ms)
     }
 
-    #[test]
-    fn parse_minimal_config_uses_defaults() {
-        let toml = r#"handler = "/bin/handler.sh""#;
-        let config = parse_config(toml).unwrap();
-        assert_eq!(config.gesture_timeout_ms, 600);
-        assert_eq!(config.log_level, "info");
-        assert!(config.profiles.is_empty());
+    pub fn lid_reminder(&self) -> Option<Duration> {
+        self.lid_reminder_minutes
+            .filter(|&m| m > 0)
+            .map(|m| Duration::from_secs(m * 60))
     }
 
-    #[test]
-    fn parse_invalid_profile_key() {
-        let toml = r#"
-            handler = "/bin/h.sh"
-            [profiles]
-            abc = "bad"
-        "#;
-        assert!(parse_config(toml).is_err());
+    pub fn idle(&self) -> Option<Duration> {
+        self.idle_minutes
+            .filter(|&m| m > 0)
+            .map(|m| Duration::from_secs(m * 60))
     }
 
-    #[test]
-    fn parse_invalid_toml() {
-        assert!(parse_config("not valid toml {{{{").is_err());
+    /// How long sensor changes must last to count, for the debouncer.
+    pub fn settle(&self) -> Settle {
+        Settle {
+            paper: Duration::from_millis(self.paper_debounce_ms),
+            button: Duration::from_millis(self.button_debounce_ms),
+        }
     }
 
-    #[test]
-    fn parse_missing_handler() {
-        let toml = r#"
-            gesture_timeout_ms = 400
-            [profiles]
-            1 = "standard"
-        "#;
-        assert!(parse_config(toml).is_err());
+    /// How long after a scan the sensors are left to settle, if at all.
+    pub fn post_scan_cooldown(&self) -> Option<Duration> {
+        (self.post_scan_cooldown_ms > 0).then(|| Duration::from_millis(self.post_scan_cooldown_ms))
     }
 
-    #[test]
-    fn gesture_timeout_conversion() {
-        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
-        assert_eq!(config.gesture_timeout(), Duration::from_millis(600));
+    /// The hold threshold, if any profile is mapped to a hold gesture.
+    /// Without one, long presses count as ordinary presses.
+    pub fn long_press(&self) -> Option<Duration> {
+        let holds = std::iter::once(&self.profiles)
+            .chain(std::iter::once(&self.profiles_no_paper))
+            .chain(self.schedule.iter().map(|e| &e.profiles))
+            .flat_map(HashMap::keys)
+            .chain(self.batch.as_ref().map(|b| &b.gesture))
+            .chain(
+                self.quiet_hours
+                    .as_ref()
+                    .and_then(|q| q.override_gesture.as_ref()),
+            )
+            .any(|g| matches!(g, Gesture::Hold(_)));
+        (holds || !self.long_press_profiles.is_empty())
+            .then(|| Duration::from_millis(self.long_press_ms))
+    }
+
+    /// The longest `[long_press_profiles]` tier: a single press held this
+    /// long fires without waiting for the release.
+    pub fn longest_hold(&self) -> Option<Duration> {
+        let (&ms, _) = self.long_press_profiles.last_key_value()?;
+        Some(Duration::from_millis(ms))
+    }
+
+    /// The `[long_press_profiles]` profile for a single press held for
+    /// `held`: the longest tier it reached.
+    pub fn hold_profile(&self, held: Duration) -> Option<&String> {
+        let ms = u64::try_from(held.as_millis()).unwrap_or(u64::MAX);
+        self.long_press_profiles
+            .range(..=ms)
+            .next_back()
+            .map(|(_, profile)| profile)
+    }
+
+    /// Resolve a gesture to a profile at the given local time of day.
+    ///
+    /// The first `[[schedule]]` entry whose window contains `minute` and maps
+    /// `gesture` wins; otherwise the top-level `[profiles]` table is used.
+    pub fn profile_for(&self, gesture: Gesture, minute: u32) -> Option<&String> {
+        self.schedule
+            .iter()
+            .filter(|e| e.window.contains(minute))
+            .find_map(|e| e.profiles.get(&gesture))
+            .or_else(|| self.profiles.get(&gesture))
+    }
+
+    /// The level an event is logged at.
+    pub fn event_log_level(&self, event: &str) -> LevelFilter {
+        let default = if self.ignore_events.iter().any(|e| e == event) {
+            LevelFilter::Debug
+        } else {
+            LevelFilter::Info
+        };
+        self.event_log_levels.get(event).copied().unwrap_or(default)
+    }
+
+    /// Check for settings that parse but will fail once the daemon runs:
+    /// a handler that isn't there or can't be executed, a `handler_cwd`
+    /// that isn't a directory. Returns one message per problem.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut units: Vec<_> = self.units.iter().collect();
+        units.sort_unstable_by_key(|(serial, _)| *serial);
+        let handlers = units.into_iter().filter_map(|(serial, unit)| {
+            let (handler, ssh) = unit.handler.as_ref()?;
+            Some((format!("[device.{serial:?}] handler"), handler, ssh))
+        });
+        for (what, handler, ssh) in
+            std::iter::once(("handler".to_string(), &self.handler, &self.handler_ssh))
+                .chain(handlers)
+        {
+            let Some(program) = handler.first().filter(|_| ssh.is_none()) else {
+                continue;
+            };
+            match find_program(program) {
+                None => problems.push(format!("{what} not found: {program}")),
+                Some(path) if !is_executable(&path) => {
+                    problems.push(format!("{what} is not executable: {}", path.display()));
+                }
+                Some(_) => {}
+            }
+        }
+        if let Some(cwd) = &self.handler_cwd {
+            if !Path::new(cwd).is_dir() {
+                problems.push(format!("handler_cwd is not a directory: {cwd}"));
+            }
+        }
+        problems
+    }
+
+    /// Check for settings that parse fine but probably don't do what the
+    /// user intended. Returns one human-readable warning per finding.
+    pub fn lint(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.profiles.is_empty() && self.long_press_profiles.is_empty() {
+            warnings.push("no [profiles] mapped — button gestures will be ignored".into());
+        }
+        if self.profiles.contains_key(&Gesture::Presses(0)) {
+            warnings.push("profile 0 is unreachable — press counts start at 1".into());
+        }
+
+        let mut counts: Vec<u32> = self
+            .profiles
+            .keys()
+            .filter_map(|g| match *g {
+                Gesture::Presses(n) if n > 0 => Some(n),
+                _ => None,
+            })
+            .collect();
+        counts.sort_unstable();
+        if let Some(&max) = counts.last() {
+            let gaps: Vec<String> = (1..max)
+                .filter(|&n| !self.profiles.contains_key(&Gesture::Presses(n)))
+                .map(|n| n.to_string())
+                .collect();
+            if !gaps.is_empty() {
+                warnings.push(format!(
+                    "press count(s) {} unmapped below {max} — a miscounted gesture will be ignored",
+                    gaps.join(", ")
+                ));
+            }
+        }
+
+        let mut unmapped: Vec<&str> = self
+            .scan_profiles
+            .keys()
+            .filter(|name| {
+                !std::iter::once(&self.profiles)
+                    .chain(std::iter::once(&self.profiles_no_paper))
+                    .chain(self.schedule.iter().map(|e| &e.profiles))
+                    .any(|p| p.values().any(|v| v == *name))
+                    && !self.long_press_profiles.values().any(|v| v == *name)
+            })
+            .map(String::as_str)
+            .collect();
+        unmapped.sort_unstable();
+        for name in unmapped {
+            warnings.push(format!(
+                "[profiles.{name}] is never scanned — no gesture maps to {name:?}"
+            ));
+        }
+
+        if self.gesture_timeout() < self.usb.poll_interval {
+            warnings.push(format!(
+                "gesture_timeout_ms = {} is shorter than the {}ms poll interval — \
+                 multi-press gestures cannot be detected",
+                self.gesture_timeout_ms,
+                self.usb.poll_interval.as_millis()
+            ));
+        }
+
+        if let Some(slow) = self.usb.slow_poll_interval {
+            if slow < self.usb.poll_interval {
+                warnings.push(format!(
+                    "[usb] slow_poll_interval_ms = {} is shorter than poll_interval_ms = {} — \
+                     idle polling is faster than active polling",
+                    slow.as_millis(),
+                    self.usb.poll_interval.as_millis()
+                ));
+            }
+        }
+
+        if let Some(user) = &self.handler_user {
+            // SAFETY: geteuid cannot fail and has no preconditions.
+            warnings.extend(handler_user_warning(user, unsafe { libc::geteuid() }));
+        }
+
+        if self.event_log.is_some() && self.log_timestamps.format == TimestampFormat::None {
+            warnings.push(
+                "log_timestamp = \"none\" leaves event_log records without a timestamp".into(),
+            );
+        }
+
+        if self.dbus.is_some() && !cfg!(feature = "dbus") {
+            warnings.push(
+                "[dbus] is ignored — this build lacks the dbus feature \
+                 (cargo build --features dbus)"
+                    .into(),
+            );
+        }
+        if self.mqtt.is_some() && !cfg!(feature = "mqtt") {
+            warnings.push(
+                "[mqtt] is ignored — this build lacks the mqtt feature \
+                 (cargo build --features mqtt)"
+                    .into(),
+            );
+        }
+        if self.webhook_url.is_some() && !cfg!(feature = "webhook") {
+            warnings.push(
+                "webhook_url is ignored — this build lacks the webhook feature \
+                 (cargo build --features webhook)"
+                    .into(),
+            );
+        }
+
+        warnings
+    }
+
+    /// The settings that differ in `new`, by config key.
+    pub fn changes(&self, new: &Config) -> Vec<&'static str> {
+        [
+            ("handler", self.handler != new.handler),
+            ("handler_ssh", self.handler_ssh != new.handler_ssh),
+            (
+                "gesture_timeout_ms",
+                self.gesture_timeout_ms != new.gesture_timeout_ms,
+            ),
+            ("long_press_ms", self.long_press_ms != new.long_press_ms),
+            (
+                "paper_debounce_ms",
+                self.paper_debounce_ms != new.paper_debounce_ms,
+            ),
+            (
+                "button_debounce_ms",
+                self.button_debounce_ms != new.button_debounce_ms,
+            ),
+            (
+                "post_scan_cooldown_ms",
+                self.post_scan_cooldown_ms != new.post_scan_cooldown_ms,
+            ),
+            ("log_level", self.log_level != new.log_level),
+            ("log_timestamps", self.log_timestamps != new.log_timestamps),
+            ("log_format", self.log_format != new.log_format),
+            (
+                "event_log_levels",
+                self.event_log_levels != new.event_log_levels,
+            ),
+            ("ignore_events", self.ignore_events != new.ignore_events),
+            (
+                "lid_reminder_minutes",
+                self.lid_reminder_minutes != new.lid_reminder_minutes,
+            ),
+            ("idle_minutes", self.idle_minutes != new.idle_minutes),
+            ("journal", self.journal != new.journal),
+            ("on_start", self.on_start != new.on_start),
+            ("state_file", self.state_file != new.state_file),
+            ("audit_log", self.audit_log != new.audit_log),
+            ("event_log", self.event_log != new.event_log),
+            ("control_socket", self.control_socket != new.control_socket),
+            ("inhibit_file", self.inhibit_file != new.inhibit_file),
+            (
+                "maintenance_presses",
+                self.maintenance_presses != new.maintenance_presses,
+            ),
+            ("batch", self.batch != new.batch),
+            ("device", self.device != new.device),
+            ("device.<serial>", self.units != new.units),
+            ("usb", self.usb != new.usb),
+            ("usb_health", self.usb_health != new.usb_health),
+            ("rate_limit", self.rate_limit != new.rate_limit),
+            ("quiet_hours", self.quiet_hours != new.quiet_hours),
+            ("dbus", self.dbus != new.dbus),
+            ("mqtt", self.mqtt != new.mqtt),
+            ("webhook_url", self.webhook_url != new.webhook_url),
+            (
+                "webhook_headers",
+                self.webhook_headers != new.webhook_headers,
+            ),
+            (
+                "webhook_retries",
+                self.webhook_retries != new.webhook_retries,
+            ),
+            ("webhook_spool", self.webhook_spool != new.webhook_spool),
+            (
+                "webhook_backlog",
+                self.webhook_backlog != new.webhook_backlog,
+            ),
+            ("backpressure", self.backpressure != new.backpressure),
+            ("queue_size", self.queue_size != new.queue_size),
+            ("handler_json", self.handler_json != new.handler_json),
+            (
+                "handler_timeout_s",
+                self.handler_timeout_s != new.handler_timeout_s,
+            ),
+            (
+                "handler_retries",
+                self.handler_retries != new.handler_retries,
+            ),
+            (
+                "on_handler_failure",
+                self.on_handler_failure != new.on_handler_failure,
+            ),
+            ("handler_nice", self.handler_nice != new.handler_nice),
+            ("handler_ionice", self.handler_ionice != new.handler_ionice),
+            (
+                "handler_user",
+                self.handler_user.as_ref().map(|u| u.uid)
+                    != new.handler_user.as_ref().map(|u| u.uid),
+            ),
+            (
+                "handler_group",
+                self.handler_user.as_ref().map(|u| (u.gid, &u.groups))
+                    != new.handler_user.as_ref().map(|u| (u.gid, &u.groups)),
+            ),
+            (
+                "handler_clear_env",
+                self.handler_clear_env != new.handler_clear_env,
+            ),
+            ("handler_path", self.handler_path != new.handler_path),
+            ("handler_cwd", self.handler_cwd != new.handler_cwd),
+            ("handler_cgroup", self.handler_cgroup != new.handler_cgroup),
+            (
+                "profiles",
+                self.profiles != new.profiles
+                    || self.scan_profiles != new.scan_profiles
+                    || self.profile_args != new.profile_args,
+            ),
+            (
+                "profiles_no_paper",
+                self.profiles_no_paper != new.profiles_no_paper,
+            ),
+            (
+                "long_press_profiles",
+                self.long_press_profiles != new.long_press_profiles,
+            ),
+            ("schedule", self.schedule != new.schedule),
+            ("watch", self.watches != new.watches),
+            ("state_dump", self.state_dump != new.state_dump),
+        ]
+        .into_iter()
+        .filter_map(|(key, changed)| changed.then_some(key))
+        .collect()
+    }
+
+    /// Take the [`RESTART_KEYS`] settings from `running`, so this config
+    /// describes what the daemon is actually doing.
+    pub fn keep_startup_settings(&mut self, running: &Config) {
+        self.log_level.clone_from(&running.log_level);
+        self.log_timestamps = running.log_timestamps.clone();
+        self.log_format = running.log_format;
+        self.journal.clone_from(&running.journal);
+        self.on_start = running.on_start;
+        self.state_file.clone_from(&running.state_file);
+        self.control_socket.clone_from(&running.control_socket);
+        self.device = running.device;
+        self.usb = running.usb.clone();
+        self.usb_health = running.usb_health;
+        self.dbus.clone_from(&running.dbus);
+        self.mqtt.clone_from(&running.mqtt);
+        self.webhook_url.clone_from(&running.webhook_url);
+        self.webhook_headers.clone_from(&running.webhook_headers);
+        self.webhook_retries = running.webhook_retries;
+        self.webhook_spool.clone_from(&running.webhook_spool);
+        self.webhook_backlog = running.webhook_backlog;
+        self.backpressure = running.backpressure;
+        self.queue_size = running.queue_size;
+    }
+}
+
+/// What's wrong with `handler_user` for a daemon running as `euid`: only
+/// root can switch users, and a daemon already running as the user gains
+/// nothing from it.
+fn handler_user_warning(user: &RunAs, euid: libc::uid_t) -> Option<String> {
+    if euid == 0 {
+        None
+    } else if euid == user.uid {
+        Some(format!(
+            "handler_user = {:?} is the user the daemon already runs as — it has no \
+             effect, and handlers keep the daemon's groups",
+            user.name
+        ))
+    } else {
+        Some(format!(
+            "handler_user = {:?} needs the daemon to run as root (it runs as uid {euid}) — \
+             handlers will fail to start",
+            user.name
+        ))
+    }
+}
+
+fn parse_device(raw: RawDevice) -> Result<Model, String> {
+    let model = match raw.model.as_deref() {
+        None => Model::S1500,
+        Some(name) => Model::by_name(name).ok_or_else(|| {
+            let names: Vec<&str> = Model::ALL.iter().map(|m| m.name).collect();
+            format!(
+                "device: unknown model {name:?} (expected one of {})",
+                names.join(", ")
+            )
+        })?,
+    };
+    let model = Model {
+        vid: raw.vid.unwrap_or(model.vid),
+        pid: raw.pid.unwrap_or(model.pid),
+        hopper_empty: raw.hopper_empty.unwrap_or(model.hopper_empty),
+        button: raw.button.unwrap_or(model.button),
+        ..model
+    };
+    for (what, (byte, mask)) in [
+        ("hopper_empty", model.hopper_empty),
+        ("button", model.button),
+    ] {
+        // GET_HW_STATUS returns 12 bytes.
+        if byte >= 12 || mask == 0 {
+            return Err(format!(
+                "device: {what} = [{byte}, {mask:#04x}] needs a byte below 12 and a non-zero mask"
+            ));
+        }
+    }
+    Ok(model)
+}
+
+fn parse_units(raw: HashMap<String, RawUnit>) -> Result<HashMap<String, UnitConfig>, String> {
+    raw.into_iter()
+        .map(|(serial, unit)| {
+            let context = |e: String| format!("device.{serial:?}: {e}");
+            let unit = UnitConfig {
+                handler: unit
+                    .handler
+                    .map(parse_handler)
+                    .transpose()
+                    .map_err(context)?,
+                gesture_timeout_ms: unit.gesture_timeout_ms,
+                profiles: parse_profiles(unit.profiles).map_err(context)?,
+            };
+            Ok((serial, unit))
+        })
+        .collect()
+}
+
+fn parse_profiles(raw: HashMap<String, String>) -> Result<HashMap<Gesture, String>, String> {
+    let mut profiles = HashMap::new();
+    for (k, v) in raw {
+        let gesture = Gesture::parse(&k)?;
+        if profiles.insert(gesture, v).is_some() {
+            return Err(format!("profile key {k:?}: {gesture} is mapped twice"));
+        }
+    }
+    Ok(profiles)
+}
+
+/// Split `[profiles]` into the gesture mappings, the scan profiles
+/// (`[profiles.NAME]` tables) and the handler arguments of mappings given
+/// as `N = { name, args }`.
+#[allow(clippy::type_complexity)]
+fn split_profiles(
+    raw: HashMap<String, toml::Value>,
+) -> Result<
+    (
+        HashMap<String, String>,
+        HashMap<String, ScanProfile>,
+        HashMap<String, ArgsTemplate>,
+    ),
+    String,
+> {
+    let mut names = HashMap::new();
+    let mut scans = HashMap::new();
+    let mut templates: HashMap<String, ArgsTemplate> = HashMap::new();
+    for (key, value) in raw {
+        match value {
+            toml::Value::String(name) => {
+                names.insert(key, name);
+            }
+            toml::Value::Table(table)
+                if Gesture::parse(&key).is_ok() && table.contains_key("name") =>
+            {
+                let raw: RawProfileArgs = toml::Value::Table(table)
+                    .try_into()
+                    .map_err(|e| format!("profiles.{key}: {e}"))?;
+                let args =
+                    ArgsTemplate::parse(raw.args).map_err(|e| format!("profiles.{key}: {e}"))?;
+                if templates.get(&raw.name).is_some_and(|t| *t != args) {
+                    return Err(format!(
+                        "profiles.{key}: {:?} is mapped twice with different args",
+                        raw.name
+                    ));
+                }
+                templates.insert(raw.name.clone(), args);
+                names.insert(key, raw.name);
+            }
+            toml::Value::Table(table) => {
+                if Gesture::parse(&key).is_ok() {
+                    return Err(format!(
+                        "profiles.{key}: scan settings belong to a profile name, \
+                         not a gesture (map {key} = \"NAME\" and add [profiles.NAME])"
+                    ));
+                }
+                let raw: RawScanProfile = toml::Value::Table(table)
+                    .try_into()
+                    .map_err(|e| format!("profiles.{key}: {e}"))?;
+                let scan = parse_scan_profile(raw).map_err(|e| format!("profiles.{key}: {e}"))?;
+                scans.insert(key, scan);
+            }
+            other => {
+                return Err(format!(
+                    "profiles: {key} should be a profile name or a table, not {}",
+                    other.type_str()
+                ))
+            }
+        }
+    }
+    Ok((names, scans, templates))
+}
+
+/// `[watch]`, sorted by name. Names are lowercase letters, digits and
+/// hyphens, like the built-in events.
+fn parse_watches(raw: HashMap<String, RawWatch>) -> Result<Vec<Watch>, String> {
+    if raw.len() > watch::MAX_WATCHES {
+        return Err(format!(
+            "watch: {} entries (at most {})",
+            raw.len(),
+            watch::MAX_WATCHES
+        ));
+    }
+    let mut watches = Vec::new();
+    for (name, w) in raw {
+        let word = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+        if name.is_empty() || !name.chars().all(word) {
+            return Err(format!(
+                "watch: invalid name {name:?} (use lowercase letters, digits and -)"
+            ));
+        }
+        // GET_HW_STATUS returns 12 bytes.
+        if w.byte >= 12 || w.bit > 7 {
+            return Err(format!(
+                "watch.{name}: byte = {}, bit = {} needs a byte below 12 and a bit 0-7",
+                w.byte, w.bit
+            ));
+        }
+        watches.push(Watch {
+            name,
+            byte: w.byte,
+            bit: w.bit,
+            invert: w.invert,
+        });
+    }
+    watches.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(watches)
+}
+
+fn parse_scan_profile(raw: RawScanProfile) -> Result<ScanProfile, String> {
+    if !raw.output_dir.starts_with('/') {
+        return Err(format!(
+            "output_dir {:?} must be an absolute path",
+            raw.output_dir
+        ));
+    }
+    if !(50..=600).contains(&raw.resolution) {
+        return Err(format!(
+            "resolution = {} is out of range (50 to 600)",
+            raw.resolution
+        ));
+    }
+    if !["Color", "Gray", "Halftone", "Lineart"].contains(&raw.mode.as_str()) {
+        return Err(format!(
+            "invalid mode {:?} (expected Color, Gray, Halftone or Lineart)",
+            raw.mode
+        ));
+    }
+    let source = match (raw.source, raw.duplex) {
+        (Some(_), Some(_)) => return Err("set duplex or source, not both".into()),
+        (Some(source), None) if source.is_empty() => return Err("source is empty".into()),
+        (Some(source), None) => source,
+        (None, Some(false)) => "ADF Front".into(),
+        (None, _) => "ADF Duplex".into(),
+    };
+    if !["tiff", "png", "jpeg", "pnm"].contains(&raw.format.as_str()) {
+        return Err(format!(
+            "invalid format {:?} (expected tiff, png, jpeg or pnm)",
+            raw.format
+        ));
+    }
+    Ok(ScanProfile {
+        output_dir: raw.output_dir,
+        resolution: raw.resolution,
+        mode: raw.mode,
+        source,
+        format: raw.format,
+    })
+}
+
+/// The handler's argv, and the host to run it on over SSH.
+fn parse_handler(raw: RawHandler) -> Result<(Vec<String>, Option<String>), String> {
+    let (command, handler_ssh) = match raw {
+        RawHandler::Local(command) => (command, None),
+        RawHandler::Remote { ssh, .. } if ssh.is_empty() || ssh.starts_with('-') => {
+            return Err(format!("handler: invalid ssh host {ssh:?}"))
+        }
+        RawHandler::Remote { ssh, command } => (command, Some(ssh)),
+    };
+    let handler = match command {
+        RawCommand::Path(path) => vec![path],
+        RawCommand::Argv(argv) if argv.is_empty() => {
+            return Err("handler must name a program".into())
+        }
+        RawCommand::Argv(argv) => argv,
+    };
+    Ok((handler, handler_ssh))
+}
+
+pub fn parse_config(text: &str) -> Result<Config, String> {
+    let raw: RawConfig = toml::from_str(text).map_err(|e| format!("invalid config: {e}"))?;
+    let (profiles, scan_profiles, profile_args) = split_profiles(raw.profiles)?;
+    if let Some(name) = profile_args.keys().find(|n| scan_profiles.contains_key(*n)) {
+        return Err(format!(
+            "profiles: {name:?} is scanned by the daemon, so it takes no handler args"
+        ));
+    }
+    let (handler, handler_ssh) = match raw.handler {
+        Some(handler) => parse_handler(handler)?,
+        None if raw.webhook_url.is_some() || !scan_profiles.is_empty() => (Vec::new(), None),
+        None => {
+            return Err(
+                "handler: missing (set handler, or webhook_url or a scan profile instead)".into(),
+            )
+        }
+    };
+    let profiles = parse_profiles(profiles)?;
+    let profiles_no_paper = parse_profiles(raw.profiles_no_paper)?;
+    let schedule: Vec<ScheduleEntry> = raw
+        .schedule
+        .into_iter()
+        .map(|e| {
+            Ok(ScheduleEntry {
+                window: TimeWindow::parse(&e.from, &e.to)
+                    .map_err(|err| format!("schedule: {err}"))?,
+                profiles: parse_profiles(e.profiles)?,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    if raw.handler_timeout_s == Some(0) {
+        return Err("handler_timeout_s must be at least 1".into());
+    }
+    if let Some(n) = raw.handler_nice {
+        if !(-20..=19).contains(&n) {
+            return Err(format!("handler_nice = {n} is out of range (-20 to 19)"));
+        }
+    }
+    if raw.long_press_ms == 0 {
+        return Err("long_press_ms must be at least 1".into());
+    }
+    let handler_user = match (&raw.handler_user, &raw.handler_group) {
+        (Some(user), group) => {
+            Some(RunAs::resolve(user, group.as_deref()).map_err(|e| format!("handler_user: {e}"))?)
+        }
+        (None, Some(_)) => return Err("handler_group needs handler_user".into()),
+        (None, None) => None,
+    };
+    if let Some(cwd) = &raw.handler_cwd {
+        if !cwd.starts_with('/') {
+            return Err(format!("handler_cwd must be an absolute path, not {cwd:?}"));
+        }
+    }
+    if let Some(n) = raw.maintenance_presses {
+        if n == 0 {
+            return Err("maintenance_presses must be at least 1".into());
+        }
+        let mapped = std::iter::once(&profiles)
+            .chain(std::iter::once(&profiles_no_paper))
+            .chain(schedule.iter().map(|e| &e.profiles))
+            .find_map(|p| p.get(&Gesture::Presses(n)));
+        if let Some(profile) = mapped {
+            return Err(format!(
+                "maintenance_presses = {n} is also mapped to profile {profile:?}"
+            ));
+        }
+    }
+    let batch = raw
+        .batch
+        .map(|b| {
+            let gesture = Gesture::parse(&b.gesture).map_err(|e| format!("batch: {e}"))?;
+            if b.idle_seconds == 0 {
+                return Err("batch: idle_seconds must be at least 1".to_string());
+            }
+            let mapped = std::iter::once(&profiles)
+                .chain(std::iter::once(&profiles_no_paper))
+                .chain(schedule.iter().map(|e| &e.profiles))
+                .find_map(|p| p.get(&gesture));
+            if let Some(profile) = mapped {
+                return Err(format!(
+                    "batch: gesture {:?} is also mapped to profile {profile:?}",
+                    b.gesture
+                ));
+            }
+            if raw.maintenance_presses.map(Gesture::Presses) == Some(gesture) {
+                return Err(format!(
+                    "batch: gesture {:?} is also maintenance_presses",
+                    b.gesture
+                ));
+            }
+            Ok(BatchConfig {
+                gesture,
+                idle: Duration::from_secs(b.idle_seconds),
+            })
+        })
+        .transpose()?;
+    let quiet_hours = raw
+        .quiet_hours
+        .map(|q| {
+            let window =
+                TimeWindow::parse(&q.from, &q.to).map_err(|e| format!("quiet_hours: {e}"))?;
+            let Some(name) = q.override_gesture else {
+                return Ok(QuietHours {
+                    window,
+                    override_gesture: None,
+                });
+            };
+            let gesture = Gesture::parse(&name).map_err(|e| format!("quiet_hours: {e}"))?;
+            let mapped = std::iter::once(&profiles)
+                .chain(std::iter::once(&profiles_no_paper))
+                .chain(schedule.iter().map(|e| &e.profiles))
+                .find_map(|p| p.get(&gesture));
+            if let Some(profile) = mapped {
+                return Err(format!(
+                    "quiet_hours: override {name:?} is also mapped to profile {profile:?}"
+                ));
+            }
+            if raw.maintenance_presses.map(Gesture::Presses) == Some(gesture)
+                || batch.as_ref().is_some_and(|b| b.gesture == gesture)
+            {
+                return Err(format!(
+                    "quiet_hours: override {name:?} is already used by maintenance_presses or [batch]"
+                ));
+            }
+            Ok(QuietHours {
+                window,
+                override_gesture: Some(gesture),
+            })
+        })
+        .transpose()?;
+    let long_press_profiles = raw
+        .long_press_profiles
+        .into_iter()
+        .map(|(key, profile)| match key.parse::<u64>() {
+            Ok(ms) if ms >= raw.long_press_ms => Ok((ms, profile)),
+            Ok(ms) => Err(format!(
+                "long_press_profiles: {ms} is shorter than long_press_ms ({}) — \
+                 such a press isn't a long press",
+                raw.long_press_ms
+            )),
+            Err(_) => Err(format!(
+                "long_press_profiles: key {key:?} is not a hold duration in milliseconds"
+            )),
+        })
+        .collect::<Result<BTreeMap<_, _>, String>>()?;
+    let long_elsewhere = std::iter::once(&profiles)
+        .chain(std::iter::once(&profiles_no_paper))
+        .chain(schedule.iter().map(|e| &e.profiles))
+        .any(|p| p.contains_key(&Gesture::Hold(1)))
+        || batch
+            .as_ref()
+            .is_some_and(|b| b.gesture == Gesture::Hold(1))
+        || quiet_hours
+            .as_ref()
+            .is_some_and(|q| q.override_gesture == Some(Gesture::Hold(1)));
+    if !long_press_profiles.is_empty() && long_elsewhere {
+        return Err(
+            "long_press_profiles: the `long` gesture is also used in [profiles], \
+             [profiles_no_paper], [[schedule]], [batch] or [quiet_hours]"
+                .into(),
+        );
+    }
+    let mut raw_device = raw.device;
+    let units = parse_units(std::mem::take(&mut raw_device.units))?;
+    let device = parse_device(raw_device)?;
+    let defaults = if raw.usb.remote {
+        UsbConfig::REMOTE
+    } else {
+        UsbConfig::LOCAL
+    };
+    let usb = UsbConfig {
+        remote: raw.usb.remote,
+        timeout: raw
+            .usb
+            .timeout_ms
+            .map_or(defaults.timeout, Duration::from_millis),
+        poll_interval: raw
+            .usb
+            .poll_interval_ms
+            .map_or(defaults.poll_interval, Duration::from_millis),
+        slow_poll_interval: raw.usb.slow_poll_interval_ms.map(Duration::from_millis),
+        slow_after: raw
+            .usb
+            .slow_after_seconds
+            .map_or(defaults.slow_after, Duration::from_secs),
+        reconnect_interval: raw
+            .usb
+            .reconnect_seconds
+            .map_or(defaults.reconnect_interval, Duration::from_secs),
+        max_poll_failures: raw
+            .usb
+            .max_poll_failures
+            .unwrap_or(defaults.max_poll_failures),
+        serial: raw.usb.serial,
+    };
+    if usb.timeout.is_zero() || usb.poll_interval.is_zero() || usb.reconnect_interval.is_zero() {
+        return Err(
+            "usb: timeout_ms, poll_interval_ms and reconnect_seconds must be at least 1".into(),
+        );
+    }
+    if usb.slow_poll_interval.is_some_and(|d| d.is_zero()) || usb.slow_after.is_zero() {
+        return Err("usb: slow_poll_interval_ms and slow_after_seconds must be at least 1".into());
+    }
+    if usb.max_poll_failures == 0 {
+        return Err("usb: max_poll_failures must be at least 1".into());
+    }
+    if raw.rate_limit.max_per_minute == Some(0) || raw.rate_limit.min_interval_ms == Some(0) {
+        return Err("rate_limit: max_per_minute and min_interval_ms must be at least 1".into());
+    }
+    let rate_limit = RateLimit {
+        max_per_minute: raw.rate_limit.max_per_minute,
+        min_interval: raw.rate_limit.min_interval_ms.map(Duration::from_millis),
+    };
+    if raw.usb_health.window_seconds == 0 {
+        return Err("usb_health: window_seconds must be at least 1".into());
+    }
+    let usb_health = UsbHealthConfig {
+        max_errors: raw.usb_health.max_errors,
+        window: Duration::from_secs(raw.usb_health.window_seconds),
+    };
+    let log_timestamps = TimestampStyle {
+        format: raw.log_timestamp,
+        tz: match raw.log_timezone.as_deref() {
+            Some(name) => TimestampStyle::parse_timezone(name)?,
+            None => None,
+        },
+    };
+    let watches = parse_watches(raw.watch)?;
+    let known = |key: &str, event: &str| {
+        let watched = watches
+            .iter()
+            .any(|w| event == w.event(true) || event == w.event(false));
+        if crate::EVENT_NAMES.contains(&event) || watched {
+            Ok(())
+        } else {
+            Err(format!(
+                "{key}: unknown event {event:?} (expected one of {}, or a [watch] event)",
+                crate::EVENT_NAMES.join(", ")
+            ))
+        }
+    };
+    let event_log_levels = raw
+        .event_log_levels
+        .into_iter()
+        .map(|(event, level)| {
+            known("event_log_levels", &event)?;
+            let level = level
+                .parse()
+                .map_err(|_| format!("event_log_levels: invalid level {level:?} for {event}"))?;
+            Ok((event, level))
+        })
+        .collect::<Result<_, String>>()?;
+    let mut ignore_events = raw.ignore_events;
+    for event in &ignore_events {
+        known("ignore_events", event)?;
+    }
+    ignore_events.sort_unstable();
+    ignore_events.dedup();
+    if let Some(dbus) = &raw.dbus {
+        if !valid_bus_name(&dbus.name) {
+            return Err(format!("dbus: invalid bus name {:?}", dbus.name));
+        }
+    }
+    let mqtt = raw.mqtt.map(parse_mqtt).transpose()?;
+    let webhook_url = raw
+        .webhook_url
+        .as_deref()
+        .map(parse_webhook_url)
+        .transpose()?;
+    if raw.webhook_backlog == 0 {
+        return Err("webhook_backlog: must be at least 1".into());
+    }
+    let mut webhook_headers: Vec<(String, String)> = raw.webhook_headers.into_iter().collect();
+    webhook_headers.sort();
+    for (name, value) in &webhook_headers {
+        let token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+        if name.is_empty() || !name.chars().all(token) {
+            return Err(format!("webhook_headers: invalid header name {name:?}"));
+        }
+        if ["host", "content-length", "connection"].contains(&name.to_ascii_lowercase().as_str()) {
+            return Err(format!("webhook_headers: {name} is set by s1500d"));
+        }
+        if value.contains(['\r', '\n']) {
+            return Err(format!("webhook_headers: value of {name} spans lines"));
+        }
+    }
+    let handler_ionice = raw
+        .handler_ionice
+        .as_deref()
+        .map(IoPriority::parse)
+        .transpose()?;
+    if raw.event_log_max_days == Some(0) {
+        return Err("event_log_max_days must be at least 1".into());
+    }
+    let event_log = raw.event_log.map(|path| EventLogConfig {
+        path,
+        max_bytes: (raw.event_log_max_mb > 0).then(|| raw.event_log_max_mb * 1024 * 1024),
+        max_age: raw
+            .event_log_max_days
+            .map(|d| Duration::from_secs(d * 24 * 60 * 60)),
+        keep: raw.event_log_keep,
+    });
+    Ok(Config {
+        handler,
+        handler_ssh,
+        gesture_timeout_ms: raw.gesture_timeout_ms,
+        long_press_ms: raw.long_press_ms,
+        paper_debounce_ms: raw.paper_debounce_ms,
+        button_debounce_ms: raw.button_debounce_ms,
+        post_scan_cooldown_ms: raw.post_scan_cooldown_ms,
+        log_level: raw.log_level,
+        log_timestamps,
+        log_format: raw.log_format,
+        event_log_levels,
+        ignore_events,
+        lid_reminder_minutes: raw.lid_reminder_minutes,
+        idle_minutes: raw.idle_minutes,
+        journal: raw.journal,
+        on_start: raw.on_start,
+        state_file: raw.state_file,
+        audit_log: raw.audit_log,
+        event_log,
+        control_socket: raw.control_socket,
+        inhibit_file: raw.inhibit_file,
+        maintenance_presses: raw.maintenance_presses,
+        batch,
+        device,
+        units,
+        unit: None,
+        unit_defaults: None,
+        usb,
+        usb_health,
+        rate_limit,
+        quiet_hours,
+        dbus: raw.dbus,
+        mqtt,
+        webhook_url,
+        webhook_headers,
+        webhook_retries: raw.webhook_retries,
+        webhook_spool: raw.webhook_spool,
+        webhook_backlog: raw.webhook_backlog,
+        backpressure: raw.backpressure,
+        queue_size: raw.queue_size,
+        handler_json: raw.handler_json,
+        handler_timeout_s: raw.handler_timeout_s,
+        handler_retries: raw.handler_retries,
+        on_handler_failure: raw.on_handler_failure,
+        handler_nice: raw.handler_nice,
+        handler_ionice,
+        handler_user,
+        handler_clear_env: raw.handler_clear_env,
+        handler_path: raw.handler_path,
+        handler_cwd: raw.handler_cwd,
+        handler_cgroup: raw.handler_cgroup,
+        profiles,
+        profiles_no_paper,
+        long_press_profiles,
+        scan_profiles,
+        profile_args,
+        schedule,
+        watches,
+        state_dump: raw.state_dump,
+        digest: fnv1a(text.as_bytes()),
+    })
+}
+
+fn parse_mqtt(raw: RawMqtt) -> Result<MqttConfig, String> {
+    let (host, port) = parse_authority(&raw.broker, 1883).ok_or_else(|| {
+        format!(
+            "mqtt: invalid broker {:?} (expected host or host:port)",
+            raw.broker
+        )
+    })?;
+    for (key, prefix) in [
+        ("topic_prefix", &raw.topic_prefix),
+        ("discovery_prefix", &raw.discovery_prefix),
+    ] {
+        if prefix.is_empty()
+            || prefix.starts_with('/')
+            || prefix.ends_with('/')
+            || prefix.contains(['+', '#', '\0'])
+        {
+            return Err(format!("mqtt: invalid {key} {prefix:?}"));
+        }
+    }
+    if let Some(id) = &raw.node_id {
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!(
+                "mqtt: node_id {id:?} may only contain letters, digits, _ and -"
+            ));
+        }
+    }
+    if raw.password.is_some() && raw.username.is_none() {
+        return Err("mqtt: password needs a username".into());
+    }
+    if raw.backlog == 0 {
+        return Err("mqtt: backlog must be at least 1".into());
+    }
+    Ok(MqttConfig {
+        host,
+        port,
+        topic_prefix: raw.topic_prefix,
+        username: raw.username,
+        password: raw.password,
+        node_id: raw.node_id,
+        discovery: raw.discovery,
+        discovery_prefix: raw.discovery_prefix,
+        spool: raw.spool,
+        backlog: raw.backlog,
+    })
+}
+
+fn parse_webhook_url(url: &str) -> Result<WebhookUrl, String> {
+    let invalid = |why: &str| format!("webhook_url: {why} in {url:?}");
+    let Some(rest) = url.strip_prefix("http://") else {
+        return Err(if url.starts_with("https://") {
+            invalid("https is not supported (use a local TLS proxy)")
+        } else {
+            invalid("expected http://")
+        });
+    };
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    if authority.contains('@') {
+        return Err(invalid(
+            "credentials in the URL are not supported (use webhook_headers)",
+        ));
+    }
+    let (host, port) = parse_authority(authority, 80).ok_or_else(|| invalid("bad host or port"))?;
+    if path.contains(|c: char| c.is_whitespace() || c.is_control()) {
+        return Err(invalid("spaces in the path"));
+    }
+    let path = match path {
+        "" => "/".to_string(),
+        p if p.starts_with('?') => format!("/{p}"),
+        p => p.to_string(),
+    };
+    Ok(WebhookUrl {
+        url: url.to_string(),
+        authority: authority.to_string(),
+        host,
+        port,
+        path,
+    })
+}
+
+/// Split `host`, `host:port` or `[v6addr]:port`.
+fn parse_authority(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')?;
+        match rest {
+            "" => (host, None),
+            _ => (host, Some(rest.strip_prefix(':')?)),
+        }
+    } else {
+        match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    let port = match port {
+        Some(p) => p.parse().ok().filter(|&p| p != 0)?,
+        None => default_port,
+    };
+    (!host.is_empty() && !host.contains(char::is_whitespace)).then(|| (host.to_string(), port))
+}
+
+/// A well-known D-Bus name: two or more dot-separated elements of ASCII
+/// letters, digits, `_` and `-`, none starting with a digit.
+fn valid_bus_name(name: &str) -> bool {
+    name.len() <= 255
+        && name.split('.').count() >= 2
+        && name.split('.').all(|e| {
+            e.bytes().next().is_some_and(|b| !b.is_ascii_digit())
+                && e.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+        })
+}
+
+/// 64-bit FNV-1a: stable across builds, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Return `text` with the top-level `gesture_timeout_ms` set to `ms`,
+/// keeping everything else (comments included) as it was.
+///
+/// An existing setting is replaced in place; otherwise one is added before
+/// the first table. The result is re-parsed to make sure it took effect.
+pub fn set_gesture_timeout(text: &str, ms: u64) -> Result<String, String> {
+    let setting = format!("gesture_timeout_ms = {ms}");
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    let first_table = lines
+        .iter()
+        .position(|l| l.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    let existing = lines[..first_table].iter().position(|l| {
+        l.split_once('=')
+            .is_some_and(|(key, _)| key.trim() == "gesture_timeout_ms")
+    });
+    match existing {
+        Some(i) => lines[i] = setting,
+        None => lines.insert(first_table, setting),
+    }
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+
+    let config = parse_config(&updated)?;
+    if config.gesture_timeout_ms != ms {
+        return Err("gesture_timeout_ms did not take effect".into());
+    }
+    Ok(updated)
+}
+
+/// Settings the daemon only reads at startup: a reload reports changes to
+/// them but keeps the running values until the next restart.
+pub const RESTART_KEYS: &[&str] = &[
+    "log_level",
+    "log_timestamps",
+    "log_format",
+    "journal",
+    "on_start",
+    "state_file",
+    "control_socket",
+    "device",
+    "usb",
+    "usb_health",
+    "dbus",
+    "mqtt",
+    "webhook_url",
+    "webhook_headers",
+    "webhook_retries",
+    "webhook_spool",
+    "webhook_backlog",
+    "backpressure",
+    "queue_size",
+];
+
+/// Read and check the config again for a reload (SIGHUP). Unlike
+/// [`load_config`], problems are returned, since the daemon keeps running
+/// on the old config.
+pub fn reload_config(path: &str) -> Result<Config, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("cannot read config {path}: {e}"))?;
+    parse_config(&text)
+}
+
+/// Where `program` is run from: the path itself, or for a bare name the
+/// first match on `PATH`.
+fn find_program(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return path.exists().then_some(path);
+    }
+    std::env::var_os("PATH").and_then(|dirs| {
+        std::env::split_paths(&dirs)
+            .map(|dir| dir.join(program))
+            .find(|path| path.is_file())
+    })
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+pub fn load_config(path: &str) -> Config {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("s1500d: cannot read config {path}: {e}");
+        std::process::exit(crate::error::EXIT_CONFIG);
+    });
+    let config = parse_config(&text).unwrap_or_else(|e| {
+        eprintln!("s1500d: {e}");
+        std::process::exit(crate::error::EXIT_CONFIG);
+    });
+    for problem in config.problems() {
+        eprintln!("s1500d: warning: {problem}");
+    }
+    for warning in config.lint() {
+        eprintln!("s1500d: warning: {warning}");
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_full_config() {
+        let toml = r#"
+            handler = "/usr/bin/scan.sh"
+            gesture_timeout_ms = 500
+            log_level = "debug"
+
+            [profiles]
+            1 = "standard"
+            2 = "legal"
+            3 = "photo"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.handler, ["/usr/bin/scan.sh"]);
+        assert_eq!(config.gesture_timeout_ms, 500);
+        assert_eq!(config.log_level, "debug");
+        assert_eq!(config.profiles.len(), 3);
+        assert_eq!(config.profiles[&Gesture::Presses(1)], "standard");
+        assert_eq!(config.profiles[&Gesture::Presses(2)], "legal");
+        assert_eq!(config.profiles[&Gesture::Presses(3)], "photo");
+    }
+
+    #[test]
+    fn parse_minimal_config_uses_defaults() {
+        let toml = r#"handler = "/bin/handler.sh""#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.gesture_timeout_ms, 600);
+        assert_eq!(config.log_level, "info");
+        assert!(config.profiles.is_empty());
+        assert!(config.lid_reminder().is_none());
+        assert!(config.idle().is_none());
+        assert!(config.journal.is_none());
+        assert_eq!(config.backpressure, Backpressure::Queue);
+        assert_eq!(config.queue_size, 16);
+    }
+
+    #[test]
+    fn parse_invalid_profile_key() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [profiles]
+            abc = "bad"
+        "#;
+        assert!(parse_config(toml).is_err());
+    }
+
+    #[test]
+    fn parse_invalid_toml() {
+        assert!(parse_config("not valid toml {{{{").is_err());
+    }
+
+    #[test]
+    fn parse_remote_handler() {
+        let toml = r#"handler = { ssh = "scan@ocrbox", command = "/opt/scan/handle" }"#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.handler, ["/opt/scan/handle"]);
+        assert_eq!(config.handler_ssh.as_deref(), Some("scan@ocrbox"));
+
+        let toml = r#"handler = { ssh = "ocrbox", command = ["/usr/bin/python3", "/opt/h.py"] }"#;
+        assert_eq!(
+            parse_config(toml).unwrap().handler,
+            ["/usr/bin/python3", "/opt/h.py"]
+        );
+        let toml = r#"handler = { ssh = "-oProxyCommand=x", command = "/opt/h" }"#;
+        assert!(parse_config(toml).unwrap_err().contains("ssh host"));
+        assert!(parse_config(r#"handler = "/bin/h.sh""#)
+            .unwrap()
+            .handler_ssh
+            .is_none());
+    }
+
+    #[test]
+    fn parse_missing_handler() {
+        let toml = r#"
+            gesture_timeout_ms = 400
+            [profiles]
+            1 = "standard"
+        "#;
+        assert!(parse_config(toml).is_err());
+    }
+
+    #[test]
+    fn gesture_timeout_conversion() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.gesture_timeout(), Duration::from_millis(600));
+    }
+
+    #[test]
+    fn parse_backpressure() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            backpressure = "coalesce"
+            queue_size = 4
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.backpressure, Backpressure::Coalesce);
+        assert_eq!(config.queue_size, 4);
+        assert!(parse_config(
+            r#"handler = "/bin/h.sh"
+            backpressure = "newest""#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_watches() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [watch]
+            jam = { byte = 3, bit = 7, invert = true }
+            cover-sensor = { byte = 5, bit = 2 }
+            [event_log_levels]
+            cover-sensor-off = "debug"
+        "#;
+        let config = parse_config(toml).unwrap();
+        let names: Vec<&str> = config.watches.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, ["cover-sensor", "jam"]);
+        assert_eq!(
+            config.watches[1],
+            Watch {
+                name: "jam".into(),
+                byte: 3,
+                bit: 7,
+                invert: true,
+            }
+        );
+
+        for (watch, expect) in [
+            ("Cover = { byte = 5, bit = 2 }", "invalid name"),
+            ("cover = { byte = 12, bit = 2 }", "byte below 12"),
+            ("cover = { byte = 5, bit = 8 }", "bit 0-7"),
+            ("cover = { byte = 5, bit = 2, mask = 4 }", "unknown field"),
+        ] {
+            let toml = format!("handler = \"/bin/h.sh\"\n[watch]\n{watch}");
+            let err = parse_config(&toml).unwrap_err();
+            assert!(err.contains(expect), "{watch}: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_debounce() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.settle(), Settle::default());
+        let toml = r#"
+            handler = "/bin/h.sh"
+            paper_debounce_ms = 400
+            button_debounce_ms = 20
+        "#;
+        let settle = parse_config(toml).unwrap().settle();
+        assert_eq!(settle.paper, Duration::from_millis(400));
+        assert_eq!(settle.button, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn parse_post_scan_cooldown() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.post_scan_cooldown(), None);
+        let toml = "handler = \"/bin/h.sh\"\npost_scan_cooldown_ms = 1500";
+        assert_eq!(
+            parse_config(toml).unwrap().post_scan_cooldown(),
+            Some(Duration::from_millis(1500))
+        );
+    }
+
+    #[test]
+    fn parse_on_start() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.on_start, OnStart::Quiet);
+        assert!(config.state_file.is_none());
+        let toml = "handler = \"/bin/h.sh\"\non_start = \"emit-current\"\n\
+                    state_file = \"/var/lib/s1500d/state\"";
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.on_start, OnStart::EmitCurrent);
+        assert_eq!(config.state_file.as_deref(), Some("/var/lib/s1500d/state"));
+        assert!(parse_config("handler = \"/bin/h.sh\"\non_start = \"loud\"").is_err());
+    }
+
+    #[test]
+    fn parse_handler_failure_policy() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.handler_timeout_s, None);
+        assert_eq!(config.handler_retries, 3);
+        assert_eq!(config.on_handler_failure, OnFailure::Ignore);
+
+        let toml = r#"
+            handler = "/bin/h.sh"
+            handler_timeout_s = 120
+            handler_retries = 1
+            on_handler_failure = "retry"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.handler_timeout_s, Some(120));
+        assert_eq!(config.handler_retries, 1);
+        assert_eq!(config.on_handler_failure, OnFailure::Retry);
+
+        let err = parse_config(
+            r#"handler = "/bin/h.sh"
+            handler_timeout_s = 0"#,
+        )
+        .unwrap_err();
+        assert!(err.contains("handler_timeout_s"));
+        assert!(parse_config(
+            r#"handler = "/bin/h.sh"
+            on_handler_failure = "panic""#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn handler_user_needs_root() {
+        let user = RunAs {
+            name: "scanner".into(),
+            uid: 1000,
+            gid: 1000,
+            groups: vec![1000],
+            home: None,
+        };
+        assert_eq!(handler_user_warning(&user, 0), None);
+        let same = handler_user_warning(&user, 1000).unwrap();
+        assert!(same.contains("already runs as"), "{same}");
+        let other = handler_user_warning(&user, 1001).unwrap();
+        assert!(other.contains("needs the daemon to run as root"), "{other}");
+    }
+
+    #[test]
+    fn parse_handler_user() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            handler_user = "root"
+            handler_clear_env = true
+            handler_path = "/usr/bin:/bin"
+            handler_cwd = "/var/lib/s1500d"
+        "#;
+        let config = parse_config(toml).unwrap();
+        let user = config.handler_user.unwrap();
+        assert_eq!((user.name.as_str(), user.uid, user.gid), ("root", 0, 0));
+        assert_eq!(user.groups.first(), Some(&0));
+        assert_eq!(user.home.as_deref(), Some("/root"));
+        assert!(config.handler_clear_env);
+        assert_eq!(config.handler_path.as_deref(), Some("/usr/bin:/bin"));
+        assert_eq!(config.handler_cwd.as_deref(), Some("/var/lib/s1500d"));
+
+        // A uid without an account gets the group of the same number.
+        let toml = "handler = \"/bin/h.sh\"\nhandler_user = \"54321\"";
+        let user = parse_config(toml).unwrap().handler_user.unwrap();
+        assert_eq!(
+            (user.uid, user.gid, user.groups),
+            (54321, 54321, vec![54321])
+        );
+        assert!(user.home.is_none());
+        let toml = "handler = \"/bin/h.sh\"\nhandler_user = \"0\"\nhandler_group = \"7\"";
+        let user = parse_config(toml).unwrap().handler_user.unwrap();
+        assert_eq!((user.name.as_str(), user.gid), ("root", 7));
+
+        for (toml, error) in [
+            ("handler_user = \"no-such-user-s1500d\"", "unknown user"),
+            (
+                "handler_user = \"root\"\nhandler_group = \"no-such-group-s1500d\"",
+                "unknown group",
+            ),
+            ("handler_group = \"root\"", "needs handler_user"),
+            ("handler_cwd = \"scans\"", "absolute"),
+        ] {
+            let err = parse_config(&format!("handler = \"/bin/h.sh\"\n{toml}")).unwrap_err();
+            assert!(err.contains(error), "{toml}: {err}");
+        }
+        assert!(parse_config("handler = \"/bin/h.sh\"")
+            .unwrap()
+            .handler_user
+            .is_none());
+    }
+
+    #[test]
+    fn parse_handler_priority() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            handler_nice = 10
+            handler_ionice = "best-effort:7"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.handler_nice, Some(10));
+        assert_eq!(config.handler_ionice, Some(IoPriority::BestEffort(7)));
+
+        let toml = r#"
+            handler = "/bin/h.sh"
+            handler_nice = 25
+        "#;
+        assert!(parse_config(toml).unwrap_err().contains("handler_nice"));
+
+        let toml = r#"
+            handler = "/bin/h.sh"
+            handler_ionice = "background"
+        "#;
+        assert!(parse_config(toml).unwrap_err().contains("ionice"));
+    }
+
+    #[test]
+    fn parse_handler_argv() {
+        let toml = r#"
+            handler = ["/usr/bin/python3", "/opt/scan/handler.py", "--config", "/etc/scan.yaml"]
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(
+            config.handler,
+            [
+                "/usr/bin/python3",
+                "/opt/scan/handler.py",
+                "--config",
+                "/etc/scan.yaml"
+            ]
+        );
+        assert!(parse_config("handler = []")
+            .unwrap_err()
+            .contains("program"));
+        assert!(parse_config("handler = 3").is_err());
+    }
+
+    #[test]
+    fn parse_log_timestamp() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.log_timestamps.format, TimestampFormat::Iso8601);
+        assert!(config.log_timestamps.tz.is_none());
+
+        let toml = r#"
+            handler = "/bin/h.sh"
+            log_timestamp = "epoch"
+            log_timezone = "Europe/Berlin"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.log_timestamps.format, TimestampFormat::Epoch);
+        assert!(config.log_timestamps.tz.is_some());
+
+        let toml = r#"
+            handler = "/bin/h.sh"
+            log_timestamp = "rfc2822"
+        "#;
+        assert!(parse_config(toml).is_err());
+        let toml = r#"
+            handler = "/bin/h.sh"
+            log_timezone = "Nowhere/Special"
+        "#;
+        assert!(parse_config(toml).unwrap_err().contains("log_timezone"));
+    }
+
+    #[test]
+    fn parse_log_format() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.log_format, LogFormat::Text);
+        let toml = "handler = \"/bin/h.sh\"\nlog_format = \"json\"";
+        assert_eq!(parse_config(toml).unwrap().log_format, LogFormat::Json);
+        let toml = "handler = \"/bin/h.sh\"\nlog_format = \"logfmt\"";
+        assert!(parse_config(toml).is_err());
+    }
+
+    #[test]
+    fn parse_event_log_levels() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [event_log_levels]
+            paper-in = "debug"
+            paper-out = "off"
+            scan = "warn"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.event_log_level("paper-in"), LevelFilter::Debug);
+        assert_eq!(config.event_log_level("paper-out"), LevelFilter::Off);
+        assert_eq!(config.event_log_level("scan"), LevelFilter::Warn);
+        assert_eq!(config.event_log_level("device-left"), LevelFilter::Info);
+
+        let bad_event = "handler = \"/bin/h.sh\"\n[event_log_levels]\npaper = \"debug\"";
+        assert!(parse_config(bad_event).unwrap_err().contains("paper-in"));
+        let bad_level = "handler = \"/bin/h.sh\"\n[event_log_levels]\nscan = \"loud\"";
+        assert!(parse_config(bad_level).unwrap_err().contains("loud"));
+    }
+
+    #[test]
+    fn parse_ignore_events() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            ignore_events = ["paper-out", "paper-in", "device-left", "paper-in"]
+            [event_log_levels]
+            device-left = "warn"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(
+            config.ignore_events,
+            ["device-left", "paper-in", "paper-out"]
+        );
+        // Ignored events drop to debug unless given a level.
+        assert_eq!(config.event_log_level("paper-in"), LevelFilter::Debug);
+        assert_eq!(config.event_log_level("device-left"), LevelFilter::Warn);
+        assert_eq!(config.event_log_level("scan"), LevelFilter::Info);
+
+        let toml = "handler = \"/bin/h.sh\"\nignore_events = [\"paper\"]";
+        assert!(parse_config(toml)
+            .unwrap_err()
+            .contains("ignore_events: unknown event"));
+    }
+
+    #[test]
+    fn set_gesture_timeout_replaces_or_inserts() {
+        let text = "# my config\nhandler = \"/bin/h.sh\"\ngesture_timeout_ms = 600 # slow\n\n[profiles]\n1 = \"standard\"\n";
+        let updated = set_gesture_timeout(text, 450).unwrap();
+        assert_eq!(
+            updated,
+            "# my config\nhandler = \"/bin/h.sh\"\ngesture_timeout_ms = 450\n\n[profiles]\n1 = \"standard\"\n"
+        );
+
+        let text = "handler = \"/bin/h.sh\"\n\n[profiles]\n1 = \"standard\"\n";
+        let updated = set_gesture_timeout(text, 300).unwrap();
+        assert!(updated.contains("gesture_timeout_ms = 300\n[profiles]"));
+        assert_eq!(
+            parse_config(&updated).unwrap().profiles[&Gesture::Presses(1)],
+            "standard"
+        );
+
+        let updated = set_gesture_timeout("handler = \"/bin/h.sh\"", 350).unwrap();
+        assert_eq!(
+            updated,
+            "handler = \"/bin/h.sh\"\ngesture_timeout_ms = 350\n"
+        );
+    }
+
+    #[test]
+    fn parse_audit_log() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            audit_log = "/var/log/s1500d/audit.log"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(
+            config.audit_log.as_deref(),
+            Some("/var/log/s1500d/audit.log")
+        );
+        assert!(parse_config("handler = \"/bin/h.sh\"")
+            .unwrap()
+            .audit_log
+            .is_none());
+    }
+
+    #[test]
+    fn parse_event_log() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            event_log = "/var/log/s1500d/events.ndjson"
+        "#;
+        let log = parse_config(toml).unwrap().event_log.unwrap();
+        assert_eq!(log.path, "/var/log/s1500d/events.ndjson");
+        assert_eq!(log.max_bytes, Some(10 * 1024 * 1024));
+        assert_eq!(log.max_age, None);
+        assert_eq!(log.keep, 5);
+
+        let toml = r#"
+            handler = "/bin/h.sh"
+            event_log = "/var/log/s1500d/events.ndjson"
+            event_log_max_mb = 0
+            event_log_max_days = 30
+            event_log_keep = 12
+        "#;
+        let log = parse_config(toml).unwrap().event_log.unwrap();
+        assert_eq!(log.max_bytes, None);
+        assert_eq!(log.max_age, Some(Duration::from_secs(30 * 86400)));
+        assert_eq!(log.keep, 12);
+
+        let toml = "handler = \"/bin/h.sh\"\nevent_log = \"/tmp/e\"\nevent_log_max_days = 0";
+        assert!(parse_config(toml).is_err());
+        assert!(parse_config("handler = \"/bin/h.sh\"")
+            .unwrap()
+            .event_log
+            .is_none());
+    }
+
+    #[test]
+    fn parse_control_socket() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            control_socket = "/run/s1500d/control.sock"
+            inhibit_file = "/run/s1500d/inhibit"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(
+            config.control_socket.as_deref(),
+            Some("/run/s1500d/control.sock")
+        );
+        assert_eq!(config.inhibit_file.as_deref(), Some("/run/s1500d/inhibit"));
+    }
+
+    #[test]
+    fn parse_named_gestures() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [profiles]
+            single = "standard"
+            double = "legal"
+            4 = "receipts"
+            long = "photo"
+            double-hold = "archive"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.profiles[&Gesture::Presses(1)], "standard");
+        assert_eq!(config.profiles[&Gesture::Presses(2)], "legal");
+        assert_eq!(config.profiles[&Gesture::Presses(4)], "receipts");
+        assert_eq!(config.profiles[&Gesture::Hold(1)], "photo");
+        assert_eq!(config.profiles[&Gesture::Hold(2)], "archive");
+        assert_eq!(config.long_press(), Some(Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn parse_gesture_errors() {
+        let dup = "handler = \"/bin/h.sh\"\n[profiles]\n1 = \"a\"\nsingle = \"b\"";
+        assert!(parse_config(dup).unwrap_err().contains("mapped twice"));
+        let bad = "handler = \"/bin/h.sh\"\n[profiles]\nquadruple-tap = \"a\"";
+        assert!(parse_config(bad).unwrap_err().contains("quadruple-tap"));
+        assert!(parse_config("handler = \"/bin/h.sh\"\nlong_press_ms = 0").is_err());
+    }
+
+    #[test]
+    fn long_press_only_when_holds_mapped() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            long_press_ms = 1500
+            [profiles]
+            1 = "standard"
+        "#;
+        assert!(parse_config(toml).unwrap().long_press().is_none());
+        let toml = r#"
+            handler = "/bin/h.sh"
+            long_press_ms = 1500
+            [[schedule]]
+            from = "09:00"
+            to = "17:00"
+            profiles = { long = "office" }
+        "#;
+        assert_eq!(
+            parse_config(toml).unwrap().long_press(),
+            Some(Duration::from_millis(1500))
+        );
+    }
+
+    #[test]
+    fn parse_profiles_no_paper() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            maintenance_presses = 3
+            [profiles]
+            1 = "standard"
+            [profiles_no_paper]
+            single = "clean"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.profiles_no_paper[&Gesture::Presses(1)], "clean");
+        assert!(config.lint().is_empty());
+
+        let clash = format!("{toml}\n3 = \"calibrate\"");
+        assert!(parse_config(&clash)
+            .unwrap_err()
+            .contains("maintenance_presses"));
+    }
+
+    #[test]
+    fn parse_long_press_profiles() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [long_press_profiles]
+            1000 = "photo"
+            3000 = "archive"
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.long_press(), Some(Duration::from_millis(1000)));
+        assert_eq!(config.longest_hold(), Some(Duration::from_millis(3000)));
+        let profile = |ms| {
+            config
+                .hold_profile(Duration::from_millis(ms))
+                .map(String::as_str)
+        };
+        assert_eq!(profile(999), None);
+        assert_eq!(profile(2999), Some("photo"));
+        assert_eq!(profile(3000), Some("archive"));
+
+        for bad in [
+            "[long_press_profiles]\n500 = \"photo\"",
+            "[long_press_profiles]\n2s = \"photo\"",
+            "[profiles]\nlong = \"x\"\n[long_press_profiles]\n2000 = \"photo\"",
+            "[batch]\ngesture = \"long\"\n[long_press_profiles]\n2000 = \"photo\"",
+        ] {
+            let toml = format!("handler = \"/bin/h.sh\"\n{bad}");
+            assert!(parse_config(&toml).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn gesture_display() {
+        assert_eq!(Gesture::Presses(2).to_string(), "2x press");
+        assert_eq!(Gesture::Hold(1).to_string(), "long press");
+        assert_eq!(Gesture::Hold(2).to_string(), "2x press, last held");
+    }
+
+    #[test]
+    fn parse_batch() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [profiles]
+            1 = "standard"
+            [batch]
+            gesture = "long"
+        "#;
+        let config = parse_config(toml).unwrap();
+        let batch = config.batch.as_ref().unwrap();
+        assert_eq!(batch.gesture, Gesture::Hold(1));
+        assert_eq!(batch.idle, Duration::from_secs(300));
+        // A hold batch gesture turns on hold detection by itself.
+        assert!(config.long_press().is_some());
+
+        let clash =
+            "handler = \"/bin/h.sh\"\n[profiles]\n3 = \"photo\"\n[batch]\ngesture = \"triple\"";
+        assert!(parse_config(clash).unwrap_err().contains("photo"));
+        let clash = "handler = \"/bin/h.sh\"\nmaintenance_presses = 4\n[batch]\ngesture = \"4\"";
+        assert!(parse_config(clash).is_err());
+        let idle = "handler = \"/bin/h.sh\"\n[batch]\ngesture = \"4\"\nidle_seconds = 0";
+        assert!(parse_config(idle).is_err());
+    }
+
+    #[test]
+    fn parse_quiet_hours() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [profiles]
+            1 = "standard"
+            [quiet_hours]
+            from = "22:00"
+            to = "07:00"
+            override = "long"
+        "#;
+        let config = parse_config(toml).unwrap();
+        let quiet = config.quiet_hours.unwrap();
+        assert!(quiet.window.contains(2 * 60));
+        assert!(!quiet.window.contains(12 * 60));
+        assert_eq!(quiet.override_gesture, Some(Gesture::Hold(1)));
+        // A hold override turns on hold detection by itself.
+        assert!(config.long_press().is_some());
+
+        let toml = "handler = \"/bin/h.sh\"\n[quiet_hours]\nfrom = \"22:00\"\nto = \"07:00\"";
+        assert!(parse_config(toml)
+            .unwrap()
+            .quiet_hours
+            .unwrap()
+            .override_gesture
+            .is_none());
+
+        let clash = "handler = \"/bin/h.sh\"\n[profiles]\n1 = \"standard\"\n\
+                     [quiet_hours]\nfrom = \"22:00\"\nto = \"07:00\"\noverride = \"single\"";
+        assert!(parse_config(clash).unwrap_err().contains("standard"));
+        let clash = "handler = \"/bin/h.sh\"\n[batch]\ngesture = \"3\"\n\
+                     [quiet_hours]\nfrom = \"22:00\"\nto = \"07:00\"\noverride = \"triple\"";
+        assert!(parse_config(clash).is_err());
+        let bad = "handler = \"/bin/h.sh\"\n[quiet_hours]\nfrom = \"22:00\"\nto = \"22:00\"";
+        assert!(parse_config(bad).is_err());
+    }
+
+    #[test]
+    fn parse_device() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.device, Model::S1500);
+
+        let toml = "handler = \"/bin/h.sh\"\n[device]\nmodel = \"ix500\"";
+        assert_eq!(parse_config(toml).unwrap().device, Model::IX500);
+
+        // Overrides apply on top of the model, e.g. a mapped unlisted model.
+        let toml = "handler = \"/bin/h.sh\"\n[device]\npid = 0x1234\nbutton = [5, 0x02]";
+        let device = parse_config(toml).unwrap().device;
+        assert_eq!((device.vid, device.pid), (0x04C5, 0x1234));
+        assert_eq!(device.button, (5, 0x02));
+        assert_eq!(device.hopper_empty, Model::S1500.hopper_empty);
+
+        let toml = "handler = \"/bin/h.sh\"\n[device]\nmodel = \"fi-7160\"";
+        assert!(parse_config(toml)
+            .unwrap_err()
+            .contains("s1500, ix500, s1300i"));
+        let toml = "handler = \"/bin/h.sh\"\n[device]\nbutton = [12, 0x01]";
+        assert!(parse_config(toml).is_err());
+        let toml = "handler = \"/bin/h.sh\"\n[device]\nhopper_empty = [3, 0]";
+        assert!(parse_config(toml).is_err());
+    }
+
+    #[test]
+    fn device_sections_override_per_unit() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            gesture_timeout_ms = 400
+            [profiles]
+            1 = "standard"
+            2 = "legal"
+            [device]
+            model = "s1500"
+            [device."S1500-A"]
+            handler = { ssh = "nas", command = "/opt/scan.sh" }
+            gesture_timeout_ms = 700
+            [device."S1500-A".profiles]
+            2 = "photo"
+            [device."S1500-B"]
+            gesture_timeout_ms = 500
+        "#;
+        let mut config = parse_config(toml).unwrap();
+        assert_eq!(config.device, Model::S1500);
+        assert_eq!(config.units.len(), 2);
+
+        // A unit without a section keeps the top-level settings.
+        assert!(!config.select_unit(Some("S1500-Z")));
+        assert!(!config.select_unit(None));
+
+        assert!(config.select_unit(Some("S1500-A")));
+        assert_eq!(config.unit.as_deref(), Some("S1500-A"));
+        assert_eq!(config.handler, ["/opt/scan.sh"]);
+        assert_eq!(config.handler_ssh.as_deref(), Some("nas"));
+        assert_eq!(config.gesture_timeout_ms, 700);
+        assert_eq!(config.profiles[&Gesture::Presses(1)], "standard");
+        assert_eq!(config.profiles[&Gesture::Presses(2)], "photo");
+        assert!(!config.select_unit(Some("S1500-A")));
+
+        // Switching units starts again from the top-level settings.
+        assert!(config.select_unit(Some("S1500-B")));
+        assert_eq!(config.handler, ["/bin/h.sh"]);
+        assert_eq!(config.handler_ssh, None);
+        assert_eq!(config.gesture_timeout_ms, 500);
+        assert_eq!(config.profiles[&Gesture::Presses(2)], "legal");
+        assert!(config.select_unit(Some("S1500-Z")));
+        assert_eq!(config.unit, None);
+        assert_eq!(config.gesture_timeout_ms, 400);
+
+        let bad = "handler = \"/bin/h.sh\"\n[device.\"S1500-A\"]\ngesture_timout_ms = 1";
+        assert!(parse_config(bad).is_err());
+        let bad = "handler = \"/bin/h.sh\"\n[device.\"S1500-A\".profiles]\nx = \"legal\"";
+        assert!(parse_config(bad)
+            .unwrap_err()
+            .starts_with("device.\"S1500-A\": "));
+    }
+
+    #[test]
+    fn parse_usb() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.usb, UsbConfig::LOCAL);
+
+        let toml = "handler = \"/bin/h.sh\"\n[usb]\nremote = true";
+        assert_eq!(parse_config(toml).unwrap().usb, UsbConfig::REMOTE);
+
+        // Explicit values override the remote defaults one by one.
+        let toml = "handler = \"/bin/h.sh\"\n[usb]\nremote = true\npoll_interval_ms = 500";
+        let usb = parse_config(toml).unwrap().usb;
+        assert_eq!(usb.poll_interval, Duration::from_millis(500));
+        assert_eq!(usb.timeout, UsbConfig::REMOTE.timeout);
+
+        let toml = "handler = \"/bin/h.sh\"\n[usb]\nserial = \"A1B2C3\"";
+        let usb = parse_config(toml).unwrap().usb;
+        assert_eq!(usb.serial.as_deref(), Some("A1B2C3"));
+        assert_eq!(usb.timeout, UsbConfig::LOCAL.timeout);
+
+        let toml = "handler = \"/bin/h.sh\"\n[usb]\ntimeout_ms = 0";
+        assert!(parse_config(toml).is_err());
+        let toml = "handler = \"/bin/h.sh\"\n[usb]\nmax_poll_failures = 0";
+        assert!(parse_config(toml).is_err());
+
+        let toml = "handler = \"/bin/h.sh\"\n[usb]\nslow_poll_interval_ms = 500";
+        let usb = parse_config(toml).unwrap().usb;
+        assert_eq!(usb.slow_poll_interval, Some(Duration::from_millis(500)));
+        assert_eq!(usb.slow_after, UsbConfig::LOCAL.slow_after);
+        let toml = "handler = \"/bin/h.sh\"\n[usb]\nslow_poll_interval_ms = 0";
+        assert!(parse_config(toml).is_err());
+        let toml =
+            "handler = \"/bin/h.sh\"\n[usb]\nslow_poll_interval_ms = 500\nslow_after_seconds = 0";
+        assert!(parse_config(toml).is_err());
+        let toml = "handler = \"/bin/h.sh\"\n[usb]\nslow_poll_interval_ms = 50";
+        let warnings = parse_config(toml).unwrap().lint();
+        assert!(warnings
+            .iter()
+            .any(|w| w.contains("slow_poll_interval_ms = 50")));
+    }
+
+    #[test]
+    fn changes_and_startup_settings() {
+        let running = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        let toml = "handler = \"/bin/h.sh\"\nidle_minutes = 5\n[usb]\nremote = true";
+        let mut new = parse_config(toml).unwrap();
+        assert_eq!(running.changes(&new), vec!["idle_minutes", "usb"]);
+        assert!(running.changes(&running).is_empty());
+
+        new.keep_startup_settings(&running);
+        assert_eq!(new.usb, UsbConfig::LOCAL);
+        assert_eq!(running.changes(&new), vec!["idle_minutes"]);
+    }
+
+    #[test]
+    fn parse_dbus() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.dbus, None);
+
+        let dbus = parse_config("handler = \"/bin/h.sh\"\n[dbus]")
+            .unwrap()
+            .dbus
+            .unwrap();
+        assert_eq!(dbus.bus, Bus::System);
+        assert_eq!(dbus.name, "io.s1500d");
+
+        let toml =
+            "handler = \"/bin/h.sh\"\n[dbus]\nbus = \"session\"\nname = \"io.s1500d.office\"";
+        let dbus = parse_config(toml).unwrap().dbus.unwrap();
+        assert_eq!(dbus.bus, Bus::Session);
+        assert_eq!(dbus.name, "io.s1500d.office");
+
+        for name in ["s1500d", "io..s1500d", "io.1s1500d", ":1.42"] {
+            let toml = format!("handler = \"/bin/h.sh\"\n[dbus]\nname = \"{name}\"");
+            assert!(parse_config(&toml).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn parse_mqtt() {
+        let toml = "handler = \"/bin/h.sh\"\n[mqtt]\nbroker = \"mqtt.lan\"";
+        let mqtt = parse_config(toml).unwrap().mqtt.unwrap();
+        assert_eq!((mqtt.host.as_str(), mqtt.port), ("mqtt.lan", 1883));
+        assert_eq!(mqtt.topic_prefix, "s1500d");
+        assert!(mqtt.discovery);
+        assert_eq!(mqtt.discovery_prefix, "homeassistant");
+        assert_eq!(mqtt.node_id, None);
+        assert_eq!(mqtt.spool, None);
+        assert_eq!(mqtt.backlog, 1000);
+
+        let toml = r#"
+handler = "/bin/h.sh"
+[mqtt]
+broker = "[fd00::2]:8883"
+topic_prefix = "home/scanners"
+username = "s1500d"
+password = "secret"
+node_id = "office"
+discovery = false
+backlog = 50
+"#;
+        let mqtt = parse_config(toml).unwrap().mqtt.unwrap();
+        assert_eq!((mqtt.host.as_str(), mqtt.port), ("fd00::2", 8883));
+        assert_eq!(mqtt.topic_prefix, "home/scanners");
+        assert_eq!(mqtt.username.as_deref(), Some("s1500d"));
+        assert_eq!(mqtt.node_id.as_deref(), Some("office"));
+        assert!(!mqtt.discovery);
+        assert_eq!(mqtt.backlog, 50);
+
+        for bad in [
+            "broker = \"\"",
+            "broker = \"mqtt.lan:0\"",
+            "broker = \"mqtt.lan:x\"",
+            "broker = \"[::1\"",
+            "broker = \"m\"\ntopic_prefix = \"s1500d/#\"",
+            "broker = \"m\"\ntopic_prefix = \"/s1500d\"",
+            "broker = \"m\"\nnode_id = \"my scanner\"",
+            "broker = \"m\"\npassword = \"secret\"",
+            "broker = \"m\"\nbacklog = 0",
+        ] {
+            let toml = format!("handler = \"/bin/h.sh\"\n[mqtt]\n{bad}");
+            assert!(parse_config(&toml).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn parse_webhook() {
+        let toml = r#"
+webhook_url = "http://paperless.lan:8000/api/scan?source=s1500d"
+webhook_headers = { Authorization = "Bearer abc", X-Station = "office" }
+webhook_retries = 2
+webhook_spool = "/var/lib/s1500d/webhook.spool"
+webhook_backlog = 200
+"#;
+        let config = parse_config(toml).unwrap();
+        assert!(config.handler.is_empty());
+        let url = config.webhook_url.unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("paperless.lan", 8000));
+        assert_eq!(url.authority, "paperless.lan:8000");
+        assert_eq!(url.path, "/api/scan?source=s1500d");
+        assert_eq!(config.webhook_headers[0].0, "Authorization");
+        assert_eq!(config.webhook_retries, 2);
+        assert_eq!(
+            config.webhook_spool.as_deref(),
+            Some("/var/lib/s1500d/webhook.spool")
+        );
+        assert_eq!(config.webhook_backlog, 200);
+        let toml = "webhook_url = \"http://h\"\nwebhook_backlog = 0";
+        assert!(parse_config(toml).is_err());
+
+        let url = parse_webhook_url("http://[::1]").unwrap();
+        assert_eq!(
+            (url.host.as_str(), url.port, url.path.as_str()),
+            ("::1", 80, "/")
+        );
+        assert_eq!(parse_webhook_url("http://h?x=1").unwrap().path, "/?x=1");
+
+        for bad in [
+            "https://example.com/hook",
+            "ftp://example.com",
+            "http://user:pw@example.com/",
+            "http://:80/",
+            "http://example.com/a b",
+        ] {
+            assert!(parse_webhook_url(bad).is_err(), "{bad}");
+        }
+        for headers in [
+            r#"{ "Bad Name" = "x" }"#,
+            r#"{ Content-Length = "1" }"#,
+            r#"{ X-A = "a
+b" }"#,
+        ] {
+            let toml = format!("webhook_url = \"http://h/\"\nwebhook_headers = {headers}");
+            assert!(parse_config(&toml).is_err(), "{headers}");
+        }
+
+        // Without a webhook, the handler is still required.
+        assert!(parse_config("idle_minutes = 5")
+            .unwrap_err()
+            .contains("handler"));
+    }
+
+    #[test]
+    fn parse_scan_profiles() {
+        let toml = r#"
+[profiles]
+1 = "standard"
+2 = "legal"
+
+[profiles.legal]
+output_dir = "/srv/scans"
+resolution = 200
+mode = "Gray"
+duplex = false
+"#;
+        let config = parse_config(toml).unwrap();
+        assert!(config.handler.is_empty());
+        assert_eq!(config.profiles[&Gesture::Presses(2)], "legal");
+        assert_eq!(
+            config.scan_profiles["legal"],
+            ScanProfile {
+                output_dir: "/srv/scans".into(),
+                resolution: 200,
+                mode: "Gray".into(),
+                source: "ADF Front".into(),
+                format: "tiff".into(),
+            }
+        );
+        assert!(!config.scan_profiles.contains_key("standard"));
+        assert!(config.lint().is_empty());
+
+        let toml = "[profiles.archive]\noutput_dir = \"/srv/scans\"";
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.scan_profiles["archive"].source, "ADF Duplex");
+        assert!(config
+            .lint()
+            .iter()
+            .any(|w| w.contains("[profiles.archive] is never scanned")));
+
+        for bad in [
+            "output_dir = \"scans\"",
+            "output_dir = \"/s\"\nresolution = 1200",
+            "output_dir = \"/s\"\nmode = \"colour\"",
+            "output_dir = \"/s\"\nduplex = true\nsource = \"ADF Back\"",
+            "output_dir = \"/s\"\nformat = \"pdf\"",
+            "output_dir = \"/s\"\ndpi = 300",
+            "resolution = 300",
+        ] {
+            let toml = format!("[profiles.legal]\n{bad}");
+            assert!(parse_config(&toml).is_err(), "{bad}");
+        }
+        // Scan settings need a profile name to attach to.
+        let toml = "[profiles.double]\noutput_dir = \"/srv/scans\"";
+        assert!(parse_config(toml).unwrap_err().contains("not a gesture"));
+        let toml = "handler = \"/bin/h.sh\"\n[profiles]\n1 = 2";
+        assert!(parse_config(toml).is_err());
+    }
+
+    #[test]
+    fn parse_profile_args() {
+        let toml = r#"
+handler = "/bin/h.sh"
+[profiles]
+1 = "standard"
+2 = { name = "legal", args = ["scan", "--size", "legal", "--count", "{press_count}"] }
+double-hold = { name = "legal", args = ["scan", "--size", "legal", "--count", "{press_count}"] }
+"#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.profiles[&Gesture::Presses(2)], "legal");
+        assert_eq!(config.profiles[&Gesture::Hold(2)], "legal");
+        assert!(!config.profile_args.contains_key("standard"));
+        assert_eq!(
+            config.profile_args["legal"].expand("legal", Some(2), Some("A1B2C3")),
+            ["scan", "--size", "legal", "--count", "2"]
+        );
+
+        let template = ArgsTemplate::parse(vec![
+            "{event}".into(),
+            "{profile}@{serial}".into(),
+            "{}".into(),
+        ])
+        .unwrap();
+        assert_eq!(
+            template.expand("photo", None, None),
+            ["scan", "photo@", "{}"]
+        );
+
+        for (bad, error) in [
+            (
+                r#"{ name = "legal", args = ["{size}"] }"#,
+                "unknown placeholder {size}",
+            ),
+            (r#"{ name = "legal", args = [] }"#, "must not be empty"),
+            (
+                r#"{ name = "legal", args = ["scan"], extra = 1 }"#,
+                "unknown field",
+            ),
+        ] {
+            let toml = format!("handler = \"/bin/h.sh\"\n[profiles]\n2 = {bad}");
+            assert!(parse_config(&toml).unwrap_err().contains(error), "{bad}");
+        }
+        let toml = "handler = \"/bin/h.sh\"\n[profiles]\n\
+                    1 = { name = \"a\", args = [\"x\"] }\n2 = { name = \"a\", args = [\"y\"] }";
+        assert!(parse_config(toml).unwrap_err().contains("different args"));
+        let toml = "[profiles]\n2 = { name = \"legal\", args = [\"scan\"] }\n\
+                    [profiles.legal]\noutput_dir = \"/srv/scans\"";
+        assert!(parse_config(toml).unwrap_err().contains("no handler args"));
+    }
+
+    #[test]
+    fn parse_rate_limit() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.rate_limit, RateLimit::default());
+
+        let toml =
+            "handler = \"/bin/h.sh\"\n[rate_limit]\nmax_per_minute = 6\nmin_interval_ms = 2000";
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.rate_limit.max_per_minute, Some(6));
+        assert_eq!(config.rate_limit.min_interval, Some(Duration::from_secs(2)));
+
+        for bad in [
+            "max_per_minute = 0",
+            "min_interval_ms = 0",
+            "per_minute = 6",
+        ] {
+            let toml = format!("handler = \"/bin/h.sh\"\n[rate_limit]\n{bad}");
+            assert!(parse_config(&toml).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn parse_usb_health() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert_eq!(config.usb_health, UsbHealthConfig::default());
+
+        let toml = "handler = \"/bin/h.sh\"\n[usb_health]\nmax_errors = 0";
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.usb_health.max_errors, 0);
+        assert_eq!(config.usb_health.window, Duration::from_secs(300));
+
+        let toml = "handler = \"/bin/h.sh\"\n[usb_health]\nwindow_seconds = 0";
+        assert!(parse_config(toml).is_err());
+    }
+
+    #[test]
+    fn parse_maintenance_presses() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            maintenance_presses = 5
+            [profiles]
+            1 = "standard"
+        "#;
+        assert_eq!(parse_config(toml).unwrap().maintenance_presses, Some(5));
+
+        let toml = r#"
+            handler = "/bin/h.sh"
+            maintenance_presses = 1
+            [profiles]
+            1 = "standard"
+        "#;
+        assert!(parse_config(toml).unwrap_err().contains("standard"));
+
+        let toml = r#"
+            handler = "/bin/h.sh"
+            maintenance_presses = 3
+            [[schedule]]
+            from = "09:00"
+            to = "17:00"
+            profiles = { 3 = "office" }
+        "#;
+        assert!(parse_config(toml).unwrap_err().contains("office"));
+        assert!(parse_config("handler = \"/bin/h.sh\"\nmaintenance_presses = 0").is_err());
+    }
+
+    #[test]
+    fn parse_handler_cgroup() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [handler_cgroup]
+            path = "/sys/fs/cgroup/s1500d.service/handlers"
+            memory_max_mb = 1024
+            cpu_max_percent = 150
+        "#;
+        let cg = parse_config(toml).unwrap().handler_cgroup.unwrap();
+        assert_eq!(
+            cg.path,
+            std::path::Path::new("/sys/fs/cgroup/s1500d.service/handlers")
+        );
+        assert_eq!(cg.memory_max_mb, Some(1024));
+        assert_eq!(cg.cpu_max_percent, Some(150));
+    }
+
+    #[test]
+    fn digest_tracks_config_text() {
+        let a = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        let b = parse_config("handler = \"/bin/h.sh\"\n").unwrap();
+        assert_ne!(a.digest, b.digest);
+        assert_eq!(
+            a.digest,
+            parse_config(r#"handler = "/bin/h.sh""#).unwrap().digest
+        );
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn idle_conversion() {
+        let config = parse_config(
+            r#"
+            handler = "/bin/h.sh"
+            idle_minutes = 20
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.idle(), Some(Duration::from_secs(1200)));
+
+        let config = parse_config(
+            r#"
+            handler = "/bin/h.sh"
+            idle_minutes = 0
+        "#,
+        )
+        .unwrap();
+        assert!(config.idle().is_none());
+    }
+
+    #[test]
+    fn lid_reminder_conversion() {
+        let config = parse_config(
+            r#"
+            handler = "/bin/h.sh"
+            lid_reminder_minutes = 30
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.lid_reminder(), Some(Duration::from_secs(1800)));
+
+        let config = parse_config(
+            r#"
+            handler = "/bin/h.sh"
+            lid_reminder_minutes = 0
+        "#,
+        )
+        .unwrap();
+        assert!(config.lid_reminder().is_none());
+    }
+
+    #[test]
+    fn parse_schedule() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [profiles]
+            1 = "standard"
+            2 = "legal"
+
+            [[schedule]]
+            from = "09:00"
+            to = "17:00"
+            profiles = { 1 = "office-duplex" }
+
+            [[schedule]]
+            from = "18:00"
+            to = "23:00"
+            profiles = { 1 = "family-photos" }
+        "#;
+        let config = parse_config(toml).unwrap();
+        assert_eq!(config.schedule.len(), 2);
+
+        let noon = 12 * 60;
+        let evening = 20 * 60;
+        let night = 3 * 60;
+        assert_eq!(
+            config.profile_for(Gesture::Presses(1), noon).unwrap(),
+            "office-duplex"
+        );
+        assert_eq!(
+            config.profile_for(Gesture::Presses(1), evening).unwrap(),
+            "family-photos"
+        );
+        assert_eq!(
+            config.profile_for(Gesture::Presses(1), night).unwrap(),
+            "standard"
+        );
+        // Counts not overridden by the active window fall back to [profiles].
+        assert_eq!(
+            config.profile_for(Gesture::Presses(2), noon).unwrap(),
+            "legal"
+        );
+        assert!(config.profile_for(Gesture::Presses(3), noon).is_none());
+    }
+
+    #[test]
+    fn parse_schedule_invalid_window() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [[schedule]]
+            from = "9am"
+            to = "17:00"
+            profiles = { 1 = "office" }
+        "#;
+        assert!(parse_config(toml).unwrap_err().contains("schedule"));
+    }
+
+    #[test]
+    fn lint_clean_config() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [profiles]
+            1 = "standard"
+            2 = "legal"
+        "#;
+        assert!(parse_config(toml).unwrap().lint().is_empty());
+    }
+
+    #[test]
+    fn lint_press_count_gap() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [profiles]
+            1 = "standard"
+            4 = "photo"
+        "#;
+        let warnings = parse_config(toml).unwrap().lint();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("2, 3 unmapped below 4"));
+    }
+
+    #[test]
+    fn lint_unreachable_zero_and_empty() {
+        let config = parse_config(r#"handler = "/bin/h.sh""#).unwrap();
+        assert!(config.lint()[0].contains("no [profiles]"));
+
+        let toml = r#"
+            handler = "/bin/h.sh"
+            [profiles]
+            0 = "never"
+            1 = "standard"
+        "#;
+        let warnings = parse_config(toml).unwrap().lint();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("profile 0"));
+    }
+
+    #[test]
+    fn problems_with_the_handler() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("s1500d-{}-handler", std::process::id()));
+        let handler = path.to_str().unwrap();
+        let toml = format!("handler = {handler:?}");
+        assert_eq!(
+            parse_config(&toml).unwrap().problems(),
+            [format!("handler not found: {handler}")]
+        );
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        assert_eq!(
+            parse_config(&toml).unwrap().problems(),
+            [format!("handler is not executable: {handler}")]
+        );
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(parse_config(&toml).unwrap().problems().is_empty());
+        std::fs::remove_file(&path).unwrap();
+
+        // Bare names are looked up on PATH; remote handlers aren't checked.
+        assert!(parse_config(r#"handler = ["sh", "-c", "true"]"#)
+            .unwrap()
+            .problems()
+            .is_empty());
+        let toml = r#"handler = { ssh = "scan@nas", command = "/opt/scan/handle" }"#;
+        assert!(parse_config(toml).unwrap().problems().is_empty());
+        let toml = r#"
+            handler = "/bin/sh"
+            handler_cwd = "/nonexistent/s1500d"
+        "#;
+        assert_eq!(
+            parse_config(toml).unwrap().problems(),
+            ["handler_cwd is not a directory: /nonexistent/s1500d"]
+        );
+    }
+
+    #[test]
+    fn lint_timeout_below_poll_interval() {
+        let toml = r#"
+            handler = "/bin/h.sh"
+            gesture_timeout_ms = 50
+            [profiles]
+            1 = "standard"
+        "#;
+        let warnings = parse_config(toml).unwrap().lint();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("gesture_timeout_ms = 50"));
     }
 }
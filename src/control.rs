@@ -0,0 +1,179 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+/// How long a client may take to send its command before it is dropped,
+/// so a stuck client can't stall the poll loop.
+const CLIENT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A command received on the control socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Stop dispatching handlers (events are still monitored and logged).
+    Pause,
+    /// Resume handler dispatch.
+    Resume,
+    /// Enter (`true`) or leave maintenance mode.
+    Maintenance(bool),
+    /// Report whether dispatch is running, paused or in maintenance.
+    Status,
+    /// Switch debug logging on (`true`) or back to the configured level.
+    Debug(bool),
+    /// Dump the daemon's internal state as JSON.
+    Dump,
+    /// Release the scanner and stop polling (`true`) so another tool can
+    /// use it, or take it back.
+    Inhibit(bool),
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        match line.trim() {
+            "pause" => Ok(Self::Pause),
+            "resume" => Ok(Self::Resume),
+            "status" => Ok(Self::Status),
+            "dump" => Ok(Self::Dump),
+            "maintenance on" => Ok(Self::Maintenance(true)),
+            "maintenance off" => Ok(Self::Maintenance(false)),
+            "debug on" => Ok(Self::Debug(true)),
+            "debug off" => Ok(Self::Debug(false)),
+            "inhibit" => Ok(Self::Inhibit(true)),
+            "uninhibit" => Ok(Self::Inhibit(false)),
+            other => Err(format!("unknown command {other:?}")),
+        }
+    }
+}
+
+/// A client connection waiting for its reply.
+pub struct Request {
+    pub command: Result<Command, String>,
+    stream: UnixStream,
+}
+
+impl Request {
+    /// Send the one-line reply and close the connection.
+    pub fn reply(mut self, text: &str) {
+        if let Err(e) = writeln!(self.stream, "{text}") {
+            debug!("control: cannot reply: {e}");
+        }
+    }
+}
+
+/// Unix socket accepting one-line commands from `s1500ctl`.
+///
+/// The listener is non-blocking and polled from the event loop, so commands
+/// are picked up within one poll interval. Each connection carries a single
+/// command line and gets a single reply line (`ok …` or `error: …`).
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Bind the socket, replacing a stale one left by a previous run.
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        // Owner and group may send commands; access is otherwise governed
+        // by the enclosing directory.
+        fs::set_permissions(path, fs::Permissions::from_mode(0o660))?;
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Accept all pending connections and read their commands.
+    pub fn requests(&self) -> Vec<Request> {
+        let mut requests = Vec::new();
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Some(req) = read_request(stream) {
+                        requests.push(req);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("control: accept failed: {e}");
+                    break;
+                }
+            }
+        }
+        requests
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn read_request(stream: UnixStream) -> Option<Request> {
+    stream.set_nonblocking(false).ok()?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+    let mut line = String::new();
+    if let Err(e) = BufReader::new(&stream).read_line(&mut line) {
+        debug!("control: dropping client: {e}");
+        return None;
+    }
+    debug!("control: {:?}", line.trim());
+    Some(Request {
+        command: Command::parse(&line),
+        stream,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse("pause\n"), Ok(Command::Pause));
+        assert_eq!(Command::parse(" resume "), Ok(Command::Resume));
+        assert_eq!(Command::parse("status"), Ok(Command::Status));
+        assert_eq!(
+            Command::parse("maintenance on"),
+            Ok(Command::Maintenance(true))
+        );
+        assert!(Command::parse("maintenance").is_err());
+        assert_eq!(Command::parse("debug off"), Ok(Command::Debug(false)));
+        assert_eq!(Command::parse("dump"), Ok(Command::Dump));
+        assert_eq!(Command::parse("inhibit"), Ok(Command::Inhibit(true)));
+        assert_eq!(Command::parse("uninhibit"), Ok(Command::Inhibit(false)));
+        assert!(Command::parse("reboot").unwrap_err().contains("reboot"));
+    }
+
+    #[test]
+    fn request_reply_roundtrip() {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-control", std::process::id()));
+        let socket = ControlSocket::bind(&path).unwrap();
+        assert!(socket.requests().is_empty());
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        writeln!(client, "pause").unwrap();
+        let mut requests = socket.requests();
+        assert_eq!(requests.len(), 1);
+        let req = requests.pop().unwrap();
+        assert_eq!(req.command, Ok(Command::Pause));
+        req.reply("ok paused");
+
+        let mut reply = String::new();
+        BufReader::new(&client).read_line(&mut reply).unwrap();
+        assert_eq!(reply, "ok paused\n");
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,856 @@
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use s1500d::Event;
+
+use crate::config::{Bus, DbusConfig};
+
+/// The interface the daemon's object implements.
+pub const INTERFACE: &str = "io.s1500d.Scanner1";
+/// The object the signals come from and `GetStatus` is called on.
+pub const PATH: &str = "/io/s1500d/Scanner1";
+
+/// How long to wait for the bus while connecting, and for each write.
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// Time between attempts to reach the bus while it is unavailable.
+const RETRY: Duration = Duration::from_secs(30);
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+const NO_REPLY_EXPECTED: u8 = 0x1;
+
+// Header field codes.
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// The largest message the specification allows.
+const MAX_MESSAGE: usize = 128 << 20;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="io.s1500d.Scanner1">
+    <method name="GetStatus">
+      <arg name="status" type="a{sv}" direction="out"/>
+    </method>
+    <signal name="Event">
+      <arg name="seq" type="t"/>
+      <arg name="name" type="s"/>
+      <arg name="args" type="as"/>
+    </signal>
+    <signal name="GestureCompleted">
+      <arg name="profile" type="s"/>
+      <arg name="presses" type="u"/>
+      <arg name="held" type="b"/>
+    </signal>
+    <signal name="LidOpened">
+      <arg name="seq" type="t"/>
+    </signal>
+    <signal name="LidClosed">
+      <arg name="seq" type="t"/>
+    </signal>
+    <signal name="PaperInserted">
+      <arg name="seq" type="t"/>
+    </signal>
+    <signal name="PaperRemoved">
+      <arg name="seq" type="t"/>
+    </signal>
+    <signal name="ButtonPressed">
+      <arg name="seq" type="t"/>
+    </signal>
+    <signal name="ButtonReleased">
+      <arg name="seq" type="t"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+/// The bus connection, once [`start`] has been called.
+static PUBLISHER: Mutex<Option<Publisher>> = Mutex::new(None);
+
+/// A value in the `GetStatus` dictionary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    U64(u64),
+    Str(String),
+}
+
+struct Publisher {
+    config: DbusConfig,
+    conn: Option<Connection>,
+    /// When to try the bus again after failing to reach it.
+    retry_at: Option<Instant>,
+}
+
+impl Publisher {
+    /// The connection, reconnecting first if it was lost and it's time.
+    fn connection(&mut self) -> Option<&mut Connection> {
+        if self.conn.is_none() && self.retry_at.map_or(true, |t| Instant::now() >= t) {
+            match Connection::open(&self.config) {
+                Ok(conn) => {
+                    info!(
+                        "dbus: publishing as {} on the {} bus",
+                        self.config.name,
+                        bus_name(self.config.bus)
+                    );
+                    self.conn = Some(conn);
+                    self.retry_at = None;
+                }
+                Err(e) => {
+                    warn!(
+                        "dbus: cannot connect to the {} bus: {e} — retrying in {}s",
+                        bus_name(self.config.bus),
+                        RETRY.as_secs()
+                    );
+                    self.retry_at = Some(Instant::now() + RETRY);
+                }
+            }
+        }
+        self.conn.as_mut()
+    }
+
+    /// Run `f` on the connection, dropping it (to be reopened later) if
+    /// it fails.
+    fn with<T>(&mut self, f: impl FnOnce(&mut Connection) -> io::Result<T>) -> Option<T> {
+        let result = f(self.connection()?);
+        result
+            .map_err(|e| {
+                warn!("dbus: connection lost: {e}");
+                self.conn = None;
+                self.retry_at = Some(Instant::now() + RETRY);
+            })
+            .ok()
+    }
+}
+
+fn bus_name(bus: Bus) -> &'static str {
+    match bus {
+        Bus::System => "system",
+        Bus::Session => "session",
+    }
+}
+
+/// Connect to the bus and claim the configured name. If the bus can't be
+/// reached, the daemon carries on and tries again later.
+pub fn start(config: &DbusConfig) {
+    let mut publisher = Publisher {
+        config: config.clone(),
+        conn: None,
+        retry_at: None,
+    };
+    publisher.connection();
+    *PUBLISHER.lock().unwrap_or_else(|e| e.into_inner()) = Some(publisher);
+}
+
+fn with_publisher(f: impl FnOnce(&mut Publisher)) {
+    if let Some(publisher) = PUBLISHER.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        f(publisher);
+    }
+}
+
+fn signal(member: &'static str, signature: &'static str, body: Vec<u8>) {
+    with_publisher(|p| {
+        p.with(|conn| conn.signal(member, signature, &body));
+    });
+}
+
+/// Emit `Event(seq, name, args)` for an event the handler would see.
+pub fn event(seq: u64, args: &[&str]) {
+    let Some((name, rest)) = args.split_first() else {
+        return;
+    };
+    let mut body = Writer::default();
+    body.u64(seq);
+    body.str(name);
+    body.array(4, |w| rest.iter().for_each(|a| w.str(a)));
+    signal("Event", "tsas", body.buf);
+}
+
+/// Emit the signal for a raw scanner transition, numbered `seq` — also
+/// button presses, which in config mode only feed gesture detection.
+pub fn transition(ev: Event, seq: u64) {
+    let member = match ev {
+        Event::DeviceArrived => "LidOpened",
+        Event::DeviceLeft => "LidClosed",
+        Event::PaperIn => "PaperInserted",
+        Event::PaperOut => "PaperRemoved",
+        Event::ButtonDown => "ButtonPressed",
+        Event::ButtonUp => "ButtonReleased",
+        _ => return,
+    };
+    let mut body = Writer::default();
+    body.u64(seq);
+    signal(member, "t", body.buf);
+}
+
+/// Emit `GestureCompleted(profile, presses, held)`.
+pub fn gesture(profile: &str, presses: u32, held: bool) {
+    let mut body = Writer::default();
+    body.str(profile);
+    body.u32(presses);
+    body.bool(held);
+    signal("GestureCompleted", "sub", body.buf);
+}
+
+/// Answer method calls that have arrived, reconnecting first if the bus
+/// went away. `status` builds the `GetStatus` reply; called from the event
+/// loop once per cycle.
+pub fn service(status: impl Fn() -> Vec<(&'static str, Value)>) {
+    with_publisher(|p| {
+        let Some(calls) = p.with(Connection::receive) else {
+            return;
+        };
+        for call in calls {
+            p.with(|conn| conn.answer(&call, &status));
+        }
+    });
+}
+
+/// A message received from the bus (only what's needed to answer calls).
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Incoming {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    sender: Option<String>,
+    reply_serial: Option<u32>,
+    big_endian: bool,
+    body: Vec<u8>,
+}
+
+struct Connection {
+    stream: UnixStream,
+    /// Serial of the last message sent.
+    serial: u32,
+    /// Bytes received but not yet parsed.
+    pending: Vec<u8>,
+}
+
+impl Connection {
+    fn open(config: &DbusConfig) -> io::Result<Self> {
+        let addr = bus_address(config.bus)?;
+        let mut stream = UnixStream::connect_addr(&addr)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        authenticate(&mut stream)?;
+        let mut conn = Self {
+            stream,
+            serial: 0,
+            pending: Vec::new(),
+        };
+        conn.call("Hello", "", &[])?;
+        let mut body = Writer::default();
+        body.str(&config.name);
+        // DBUS_NAME_FLAG_DO_NOT_QUEUE: fail rather than wait for the name.
+        body.u32(4);
+        let reply = conn.call("RequestName", "su", &body.buf)?;
+        // 1: primary owner; 4: already the owner.
+        match Reader::new(&reply.body, reply.big_endian).u32() {
+            Some(1 | 4) => Ok(conn),
+            _ => Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is owned by another process", config.name),
+            )),
+        }
+    }
+
+    fn send(
+        &mut self,
+        kind: u8,
+        flags: u8,
+        fields: &[(u8, Field)],
+        signature: &str,
+        body: &[u8],
+    ) -> io::Result<u32> {
+        self.serial = self.serial.wrapping_add(1).max(1);
+        let msg = encode(kind, flags, self.serial, fields, signature, body);
+        self.stream.write_all(&msg)?;
+        Ok(self.serial)
+    }
+
+    /// Call a method on the bus itself and wait for the reply.
+    fn call(&mut self, member: &str, signature: &str, body: &[u8]) -> io::Result<Incoming> {
+        let fields = [
+            (FIELD_PATH, Field::Path("/org/freedesktop/DBus")),
+            (FIELD_INTERFACE, Field::Str("org.freedesktop.DBus")),
+            (FIELD_MEMBER, Field::Str(member)),
+            (FIELD_DESTINATION, Field::Str("org.freedesktop.DBus")),
+        ];
+        let serial = self.send(METHOD_CALL, 0, &fields, signature, body)?;
+        loop {
+            let msg = self.read_message()?;
+            if msg.reply_serial != Some(serial) {
+                continue;
+            }
+            if msg.kind == ERROR {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{member} refused"),
+                ));
+            }
+            return Ok(msg);
+        }
+    }
+
+    /// Wait for the next message (during setup).
+    fn read_message(&mut self) -> io::Result<Incoming> {
+        loop {
+            if let Some((msg, len)) = parse(&self.pending).map_err(invalid)? {
+                self.pending.drain(..len);
+                return Ok(msg);
+            }
+            self.fill()?;
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+        let n = self.stream.read(&mut buf)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.pending.extend_from_slice(&buf[..n]);
+        Ok(())
+    }
+
+    /// The method calls that have arrived, without blocking.
+    fn receive(&mut self) -> io::Result<Vec<Incoming>> {
+        let mut fd = libc::pollfd {
+            fd: self.stream.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: fd points to one valid pollfd.
+        while unsafe { libc::poll(&mut fd, 1, 0) } > 0 {
+            self.fill()?;
+        }
+        let mut calls = Vec::new();
+        while let Some((msg, len)) = parse(&self.pending).map_err(invalid)? {
+            self.pending.drain(..len);
+            if msg.kind == METHOD_CALL {
+                calls.push(msg);
+            }
+        }
+        Ok(calls)
+    }
+
+    fn signal(&mut self, member: &str, signature: &str, body: &[u8]) -> io::Result<()> {
+        let fields = [
+            (FIELD_PATH, Field::Path(PATH)),
+            (FIELD_INTERFACE, Field::Str(INTERFACE)),
+            (FIELD_MEMBER, Field::Str(member)),
+        ];
+        self.send(SIGNAL, 0, &fields, signature, body).map(drop)
+    }
+
+    fn answer(
+        &mut self,
+        call: &Incoming,
+        status: &impl Fn() -> Vec<(&'static str, Value)>,
+    ) -> io::Result<()> {
+        let path = call.path.as_deref().unwrap_or_default();
+        let member = call.member.as_deref().unwrap_or_default();
+        debug!("dbus: {member} on {path} from {:?}", call.sender);
+        let mut body = Writer::default();
+        let (signature, error) = match (call.interface.as_deref(), member) {
+            (Some(INTERFACE) | None, "GetStatus") if path == PATH => {
+                body.array(8, |w| {
+                    for (key, value) in status() {
+                        w.pad(8);
+                        w.str(key);
+                        w.variant(&value);
+                    }
+                });
+                ("a{sv}", None)
+            }
+            (Some("org.freedesktop.DBus.Introspectable") | None, "Introspect") => {
+                body.str(&introspect(path));
+                ("s", None)
+            }
+            (Some("org.freedesktop.DBus.Peer") | None, "Ping") => ("", None),
+            _ => {
+                body.str(&format!("no method {member} on {path}"));
+                ("s", Some("org.freedesktop.DBus.Error.UnknownMethod"))
+            }
+        };
+        if call.flags & NO_REPLY_EXPECTED != 0 {
+            return Ok(());
+        }
+        let mut fields = vec![(FIELD_REPLY_SERIAL, Field::U32(call.serial))];
+        if let Some(sender) = &call.sender {
+            fields.push((FIELD_DESTINATION, Field::Str(sender)));
+        }
+        let kind = match error {
+            Some(name) => {
+                fields.push((FIELD_ERROR_NAME, Field::Str(name)));
+                ERROR
+            }
+            None => METHOD_RETURN,
+        };
+        self.send(kind, 0, &fields, signature, &body.buf).map(drop)
+    }
+}
+
+/// Introspection data for `path`: the object itself, or the nodes leading
+/// to it (so `busctl tree` can find it).
+fn introspect(path: &str) -> String {
+    if path == PATH {
+        return INTROSPECTION.into();
+    }
+    let prefix = if path == "/" {
+        "/".to_string()
+    } else {
+        format!("{path}/")
+    };
+    match PATH.strip_prefix(&prefix) {
+        Some(rest) => {
+            let child = rest.split('/').next().unwrap_or_default();
+            format!("<node>\n  <node name=\"{child}\"/>\n</node>\n")
+        }
+        None => "<node/>\n".into(),
+    }
+}
+
+fn invalid(e: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// SASL `EXTERNAL`: the bus checks our credentials on the socket.
+fn authenticate(stream: &mut UnixStream) -> io::Result<()> {
+    // SAFETY: getuid has no preconditions.
+    let uid = unsafe { libc::getuid() }.to_string();
+    let hex: String = uid.bytes().map(|b| format!("{b:02x}")).collect();
+    stream.write_all(format!("\0AUTH EXTERNAL {hex}\r\n").as_bytes())?;
+    // Byte by byte: nothing after the reply line may be consumed here.
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    while !line.ends_with(b"\r\n") {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        line.push(byte[0]);
+    }
+    if !line.starts_with(b"OK ") {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "authentication rejected",
+        ));
+    }
+    stream.write_all(b"BEGIN\r\n")
+}
+
+/// The socket of `bus`, from `DBUS_SYSTEM_BUS_ADDRESS` /
+/// `DBUS_SESSION_BUS_ADDRESS` or the usual default.
+fn bus_address(bus: Bus) -> io::Result<SocketAddr> {
+    let (var, default) = match bus {
+        Bus::System => (
+            "DBUS_SYSTEM_BUS_ADDRESS",
+            Some("unix:path=/run/dbus/system_bus_socket".to_string()),
+        ),
+        Bus::Session => (
+            "DBUS_SESSION_BUS_ADDRESS",
+            std::env::var("XDG_RUNTIME_DIR")
+                .ok()
+                .map(|dir| format!("unix:path={dir}/bus")),
+        ),
+    };
+    let address = std::env::var(var)
+        .ok()
+        .or(default)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{var} is not set")))?;
+    parse_address(&address).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("no unix socket in bus address {address:?}"),
+        )
+    })?
+}
+
+/// The first `unix:path=` or `unix:abstract=` entry of a bus address.
+fn parse_address(address: &str) -> Option<io::Result<SocketAddr>> {
+    address.split(';').find_map(|entry| {
+        let params = entry.strip_prefix("unix:")?;
+        params.split(',').find_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            let value = unescape(value);
+            match key {
+                "path" => Some(SocketAddr::from_pathname(value)),
+                "abstract" => Some(SocketAddr::from_abstract_name(value)),
+                _ => None,
+            }
+        })
+    })
+}
+
+/// Undo the `%xx` escaping of bus address values.
+fn unescape(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| {
+            std::str::from_utf8(h)
+                .ok()
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+        });
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// A header field value.
+enum Field<'a> {
+    Str(&'a str),
+    Path(&'a str),
+    Signature(&'a str),
+    U32(u32),
+}
+
+/// Marshal a little-endian message.
+fn encode(
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    fields: &[(u8, Field)],
+    signature: &str,
+    body: &[u8],
+) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.buf.extend_from_slice(&[b'l', kind, flags, 1]);
+    w.u32(u32::try_from(body.len()).unwrap_or(u32::MAX));
+    w.u32(serial);
+    let signature_field = [(FIELD_SIGNATURE, Field::Signature(signature))];
+    let signature_field = if signature.is_empty() {
+        &[][..]
+    } else {
+        &signature_field[..]
+    };
+    w.array(8, |w| {
+        for (code, field) in fields.iter().chain(signature_field) {
+            w.pad(8);
+            w.byte(*code);
+            match field {
+                Field::Str(s) => {
+                    w.signature("s");
+                    w.str(s);
+                }
+                Field::Path(p) => {
+                    w.signature("o");
+                    w.str(p);
+                }
+                Field::Signature(g) => {
+                    w.signature("g");
+                    w.signature(g);
+                }
+                Field::U32(n) => {
+                    w.signature("u");
+                    w.u32(*n);
+                }
+            }
+        }
+    });
+    w.pad(8);
+    w.buf.extend_from_slice(body);
+    w.buf
+}
+
+/// Builds D-Bus wire format, little-endian. Alignment is relative to the
+/// start of the buffer, so a body must be built in its own `Writer` (bodies
+/// start 8-aligned).
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn pad(&mut self, align: usize) {
+        while self.buf.len() % align != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn byte(&mut self, b: u8) {
+        self.buf.push(b);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.pad(4);
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.pad(8);
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn bool(&mut self, b: bool) {
+        self.u32(u32::from(b));
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u32(u32::try_from(s.len()).unwrap_or(u32::MAX));
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.byte(u8::try_from(s.len()).unwrap_or(u8::MAX));
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// An array whose elements need `align`; `f` writes the elements.
+    fn array(&mut self, align: usize, f: impl FnOnce(&mut Self)) {
+        self.u32(0);
+        let len_at = self.buf.len() - 4;
+        self.pad(align);
+        let start = self.buf.len();
+        f(self);
+        let len = u32::try_from(self.buf.len() - start).unwrap_or(u32::MAX);
+        self.buf[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    fn variant(&mut self, value: &Value) {
+        match value {
+            Value::Bool(b) => {
+                self.signature("b");
+                self.bool(*b);
+            }
+            Value::U64(n) => {
+                self.signature("t");
+                self.u64(*n);
+            }
+            Value::Str(s) => {
+                self.signature("s");
+                self.str(s);
+            }
+        }
+    }
+}
+
+/// `n` rounded up to a multiple of `align`.
+fn aligned(n: usize, align: usize) -> usize {
+    (n + align - 1) / align * align
+}
+
+/// Reads D-Bus wire format in either byte order.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8], big_endian: bool) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            big_endian,
+        }
+    }
+
+    fn align(&mut self, align: usize) {
+        self.pos = aligned(self.pos, align);
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let b = *self.buf.get(self.pos)?;
+        self.pos += 1;
+        Some(b)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.align(4);
+        let bytes: [u8; 4] = self.buf.get(self.pos..self.pos + 4)?.try_into().ok()?;
+        self.pos += 4;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<String> {
+        let s = self.buf.get(self.pos..self.pos + len)?;
+        // Skip the terminating NUL too.
+        self.pos += len + 1;
+        Some(String::from_utf8_lossy(s).into_owned())
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = usize::try_from(self.u32()?).ok()?;
+        self.bytes(len)
+    }
+
+    fn signature(&mut self) -> Option<String> {
+        let len = usize::from(self.byte()?);
+        self.bytes(len)
+    }
+}
+
+/// Parse the first message in `buf`: `Ok(None)` if it hasn't all arrived
+/// yet, else the message and its length.
+fn parse(buf: &[u8]) -> Result<Option<(Incoming, usize)>, String> {
+    if buf.len() < 16 {
+        return Ok(None);
+    }
+    let big_endian = match buf[0] {
+        b'l' => false,
+        b'B' => true,
+        other => return Err(format!("bad byte order {other:#04x}")),
+    };
+    let mut r = Reader::new(buf, big_endian);
+    r.pos = 4;
+    let (Some(body_len), Some(serial)) = (r.u32(), r.u32()) else {
+        return Ok(None);
+    };
+    let fields_len = r.u32().unwrap_or_default();
+    let fields_end = 16 + fields_len as usize;
+    let body_start = aligned(fields_end, 8);
+    let total = body_start + body_len as usize;
+    if total > MAX_MESSAGE {
+        return Err(format!("message of {total} bytes is too large"));
+    }
+    if buf.len() < total {
+        return Ok(None);
+    }
+    let mut msg = Incoming {
+        kind: buf[1],
+        flags: buf[2],
+        serial,
+        big_endian,
+        body: buf[body_start..total].to_vec(),
+        ..Incoming::default()
+    };
+    let mut r = Reader::new(&buf[..fields_end], big_endian);
+    r.pos = 16;
+    while r.pos < fields_end {
+        r.align(8);
+        let at = r.pos;
+        let bad = || format!("malformed header field at byte {at}");
+        let code = r.byte().ok_or_else(bad)?;
+        let signature = r.signature().ok_or_else(bad)?;
+        let (text, number) = match signature.as_str() {
+            "s" | "o" => (Some(r.str().ok_or_else(bad)?), None),
+            "g" => (Some(r.signature().ok_or_else(bad)?), None),
+            "u" => (None, Some(r.u32().ok_or_else(bad)?)),
+            other => return Err(format!("unexpected header field type {other:?}")),
+        };
+        match code {
+            FIELD_PATH => msg.path = text,
+            FIELD_INTERFACE => msg.interface = text,
+            FIELD_MEMBER => msg.member = text,
+            FIELD_SENDER => msg.sender = text,
+            FIELD_REPLY_SERIAL => msg.reply_serial = number,
+            _ => {}
+        }
+    }
+    Ok(Some((msg, total)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let mut body = Writer::default();
+        body.u64(7);
+        body.str("scan");
+        body.array(4, |w| w.str("legal"));
+        let fields = [
+            (FIELD_PATH, Field::Path(PATH)),
+            (FIELD_INTERFACE, Field::Str(INTERFACE)),
+            (FIELD_MEMBER, Field::Str("Event")),
+        ];
+        let msg = encode(SIGNAL, 0, 3, &fields, "tsas", &body.buf);
+        assert_eq!(msg.len() % 8, body.buf.len() % 8);
+
+        // Incomplete until the last byte arrives.
+        assert_eq!(parse(&msg[..msg.len() - 1]), Ok(None));
+        let (parsed, len) = parse(&msg).unwrap().unwrap();
+        assert_eq!(len, msg.len());
+        assert_eq!(parsed.kind, SIGNAL);
+        assert_eq!(parsed.serial, 3);
+        assert_eq!(parsed.path.as_deref(), Some(PATH));
+        assert_eq!(parsed.interface.as_deref(), Some(INTERFACE));
+        assert_eq!(parsed.member.as_deref(), Some("Event"));
+        assert_eq!(parsed.body, body.buf);
+
+        let mut r = Reader::new(&parsed.body, false);
+        r.pos = 8;
+        assert_eq!(r.str().as_deref(), Some("scan"));
+    }
+
+    #[test]
+    fn arrays_are_padded_to_their_elements() {
+        let mut w = Writer::default();
+        w.byte(1);
+        w.array(8, |w| {
+            w.pad(8);
+            w.str("k");
+            w.variant(&Value::Bool(true));
+        });
+        // Length at 4, elements from 8: "k" (4 + 2), pad, signature "b" (3), pad, 1u32.
+        assert_eq!(&w.buf[4..8], &16u32.to_le_bytes());
+        assert_eq!(w.buf.len(), 24);
+        assert_eq!(&w.buf[20..24], &1u32.to_le_bytes());
+    }
+
+    #[test]
+    fn bus_addresses() {
+        assert_eq!(unescape("/run/user/1000/b%75s"), "/run/user/1000/bus");
+        let addr = parse_address("tcp:host=x;unix:guid=0,path=/run/dbus/system_bus_socket")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            addr.as_pathname(),
+            Some(std::path::Path::new("/run/dbus/system_bus_socket"))
+        );
+        let addr = parse_address("unix:abstract=/tmp/dbus-x").unwrap().unwrap();
+        assert_eq!(addr.as_abstract_name(), Some(&b"/tmp/dbus-x"[..]));
+        assert!(parse_address("tcp:host=localhost,port=1").is_none());
+    }
+
+    #[test]
+    fn introspection_leads_to_the_object() {
+        assert!(introspect("/").contains("<node name=\"io\"/>"));
+        assert!(introspect("/io/s1500d").contains("<node name=\"Scanner1\"/>"));
+        assert!(introspect(PATH).contains("GetStatus"));
+        assert_eq!(introspect("/elsewhere"), "<node/>\n");
+    }
+}
@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+
+use s1500d::protocol::State;
+
+/// How long each sensor must keep reading a new value before the change
+/// counts (`paper_debounce_ms`, `button_debounce_ms`). Zero passes every
+/// change through at once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Settle {
+    pub paper: Duration,
+    pub button: Duration,
+}
+
+/// Holds back sensor changes until they have settled, so a stack of pages
+/// shifting in the feeder doesn't flap between paper-in and paper-out.
+#[derive(Debug, Default)]
+pub struct Debouncer {
+    /// A paper reading that differs from the stable state, and since when.
+    paper: Option<(bool, Instant)>,
+    button: Option<(bool, Instant)>,
+}
+
+impl Debouncer {
+    /// The state to compare against `stable` for transitions, given the
+    /// latest `raw` reading: each sensor takes its raw value only once that
+    /// has read the same for its [`Settle`] time.
+    pub fn filter(&mut self, stable: State, raw: State, settle: Settle, now: Instant) -> State {
+        State {
+            paper: settled(&mut self.paper, stable.paper, raw.paper, settle.paper, now),
+            button: settled(
+                &mut self.button,
+                stable.button,
+                raw.button,
+                settle.button,
+                now,
+            ),
+        }
+    }
+}
+
+/// One sensor of [`Debouncer::filter`].
+fn settled(
+    pending: &mut Option<(bool, Instant)>,
+    stable: bool,
+    raw: bool,
+    settle: Duration,
+    now: Instant,
+) -> bool {
+    if raw == stable || settle.is_zero() {
+        *pending = None;
+        return raw;
+    }
+    match *pending {
+        Some((value, since)) if value == raw => {
+            if now.duration_since(since) >= settle {
+                *pending = None;
+                raw
+            } else {
+                stable
+            }
+        }
+        _ => {
+            *pending = Some((raw, now));
+            stable
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTLE: Settle = Settle {
+        paper: Duration::from_millis(300),
+        button: Duration::ZERO,
+    };
+
+    fn state(paper: bool, button: bool) -> State {
+        State { paper, button }
+    }
+
+    #[test]
+    fn paper_change_waits_to_settle() {
+        let mut d = Debouncer::default();
+        let t = Instant::now();
+        let empty = state(false, false);
+        let loaded = state(true, false);
+        assert_eq!(d.filter(empty, loaded, SETTLE, t), empty);
+        let later = t + Duration::from_millis(200);
+        assert_eq!(d.filter(empty, loaded, SETTLE, later), empty);
+        let later = t + Duration::from_millis(300);
+        assert_eq!(d.filter(empty, loaded, SETTLE, later), loaded);
+    }
+
+    #[test]
+    fn flapping_paper_never_changes() {
+        let mut d = Debouncer::default();
+        let t = Instant::now();
+        let loaded = state(true, false);
+        let empty = state(false, false);
+        for step in 0..10 {
+            let now = t + Duration::from_millis(100 * step);
+            let raw = if step % 2 == 0 { empty } else { loaded };
+            assert_eq!(d.filter(loaded, raw, SETTLE, now), loaded);
+        }
+    }
+
+    #[test]
+    fn zero_settle_passes_through() {
+        let mut d = Debouncer::default();
+        let t = Instant::now();
+        // The button has no settle time; paper still waits.
+        let raw = state(true, true);
+        assert_eq!(
+            d.filter(state(false, false), raw, SETTLE, t),
+            state(false, true)
+        );
+    }
+}
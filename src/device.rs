@@ -0,0 +1,399 @@
+//! Finding, opening and polling the scanner over libusb.
+
+use std::collections::VecDeque;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, warn};
+use rusb::{Context, Device, DeviceHandle, UsbContext};
+
+use crate::protocol::{
+    changes, envelope, scsi_status, Event, Inquiry, Model, Sense, State, EP_IN, EP_OUT, GHS_CDB,
+    IFACE, INQUIRY_CDB, REQUEST_SENSE_CDB, STATUS_BUSY, STATUS_CHECK_CONDITION, STATUS_GOOD,
+};
+
+/// Per-transfer timeout for a scanner on a local port.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Every attached scanner of this model, in bus order.
+pub fn find_devices(ctx: &Context, model: &Model) -> rusb::Result<Vec<Device<Context>>> {
+    Ok(ctx
+        .devices()?
+        .iter()
+        .filter(|d| {
+            d.device_descriptor()
+                .is_ok_and(|desc| desc.vendor_id() == model.vid && desc.product_id() == model.pid)
+        })
+        .collect())
+}
+
+/// The scanner's USB device, if it is attached — the one with `serial` if
+/// given, else the first found.
+pub fn find_device(
+    ctx: &Context,
+    model: &Model,
+    serial: Option<&str>,
+) -> rusb::Result<Device<Context>> {
+    find_devices(ctx, model)?
+        .into_iter()
+        .find(|d| serial.map_or(true, |s| serial_number(d).is_ok_and(|n| n == s)))
+        .ok_or(rusb::Error::NotFound)
+}
+
+/// The device's USB serial number. Opens the device briefly, but doesn't
+/// claim it.
+pub fn serial_number(device: &Device<Context>) -> rusb::Result<String> {
+    let desc = device.device_descriptor()?;
+    device.open()?.read_serial_number_string_ascii(&desc)
+}
+
+/// The device node a user needs read-write access to for opening `device`,
+/// on platforms that have one per USB device (not macOS).
+pub fn device_node(device: &Device<Context>) -> Option<String> {
+    let (bus, address) = (device.bus_number(), device.address());
+    if cfg!(target_os = "linux") {
+        Some(format!("/dev/bus/usb/{bus:03}/{address:03}"))
+    } else if cfg!(target_os = "freebsd") {
+        Some(format!("/dev/ugen{bus}.{address}"))
+    } else {
+        None
+    }
+}
+
+/// Open the scanner (see [`find_device`]), returning a claimed device handle.
+///
+/// Distinguishes an absent scanner (`rusb::Error::NotFound`) from one we may
+/// not open (`rusb::Error::Access`) — on macOS, usually because another
+/// program (Image Capture, ScanSnap Home) holds it.
+pub fn open_device(
+    ctx: &Context,
+    model: &Model,
+    serial: Option<&str>,
+) -> rusb::Result<DeviceHandle<Context>> {
+    let handle = find_device(ctx, model, serial)?.open()?;
+    // No kernel driver binds the scanner on macOS, and libusb's detach
+    // there means capturing the device, which only root may do — with it
+    // on, every claim fails with an access error.
+    #[cfg(not(target_os = "macos"))]
+    let _ = handle.set_auto_detach_kernel_driver(true);
+    handle.claim_interface(IFACE)?;
+    // A suspend and resume can leave the endpoints halted, or their data
+    // toggles out of step with the scanner's; start from a clean slate.
+    let _ = clear_halts(&handle);
+    Ok(handle)
+}
+
+/// Clear a halt on both bulk endpoints, which also resets their data
+/// toggles — the cure for transfers failing with [`rusb::Error::Pipe`].
+pub fn clear_halts(handle: &DeviceHandle<Context>) -> rusb::Result<()> {
+    handle.clear_halt(EP_OUT)?;
+    handle.clear_halt(EP_IN)
+}
+
+/// The data phase of a GET_HW_STATUS exchange, as received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Response {
+    buf: [u8; 12],
+    len: usize,
+}
+
+impl Response {
+    /// A response received earlier (say, from a recording); anything past
+    /// 12 bytes is dropped, as a live read would.
+    pub fn new(bytes: &[u8]) -> Self {
+        let len = bytes.len().min(12);
+        let mut response = Self { buf: [0; 12], len };
+        response.buf[..len].copy_from_slice(&bytes[..len]);
+        response
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Send GET_HW_STATUS and return the raw response, waiting at most `timeout`
+/// for each transfer.
+///
+/// A command the scanner refuses fails with [`rusb::Error::Busy`] if it
+/// should work again shortly (SCSI BUSY, or sense NOT READY / UNIT
+/// ATTENTION) and [`rusb::Error::Io`] otherwise; the sense data is logged.
+/// [`rusb::Error::NoDevice`] means the scanner is gone.
+pub fn get_hw_status(handle: &DeviceHandle<Context>, timeout: Duration) -> rusb::Result<Response> {
+    // 12 bytes of hardware status.
+    let mut buf = [0u8; 64];
+    let n = exchange(handle, "GET_HW_STATUS", &GHS_CDB, &mut buf, timeout)?;
+    Ok(Response::new(&buf[..n]))
+}
+
+/// Send INQUIRY: the scanner's vendor, product and firmware revision.
+/// Errors are as for [`get_hw_status`].
+pub fn inquiry(handle: &DeviceHandle<Context>, timeout: Duration) -> rusb::Result<Inquiry> {
+    let mut buf = [0u8; 96];
+    let n = exchange(handle, "INQUIRY", &INQUIRY_CDB, &mut buf, timeout)?;
+    Inquiry::parse(&buf[..n]).ok_or(rusb::Error::Other)
+}
+
+/// Run `cdb` through the three phases — command, data into `buf`, status —
+/// and return the length of the data.
+fn exchange(
+    handle: &DeviceHandle<Context>,
+    command: &str,
+    cdb: &[u8],
+    buf: &mut [u8],
+    timeout: Duration,
+) -> rusb::Result<usize> {
+    handle.write_bulk(EP_OUT, &envelope(cdb), timeout)?;
+
+    let n = handle.read_bulk(EP_IN, buf, timeout)?;
+    if scsi_status(&buf[..n]).is_some() {
+        // No data: the scanner went straight to the status phase.
+        check_status(handle, command, &buf[..n], timeout)?;
+        return Err(rusb::Error::Other);
+    }
+
+    let mut status = [0u8; 64];
+    match handle.read_bulk(EP_IN, &mut status, timeout / 5) {
+        Ok(len) => check_status(handle, command, &status[..len], timeout)?,
+        Err(rusb::Error::NoDevice) => return Err(rusb::Error::NoDevice),
+        // The data arrived; a lost status phase doesn't spoil it.
+        Err(e) => debug!("{command}: no status phase: {e}"),
+    }
+    Ok(n)
+}
+
+/// Act on the status envelope that ended `command`: `Ok` for GOOD, else
+/// the error [`get_hw_status`] describes, asking for sense data on CHECK
+/// CONDITION. Anything that isn't a status envelope is logged and let
+/// pass, as the command's data already arrived.
+fn check_status(
+    handle: &DeviceHandle<Context>,
+    command: &str,
+    envelope: &[u8],
+    timeout: Duration,
+) -> rusb::Result<()> {
+    match scsi_status(envelope) {
+        None => {
+            debug!("{command}: unexpected {}-byte status phase", envelope.len());
+            Ok(())
+        }
+        Some(STATUS_GOOD) => Ok(()),
+        Some(STATUS_BUSY) => {
+            debug!("{command}: scanner busy");
+            Err(rusb::Error::Busy)
+        }
+        Some(STATUS_CHECK_CONDITION) => match request_sense(handle, timeout) {
+            Ok(sense) if sense.is_transient() => {
+                debug!("{command}: CHECK CONDITION, {sense}");
+                Err(rusb::Error::Busy)
+            }
+            Ok(sense) => {
+                warn!("{command}: CHECK CONDITION, {sense}");
+                Err(rusb::Error::Io)
+            }
+            Err(rusb::Error::NoDevice) => Err(rusb::Error::NoDevice),
+            Err(e) => {
+                warn!("{command}: CHECK CONDITION, and REQUEST SENSE failed: {e}");
+                Err(rusb::Error::Io)
+            }
+        },
+        Some(status) => {
+            warn!("{command}: unexpected SCSI status {status:#04x}");
+            Err(rusb::Error::Io)
+        }
+    }
+}
+
+/// Send REQUEST SENSE after a CHECK CONDITION and decode the answer.
+fn request_sense(handle: &DeviceHandle<Context>, timeout: Duration) -> rusb::Result<Sense> {
+    handle.write_bulk(EP_OUT, &envelope(&REQUEST_SENSE_CDB), timeout)?;
+    let mut buf = [0u8; 64];
+    let n = handle.read_bulk(EP_IN, &mut buf, timeout)?;
+    let mut status = [0u8; 64];
+    let _ = handle.read_bulk(EP_IN, &mut status, timeout / 5);
+    Sense::parse(&buf[..n]).ok_or(rusb::Error::Other)
+}
+
+/// Send GET_HW_STATUS and decode the response. A response too short to
+/// decode is reported as [`rusb::Error::Other`].
+pub fn read_status(
+    handle: &DeviceHandle<Context>,
+    model: &Model,
+    timeout: Duration,
+) -> rusb::Result<State> {
+    let response = get_hw_status(handle, timeout)?;
+    model.decode(response.bytes()).ok_or(rusb::Error::Other)
+}
+
+/// An opened, claimed S1500.
+///
+/// ```no_run
+/// let ctx = rusb::Context::new()?;
+/// let scanner = s1500d::Scanner::open(&ctx)?;
+/// let state = scanner.status()?;
+/// println!("paper: {}, button: {}", state.paper, state.button);
+/// scanner.release();
+/// # Ok::<(), rusb::Error>(())
+/// ```
+pub struct Scanner {
+    handle: DeviceHandle<Context>,
+    model: Model,
+    timeout: Duration,
+}
+
+impl Scanner {
+    /// Find and claim the scanner. Fails with `rusb::Error::NotFound` while
+    /// the lid is closed.
+    pub fn open(ctx: &Context) -> rusb::Result<Self> {
+        Self::open_with(ctx, Model::S1500, None)
+    }
+
+    /// Like [`Scanner::open`], for another model, or only the scanner with
+    /// this serial number — for machines with more than one.
+    pub fn open_with(ctx: &Context, model: Model, serial: Option<&str>) -> rusb::Result<Self> {
+        Ok(Self {
+            handle: open_device(ctx, &model, serial)?,
+            model,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Use `timeout` for each USB transfer, e.g. over USB/IP.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Poll the hardware status.
+    pub fn status(&self) -> rusb::Result<State> {
+        read_status(&self.handle, &self.model, self.timeout)
+    }
+
+    /// Ask the scanner for its vendor, product and firmware revision.
+    pub fn inquiry(&self) -> rusb::Result<Inquiry> {
+        inquiry(&self.handle, self.timeout)
+    }
+
+    /// The underlying libusb handle.
+    pub fn handle(&self) -> &DeviceHandle<Context> {
+        &self.handle
+    }
+
+    /// Release the interface so another program (`scanimage`) can claim the
+    /// scanner.
+    pub fn release(self) {
+        let _ = self.handle.release_interface(IFACE);
+    }
+}
+
+/// The scanner's events as an iterator, for programs that embed it: polls
+/// every `interval` (100 ms unless [`Monitor::with_interval`] says
+/// otherwise), opening the scanner when it appears and letting it go when
+/// it leaves.
+///
+/// Events are changes from how things stand when the monitor starts, so a
+/// scanner already there yields no `DeviceArrived`. Errors other than the
+/// scanner being absent or busy are handed to the caller, who may keep
+/// iterating to try again an interval later.
+///
+/// ```no_run
+/// let ctx = rusb::Context::new()?;
+/// for event in s1500d::Monitor::new(&ctx) {
+///     println!("{}", event?.tag());
+/// }
+/// # Ok::<(), rusb::Error>(())
+/// ```
+pub struct Monitor {
+    ctx: Context,
+    model: Model,
+    serial: Option<String>,
+    interval: Duration,
+    timeout: Duration,
+    scanner: Option<Scanner>,
+    /// Whether the scanner has been looked at yet.
+    started: bool,
+    /// What the last look found; `None` before the first that worked.
+    last: Option<Option<State>>,
+    pending: VecDeque<Event>,
+}
+
+impl Monitor {
+    /// Watch for an S1500.
+    pub fn new(ctx: &Context) -> Self {
+        Self::with(ctx, Model::S1500, None)
+    }
+
+    /// Like [`Monitor::new`], for another model, or only the scanner with
+    /// this serial number.
+    pub fn with(ctx: &Context, model: Model, serial: Option<&str>) -> Self {
+        Self {
+            ctx: ctx.clone(),
+            model,
+            serial: serial.map(str::to_string),
+            interval: Duration::from_millis(100),
+            timeout: DEFAULT_TIMEOUT,
+            scanner: None,
+            started: false,
+            last: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Poll every `interval`.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Use `timeout` for each USB transfer, as [`Scanner::with_timeout`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Look at the scanner once: `None` while it is absent.
+    fn look(&mut self) -> rusb::Result<Option<State>> {
+        if self.scanner.is_none() {
+            match Scanner::open_with(&self.ctx, self.model, self.serial.as_deref()) {
+                Ok(scanner) => self.scanner = Some(scanner.with_timeout(self.timeout)),
+                Err(rusb::Error::NotFound) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+        match self.scanner.as_ref().map(Scanner::status) {
+            Some(Ok(state)) => Ok(Some(state)),
+            Some(Err(rusb::Error::NoDevice | rusb::Error::NotFound)) | None => {
+                self.scanner = None;
+                Ok(None)
+            }
+            Some(Err(e)) => Err(e),
+        }
+    }
+}
+
+impl Iterator for Monitor {
+    type Item = rusb::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.started {
+                thread::sleep(self.interval);
+            }
+            self.started = true;
+            match self.look() {
+                Ok(now) => {
+                    if let Some(prev) = self.last {
+                        self.pending.extend(changes(prev, now));
+                    }
+                    self.last = Some(now);
+                }
+                // Ask again next time.
+                Err(rusb::Error::Busy) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
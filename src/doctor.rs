@@ -1,16 +1,506 @@
 use std::io::{self, BufRead, Write as IoWrite};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::{poll_status, try_open, State};
+use clap::Args;
+use s1500d::protocol::{EP_IN, EP_OUT, IFACE};
+
+use crate::config::Gesture;
+use crate::error::{self, UsbError};
+use crate::json;
+use crate::queue::{self, Backpressure, DispatchQueue};
+use crate::{
+    access_denied_help, check_gesture_timeout, find_device, identify, model, open_device,
+    poll_status, process_transitions, GestureState, Mode, State,
+};
+
+/// An open, claimed scanner.
+type Handle = rusb::DeviceHandle<rusb::Context>;
 
 const DOCTOR_TIMEOUT: Duration = Duration::from_secs(15);
 
+/// Sensor steps (paper in and out, button down and up) after the
+/// unattended checks.
+const SENSOR_STEPS: usize = 4;
+
+/// Double presses measured during gesture calibration.
+const CALIBRATION_ATTEMPTS: usize = 5;
+
+/// Poll interval while calibrating — finer than the daemon's, so the
+/// measured gaps aren't quantised to 100ms.
+const CALIBRATION_POLL: Duration = Duration::from_millis(10);
+
+/// What `doctor` was asked to do.
+#[derive(Debug, Default, PartialEq, Eq, Args)]
+pub struct Options {
+    /// Run only the checks that need nobody at the scanner.
+    #[arg(long)]
+    pub non_interactive: bool,
+    /// Print those checks as one JSON object (implies --non-interactive).
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// How an unattended check went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Fail,
+    /// Not run, because an earlier check it needs failed.
+    Skip,
+}
+
+impl Status {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::Skip => "skip",
+        }
+    }
+}
+
+/// One unattended check and what it found.
+#[derive(Debug)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+}
+
+impl Check {
+    fn skip(name: &'static str, why: &str) -> Self {
+        Self {
+            name,
+            status: Status::Skip,
+            detail: why.into(),
+        }
+    }
+
+    fn new(name: &'static str, result: Result<String, String>) -> Self {
+        let (status, detail) = match result {
+            Ok(detail) => (Status::Pass, detail),
+            Err(detail) => (Status::Fail, detail),
+        };
+        Self {
+            name,
+            status,
+            detail,
+        }
+    }
+}
+
+/// Run the checks that need nobody at the scanner: open it, read its
+/// status, ask who it is, sanity-check its descriptors, and see who else
+/// has its interface and whether this user may use its device node.
+/// Returns them with the open scanner, or why it couldn't be opened.
+fn unattended_checks(ctx: &rusb::Context) -> (Vec<Check>, Result<Handle, UsbError>) {
+    let device = match find_device(ctx) {
+        Ok(d) => d,
+        Err(e) => {
+            let e = UsbError::from(e);
+            let mut checks = vec![Check::new("usb-open", Err(e.to_string()))];
+            checks.extend(
+                [
+                    "hw-status",
+                    "inquiry",
+                    "descriptors",
+                    "kernel-driver",
+                    "permissions",
+                ]
+                .map(|name| Check::skip(name, "scanner not found")),
+            );
+            return (checks, Err(e));
+        }
+    };
+    // Before opening: once claimed, the interface belongs to usbfs.
+    let driver = if cfg!(target_os = "linux") {
+        Check::new("kernel-driver", check_kernel_driver(&device))
+    } else {
+        Check::skip("kernel-driver", "no sysfs on this platform")
+    };
+    let permissions = match s1500d::device::device_node(&device) {
+        Some(node) => Check::new("permissions", check_permissions(&node)),
+        None => Check::skip("permissions", "no USB device nodes on this platform"),
+    };
+    let descriptors = Check::new("descriptors", check_descriptors(&device));
+    let opened = open_device(ctx);
+    let mut checks = match &opened {
+        Ok(handle) => {
+            let serial = s1500d::device::serial_number(&handle.device())
+                .unwrap_or_else(|_| "unknown".into());
+            let status = poll_status(handle)
+                .map(|s| format!("paper={}, button={}", s.paper, s.button))
+                .map_err(|e| e.to_string());
+            let identity = identify(handle)
+                .map(|i| i.to_string())
+                .ok_or_else(|| "no answer to INQUIRY".to_string());
+            vec![
+                Check::new("usb-open", Ok(format!("serial {serial}"))),
+                Check::new("hw-status", status),
+                Check::new("inquiry", identity),
+            ]
+        }
+        Err(e) => {
+            let detail = match e {
+                UsbError::AccessDenied => access_denied_help(ctx),
+                e => e.to_string(),
+            };
+            let mut checks = vec![Check::new("usb-open", Err(detail))];
+            checks
+                .extend(["hw-status", "inquiry"].map(|name| Check::skip(name, "scanner not open")));
+            checks
+        }
+    };
+    checks.extend([descriptors, driver, permissions]);
+    (checks, opened)
+}
+
+/// Confirm the device is vendor-specific (class FF) and has the bulk
+/// endpoints the protocol uses, with the packet size its speed calls for.
+fn check_descriptors(device: &rusb::Device<rusb::Context>) -> Result<String, String> {
+    let desc = device
+        .device_descriptor()
+        .map_err(|e| format!("cannot read device descriptor: {e}"))?;
+    let config = device
+        .active_config_descriptor()
+        .map_err(|e| format!("cannot read config descriptor: {e}"))?;
+    let iface = config
+        .interfaces()
+        .find(|i| i.number() == IFACE)
+        .and_then(|i| i.descriptors().next())
+        .ok_or_else(|| format!("no interface {IFACE}"))?;
+    if desc.class_code() != 0xff && iface.class_code() != 0xff {
+        return Err(format!(
+            "class {:02x}, not vendor-specific (ff)",
+            iface.class_code()
+        ));
+    }
+    let packet = bulk_packet_size(device.speed());
+    for address in [EP_OUT, EP_IN] {
+        let endpoint = iface
+            .endpoint_descriptors()
+            .find(|e| e.address() == address)
+            .ok_or_else(|| format!("no endpoint {address:#04x}"))?;
+        if endpoint.transfer_type() != rusb::TransferType::Bulk {
+            return Err(format!("endpoint {address:#04x} is not bulk"));
+        }
+        let size = endpoint.max_packet_size();
+        if packet.is_some_and(|p| p != size) {
+            return Err(format!(
+                "endpoint {address:#04x} has {size}-byte packets, expected {} at {:?} speed",
+                packet.unwrap_or_default(),
+                device.speed()
+            ));
+        }
+    }
+    Ok(format!(
+        "class ff, bulk endpoints {EP_OUT:#04x} and {EP_IN:#04x}, {:?} speed",
+        device.speed()
+    ))
+}
+
+/// The max packet size of a bulk endpoint at `speed`, if USB fixes one.
+fn bulk_packet_size(speed: rusb::Speed) -> Option<u16> {
+    match speed {
+        rusb::Speed::Full => Some(64),
+        rusb::Speed::High => Some(512),
+        rusb::Speed::Super | rusb::Speed::SuperPlus => Some(1024),
+        _ => None,
+    }
+}
+
+/// Which kernel driver, if any, has the scanner's interface (from sysfs).
+/// Another driver is fine — opening detaches it — but usbfs means another
+/// program has claimed it.
+fn check_kernel_driver(device: &rusb::Device<rusb::Context>) -> Result<String, String> {
+    let ports = device
+        .port_numbers()
+        .map_err(|e| format!("cannot read port path: {e}"))?;
+    let ports: Vec<String> = ports.iter().map(u8::to_string).collect();
+    let config = device.active_config_descriptor().map_or(1, |c| c.number());
+    let iface = format!(
+        "/sys/bus/usb/devices/{}-{}:{config}.{IFACE}",
+        device.bus_number(),
+        ports.join(".")
+    );
+    if !std::path::Path::new(&iface).exists() {
+        return Err(format!("no sysfs entry {iface}"));
+    }
+    let Ok(target) = std::fs::read_link(format!("{iface}/driver")) else {
+        return Ok("none".into());
+    };
+    match target.file_name().map(|n| n.to_string_lossy()) {
+        Some(name) if name == "usbfs" => {
+            Err("claimed by another program (usbfs) — is s1500d or scanbd already running?".into())
+        }
+        Some(name) => Ok(format!("{name} (detached when s1500d opens the scanner)")),
+        None => Ok("none".into()),
+    }
+}
+
+/// Whether this user may read and write the scanner's device node.
+fn check_permissions(path: &str) -> Result<String, String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = std::fs::metadata(path).map_err(|e| format!("cannot stat {path}: {e}"))?;
+    let node = format!(
+        "{path} mode {:04o}, owner {}:{}",
+        meta.mode() & 0o7777,
+        meta.uid(),
+        meta.gid()
+    );
+    let c_path = std::ffi::CString::new(path.as_bytes()).map_err(|e| e.to_string())?;
+    // SAFETY: c_path is a valid NUL-terminated string.
+    if unsafe { libc::access(c_path.as_ptr(), libc::R_OK | libc::W_OK) } == 0 {
+        Ok(node)
+    } else {
+        // SAFETY: getuid has no preconditions.
+        let uid = unsafe { libc::getuid() };
+        Err(format!("{node} — not writable by uid {uid}"))
+    }
+}
+
+/// The udev rule that gives users access to the scanner, for when
+/// opening it fails with a permission error.
+fn udev_rule() -> String {
+    format!(
+        "SUBSYSTEM==\"usb\", ATTR{{idVendor}}==\"{:04x}\", ATTR{{idProduct}}==\"{:04x}\", \
+         MODE=\"0666\", TAG+=\"uaccess\"",
+        model().vid,
+        model().pid
+    )
+}
+
+/// The devd rule that gives the `operator` group access to the scanner on
+/// FreeBSD, the counterpart of [`udev_rule`].
+fn devd_rule() -> String {
+    format!(
+        "notify 100 {{ match \"system\" \"USB\"; match \"subsystem\" \"DEVICE\"; \
+         match \"type\" \"ATTACH\"; match \"vendor\" \"0x{:04x}\"; \
+         match \"product\" \"0x{:04x}\"; \
+         action \"chgrp operator /dev/$cdev; chmod 0660 /dev/$cdev\"; }};",
+        model().vid,
+        model().pid
+    )
+}
+
+/// Print how to let this user open the scanner on this platform.
+fn print_access_help() {
+    if cfg!(target_os = "macos") {
+        println!("      macOS needs no permission rule, but only one program may claim");
+        println!("      the scanner: quit Image Capture, ScanSnap Home or ScanSnap Manager");
+        println!("      (and anything else using it), then close and reopen the lid.");
+    } else if cfg!(target_os = "freebsd") {
+        println!("      Add this rule to /etc/devd/scansnap.conf:\n");
+        println!("        {}\n", devd_rule());
+        println!("      then run: sudo service devd restart, add your user to the");
+        println!("      operator group, and close and reopen the lid.");
+    } else {
+        println!("      Install this rule as /etc/udev/rules.d/99-scansnap.rules:\n");
+        println!("        {}\n", udev_rule());
+        println!("      then run: sudo udevadm control --reload-rules && sudo udevadm trigger");
+        println!("      and close and reopen the lid.");
+    }
+}
+
+/// The command that lists attached USB devices on this platform.
+fn list_usb_command() -> String {
+    let vid = model().vid;
+    if cfg!(target_os = "macos") {
+        format!("system_profiler SPUSBDataType | grep -B6 0x{vid:04x}")
+    } else if cfg!(target_os = "freebsd") {
+        "usbconfig list".into()
+    } else {
+        format!("lsusb | grep {vid:04x}")
+    }
+}
+
+/// The unattended checks as one JSON object.
+fn report_json(checks: &[Check]) -> String {
+    let items = checks.iter().map(|c| {
+        json::Object::new()
+            .str("name", c.name)
+            .str("status", c.status.name())
+            .str("detail", &c.detail)
+            .finish()
+    });
+    json::Object::new()
+        .str("version", env!("CARGO_PKG_VERSION"))
+        .bool("ok", checks.iter().all(|c| c.status == Status::Pass))
+        .raw("checks", &json::array(items))
+        .finish()
+}
+
+/// `doctor --non-interactive`: run the unattended checks, print them (as
+/// JSON with `--json`) and exit non-zero if any failed.
+fn unattended(json: bool) -> ! {
+    let print = |checks: &[Check]| {
+        if json {
+            println!("{}", report_json(checks));
+            return;
+        }
+        for c in checks {
+            let status = c.status.name().to_ascii_uppercase();
+            println!(
+                "{:.<16} {status:<4}  ({})",
+                format!("{} ", c.name),
+                c.detail
+            );
+        }
+    };
+    let (checks, opened) = match rusb::Context::new() {
+        Ok(ctx) => unattended_checks(&ctx),
+        Err(e) => {
+            print(&[Check::new(
+                "usb-context",
+                Err(format!("cannot initialize libusb: {e}")),
+            )]);
+            std::process::exit(error::EXIT_USB);
+        }
+    };
+    print(&checks);
+    let code = match opened.err() {
+        Some(UsbError::NotFound) => error::EXIT_NOT_FOUND,
+        Some(UsbError::AccessDenied) => error::EXIT_ACCESS,
+        Some(_) => error::EXIT_FAILURE,
+        None if checks.iter().all(|c| c.status == Status::Pass) => 0,
+        None => error::EXIT_FAILURE,
+    };
+    std::process::exit(code);
+}
+
 /// Block until the user presses Enter.
 fn wait_enter() {
     let _ = io::stdout().flush();
     let _ = io::stdin().lock().read_line(&mut String::new());
 }
 
+/// Ask a yes/no question; anything but `y`/`yes` means no.
+fn confirm(prompt: &str) -> bool {
+    print!("{prompt} [y/N] ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    let _ = io::stdin().lock().read_line(&mut answer);
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Poll quickly until the button reaches `pressed`, returning when it did.
+fn wait_button(
+    handle: &rusb::DeviceHandle<rusb::Context>,
+    pressed: bool,
+    timeout: Duration,
+) -> Option<Instant> {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if poll_status(handle).is_ok_and(|s| s.button == pressed) {
+            return Some(Instant::now());
+        }
+        std::thread::sleep(CALIBRATION_POLL);
+    }
+    None
+}
+
+/// Recommend a `gesture_timeout_ms` from measured release-to-press gaps.
+///
+/// Takes the slowest gap, adds 25% headroom plus one daemon poll interval
+/// (the daemon only sees the button every 100ms), and rounds up to 50ms,
+/// staying within 200–2000ms.
+fn recommend_timeout(gaps: &[Duration]) -> Option<u64> {
+    let slowest = gaps.iter().max()?.as_millis() as u64;
+    let ms = slowest * 5 / 4 + crate::POLL_INTERVAL.as_millis() as u64;
+    Some(((ms + 49) / 50 * 50).clamp(200, 2000))
+}
+
+/// Measure the user's natural double-press gap and offer to save the
+/// recommended gesture window to the config file.
+fn calibrate(handle: &rusb::DeviceHandle<rusb::Context>, config_path: Option<&str>) {
+    println!("\n[+] Gesture calibration");
+    println!("      Double-press the scan button at your natural pace,");
+    println!("      {CALIBRATION_ATTEMPTS} times. Pause between attempts.\n");
+
+    let mut gaps = Vec::new();
+    for attempt in 1..=CALIBRATION_ATTEMPTS {
+        print!("      Double-press {attempt}/{CALIBRATION_ATTEMPTS}: ");
+        let _ = io::stdout().flush();
+        let gap = wait_button(handle, true, DOCTOR_TIMEOUT)
+            .and_then(|_| wait_button(handle, false, DOCTOR_TIMEOUT))
+            .and_then(|released| {
+                let pressed = wait_button(handle, true, Duration::from_secs(3))?;
+                wait_button(handle, false, DOCTOR_TIMEOUT)?;
+                Some(pressed - released)
+            });
+        match gap {
+            Some(gap) => {
+                println!("gap {}ms", gap.as_millis());
+                gaps.push(gap);
+            }
+            None => println!("no second press — skipped"),
+        }
+        // Let a stray third press settle before the next attempt.
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    let Some(ms) = recommend_timeout(&gaps) else {
+        println!("\n      No double presses measured — nothing to recommend.");
+        return;
+    };
+    println!("\n      Recommended: gesture_timeout_ms = {ms}");
+
+    let Some(path) = config_path else {
+        println!("      Set it in your config file (or rerun with s1500d doctor -c CONFIG).");
+        return;
+    };
+    if !confirm(&format!("      Write it to {path}?")) {
+        return;
+    }
+    let result = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| crate::config::set_gesture_timeout(&text, ms))
+        .and_then(|text| std::fs::write(path, text).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => println!("      Saved. Restart s1500d to apply."),
+        Err(e) => println!("      Could not update {path}: {e}"),
+    }
+}
+
+/// Feed a double press through the daemon's own gesture detection, with
+/// the config's gesture window and profiles, and check that it comes out
+/// as one. The profile it would pick is logged, but nothing runs.
+fn gesture_test(handle: &Handle, config_path: &str) -> bool {
+    println!("\n[+] Gesture test");
+    let mode = Mode::ConfigMode(Box::new(crate::load_config(config_path)));
+    let mut queue = DispatchQueue::new(Backpressure::default(), queue::DEFAULT_CAPACITY);
+    let mut gesture = GestureState::Idle;
+    let mut prev: Option<State> = None;
+    println!("      Double-press the scan button at your natural pace.");
+    let start = Instant::now();
+    while start.elapsed() < DOCTOR_TIMEOUT {
+        if let Ok(state) = poll_status(handle) {
+            if let Some(p) = prev {
+                process_transitions(p, state, &mode, &mut gesture, &mut None, &mut queue);
+            }
+            prev = Some(state);
+            if check_gesture_timeout(&gesture, &mode, state.paper).is_some() {
+                return match gesture.completed() {
+                    Some(Gesture::Presses(2)) => {
+                        println!("      Detected a 2x press.        PASS");
+                        true
+                    }
+                    Some(other) => {
+                        println!("      Detected a {other}, not a 2x press.   FAIL");
+                        false
+                    }
+                    None => false,
+                };
+            }
+        }
+        // The daemon's own poll rate, so the test sees what it would.
+        std::thread::sleep(crate::POLL_INTERVAL);
+    }
+    println!("      No gesture detected.        FAIL");
+    false
+}
+
 /// Poll until `predicate` is satisfied or `timeout` elapses.
 /// Prints dots to show progress. Returns the matching state or None.
 fn wait_for_state(
@@ -23,7 +513,7 @@ fn wait_for_state(
     print!("      Polling");
     let _ = io::stdout().flush();
     loop {
-        if let Some(state) = poll_status(handle) {
+        if let Ok(state) = poll_status(handle) {
             if predicate(&state) {
                 return Some(state);
             }
@@ -42,7 +532,12 @@ fn wait_for_state(
     }
 }
 
-pub fn doctor() {
+/// `s1500d doctor`; `config_path` is the `-c` file, for the gesture test
+/// and to offer the calibrated window to.
+pub fn doctor(opts: &Options, config_path: Option<&str>) {
+    if opts.non_interactive || opts.json {
+        unattended(opts.json);
+    }
     println!("s1500d doctor");
     println!("=============\n");
     println!("Verifying USB communication and hardware event detection");
@@ -51,48 +546,61 @@ pub fn doctor() {
     let ctx = match rusb::Context::new() {
         Ok(c) => c,
         Err(e) => {
-            println!("[1/6] USB context ............. FAIL ({e})");
+            println!("usb-context ............. FAIL ({e})");
             println!("\n      Cannot initialize libusb. Is it installed?");
-            std::process::exit(1);
+            std::process::exit(error::EXIT_USB);
         }
     };
 
-    // ── 1. USB connection ────────────────────────────────────────
-    print!("[1/6] USB connection .......... ");
-    let _ = io::stdout().flush();
-    let handle = match try_open(&ctx) {
-        Some(h) => {
-            println!("ok");
-            h
+    // ── 1–6. Checks that need nobody at the scanner ──────────────
+    let (checks, opened) = unattended_checks(&ctx);
+    let steps = checks.len() + SENSOR_STEPS;
+    for (i, c) in checks.iter().enumerate() {
+        let status = match c.status {
+            Status::Pass => "ok  ",
+            Status::Fail => "FAIL",
+            Status::Skip => "skip",
+        };
+        println!(
+            "[{}/{steps}] {:.<24} {status}  ({})",
+            i + 1,
+            format!("{} ", c.name),
+            c.detail
+        );
+    }
+    let handle = match opened {
+        Ok(h) => h,
+        Err(UsbError::AccessDenied) => {
+            println!("\n      Scanner found, but this user may not open it.");
+            print_access_help();
+            std::process::exit(error::EXIT_ACCESS);
         }
-        None => {
-            println!("FAIL");
-            println!("\n      Scanner not found (04c5:11a2).");
-            println!("      Is the ADF lid open? Check: lsusb | grep 04c5");
-            std::process::exit(1);
+        Err(UsbError::NotFound) => {
+            println!(
+                "\n      Scanner not found ({:04x}:{:04x}).",
+                model().vid,
+                model().pid
+            );
+            println!("      Is the ADF lid open? Check: {}", list_usb_command());
+            std::process::exit(error::EXIT_NOT_FOUND);
         }
-    };
-
-    // ── 2. GET_HW_STATUS ─────────────────────────────────────────
-    print!("[2/6] Hardware status ......... ");
-    let _ = io::stdout().flush();
-    let baseline = match poll_status(&handle) {
-        Some(s) => {
-            println!("ok  (paper={}, button={})", s.paper, s.button);
-            s
-        }
-        None => {
-            println!("FAIL");
-            println!("\n      GET_HW_STATUS returned no data. USB communication error.");
-            std::process::exit(1);
+        Err(_) => {
+            println!("\n      Scanner found, but it could not be opened.");
+            std::process::exit(error::EXIT_FAILURE);
         }
     };
+    let Ok(baseline) = poll_status(&handle) else {
+        println!("\n      GET_HW_STATUS returned no data. USB communication error.");
+        std::process::exit(error::EXIT_FAILURE);
+    };
 
-    let mut passed = 2u32;
-    let mut failed = 0u32;
+    let mut passed = checks.iter().filter(|c| c.status == Status::Pass).count();
+    let mut failed = checks.len() - passed;
+    let mut step = checks.len();
 
-    // ── 3. Paper detect ──────────────────────────────────────────
-    println!("\n[3/6] Paper detect");
+    // ── 7. Paper detect ──────────────────────────────────────────
+    step += 1;
+    println!("\n[{step}/{steps}] Paper detect");
     if baseline.paper {
         print!("      Paper already in feeder — remove it first, then press Enter: ");
         wait_enter();
@@ -114,8 +622,9 @@ pub fn doctor() {
         }
     }
 
-    // ── 4. Paper remove ──────────────────────────────────────────
-    println!("\n[4/6] Paper remove");
+    // ── 8. Paper remove ──────────────────────────────────────────
+    step += 1;
+    println!("\n[{step}/{steps}] Paper remove");
     print!("      Press Enter, then remove the paper: ");
     wait_enter();
     match wait_for_state(&handle, |s| !s.paper, DOCTOR_TIMEOUT) {
@@ -129,8 +638,9 @@ pub fn doctor() {
         }
     }
 
-    // ── 5. Button press ──────────────────────────────────────────
-    println!("\n[5/6] Button press");
+    // ── 9. Button press ──────────────────────────────────────────
+    step += 1;
+    println!("\n[{step}/{steps}] Button press");
     if baseline.button {
         print!("      Button appears held — release it first, then press Enter: ");
         wait_enter();
@@ -150,8 +660,9 @@ pub fn doctor() {
         }
     }
 
-    // ── 6. Button release ────────────────────────────────────────
-    println!("\n[6/6] Button release");
+    // ── 10. Button release ───────────────────────────────────────
+    step += 1;
+    println!("\n[{step}/{steps}] Button release");
     println!("      Release the button now.");
     match wait_for_state(&handle, |s| !s.button, DOCTOR_TIMEOUT) {
         Some(_) => {
@@ -164,13 +675,90 @@ pub fn doctor() {
         }
     }
 
+    // ── Gesture test (optional) ──────────────────────────────────
+    if let Some(path) = config_path {
+        if confirm("\nTest double-press detection with this config?") {
+            if gesture_test(&handle, path) {
+                passed += 1;
+            } else {
+                failed += 1;
+            }
+        }
+    }
+
     // ── Summary ──────────────────────────────────────────────────
     let total = passed + failed;
     println!("\n=============");
     if failed == 0 {
         println!("All {total} checks passed. Scanner is working correctly.");
+        if confirm("\nCalibrate the multi-press gesture window now?") {
+            calibrate(&handle, config_path);
+        }
     } else {
         println!("{passed}/{total} passed, {failed} failed.");
-        std::process::exit(1);
+        std::process::exit(error::EXIT_FAILURE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(v: &[u64]) -> Vec<Duration> {
+        v.iter().map(|&m| Duration::from_millis(m)).collect()
+    }
+
+    #[test]
+    fn recommendation_from_slowest_gap() {
+        // 240 * 1.25 + 100 = 400
+        assert_eq!(recommend_timeout(&ms(&[180, 240, 200])), Some(400));
+        // 310 * 1.25 + 100 = 487.5 → rounds up to 500
+        assert_eq!(recommend_timeout(&ms(&[310])), Some(500));
+    }
+
+    #[test]
+    fn json_report_lists_each_check() {
+        let checks = [
+            Check::new("usb-open", Err("scanner not found".into())),
+            Check {
+                name: "hw-status",
+                status: Status::Skip,
+                detail: "scanner not open".into(),
+            },
+        ];
+        assert_eq!(
+            report_json(&checks),
+            r#"{"version":""#.to_string()
+                + env!("CARGO_PKG_VERSION")
+                + r#"","ok":false,"checks":[{"name":"usb-open","status":"fail","detail":"scanner not found"},{"name":"hw-status","status":"skip","detail":"scanner not open"}]}"#
+        );
+    }
+
+    #[test]
+    fn udev_rule_matches_contrib() {
+        let rules = include_str!("../contrib/99-scansnap.rules");
+        assert!(rules.lines().any(|line| line == udev_rule()));
+    }
+
+    #[test]
+    fn devd_rule_matches_the_model() {
+        let rule = devd_rule();
+        assert!(rule.contains(&format!("\"vendor\" \"0x{:04x}\"", model().vid)));
+        assert!(rule.contains(&format!("\"product\" \"0x{:04x}\"", model().pid)));
+        assert!(rule.starts_with("notify 100 {") && rule.ends_with("};"));
+    }
+
+    #[test]
+    fn bulk_packet_size_follows_speed() {
+        assert_eq!(bulk_packet_size(rusb::Speed::High), Some(512));
+        assert_eq!(bulk_packet_size(rusb::Speed::Full), Some(64));
+        assert_eq!(bulk_packet_size(rusb::Speed::Unknown), None);
+    }
+
+    #[test]
+    fn recommendation_is_clamped() {
+        assert_eq!(recommend_timeout(&ms(&[20])), Some(200));
+        assert_eq!(recommend_timeout(&ms(&[3000])), Some(2000));
+        assert_eq!(recommend_timeout(&[]), None);
     }
 }
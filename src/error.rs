@@ -0,0 +1,92 @@
+use std::fmt;
+
+/// Exit status for a command that ran but failed (a doctor check, an
+/// unreadable capture file).
+pub const EXIT_FAILURE: i32 = 1;
+/// Bad command-line arguments.
+pub const EXIT_USAGE: i32 = 2;
+/// The config file is missing or invalid.
+pub const EXIT_CONFIG: i32 = 3;
+/// libusb couldn't be initialized.
+pub const EXIT_USB: i32 = 4;
+/// `doctor`, `status` or `bench` found no scanner.
+pub const EXIT_NOT_FOUND: i32 = 5;
+/// `doctor`, `status` or `bench` found the scanner but may not open it.
+pub const EXIT_ACCESS: i32 = 6;
+/// A file or socket named in the config (`journal`, `control_socket`), the
+/// `--record` file or the `--tui` terminal couldn't be opened.
+pub const EXIT_IO: i32 = 7;
+/// `wait` gave up after `--timeout`.
+pub const EXIT_TIMEOUT: i32 = 8;
+
+/// Why the scanner couldn't be opened or polled, sorted by what the
+/// daemon does about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbError {
+    /// No scanner attached — the lid is closed, or it isn't plugged in.
+    NotFound,
+    /// Attached, but its device node isn't accessible (the udev rule).
+    AccessDenied,
+    /// The scanner went away mid-exchange.
+    Gone,
+    /// The scanner is working and refused the command for now.
+    Busy,
+    /// The scanner answered with a status too short to decode.
+    BadResponse,
+    /// An endpoint stalled (a pipe error), as it tends to after the host
+    /// suspends and resumes; clearing the halt usually brings it back.
+    Stalled,
+    /// Any other libusb failure.
+    Io(rusb::Error),
+}
+
+impl From<rusb::Error> for UsbError {
+    fn from(e: rusb::Error) -> Self {
+        match e {
+            rusb::Error::NotFound => Self::NotFound,
+            rusb::Error::Access => Self::AccessDenied,
+            rusb::Error::NoDevice => Self::Gone,
+            rusb::Error::Busy => Self::Busy,
+            rusb::Error::Pipe => Self::Stalled,
+            e => Self::Io(e),
+        }
+    }
+}
+
+impl fmt::Display for UsbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFound => f.write_str("scanner not found"),
+            Self::AccessDenied => f.write_str("permission denied"),
+            Self::Gone => f.write_str("scanner disconnected"),
+            Self::Busy => f.write_str("scanner busy"),
+            Self::BadResponse => f.write_str("status response too short to decode"),
+            Self::Stalled => f.write_str("endpoint stalled"),
+            Self::Io(e) => write!(f, "USB error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for UsbError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usb_errors_keep_their_cause() {
+        assert_eq!(UsbError::from(rusb::Error::NotFound), UsbError::NotFound);
+        assert_eq!(UsbError::from(rusb::Error::Access), UsbError::AccessDenied);
+        assert_eq!(UsbError::from(rusb::Error::NoDevice), UsbError::Gone);
+        assert_eq!(UsbError::from(rusb::Error::Busy), UsbError::Busy);
+        assert_eq!(UsbError::from(rusb::Error::Pipe), UsbError::Stalled);
+        assert_eq!(
+            UsbError::from(rusb::Error::Timeout),
+            UsbError::Io(rusb::Error::Timeout)
+        );
+        assert_eq!(
+            UsbError::Io(rusb::Error::Overflow).to_string(),
+            "USB error: Overflow"
+        );
+    }
+}
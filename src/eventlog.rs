@@ -0,0 +1,233 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::error;
+
+use crate::config::EventLogConfig;
+use crate::handler::Failure;
+use crate::json;
+
+/// The open event log, once [`start`] has been called with one configured.
+static LOG: Mutex<Option<EventLog>> = Mutex::new(None);
+
+/// Append-only record of events and handler outcomes, one JSON object per
+/// line, independent of the log level.
+///
+/// Before a line would take the file past `max_bytes`, or once the file is
+/// older than `max_age`, it is renamed to `path.1` (shifting older ones up
+/// to `path.<keep>`, past which they are deleted) and a new file started.
+#[derive(Debug)]
+pub struct EventLog {
+    config: EventLogConfig,
+    file: File,
+    size: u64,
+    /// When the current file was started, for `max_age`.
+    created: SystemTime,
+}
+
+impl EventLog {
+    /// Open (or create) the log, appending to what is already there.
+    pub fn open(config: &EventLogConfig) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let meta = file.metadata()?;
+        Ok(Self {
+            config: config.clone(),
+            size: meta.len(),
+            // Without a birth time, age counts from when the daemon started.
+            created: meta.created().unwrap_or_else(|_| SystemTime::now()),
+            file,
+        })
+    }
+
+    /// Append one line, rotating first if it is due.
+    pub fn write(&mut self, line: &str, now: SystemTime) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.rotation_due(len, now) {
+            self.rotate(now)?;
+        }
+        // One write call per line on an `O_APPEND` file, as in the audit log.
+        self.file.write_all(format!("{line}\n").as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotation_due(&self, len: u64, now: SystemTime) -> bool {
+        let full = self
+            .config
+            .max_bytes
+            .is_some_and(|max| self.size + len > max);
+        let old = self
+            .config
+            .max_age
+            .is_some_and(|max| now.duration_since(self.created).is_ok_and(|age| age >= max));
+        self.size > 0 && (full || old)
+    }
+
+    fn rotate(&mut self, now: SystemTime) -> io::Result<()> {
+        let path = Path::new(&self.config.path);
+        let keep = self.config.keep;
+        if keep == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for n in (1..keep).rev() {
+                match fs::rename(rotated(path, n), rotated(path, n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(path, rotated(path, 1))?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.size = 0;
+        self.created = now;
+        Ok(())
+    }
+}
+
+/// `path.n`, the `n`th most recent rotated file.
+fn rotated(path: &Path, n: u32) -> PathBuf {
+    let mut name = OsString::from(path);
+    name.push(format!(".{n}"));
+    name.into()
+}
+
+/// Switch to the log `config` describes, or off; the previous one is
+/// closed. On error the previous log stays in use.
+pub fn start(config: Option<&EventLogConfig>) -> Result<(), String> {
+    let log = config
+        .map(|c| EventLog::open(c).map_err(|e| format!("cannot open {}: {e}", c.path)))
+        .transpose()?;
+    *LOG.lock().unwrap_or_else(|e| e.into_inner()) = log;
+    Ok(())
+}
+
+/// Record an emitted event (see `event_json`).
+pub fn event(json: &str) {
+    append(json);
+}
+
+/// Record how the handler run for event `seq` went, after `attempts`
+/// tries: `"handler":"ok"` or the failure, as `handler-failed` gives it.
+pub fn handler(
+    seq: u64,
+    args: &[&str],
+    attempts: u32,
+    failure: Option<Failure>,
+    timestamp: Option<&str>,
+) {
+    append(&handler_json(seq, args, attempts, failure, timestamp));
+}
+
+fn handler_json(
+    seq: u64,
+    args: &[&str],
+    attempts: u32,
+    failure: Option<Failure>,
+    timestamp: Option<&str>,
+) -> String {
+    let outcome = failure.map_or_else(|| "ok".to_string(), |f| f.to_string());
+    let object = json::Object::new()
+        .num("seq", seq)
+        .str("handler", &outcome)
+        .raw("args", &json::array(args.iter().map(|a| json::string(a))))
+        .num("attempts", attempts)
+        .opt_str("timestamp", timestamp);
+    crate::host::tag(object).finish()
+}
+
+fn append(line: &str) {
+    let mut guard = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(log) = guard.as_mut() {
+        if let Err(e) = log.write(line, SystemTime::now()) {
+            error!("event_log: cannot write {}: {e}", log.config.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-{name}", std::process::id()));
+        for n in 0..4 {
+            let _ = fs::remove_file(if n == 0 {
+                path.clone()
+            } else {
+                rotated(&path, n)
+            });
+        }
+        path
+    }
+
+    fn config(path: &Path, max_bytes: Option<u64>, max_age: Option<Duration>) -> EventLogConfig {
+        EventLogConfig {
+            path: path.to_str().unwrap().into(),
+            max_bytes,
+            max_age,
+            keep: 2,
+        }
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn handler_outcome_line() {
+        let line = handler_json(4, &["scan", "legal"], 1, None, Some("2026-03-14T09:26:53Z"));
+        assert!(line.starts_with(
+            r#"{"seq":4,"handler":"ok","args":["scan","legal"],"attempts":1,"timestamp":"2026-03-14T09:26:53Z","host":"#
+        ));
+        let line = handler_json(5, &["paper-in"], 3, Some(Failure::Exit(2)), None);
+        assert!(line.starts_with(
+            r#"{"seq":5,"handler":"exit-2","args":["paper-in"],"attempts":3,"timestamp":null,"#
+        ));
+    }
+
+    #[test]
+    fn rotates_by_size_keeping_the_newest() {
+        let path = temp_path("eventlog-size");
+        let now = SystemTime::now();
+        let mut log = EventLog::open(&config(&path, Some(8), None)).unwrap();
+        for line in ["a1", "a2", "b1", "b2", "c1", "c2", "d1"] {
+            log.write(line, now).unwrap();
+        }
+        assert_eq!(read(&path), "d1\n");
+        assert_eq!(read(&rotated(&path, 1)), "c1\nc2\n");
+        assert_eq!(read(&rotated(&path, 2)), "b1\nb2\n");
+        assert!(!rotated(&path, 3).exists());
+
+        // Reopening carries on with the file's size.
+        let mut log = EventLog::open(&config(&path, Some(8), None)).unwrap();
+        log.write("d2", now).unwrap();
+        log.write("e1", now).unwrap();
+        assert_eq!(read(&path), "e1\n");
+        assert_eq!(read(&rotated(&path, 1)), "d1\nd2\n");
+        let _ = temp_path("eventlog-size");
+    }
+
+    #[test]
+    fn rotates_by_age() {
+        let path = temp_path("eventlog-age");
+        let day = Duration::from_secs(86400);
+        let mut log = EventLog::open(&config(&path, None, Some(day))).unwrap();
+        let start = log.created;
+        log.write("a", start).unwrap();
+        log.write("b", start + day / 2).unwrap();
+        log.write("c", start + day).unwrap();
+        log.write("d", start + day + day / 2).unwrap();
+        assert_eq!(read(&path), "c\nd\n");
+        assert_eq!(read(&rotated(&path, 1)), "a\nb\n");
+        let _ = temp_path("eventlog-age");
+    }
+}
@@ -0,0 +1,1111 @@
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+
+use s1500d::Inquiry;
+
+use crate::audit;
+use crate::cgroup::{self, CgroupConfig, JobCgroup};
+
+/// How handler processes are started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    /// CPU scheduling priority (`nice` value, -20..=19).
+    pub nice: Option<i32>,
+    /// I/O scheduling class and level.
+    pub ionice: Option<IoPriority>,
+    /// Run each handler in its own cgroup (v2) with optional limits.
+    pub cgroup: Option<CgroupConfig>,
+    /// Append a record of every run to this file.
+    pub audit_log: Option<PathBuf>,
+    /// Run the handler on this host via `ssh` instead of locally.
+    pub ssh: Option<String>,
+    /// Serial number of the attached scanner, exported as `S1500D_SERIAL`.
+    pub serial: Option<String>,
+    /// What the attached scanner answered to INQUIRY, exported as
+    /// `S1500D_DEVICE_VENDOR`, `_MODEL` and `_FIRMWARE`.
+    pub identity: Option<Inquiry>,
+    /// Write the event as JSON to the handler's stdin (`handler_json`).
+    pub json: bool,
+    /// Kill a handler still running after this long (`handler_timeout_s`).
+    pub timeout: Option<Duration>,
+    /// What the dispatcher does when a run fails (`on_handler_failure`).
+    pub on_failure: OnFailure,
+    /// How often to run a failed handler again, under [`OnFailure::Retry`].
+    pub retries: u32,
+    /// Drop to this user and group before exec (`handler_user`).
+    pub run_as: Option<RunAs>,
+    /// Start from an empty environment (`handler_clear_env`).
+    pub clear_env: bool,
+    /// `PATH` for the handler (`handler_path`).
+    pub path: Option<String>,
+    /// Working directory for the handler (`handler_cwd`).
+    pub cwd: Option<PathBuf>,
+}
+
+/// `PATH` of a handler with a cleared environment and no `handler_path`.
+pub const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// What to do when a handler fails or times out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnFailure {
+    /// Log it and carry on.
+    #[default]
+    Ignore,
+    /// Run the handler again, up to `handler_retries` times.
+    Retry,
+    /// Announce it with a `handler-failed` event.
+    Event,
+}
+
+/// What a handler is told about its event besides the arguments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Context {
+    /// Presses of the gesture that chose the profile, for `scan`.
+    pub press_count: Option<u32>,
+    /// Whether paper is in the feeder, if the scanner is present.
+    pub paper: Option<bool>,
+    /// How long the lid was open, in seconds, for `device-left`.
+    pub lid_open_secs: Option<u64>,
+    /// When the event happened, in the log's timestamp format.
+    pub timestamp: Option<String>,
+    /// The event as a JSON object, written to stdin when set.
+    pub json: Option<String>,
+}
+
+/// I/O scheduling priority, as understood by `ionice(1)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    Realtime(u8),
+    BestEffort(u8),
+    Idle,
+}
+
+impl IoPriority {
+    /// Parse `idle`, `best-effort[:N]` or `realtime[:N]` (N = 0..=7,
+    /// default 4; lower is higher priority).
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (class, level) = match s.split_once(':') {
+            Some((c, l)) => (c, Some(l)),
+            None => (s, None),
+        };
+        let level = match level {
+            None => 4,
+            Some(l) => l
+                .parse::<u8>()
+                .ok()
+                .filter(|&n| n <= 7)
+                .ok_or_else(|| format!("invalid ionice level {l:?} (expected 0-7)"))?,
+        };
+        match class {
+            "realtime" => Ok(Self::Realtime(level)),
+            "best-effort" => Ok(Self::BestEffort(level)),
+            "idle" if s == "idle" => Ok(Self::Idle),
+            _ => Err(format!(
+                "invalid ionice {s:?} (expected idle, best-effort[:0-7] or realtime[:0-7])"
+            )),
+        }
+    }
+
+    /// The `ioprio` value passed to `ioprio_set(2)`.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn value(self) -> libc::c_int {
+        const CLASS_SHIFT: libc::c_int = 13;
+        let (class, level) = match self {
+            Self::Realtime(n) => (1, n),
+            Self::BestEffort(n) => (2, n),
+            Self::Idle => (3, 0),
+        };
+        (class << CLASS_SHIFT) | libc::c_int::from(level)
+    }
+}
+
+/// Apply scheduling settings to the current (freshly forked) process.
+///
+/// Runs between fork and exec, so it must stay async-signal-safe: plain
+/// syscalls only, no allocation or logging.
+fn apply_priority(nice: Option<i32>, ionice: Option<IoPriority>) -> io::Result<()> {
+    if let Some(nice) = nice {
+        // SAFETY: setpriority has no memory-safety preconditions.
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if let Some(prio) = ionice {
+        set_ioprio(prio)?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_ioprio(prio: IoPriority) -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    // SAFETY: ioprio_set takes only integer arguments.
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            libc::c_long::from(prio.value()),
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_ioprio(_prio: IoPriority) -> io::Result<()> {
+    Ok(())
+}
+
+/// A pipe whose ends are both close-on-exec: (read, write).
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0 as RawFd; 2];
+    // SAFETY: pipe2 fills the two-element array with new descriptors.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both descriptors were just created and are owned by nobody else.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// macOS has no pipe2, so set close-on-exec straight after creating it.
+#[cfg(target_os = "macos")]
+pub(crate) fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0 as RawFd; 2];
+    // SAFETY: pipe fills the two-element array with new descriptors.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both descriptors were just created and are owned by nobody else.
+    let ends = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    for fd in [&ends.0, &ends.1] {
+        // SAFETY: fcntl on a descriptor we own.
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(ends)
+}
+
+/// The user a handler runs as (`handler_user`, `handler_group`), resolved
+/// when the config is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunAs {
+    pub name: String,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    /// Supplementary groups: the user's own, from the group database.
+    pub groups: Vec<libc::gid_t>,
+    /// `HOME` for the handler, if the user has an account.
+    pub home: Option<String>,
+}
+
+impl RunAs {
+    /// Look up `user` (a name or a numeric uid) and optionally `group`,
+    /// which overrides the user's primary group. A numeric uid without an
+    /// account runs with `group`, or the gid of the same number, and no
+    /// other groups.
+    pub fn resolve(user: &str, group: Option<&str>) -> Result<Self, String> {
+        let numeric = user.parse::<libc::uid_t>().ok();
+        let account = match numeric {
+            Some(uid) => passwd(|pwd, buf, len, res| unsafe {
+                // SAFETY: the buffers are valid for `len` bytes.
+                libc::getpwuid_r(uid, pwd, buf, len, res)
+            }),
+            None => {
+                let name = CString::new(user).map_err(|_| format!("invalid user {user:?}"))?;
+                passwd(|pwd, buf, len, res| unsafe {
+                    // SAFETY: as above; `name` is NUL-terminated.
+                    libc::getpwnam_r(name.as_ptr(), pwd, buf, len, res)
+                })
+            }
+        }
+        .map_err(|e| format!("cannot look up user {user:?}: {e}"))?;
+        let gid = group.map(resolve_group).transpose()?;
+        let Some((name, uid, primary, home)) = account else {
+            let Some(uid) = numeric else {
+                return Err(format!("unknown user {user:?}"));
+            };
+            let gid = gid.unwrap_or(uid);
+            return Ok(Self {
+                name: user.to_string(),
+                uid,
+                gid,
+                groups: vec![gid],
+                home: None,
+            });
+        };
+        let gid = gid.unwrap_or(primary);
+        let groups = group_list(&name, gid)
+            .map_err(|e| format!("cannot list the groups of {name:?}: {e}"))?;
+        Ok(Self {
+            name,
+            uid,
+            gid,
+            groups,
+            home: Some(home),
+        })
+    }
+
+    /// Whether the process already runs as this user and group.
+    fn is_current(&self) -> bool {
+        // SAFETY: geteuid and getegid cannot fail and have no preconditions.
+        unsafe { libc::geteuid() == self.uid && libc::getegid() == self.gid }
+    }
+
+    /// Switch the current (freshly forked) process to this user: groups,
+    /// then gid, then uid, while it still may.
+    ///
+    /// Runs between fork and exec, so like [`apply_priority`] it sticks to
+    /// plain syscalls.
+    fn apply(&self) -> io::Result<()> {
+        // Already this user: nothing to switch, and setgroups would fail
+        // without CAP_SETGID.
+        if self.is_current() {
+            return Ok(());
+        }
+        // SAFETY: setgroups reads `groups.len()` gids from a live slice;
+        // setgid and setuid take only integers.
+        unsafe {
+            if libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) != 0
+                || libc::setgid(self.gid) != 0
+                || libc::setuid(self.uid) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run a `getpw*_r` lookup with a growing buffer, returning the name, uid,
+/// gid and home directory of the account, or `None` if there isn't one.
+fn passwd(
+    lookup: impl Fn(
+        *mut libc::passwd,
+        *mut libc::c_char,
+        libc::size_t,
+        *mut *mut libc::passwd,
+    ) -> libc::c_int,
+) -> io::Result<Option<(String, libc::uid_t, libc::gid_t, String)>> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        // SAFETY: an all-zero passwd is a valid value to be overwritten.
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        match lookup(&mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) {
+            0 if result.is_null() => return Ok(None),
+            // SAFETY: on success the strings point into `buf`.
+            0 => unsafe {
+                let text =
+                    |p: *const libc::c_char| CStr::from_ptr(p).to_string_lossy().into_owned();
+                return Ok(Some((
+                    text(pwd.pw_name),
+                    pwd.pw_uid,
+                    pwd.pw_gid,
+                    text(pwd.pw_dir),
+                )));
+            },
+            libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            err => return Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+/// The gid of `group`, a name or a number.
+fn resolve_group(group: &str) -> Result<libc::gid_t, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group).map_err(|_| format!("invalid group {group:?}"))?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        // SAFETY: an all-zero group is a valid value to be overwritten.
+        let mut grp: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        // SAFETY: the buffers are valid for their lengths.
+        let rc = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match rc {
+            0 if result.is_null() => return Err(format!("unknown group {group:?}")),
+            0 => return Ok(grp.gr_gid),
+            libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            err => {
+                let e = io::Error::from_raw_os_error(err);
+                return Err(format!("cannot look up group {group:?}: {e}"));
+            }
+        }
+    }
+}
+
+/// The groups `user` belongs to, with `gid` first.
+fn group_list(user: &str, gid: libc::gid_t) -> io::Result<Vec<libc::gid_t>> {
+    let name = CString::new(user).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut groups: Vec<libc::gid_t> = vec![0; 32];
+    loop {
+        let mut n = groups.len() as libc::c_int;
+        // SAFETY: `groups` holds `n` gids; getgrouplist writes at most that
+        // many and updates `n`. macOS declares the gids as c_int, which has
+        // the same size as gid_t.
+        let rc = unsafe {
+            libc::getgrouplist(name.as_ptr(), gid as _, groups.as_mut_ptr().cast(), &mut n)
+        };
+        if rc >= 0 {
+            groups.truncate(n as usize);
+            return Ok(groups);
+        }
+        // glibc reports how many it needs in `n`; others may not.
+        let needed = (n as usize).max(groups.len() * 2);
+        groups.resize(needed, 0);
+    }
+}
+
+/// Longest a handler may snooze an event for.
+const MAX_SNOOZE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Most a handler may write to its control pipe; the rest is ignored.
+const MAX_CONTROL_BYTES: u64 = 64 * 1024;
+
+/// A request a handler writes, one per line, to `$S1500D_CONTROL_FD`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// `snooze EVENT SECONDS`: don't dispatch `EVENT` for a while, e.g.
+    /// the paper-out caused by the scan the handler just ran.
+    Snooze(String, Duration),
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["snooze", event, secs] => {
+                if !crate::EVENT_NAMES.contains(event) {
+                    return Err(format!("unknown event {event:?}"));
+                }
+                let secs: u64 = secs
+                    .parse()
+                    .map_err(|_| format!("invalid snooze seconds {secs:?}"))?;
+                let duration = Duration::from_secs(secs);
+                if duration > MAX_SNOOZE {
+                    return Err(format!(
+                        "snooze of {secs}s is over the {}s limit",
+                        MAX_SNOOZE.as_secs()
+                    ));
+                }
+                Ok(Self::Snooze(event.to_string(), duration))
+            }
+            _ => Err("expected \"snooze EVENT SECONDS\"".into()),
+        }
+    }
+}
+
+/// Why a handler run failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// It could not be started or waited for.
+    Error,
+    /// It exited with this status.
+    Exit(i32),
+    /// A signal killed it.
+    Signal(i32),
+    /// It ran past `handler_timeout_s` and was killed.
+    Timeout,
+}
+
+impl fmt::Display for Failure {
+    /// One word, as passed to the `handler-failed` event.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Error => f.write_str("error"),
+            Self::Exit(code) => write!(f, "exit-{code}"),
+            Self::Signal(sig) => write!(f, "signal-{sig}"),
+            Self::Timeout => f.write_str("timeout"),
+        }
+    }
+}
+
+/// How a handler run went.
+#[derive(Debug)]
+pub struct Outcome {
+    /// Why the run failed; `None` if the handler exited successfully.
+    pub failure: Option<Failure>,
+    /// Valid requests the handler wrote to its control pipe.
+    pub requests: Vec<Request>,
+}
+
+impl Outcome {
+    /// The handler ran and exited successfully.
+    pub fn ok(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Pause before running a failed handler again (`on_handler_failure =
+/// "retry"`).
+pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How often a handler with a timeout is checked on.
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a timed-out handler has between SIGTERM and SIGKILL.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Wait for `child` until `deadline`, returning `None` if it is still
+/// running then.
+fn wait_until(child: &mut Child, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(WAIT_INTERVAL.min(deadline - now));
+    }
+}
+
+/// Wait for `child`, which leads its own process group, for at most
+/// `limit`. Past that the whole group gets SIGTERM, then SIGKILL if it
+/// hasn't exited within [`KILL_GRACE`]. Returns the status and whether the
+/// handler was killed.
+fn wait_or_kill(job: &str, child: &mut Child, limit: Duration) -> io::Result<(ExitStatus, bool)> {
+    if let Some(status) = wait_until(child, Instant::now() + limit)? {
+        return Ok((status, false));
+    }
+    warn!(
+        "job {job}: handler still running after {}s, killing it",
+        limit.as_secs()
+    );
+    let group = -(child.id() as libc::pid_t);
+    // SAFETY: kill has no memory-safety preconditions.
+    unsafe { libc::kill(group, libc::SIGTERM) };
+    if let Some(status) = wait_until(child, Instant::now() + KILL_GRACE)? {
+        return Ok((status, true));
+    }
+    warn!("job {job}: handler ignored SIGTERM, sending SIGKILL");
+    // SAFETY: as above.
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    child.wait().map(|status| (status, true))
+}
+
+/// Create the pipe handlers write requests to: a non-blocking read end for
+/// the daemon and a write end (close-on-exec until `pre_exec` clears it).
+fn control_pipe() -> io::Result<(File, OwnedFd)> {
+    let (read, write) = pipe()?;
+    // SAFETY: fcntl on a descriptor we own.
+    if unsafe { libc::fcntl(read.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((File::from(read), write))
+}
+
+/// Read whatever the handler wrote without waiting for descendants that
+/// may still hold the pipe open, and parse it into requests.
+fn read_requests(job: &str, pipe: File) -> Vec<Request> {
+    let mut buf = Vec::new();
+    // Stops at EOF or, if a background child still has the write end,
+    // at WouldBlock; either way `buf` keeps what was read.
+    let _ = pipe.take(MAX_CONTROL_BYTES).read_to_end(&mut buf);
+    String::from_utf8_lossy(&buf)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            Request::parse(line)
+                .map_err(|e| warn!("job {job}: ignoring control request {line:?}: {e}"))
+                .ok()
+        })
+        .collect()
+}
+
+/// The command line `ssh` runs on the remote host: the handler's argv,
+/// prefixed with `env` to carry the job's variables, quoted for the remote
+/// shell.
+fn remote_command(env: &[(&str, String)], argv: &[&str]) -> String {
+    let vars = env.iter().map(|(k, v)| format!("{k}={v}"));
+    std::iter::once("env".to_string())
+        .chain(vars)
+        .chain(argv.iter().map(|a| a.to_string()))
+        .map(|word| audit::shell_quote(&word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Generate a random (version 4) UUID identifying one handler run.
+///
+/// Reads from `/dev/urandom`; if that is unavailable, falls back to mixing
+/// the clock and PID so IDs are still unique in practice.
+pub fn new_job_id() -> String {
+    let mut b = [0u8; 16];
+    let urandom = std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut b));
+    if urandom.is_err() {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        b = (nanos ^ (u128::from(std::process::id()) << 96)).to_le_bytes();
+    }
+    b[6] = (b[6] & 0x0f) | 0x40; // version 4
+    b[8] = (b[8] & 0x3f) | 0x80; // RFC 4122 variant
+
+    let hex: String = b.iter().map(|x| format!("{x:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Run the handler with the given event arguments, synchronously.
+///
+/// `command` is the handler's program followed by any fixed arguments from
+/// the config; the event arguments are appended after them. Each run gets
+/// a fresh job ID, logged alongside the command and exported to the
+/// handler as `S1500D_JOB_ID` so its own logs can be correlated.
+/// `seq` is the sequence number of the triggering event, exported as
+/// `S1500D_SEQ`. The rest of `context` is exported as `S1500D_EVENT`,
+/// `S1500D_PROFILE` and so on, and its JSON, if any, written to stdin. The
+/// handler may write [`Request`]s, one per line, to the descriptor named by
+/// `S1500D_CONTROL_FD`; they are returned once it exits.
+///
+/// With [`Options::ssh`], the handler runs on that host through
+/// `ssh -o BatchMode=yes HOST -- env S1500D_…=… COMMAND ARGS…`; the control
+/// descriptor isn't available there.
+///
+/// With [`Options::timeout`], the handler runs in its own process group,
+/// and the group is killed if the handler is still running when the time
+/// is up, so a hung handler can't stall the daemon.
+pub fn run(
+    command: &[&str],
+    args: &[&str],
+    seq: u64,
+    context: &Context,
+    opts: &Options,
+) -> Outcome {
+    let job = new_job_id();
+    if command.is_empty() {
+        error!("job {job}: handler command is empty");
+        return Outcome {
+            failure: Some(Failure::Error),
+            requests: Vec::new(),
+        };
+    }
+    let handler_argv: Vec<&str> = command.iter().chain(args).copied().collect();
+    let pipe = match opts.ssh {
+        None => control_pipe()
+            .map_err(|e| warn!("job {job}: cannot create control pipe: {e}"))
+            .ok(),
+        Some(_) => None,
+    };
+    let control_fd = pipe.as_ref().map(|(_, w)| w.as_raw_fd());
+    let event = args.first().copied().unwrap_or_default();
+    let mut env = vec![
+        ("S1500D_JOB_ID", job.clone()),
+        ("S1500D_SEQ", seq.to_string()),
+        ("S1500D_EVENT", event.to_string()),
+    ];
+    if let (true, Some(profile)) = (
+        matches!(event, "scan" | "scan-started" | "scan-finished"),
+        args.get(1),
+    ) {
+        env.push(("S1500D_PROFILE", profile.to_string()));
+    }
+    if let Some(n) = context.press_count {
+        env.push(("S1500D_PRESS_COUNT", n.to_string()));
+    }
+    if let Some(paper) = context.paper {
+        env.push(("S1500D_PAPER", u8::from(paper).to_string()));
+    }
+    if let Some(secs) = context.lid_open_secs {
+        env.push(("S1500D_LID_OPEN_SECONDS", secs.to_string()));
+    }
+    if let Some(serial) = &opts.serial {
+        env.push(("S1500D_SERIAL", serial.clone()));
+    }
+    if let Some(identity) = &opts.identity {
+        env.push(("S1500D_DEVICE_VENDOR", identity.vendor.clone()));
+        env.push(("S1500D_DEVICE_MODEL", identity.product.clone()));
+        env.push(("S1500D_DEVICE_FIRMWARE", identity.revision.clone()));
+    }
+    if let Some(timestamp) = &context.timestamp {
+        env.push(("S1500D_TIMESTAMP", timestamp.clone()));
+    }
+    if let Some(fd) = control_fd {
+        env.push(("S1500D_CONTROL_FD", fd.to_string()));
+    }
+    let remote;
+    let argv: Vec<&str> = match &opts.ssh {
+        None => handler_argv,
+        Some(host) => {
+            remote = remote_command(&env, &handler_argv);
+            vec!["ssh", "-o", "BatchMode=yes", host, "--", &remote]
+        }
+    };
+    info!("job {job}: exec {}", argv.join(" "));
+    let (program, rest) = argv.split_at(1);
+    let mut cmd = Command::new(program[0]);
+    if opts.clear_env {
+        cmd.env_clear()
+            .env("PATH", opts.path.as_deref().unwrap_or(DEFAULT_PATH));
+    } else if let Some(path) = &opts.path {
+        cmd.env("PATH", path);
+    }
+    if let Some(user) = &opts.run_as {
+        cmd.env("USER", &user.name).env("LOGNAME", &user.name);
+        match &user.home {
+            Some(home) => cmd.env("HOME", home),
+            None => cmd.env_remove("HOME"),
+        };
+    }
+    if let Some(cwd) = &opts.cwd {
+        cmd.current_dir(cwd);
+    }
+    cmd.args(rest).envs(env.iter().map(|(k, v)| (k, v)));
+    if opts.timeout.is_some() {
+        // Lead a group of its own, so a timeout kills what it started too.
+        cmd.process_group(0);
+    }
+
+    let job_cgroup = opts.cgroup.as_ref().and_then(|c| {
+        JobCgroup::create(c, &job)
+            .map_err(|e| {
+                warn!(
+                    "job {job}: cannot create cgroup in {}: {e}",
+                    c.path.display()
+                )
+            })
+            .ok()
+    });
+    let procs_fd = job_cgroup.as_ref().map(JobCgroup::procs_fd);
+    let (nice, ionice, run_as) = (opts.nice, opts.ionice, opts.run_as.clone());
+    if procs_fd.is_some()
+        || nice.is_some()
+        || ionice.is_some()
+        || control_fd.is_some()
+        || run_as.is_some()
+    {
+        // SAFETY: the closure only makes async-signal-safe syscalls.
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(fd) = procs_fd {
+                    cgroup::join(fd)?;
+                }
+                // Let the control pipe's write end survive exec.
+                if let Some(fd) = control_fd {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                apply_priority(nice, ionice)?;
+                // Last, as joining the cgroup and raising the priority may
+                // need root.
+                match &run_as {
+                    Some(user) => user.apply(),
+                    None => Ok(()),
+                }
+            });
+        }
+    }
+
+    if context.json.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+
+    let started = Instant::now();
+    let mut timed_out = false;
+    let status = cmd.spawn().and_then(|mut child| {
+        if let (Some(json), Some(mut stdin)) = (&context.json, child.stdin.take()) {
+            // A handler that exits without reading it is fine.
+            let _ = writeln!(stdin, "{json}");
+        }
+        match opts.timeout {
+            None => child.wait(),
+            Some(limit) => wait_or_kill(&job, &mut child, limit).map(|(status, killed)| {
+                timed_out = killed;
+                status
+            }),
+        }
+    });
+    let duration = started.elapsed();
+    let requests = pipe.map_or_else(Vec::new, |(read, write)| {
+        drop(write);
+        read_requests(&job, read)
+    });
+    let usage = job_cgroup.map(|cg| cg.finish(&job));
+
+    if let Some(path) = &opts.audit_log {
+        let record = audit::Record {
+            job: &job,
+            event: args.first().copied().unwrap_or(""),
+            argv: &argv,
+            env: &env,
+            uid: opts.run_as.as_ref().map_or_else(
+                // SAFETY: getuid cannot fail and has no preconditions.
+                || unsafe { libc::getuid() },
+                |user| user.uid,
+            ),
+            status: &status,
+            duration,
+            usage,
+        };
+        if let Err(e) = audit::append(path, &record) {
+            error!("job {job}: cannot write audit log {}: {e}", path.display());
+        }
+    }
+
+    let failure = match status {
+        Ok(_) if timed_out => Some(Failure::Timeout),
+        Ok(s) if s.success() => {
+            debug!("job {job}: handler ok");
+            None
+        }
+        Ok(s) => {
+            warn!("job {job}: handler exited: {s}");
+            Some(match (s.code(), s.signal()) {
+                (Some(code), _) => Failure::Exit(code),
+                (None, Some(sig)) => Failure::Signal(sig),
+                (None, None) => Failure::Error,
+            })
+        }
+        Err(e) => {
+            error!("job {job}: handler failed: {e}");
+            Some(Failure::Error)
+        }
+    };
+    Outcome { failure, requests }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_id_is_uuid_v4() {
+        let id = new_job_id();
+        let parts: Vec<_> = id.split('-').map(str::len).collect();
+        assert_eq!(parts, vec![8, 4, 4, 4, 12]);
+        assert_eq!(&id[14..15], "4");
+        assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"));
+    }
+
+    #[test]
+    fn job_ids_are_unique() {
+        assert_ne!(new_job_id(), new_job_id());
+    }
+
+    #[test]
+    fn parse_ionice() {
+        assert_eq!(IoPriority::parse("idle"), Ok(IoPriority::Idle));
+        assert_eq!(
+            IoPriority::parse("best-effort"),
+            Ok(IoPriority::BestEffort(4))
+        );
+        assert_eq!(
+            IoPriority::parse("best-effort:7"),
+            Ok(IoPriority::BestEffort(7))
+        );
+        assert_eq!(IoPriority::parse("realtime:0"), Ok(IoPriority::Realtime(0)));
+        assert!(IoPriority::parse("best-effort:8").is_err());
+        assert!(IoPriority::parse("idle:3").is_err());
+        assert!(IoPriority::parse("low").is_err());
+    }
+
+    #[test]
+    fn ioprio_values() {
+        assert_eq!(IoPriority::Idle.value(), 3 << 13);
+        assert_eq!(IoPriority::BestEffort(7).value(), (2 << 13) | 7);
+        assert_eq!(IoPriority::Realtime(0).value(), 1 << 13);
+    }
+
+    #[test]
+    fn run_with_a_cleared_environment() {
+        let opts = Options {
+            clear_env: true,
+            cwd: Some("/tmp".into()),
+            ..Options::default()
+        };
+        let script = r#"test "$PATH" = /usr/local/bin:/usr/bin:/bin && test -z "$HOME" \
+            && test "$(pwd)" = /tmp && test "$S1500D_EVENT" = scan"#;
+        let command = ["/bin/sh", "-c", script];
+        assert!(run(&command, &["scan"], 0, &Context::default(), &opts).ok());
+        let opts = Options {
+            path: Some("/bin".into()),
+            ..opts
+        };
+        let command = ["/bin/sh", "-c", r#"test "$PATH" = /bin"#];
+        assert!(run(&command, &["scan"], 0, &Context::default(), &opts).ok());
+    }
+
+    #[test]
+    fn run_applies_nice() {
+        let opts = Options {
+            nice: Some(5),
+            ionice: Some(IoPriority::Idle),
+            cgroup: None,
+            audit_log: None,
+            ssh: None,
+            serial: None,
+            json: false,
+            ..Options::default()
+        };
+        assert!(run(&["/bin/true"], &[], 0, &Context::default(), &opts).ok());
+        assert!(!run(
+            &["/bin/false"],
+            &[],
+            0,
+            &Context::default(),
+            &Options::default()
+        )
+        .ok());
+        assert!(!run(
+            &["/nonexistent/handler"],
+            &[],
+            0,
+            &Context::default(),
+            &Options::default()
+        )
+        .ok());
+        assert!(!run(&[], &["scan"], 0, &Context::default(), &Options::default()).ok());
+    }
+
+    #[test]
+    fn run_passes_fixed_args_before_event() {
+        let check = r#"[ "$0" = fixed ] && [ "$1" = scan ] && [ "$2" = legal ]"#;
+        let opts = Options::default();
+        assert!(run(
+            &["/bin/sh", "-c", check, "fixed"],
+            &["scan", "legal"],
+            0,
+            &Context::default(),
+            &opts
+        )
+        .ok());
+        assert!(!run(
+            &["/bin/sh", "-c", check, "other"],
+            &["scan", "legal"],
+            0,
+            &Context::default(),
+            &opts
+        )
+        .ok());
+    }
+
+    #[test]
+    fn run_kills_handler_past_timeout() {
+        let opts = Options {
+            timeout: Some(Duration::from_millis(200)),
+            ..Options::default()
+        };
+        // The shell waits on a child of its own, which is killed with it.
+        let argv = ["/bin/sh", "-c", "sleep 30 & wait"];
+        let started = Instant::now();
+        let outcome = run(&argv, &[], 0, &Context::default(), &opts);
+        assert_eq!(outcome.failure, Some(Failure::Timeout));
+        assert!(started.elapsed() < KILL_GRACE);
+
+        let outcome = run(
+            &["/bin/sh", "-c", "exit 3"],
+            &[],
+            0,
+            &Context::default(),
+            &opts,
+        );
+        assert_eq!(outcome.failure, Some(Failure::Exit(3)));
+        assert_eq!(Failure::Exit(3).to_string(), "exit-3");
+        assert_eq!(Failure::Signal(9).to_string(), "signal-9");
+        assert!(run(&["/bin/true"], &[], 0, &Context::default(), &opts).ok());
+    }
+
+    #[test]
+    fn run_exports_event_context() {
+        let context = Context {
+            press_count: Some(2),
+            paper: Some(true),
+            lid_open_secs: None,
+            timestamp: Some("2026-03-14T09:26:53Z".into()),
+            json: Some(r#"{"event":"scan"}"#.into()),
+        };
+        let opts = Options {
+            serial: Some("A1B2C3".into()),
+            identity: Some(Inquiry {
+                vendor: "FUJITSU".into(),
+                product: "ScanSnap S1500".into(),
+                revision: "0M00".into(),
+            }),
+            ..Options::default()
+        };
+        let check = r#"[ "$S1500D_EVENT" = scan ] && [ "$S1500D_PROFILE" = legal ] &&
+            [ "$S1500D_PRESS_COUNT" = 2 ] && [ "$S1500D_PAPER" = 1 ] &&
+            [ "$S1500D_SERIAL" = A1B2C3 ] &&
+            [ "$S1500D_DEVICE_MODEL" = "ScanSnap S1500" ] &&
+            [ "$S1500D_DEVICE_FIRMWARE" = 0M00 ] &&
+            [ "$S1500D_TIMESTAMP" = 2026-03-14T09:26:53Z ] &&
+            [ "$(cat)" = '{"event":"scan"}' ]"#;
+        let argv = ["/bin/sh", "-c", check];
+        assert!(run(&argv, &["scan", "legal"], 1, &context, &opts).ok());
+        // Only scan events name a profile.
+        let check = r#"[ "$S1500D_EVENT" = batch-start ] && [ -z "${S1500D_PROFILE+set}" ]"#;
+        let argv = ["/bin/sh", "-c", check];
+        assert!(run(&argv, &["batch-start", "id"], 2, &Context::default(), &opts).ok());
+        // The session length comes in the environment, not as `$2`.
+        let context = Context {
+            lid_open_secs: Some(427),
+            ..Context::default()
+        };
+        let check =
+            r#"[ $# = 1 ] && [ "$1" = device-left ] && [ "$S1500D_LID_OPEN_SECONDS" = 427 ]"#;
+        let argv = ["/bin/sh", "-c", check, "sh"];
+        assert!(run(&argv, &["device-left"], 3, &context, &opts).ok());
+    }
+
+    #[test]
+    fn remote_command_quotes_for_the_remote_shell() {
+        let env = [
+            ("S1500D_JOB_ID", "abc".to_string()),
+            ("S1500D_SEQ", "7".to_string()),
+        ];
+        assert_eq!(
+            remote_command(&env, &["/opt/scan/handle", "scan", "tax docs"]),
+            "env S1500D_JOB_ID=abc S1500D_SEQ=7 /opt/scan/handle scan 'tax docs'"
+        );
+    }
+
+    #[test]
+    fn run_as_the_current_user_switches_nothing() {
+        // SAFETY: as in `RunAs::is_current`.
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        let user = RunAs {
+            name: "me".into(),
+            uid,
+            gid,
+            // Not the process's groups: setgroups would change them (as
+            // root) or fail (otherwise) if it ran.
+            groups: vec![54321],
+            home: None,
+        };
+        assert!(user.is_current());
+        assert!(user.apply().is_ok());
+        // SAFETY: getgroups with a zero size only counts the groups.
+        let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        let mut groups = vec![0; count as usize];
+        // SAFETY: `groups` has room for `count` gids.
+        unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+        assert!(!groups.contains(&54321));
+        assert!(!RunAs {
+            uid: uid + 1,
+            ..user
+        }
+        .is_current());
+    }
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(
+            Request::parse("snooze paper-out 30"),
+            Ok(Request::Snooze("paper-out".into(), Duration::from_secs(30)))
+        );
+        assert!(Request::parse("snooze paper-out").is_err());
+        assert!(Request::parse("snooze paper-out soon").is_err());
+        assert!(Request::parse("snooze paper-out 86400").is_ok());
+        assert!(Request::parse("snooze paper-out 86401")
+            .unwrap_err()
+            .contains("limit"));
+        assert!(Request::parse("snooze paper-out 18446744073709551615").is_err());
+        assert!(Request::parse("snooze lunch 30")
+            .unwrap_err()
+            .contains("lunch"));
+        assert!(Request::parse("reboot").is_err());
+    }
+
+    #[test]
+    fn run_returns_control_requests() {
+        let script =
+            r#"printf 'snooze paper-out 30\nbogus\nsnooze scan 5\n' >&"$S1500D_CONTROL_FD""#;
+        let outcome = run(
+            &["/bin/sh", "-c", script],
+            &[],
+            0,
+            &Context::default(),
+            &Options::default(),
+        );
+        assert!(outcome.ok());
+        assert_eq!(
+            outcome.requests,
+            vec![
+                Request::Snooze("paper-out".into(), Duration::from_secs(30)),
+                Request::Snooze("scan".into(), Duration::from_secs(5)),
+            ]
+        );
+        // A background child keeping the pipe open doesn't block the daemon.
+        let script = r#"sleep 5 & echo "snooze paper-in 1" >&"$S1500D_CONTROL_FD""#;
+        let started = Instant::now();
+        let outcome = run(
+            &["/bin/sh", "-c", script],
+            &[],
+            0,
+            &Context::default(),
+            &Options::default(),
+        );
+        assert_eq!(outcome.requests.len(), 1);
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn run_appends_audit_record() {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-audit", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let opts = Options {
+            audit_log: Some(path.clone()),
+            serial: Some("A1B2C3".into()),
+            ..Options::default()
+        };
+        assert!(run(
+            &["/bin/true"],
+            &["scan", "standard"],
+            3,
+            &Context::default(),
+            &opts
+        )
+        .ok());
+        assert!(!run(
+            &["/bin/false"],
+            &["paper-in"],
+            4,
+            &Context::default(),
+            &opts
+        )
+        .ok());
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("event=scan argv=\"/bin/true scan standard\""));
+        assert!(lines[0].contains("S1500D_SEQ=3"));
+        assert!(lines[0].contains("S1500D_SERIAL=A1B2C3"));
+        assert!(lines[0].contains("status=exit:0"));
+        assert!(lines[1].contains("status=exit:1"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::error::UsbError;
+
+/// A USB error counted against the device's health.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// A status poll timed out.
+    Timeout,
+    /// A transfer stalled.
+    Pipe,
+    /// Any other failed or garbled status poll.
+    Other,
+    /// The device was present but could not be reclaimed after a handler.
+    Reclaim,
+}
+
+impl Fault {
+    const ALL: [Self; 4] = [Self::Timeout, Self::Pipe, Self::Other, Self::Reclaim];
+
+    pub fn from_usb(e: UsbError) -> Self {
+        match e {
+            UsbError::Io(rusb::Error::Timeout) => Self::Timeout,
+            UsbError::Stalled => Self::Pipe,
+            _ => Self::Other,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Timeout => "timeout",
+            Self::Pipe => "pipe",
+            Self::Other => "other",
+            Self::Reclaim => "reclaim",
+        }
+    }
+}
+
+/// A change in health worth announcing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// The error count in the window reached the threshold.
+    Degraded,
+    /// A whole window passed without errors.
+    Recovered,
+}
+
+/// Rolling count of USB errors over a time window.
+///
+/// Errors are expected now and then (a poll racing the lid closing, say);
+/// `threshold` of them within `window` marks the connection degraded, and a
+/// full window without any brings it back.
+#[derive(Debug)]
+pub struct Health {
+    threshold: usize,
+    window: Duration,
+    /// Recent faults, oldest first; never more than `threshold` are kept.
+    recent: VecDeque<(Instant, Fault)>,
+    degraded: bool,
+    /// Faults of each kind since startup, indexed like [`Fault::ALL`].
+    totals: [u64; 4],
+}
+
+impl Health {
+    /// A `threshold` of 0 never reports the connection degraded.
+    pub fn new(threshold: usize, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            recent: VecDeque::with_capacity(threshold),
+            degraded: false,
+            totals: [0; 4],
+        }
+    }
+
+    /// Count a fault, returning [`Change::Degraded`] if this one crossed
+    /// the threshold.
+    pub fn record(&mut self, fault: Fault, now: Instant) -> Option<Change> {
+        if let Some(i) = Fault::ALL.iter().position(|&f| f == fault) {
+            self.totals[i] += 1;
+        }
+        if self.threshold == 0 {
+            return None;
+        }
+        self.prune(now);
+        if self.recent.len() == self.threshold {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((now, fault));
+        if !self.degraded && self.recent.len() >= self.threshold {
+            self.degraded = true;
+            return Some(Change::Degraded);
+        }
+        None
+    }
+
+    /// Expire old faults, returning [`Change::Recovered`] once a degraded
+    /// connection has gone a whole window without one.
+    pub fn check(&mut self, now: Instant) -> Option<Change> {
+        self.prune(now);
+        if self.degraded && self.recent.is_empty() {
+            self.degraded = false;
+            return Some(Change::Recovered);
+        }
+        None
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|&(t, _)| now.duration_since(t) >= self.window)
+        {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Faults within the window (capped at the threshold).
+    pub fn recent(&self) -> usize {
+        self.recent.len()
+    }
+
+    /// Faults of each kind since startup.
+    pub fn totals(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        Fault::ALL.iter().map(|f| f.name()).zip(self.totals)
+    }
+
+    /// The faults within the window by kind, e.g. `3 timeout, 1 pipe`.
+    pub fn summary(&self) -> String {
+        let counts: Vec<String> = Fault::ALL
+            .iter()
+            .filter_map(|&kind| {
+                let n = self.recent.iter().filter(|&&(_, f)| f == kind).count();
+                (n > 0).then(|| format!("{n} {}", kind.name()))
+            })
+            .collect();
+        counts.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn degrades_at_threshold_and_recovers_after_a_quiet_window() {
+        let t0 = Instant::now();
+        let mut h = Health::new(3, secs(60));
+        assert_eq!(h.record(Fault::Timeout, t0), None);
+        assert_eq!(h.record(Fault::Pipe, t0 + secs(10)), None);
+        assert_eq!(
+            h.record(Fault::Timeout, t0 + secs(20)),
+            Some(Change::Degraded)
+        );
+        assert!(h.is_degraded());
+        assert_eq!(h.summary(), "2 timeout, 1 pipe");
+        // Further faults while degraded aren't announced again.
+        assert_eq!(h.record(Fault::Reclaim, t0 + secs(30)), None);
+        assert_eq!(h.recent(), 3);
+
+        // The last fault at 30s keeps it degraded until 90s.
+        assert_eq!(h.check(t0 + secs(89)), None);
+        assert_eq!(h.check(t0 + secs(90)), Some(Change::Recovered));
+        assert!(!h.is_degraded());
+        assert_eq!(h.check(t0 + secs(91)), None);
+
+        let totals: Vec<_> = h.totals().collect();
+        assert_eq!(
+            totals,
+            vec![("timeout", 2), ("pipe", 1), ("other", 0), ("reclaim", 1)]
+        );
+    }
+
+    #[test]
+    fn sparse_faults_stay_healthy() {
+        let t0 = Instant::now();
+        let mut h = Health::new(3, secs(60));
+        for i in 0..10 {
+            assert_eq!(h.record(Fault::Other, t0 + secs(i * 40)), None);
+        }
+        assert!(!h.is_degraded());
+    }
+
+    #[test]
+    fn zero_threshold_is_off() {
+        let t0 = Instant::now();
+        let mut h = Health::new(0, secs(60));
+        for _ in 0..100 {
+            assert_eq!(h.record(Fault::Timeout, t0), None);
+        }
+        assert_eq!(h.totals().next(), Some(("timeout", 100)));
+    }
+
+    #[test]
+    fn usb_errors_map_to_faults() {
+        let usb = |e: rusb::Error| Fault::from_usb(e.into());
+        assert_eq!(usb(rusb::Error::Timeout), Fault::Timeout);
+        assert_eq!(usb(rusb::Error::Pipe), Fault::Pipe);
+        assert_eq!(usb(rusb::Error::Io), Fault::Other);
+        assert_eq!(Fault::from_usb(UsbError::BadResponse), Fault::Other);
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::OnceLock;
+
+use crate::json;
+
+/// This machine's hostname, or `localhost` if it can't be read.
+pub fn hostname() -> &'static str {
+    static HOSTNAME: OnceLock<String> = OnceLock::new();
+    HOSTNAME.get_or_init(|| {
+        let mut buf = [0u8; 256];
+        // SAFETY: gethostname writes at most buf.len() bytes into buf.
+        if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+            return "localhost".into();
+        }
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        match String::from_utf8_lossy(&buf[..len]) {
+            name if name.is_empty() => "localhost".into(),
+            name => name.into_owned(),
+        }
+    })
+}
+
+/// Names this daemon the same way across restarts, so events from several
+/// scan stations can be told apart: the hostname, plus the scanner's serial
+/// when `[usb]` pins one (one daemon per scanner). Only letters, digits, `_`
+/// and `-`, so it can go in MQTT topics and Home Assistant IDs as is.
+pub fn instance_id() -> String {
+    let id = match &crate::usb_config().serial {
+        Some(serial) => format!("{}-{serial}", hostname()),
+        None => hostname().to_string(),
+    };
+    sanitize(&id)
+}
+
+fn sanitize(id: &str) -> String {
+    id.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Add the `host`, `version` and `instance` fields every structured event
+/// carries.
+pub fn tag(object: json::Object) -> json::Object {
+    object
+        .str("host", hostname())
+        .str("version", env!("CARGO_PKG_VERSION"))
+        .str("instance", &instance_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_ids_are_topic_safe() {
+        assert!(!hostname().is_empty());
+        assert_eq!(sanitize("scan-1.lan"), "scan-1_lan");
+        assert_eq!(sanitize("host-A1B2/C3"), "host-A1B2_C3");
+        let id = instance_id();
+        assert!(id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-".contains(c)));
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use rusb::{Context, Device, Hotplug, HotplugBuilder, Registration, UsbContext};
+
+/// How long after arrival to wait before opening the device, so udev has
+/// applied the device node's permissions (otherwise the first open can fail
+/// with a spurious permission error).
+const SETTLE: Duration = Duration::from_millis(100);
+
+/// Wakes the event loop when the scanner is plugged in or unplugged (the
+/// ADF lid opened or closed), instead of waiting out a fixed interval.
+pub struct Watcher {
+    flags: Arc<Flags>,
+    _registration: Registration<Context>,
+}
+
+/// The libusb callback. It runs inside libusb's event handling, where
+/// synchronous transfers aren't allowed, so it only raises a flag.
+struct Callback(Arc<Flags>);
+
+#[derive(Default)]
+struct Flags {
+    arrived: AtomicBool,
+    left: AtomicBool,
+}
+
+impl Hotplug<Context> for Callback {
+    fn device_arrived(&mut self, _device: Device<Context>) {
+        self.0.arrived.store(true, Ordering::Relaxed);
+    }
+
+    fn device_left(&mut self, _device: Device<Context>) {
+        self.0.left.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Watcher {
+    /// Register for the scanner's hotplug events, or `None` where libusb
+    /// doesn't support hotplug (the caller falls back to polling).
+    pub fn new(ctx: &Context) -> Option<Self> {
+        if !rusb::has_hotplug() {
+            return None;
+        }
+        let flags = Arc::new(Flags::default());
+        let registration = HotplugBuilder::new()
+            .vendor_id(crate::model().vid)
+            .product_id(crate::model().pid)
+            .register(ctx, Box::new(Callback(Arc::clone(&flags))))
+            .map_err(|e| debug!("usb: cannot register for hotplug events: {e}"))
+            .ok()?;
+        Some(Self {
+            flags,
+            _registration: registration,
+        })
+    }
+
+    /// Sleep for up to `timeout`, returning early if the scanner arrived or
+    /// left in the meantime.
+    pub fn wait(&self, ctx: &Context, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.flags.arrived.swap(false, Ordering::Relaxed) {
+                thread::sleep(SETTLE);
+                return;
+            }
+            if self.flags.left.swap(false, Ordering::Relaxed) {
+                return;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return;
+            }
+            if ctx.handle_events(Some(remaining)).is_err() {
+                thread::sleep(remaining);
+                return;
+            }
+        }
+    }
+}
+
+/// Sleep for `timeout`, or until the scanner arrives or leaves if `watcher`
+/// is available.
+pub fn wait(watcher: Option<&Watcher>, ctx: &Context, timeout: Duration) {
+    match watcher {
+        Some(w) => w.wait(ctx, timeout),
+        None => thread::sleep(timeout),
+    }
+}
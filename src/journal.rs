@@ -0,0 +1,252 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A handler invocation that has been journaled but not yet completed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub id: u64,
+    /// Sequence number of the event the run was for, in the daemon run
+    /// that journaled it; 0 in journals written before it was recorded.
+    pub seq: u64,
+    /// Handler program and its fixed arguments.
+    pub command: Vec<String>,
+    /// Event arguments.
+    pub args: Vec<String>,
+}
+
+/// On-disk journal of handler invocations for at-least-once delivery.
+///
+/// The file holds only *pending* entries, one per line. An entry is appended
+/// (and synced) before its handler runs; once the handler succeeds the file
+/// is atomically rewritten without it. Anything left over after a crash or
+/// power loss is returned by [`Journal::open`] for replay.
+///
+/// Line format: `id@seq \t program \t arg1 \t arg2 …` with `\`, tab and
+/// newline escaped. When the handler has fixed arguments the first field is
+/// `id/n@seq`, where `n` is the length of the command (program plus fixed
+/// arguments).
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    pending: Vec<Entry>,
+    next_id: u64,
+}
+
+impl Journal {
+    /// Open (or create) the journal, returning it along with any entries
+    /// left unfinished by a previous run.
+    pub fn open(path: &Path) -> io::Result<(Self, Vec<Entry>)> {
+        let pending = match fs::read_to_string(path) {
+            Ok(text) => text.lines().filter_map(parse_line).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let next_id = pending.iter().map(|e: &Entry| e.id).max().unwrap_or(0) + 1;
+        let journal = Self {
+            path: path.to_path_buf(),
+            pending: pending.clone(),
+            next_id,
+        };
+        journal.rewrite()?;
+        Ok((journal, pending))
+    }
+
+    /// Record a handler invocation for event `seq` before running it.
+    /// Returns its entry ID.
+    pub fn begin(&mut self, command: &[&str], args: &[&str], seq: u64) -> io::Result<u64> {
+        let entry = Entry {
+            id: self.next_id,
+            seq,
+            command: command.iter().map(|a| a.to_string()).collect(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(format_line(&entry).as_bytes())?;
+        file.sync_all()?;
+        self.next_id += 1;
+        self.pending.push(entry);
+        Ok(self.next_id - 1)
+    }
+
+    /// Mark an entry as delivered, removing it from the journal.
+    pub fn complete(&mut self, id: u64) -> io::Result<()> {
+        self.pending.retain(|e| e.id != id);
+        self.rewrite()
+    }
+
+    /// Atomically replace the file with the current pending set.
+    fn rewrite(&self) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        for entry in &self.pending {
+            file.write_all(format_line(entry).as_bytes())?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn format_line(entry: &Entry) -> String {
+    let mut line = entry.id.to_string();
+    if entry.command.len() != 1 {
+        line.push_str(&format!("/{}", entry.command.len()));
+    }
+    line.push_str(&format!("@{}", entry.seq));
+    for arg in entry.command.iter().chain(&entry.args) {
+        line.push('\t');
+        line.push_str(&escape(arg));
+    }
+    line.push('\n');
+    line
+}
+
+/// Parse one journal line; torn or corrupt lines (e.g. from a crash
+/// mid-write) are skipped.
+fn parse_line(line: &str) -> Option<Entry> {
+    let mut fields = line.split('\t');
+    let first = fields.next()?;
+    // Journals written before sequence numbers were recorded have none.
+    let (first, seq) = match first.split_once('@') {
+        Some((first, seq)) => (first, seq.parse().ok()?),
+        None => (first, 0),
+    };
+    let (id, len) = match first.split_once('/') {
+        Some((id, len)) => (id.parse().ok()?, len.parse().ok()?),
+        None => (first.parse().ok()?, 1),
+    };
+    let mut args: Vec<String> = fields.map(unescape).collect();
+    if len == 0 || args.len() < len {
+        return None;
+    }
+    let command = args.drain(..len).collect();
+    Some(Entry {
+        id,
+        seq,
+        command,
+        args,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-{name}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn line_roundtrip_with_escapes() {
+        let entry = Entry {
+            id: 7,
+            seq: 12,
+            command: vec!["/bin/h.sh".into()],
+            args: vec!["scan".into(), "a\tb\\c\nd".into()],
+        };
+        let line = format_line(&entry);
+        assert_eq!(line.matches('\n').count(), 1);
+        assert!(line.starts_with("7@12\t"));
+        assert_eq!(parse_line(line.trim_end_matches('\n')), Some(entry));
+    }
+
+    #[test]
+    fn line_roundtrip_with_fixed_args() {
+        let entry = Entry {
+            id: 3,
+            seq: 5,
+            command: vec!["/usr/bin/python3".into(), "/opt/h.py".into()],
+            args: vec!["scan".into(), "legal".into()],
+        };
+        let line = format_line(&entry);
+        assert_eq!(line, "3/2@5\t/usr/bin/python3\t/opt/h.py\tscan\tlegal\n");
+        assert_eq!(parse_line(line.trim_end_matches('\n')), Some(entry));
+        assert!(parse_line("3/4@5\t/usr/bin/python3\t/opt/h.py").is_none());
+    }
+
+    #[test]
+    fn lines_without_a_sequence_number_still_parse() {
+        let entry = parse_line("3/2\t/usr/bin/python3\t/opt/h.py\tscan").unwrap();
+        assert_eq!((entry.id, entry.seq), (3, 0));
+        assert_eq!(entry.args, vec!["scan"]);
+        assert!(parse_line("3@x\t/bin/h.sh").is_none());
+    }
+
+    #[test]
+    fn corrupt_line_skipped() {
+        assert!(parse_line("").is_none());
+        assert!(parse_line("notanumber\t/bin/h.sh").is_none());
+        assert!(parse_line("3").is_none());
+    }
+
+    #[test]
+    fn completed_entries_are_removed() {
+        let path = temp_path("journal-complete");
+        let (mut journal, replay) = Journal::open(&path).unwrap();
+        assert!(replay.is_empty());
+
+        let a = journal
+            .begin(&["/bin/h.sh"], &["scan", "standard"], 1)
+            .unwrap();
+        let b = journal.begin(&["/bin/h.sh"], &["paper-in"], 2).unwrap();
+        journal.complete(a).unwrap();
+
+        let (_, replay) = Journal::open(&path).unwrap();
+        assert_eq!(replay.len(), 1);
+        assert_eq!((replay[0].id, replay[0].seq), (b, 2));
+        assert_eq!(replay[0].args, vec!["paper-in"]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn unfinished_entries_survive_reopen() {
+        let path = temp_path("journal-reopen");
+        {
+            let (mut journal, _) = Journal::open(&path).unwrap();
+            journal
+                .begin(&["/bin/h.sh"], &["scan", "legal"], 1)
+                .unwrap();
+            // Dropped without complete() — simulates a crash mid-handler.
+        }
+        let (mut journal, replay) = Journal::open(&path).unwrap();
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay[0].args, vec!["scan", "legal"]);
+
+        // New IDs don't collide with replayed ones.
+        let id = journal.begin(&["/bin/h.sh"], &["paper-out"], 2).unwrap();
+        assert!(id > replay[0].id);
+        journal.complete(replay[0].id).unwrap();
+        journal.complete(id).unwrap();
+        assert!(Journal::open(&path).unwrap().1.is_empty());
+        let _ = fs::remove_file(&path);
+    }
+}
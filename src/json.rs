@@ -0,0 +1,113 @@
+use std::fmt::Display;
+
+/// Quote and escape `s` as a JSON string.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A JSON array of already-encoded values.
+pub fn array<I: IntoIterator<Item = String>>(items: I) -> String {
+    let items: Vec<String> = items.into_iter().collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Builds a single-line JSON object, field by field, in insertion order.
+#[derive(Debug)]
+pub struct Object {
+    buf: String,
+}
+
+impl Default for Object {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Object {
+    pub fn new() -> Self {
+        Self {
+            buf: String::from("{"),
+        }
+    }
+
+    /// Add a field whose value is already encoded JSON.
+    pub fn raw(mut self, key: &str, value: &str) -> Self {
+        if self.buf.len() > 1 {
+            self.buf.push(',');
+        }
+        self.buf.push_str(&string(key));
+        self.buf.push(':');
+        self.buf.push_str(value);
+        self
+    }
+
+    pub fn str(self, key: &str, value: &str) -> Self {
+        self.raw(key, &string(value))
+    }
+
+    /// A string field, or `null`.
+    pub fn opt_str(self, key: &str, value: Option<&str>) -> Self {
+        match value {
+            Some(v) => self.str(key, v),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    /// A number field (any integer type).
+    pub fn num(self, key: &str, value: impl Display) -> Self {
+        self.raw(key, &value.to_string())
+    }
+
+    pub fn bool(self, key: &str, value: bool) -> Self {
+        self.raw(key, if value { "true" } else { "false" })
+    }
+
+    pub fn finish(mut self) -> String {
+        self.buf.push('}');
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(string("plain"), r#""plain""#);
+        assert_eq!(string("a \"b\"\\c\nd"), r#""a \"b\"\\c\nd""#);
+        assert_eq!(string("\u{1}"), r#""\u0001""#);
+        assert_eq!(string("café"), "\"café\"");
+    }
+
+    #[test]
+    fn object_fields_in_order() {
+        let inner = Object::new().num("n", 3u32).finish();
+        let json = Object::new()
+            .str("event", "scan")
+            .opt_str("profile", None)
+            .bool("ok", true)
+            .raw("inner", &inner)
+            .raw("args", &array(["a", "b"].map(string)))
+            .finish();
+        assert_eq!(
+            json,
+            r#"{"event":"scan","profile":null,"ok":true,"inner":{"n":3},"args":["a","b"]}"#
+        );
+        assert_eq!(Object::new().finish(), "{}");
+    }
+}
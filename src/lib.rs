@@ -0,0 +1,30 @@
+//! Talk to a Fujitsu ScanSnap S1500 without reimplementing its USB protocol.
+//!
+//! The `s1500d` daemon is built on this crate: [`protocol`] has the 31-byte
+//! command envelope, GET_HW_STATUS decoding and the events derived from
+//! status changes, and [`device`] finds, claims and polls the scanner.
+//! [`Monitor`] does the polling for you and yields the events as an
+//! iterator.
+//!
+//! ```no_run
+//! use s1500d::{transitions, Scanner};
+//!
+//! let ctx = rusb::Context::new()?;
+//! let scanner = Scanner::open(&ctx)?;
+//! let mut prev = scanner.status()?;
+//! loop {
+//!     let state = scanner.status()?;
+//!     for event in transitions(prev, state) {
+//!         println!("{}", event.tag());
+//!     }
+//!     prev = state;
+//!     std::thread::sleep(std::time::Duration::from_millis(100));
+//! }
+//! # Ok::<(), rusb::Error>(())
+//! ```
+
+pub mod device;
+pub mod protocol;
+
+pub use device::{Monitor, Scanner};
+pub use protocol::{changes, transitions, Event, Inquiry, Model, State};
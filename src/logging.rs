@@ -0,0 +1,458 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use jiff::tz::TimeZone;
+use jiff::Timestamp;
+use log::kv::Key;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+
+use crate::json;
+
+/// While a message keeps repeating, how often to report how many copies
+/// were suppressed.
+const REPEAT_SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A copy arriving longer than this after the previous one starts a new
+/// run instead of extending the old one.
+const REPEAT_WINDOW: Duration = Duration::from_secs(5);
+
+/// How timestamps are written in the log and the audit log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampFormat {
+    /// ISO 8601 / RFC 3339 to the second, e.g. `2026-03-14T09:26:53Z`.
+    #[default]
+    Iso8601,
+    /// Unix time with milliseconds, e.g. `1773480413.589`.
+    Epoch,
+    /// No timestamp (e.g. when journald already adds one).
+    None,
+}
+
+/// How log lines are written (`log_format`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `[<ts> LEVEL target] message`.
+    #[default]
+    Text,
+    /// One JSON object per line, for log shippers: `ts`, `level`,
+    /// `target`, `msg`, plus an event's own fields.
+    Json,
+}
+
+/// Timestamp format plus the time zone ISO 8601 timestamps are shown in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimestampStyle {
+    pub format: TimestampFormat,
+    /// `None` means UTC, written with a `Z` suffix.
+    pub tz: Option<TimeZone>,
+}
+
+impl TimestampStyle {
+    /// Resolve a `log_timezone` setting: `UTC`, `local`, or an IANA name
+    /// such as `Europe/Berlin`.
+    pub fn parse_timezone(name: &str) -> Result<Option<TimeZone>, String> {
+        match name {
+            "UTC" | "utc" => Ok(None),
+            "local" => Ok(Some(TimeZone::system())),
+            _ => TimeZone::get(name)
+                .map(Some)
+                .map_err(|e| format!("invalid log_timezone {name:?}: {e}")),
+        }
+    }
+
+    pub fn format(&self, ts: Timestamp) -> Option<String> {
+        match self.format {
+            TimestampFormat::Iso8601 => Some(match &self.tz {
+                None => ts.strftime("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                Some(tz) => ts
+                    .to_zoned(tz.clone())
+                    .strftime("%Y-%m-%dT%H:%M:%S%:z")
+                    .to_string(),
+            }),
+            TimestampFormat::Epoch => {
+                Some(format!("{}.{:03}", ts.as_second(), ts.subsec_millisecond()))
+            }
+            TimestampFormat::None => None,
+        }
+    }
+}
+
+static STYLE: OnceLock<TimestampStyle> = OnceLock::new();
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// The key an event's log line carries its fields under, as the members of
+/// a JSON object (see [`json_line`]).
+pub const EVENT_KEY: &str = "event";
+
+/// Whether debug output has been switched on at runtime (SIGUSR2 or
+/// `s1500ctl debug on`), overriding the configured filter.
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// The configured maximum level, restored when debug is switched off.
+static BASE_LEVEL: OnceLock<LevelFilter> = OnceLock::new();
+
+/// The current time in the configured style, for structured outputs.
+pub fn timestamp() -> Option<String> {
+    STYLE.get_or_init(Default::default).format(Timestamp::now())
+}
+
+/// Whether log lines are JSON, so callers know to attach [`EVENT_KEY`].
+pub fn json() -> bool {
+    FORMAT.get() == Some(&LogFormat::Json)
+}
+
+/// A log record as one line of JSON. An [`EVENT_KEY`] value is spliced in,
+/// so an event's `seq`, `event`, `args` and, for `scan`, `profile` and
+/// `press_count` sit beside `msg` rather than inside it.
+pub fn json_line(ts: Option<&str>, record: &Record) -> String {
+    let mut object = json::Object::new();
+    if let Some(ts) = ts {
+        object = object.str("ts", ts);
+    }
+    let line = object
+        .str("level", &record.level().as_str().to_ascii_lowercase())
+        .str("target", record.target())
+        .str("msg", &record.args().to_string())
+        .finish();
+    let fields = record.key_values().get(Key::from_str(EVENT_KEY));
+    match fields.as_ref().and_then(|v| v.to_borrowed_str()) {
+        Some(fields) if fields.len() > 2 => {
+            format!("{},{}", &line[..line.len() - 1], &fields[1..])
+        }
+        _ => line,
+    }
+}
+
+/// Tracks the last logged message so identical repeats can be collapsed.
+#[derive(Debug, Default)]
+struct Repeats {
+    last: Option<(Level, String, String)>,
+    /// Copies of `last` suppressed since it was last written or summarised.
+    suppressed: u64,
+    since: Option<Instant>,
+    /// When `last` was most recently seen, written or not.
+    seen: Option<Instant>,
+}
+
+impl Repeats {
+    /// Decide what to do with a message. Returns the number of suppressed
+    /// repeats to report first (if any) and whether to write the message.
+    fn observe(&mut self, level: Level, target: &str, text: &str, now: Instant) -> (u64, bool) {
+        let same = self
+            .last
+            .as_ref()
+            .is_some_and(|(l, t, m)| *l == level && t == target && m == text)
+            && self
+                .seen
+                .is_some_and(|t| now.duration_since(t) < REPEAT_WINDOW);
+        self.seen = Some(now);
+        if same {
+            self.suppressed += 1;
+            let due = self
+                .since
+                .map_or(true, |t| now.duration_since(t) >= REPEAT_SUMMARY_INTERVAL);
+            if due {
+                self.since = Some(now);
+                return (std::mem::take(&mut self.suppressed), false);
+            }
+            return (0, false);
+        }
+        let pending = std::mem::take(&mut self.suppressed);
+        self.last = Some((level, target.to_string(), text.to_string()));
+        self.since = Some(now);
+        (pending, true)
+    }
+}
+
+/// Wraps env_logger, collapsing runs of identical messages at any level —
+/// e.g. the same USB error every poll while the device misbehaves — into
+/// periodic "last message repeated N times" lines.
+struct Logger {
+    inner: env_logger::Logger,
+    /// Same output, filtered at `debug`; used while [`VERBOSE`] is set.
+    verbose: env_logger::Logger,
+    repeats: Mutex<Repeats>,
+}
+
+impl Logger {
+    fn active(&self) -> &env_logger::Logger {
+        if VERBOSE.load(Ordering::Relaxed) {
+            &self.verbose
+        } else {
+            &self.inner
+        }
+    }
+
+    fn summary(&self, level: Level, target: &str, n: u64) {
+        let plural = if n == 1 { "" } else { "s" };
+        self.active().log(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("last message repeated {n} time{plural}"))
+                .build(),
+        );
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.active().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let inner = self.active();
+        if !inner.matches(record) {
+            return;
+        }
+        let text = record.args().to_string();
+        let mut repeats = self.repeats.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = repeats.last.as_ref().map(|(l, t, _)| (*l, t.clone()));
+        let (suppressed, write) =
+            repeats.observe(record.level(), record.target(), &text, Instant::now());
+        if suppressed > 0 {
+            if let Some((level, target)) = previous {
+                self.summary(level, &target, suppressed);
+            }
+        }
+        if write {
+            inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Whether debug output is currently switched on at runtime.
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Switch debug output on or off without restarting. Off restores the
+/// configured filter. Has no visible effect if that already includes debug.
+pub fn set_verbose(on: bool) {
+    let base = BASE_LEVEL.get().copied().unwrap_or(LevelFilter::Info);
+    VERBOSE.store(on, Ordering::Relaxed);
+    log::set_max_level(if on {
+        base.max(LevelFilter::Debug)
+    } else {
+        base
+    });
+}
+
+fn builder(filter: &str) -> env_logger::Builder {
+    let mut builder = env_logger::Builder::new();
+    builder.parse_filters(filter).format(|buf, record| {
+        if json() {
+            return writeln!(buf, "{}", json_line(timestamp().as_deref(), record));
+        }
+        let level = buf.default_level_style(record.level());
+        let prefix = timestamp().map_or(String::new(), |ts| format!("{ts} "));
+        writeln!(
+            buf,
+            "[{prefix}{level}{:<5}{level:#} {}] {}",
+            record.level(),
+            record.target(),
+            record.args()
+        )
+    });
+    builder
+}
+
+/// Install the logger with `filter` (`RUST_LOG` syntax) and the given
+/// timestamp style and line format.
+pub fn init(filter: &str, style: TimestampStyle, format: LogFormat) {
+    let _ = STYLE.set(style);
+    let _ = FORMAT.set(format);
+    let inner = builder(filter).build();
+    let verbose = if inner.filter() >= LevelFilter::Debug {
+        builder(filter).build()
+    } else {
+        builder(filter).filter_level(LevelFilter::Debug).build()
+    };
+    let _ = BASE_LEVEL.set(inner.filter());
+    log::set_max_level(inner.filter());
+    let logger = Logger {
+        inner,
+        verbose,
+        repeats: Mutex::new(Repeats::default()),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_err() {
+        eprintln!("s1500d: logger already initialised");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2026-03-14T09:26:53.589Z
+    fn ts() -> Timestamp {
+        Timestamp::from_millisecond(1_773_480_413_589).unwrap()
+    }
+
+    #[test]
+    fn iso8601_utc_and_zoned() {
+        let style = TimestampStyle::default();
+        assert_eq!(style.format(ts()).unwrap(), "2026-03-14T09:26:53Z");
+
+        let style = TimestampStyle {
+            tz: TimestampStyle::parse_timezone("America/New_York").unwrap(),
+            ..style
+        };
+        assert_eq!(style.format(ts()).unwrap(), "2026-03-14T05:26:53-04:00");
+
+        let style = TimestampStyle {
+            tz: TimestampStyle::parse_timezone("Europe/Berlin").unwrap(),
+            ..style
+        };
+        assert_eq!(style.format(ts()).unwrap(), "2026-03-14T10:26:53+01:00");
+    }
+
+    #[test]
+    fn epoch_and_none() {
+        let style = TimestampStyle {
+            format: TimestampFormat::Epoch,
+            tz: None,
+        };
+        assert_eq!(style.format(ts()).unwrap(), "1773480413.589");
+        let style = TimestampStyle {
+            format: TimestampFormat::None,
+            tz: None,
+        };
+        assert!(style.format(ts()).is_none());
+    }
+
+    #[test]
+    fn repeats_are_collapsed() {
+        let mut r = Repeats::default();
+        let t0 = Instant::now();
+        let err = |r: &mut Repeats, dt: u64| {
+            r.observe(
+                Level::Warn,
+                "s1500d",
+                "usb: device unresponsive after reset",
+                t0 + Duration::from_secs(dt),
+            )
+        };
+        assert_eq!(err(&mut r, 0), (0, true));
+        for dt in 1..60 {
+            assert_eq!(err(&mut r, dt), (0, false));
+        }
+        // A minute after the first: report the copies so far.
+        assert_eq!(err(&mut r, 60), (60, false));
+        assert_eq!(err(&mut r, 61), (0, false));
+        // A different message flushes the remaining count and is written.
+        let other = r.observe(
+            Level::Info,
+            "s1500d",
+            "device-left",
+            t0 + Duration::from_secs(62),
+        );
+        assert_eq!(other, (1, true));
+        assert_eq!(err(&mut r, 63), (0, true));
+    }
+
+    #[test]
+    fn debug_repeats_are_collapsed() {
+        let mut r = Repeats::default();
+        let t0 = Instant::now();
+        let busy = |r: &mut Repeats, ms: u64| {
+            r.observe(
+                Level::Debug,
+                "s1500d",
+                "poll: scanner busy, retrying",
+                t0 + Duration::from_millis(ms),
+            )
+        };
+        assert_eq!(busy(&mut r, 0), (0, true));
+        assert_eq!(busy(&mut r, 50), (0, false));
+        assert_eq!(busy(&mut r, 100), (0, false));
+    }
+
+    #[test]
+    fn repeats_far_apart_start_a_new_run() {
+        let mut r = Repeats::default();
+        let t0 = Instant::now();
+        let err = |r: &mut Repeats, dt: u64| {
+            r.observe(
+                Level::Warn,
+                "s1500d",
+                "usb: no device",
+                t0 + Duration::from_secs(dt),
+            )
+        };
+        assert_eq!(err(&mut r, 0), (0, true));
+        assert_eq!(err(&mut r, 1), (0, false));
+        // An hour later the same line is news again: flush the old count
+        // and write it.
+        assert_eq!(err(&mut r, 3600), (1, true));
+        assert_eq!(err(&mut r, 3601), (0, false));
+    }
+
+    #[test]
+    fn same_text_different_level_is_not_a_repeat() {
+        let mut r = Repeats::default();
+        let now = Instant::now();
+        assert_eq!(r.observe(Level::Info, "s1500d", "x", now), (0, true));
+        assert_eq!(r.observe(Level::Warn, "s1500d", "x", now), (0, true));
+    }
+
+    #[test]
+    fn verbose_filter_keeps_module_directives() {
+        let verbose = builder("info,rusb=warn")
+            .filter_level(LevelFilter::Debug)
+            .build();
+        let debug = |target| {
+            verbose.enabled(
+                &Metadata::builder()
+                    .level(Level::Debug)
+                    .target(target)
+                    .build(),
+            )
+        };
+        assert!(debug("s1500d"));
+        assert!(!debug("rusb"));
+    }
+
+    #[test]
+    fn json_lines_carry_event_fields() {
+        let line = |fields: Option<&str>| {
+            let args = format_args!("#4 scan legal (2x press)");
+            let mut record = Record::builder();
+            record.level(Level::Info).target("s1500d").args(args);
+            match fields {
+                Some(fields) => json_line(
+                    Some("2026-03-14T09:26:53Z"),
+                    &record.key_values(&[(EVENT_KEY, fields)]).build(),
+                ),
+                None => json_line(None, &record.build()),
+            }
+        };
+        assert_eq!(
+            line(Some(
+                r#"{"seq":4,"event":"scan","profile":"legal","press_count":2}"#
+            )),
+            r##"{"ts":"2026-03-14T09:26:53Z","level":"info","target":"s1500d","msg":"#4 scan legal (2x press)","seq":4,"event":"scan","profile":"legal","press_count":2}"##
+        );
+        assert_eq!(
+            line(None),
+            r##"{"level":"info","target":"s1500d","msg":"#4 scan legal (2x press)"}"##
+        );
+    }
+
+    #[test]
+    fn timezone_names() {
+        assert!(TimestampStyle::parse_timezone("UTC").unwrap().is_none());
+        assert!(TimestampStyle::parse_timezone("local").unwrap().is_some());
+        assert!(TimestampStyle::parse_timezone("Mars/Olympus_Mons").is_err());
+    }
+}
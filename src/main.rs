@@ -4,25 +4,9 @@
 //! USB communication and fires a handler script on state transitions.
 //! Door open/close is detected via USB device presence.
 //!
-//! # Protocol
-//!
-//! The S1500 uses vendor-specific USB (class FF:FF:FF) with two bulk endpoints.
-//! SCSI commands are wrapped in a 31-byte envelope:
-//!
-//! ```text
-//! byte 0:     0x43  (Fujitsu USB_COMMAND_CODE)
-//! bytes 1-18: 0x00  (padding)
-//! bytes 19+:  SCSI CDB (up to 12 bytes)
-//! ```
-//!
-//! The protocol is 3-phase: command → data → status (0x53 envelope).
-//!
-//! GET_HW_STATUS (SCSI 0xC2) returns 12 bytes:
-//! - byte\[3\] bit 7: hopper empty (inverted — 1 = empty, 0 = paper present)
-//! - byte\[4\] bit 5: scan button physically held
-//!
-//! Door state is not reported in GET_HW_STATUS because opening/closing the
-//! ADF lid powers the scanner on/off, which is a USB connect/disconnect event.
+//! The protocol itself — the Fujitsu command envelope, GET_HW_STATUS
+//! decoding and device access — lives in the library half of the crate
+//! (`src/protocol.rs`, `src/device.rs`) so other tools can reuse it.
 //!
 //! # Usage
 //!
@@ -37,107 +21,280 @@
 //! s1500d -c /etc/s1500d/config.toml
 //!
 //! # Interactive hardware verification:
-//! s1500d --doctor
+//! s1500d doctor
 //! ```
 
+mod audit;
+mod bench;
+mod capture;
+mod cgroup;
+mod cli;
 mod config;
+mod control;
+#[cfg(feature = "dbus")]
+mod dbus;
+#[cfg(all(feature = "dbus", not(target_os = "linux")))]
+compile_error!("the dbus feature needs Linux (it uses abstract-namespace sockets)");
+mod debounce;
 mod doctor;
-
-use std::process::Command as ShellCommand;
+mod error;
+mod eventlog;
+mod handler;
+mod health;
+mod host;
+mod hotplug;
+mod journal;
+mod json;
+mod logging;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod notify;
+mod queue;
+mod ratelimit;
+mod record;
+mod scan;
+mod schedule;
+mod signals;
+mod simulate;
+#[cfg(any(feature = "mqtt", feature = "webhook"))]
+mod spool;
+mod statefile;
+mod status;
+#[cfg(feature = "tui")]
+mod tui;
+mod wait;
+mod watch;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use log::{debug, error, info, warn};
-use rusb::UsbContext;
+use log::{debug, error, info, log, warn, LevelFilter};
 
-use config::{load_config, Config};
+use clap::{CommandFactory, Parser};
+use config::{ArgsTemplate, Config, Gesture, UsbConfig};
+use control::{Command, ControlSocket};
+use debounce::{Debouncer, Settle};
 use doctor::doctor;
+use error::UsbError;
+use health::{Change, Fault, Health};
+use journal::Journal;
+use queue::{Backpressure, DispatchQueue, Pending};
+use ratelimit::RateGate;
+use record::Recorder;
+use s1500d::device;
+use s1500d::protocol::{transitions, Event, Model, State, IFACE};
+use schedule::TimeWindow;
+use statefile::{OnStart, Saved};
 
 // ── Device constants ──────────────────────────────────────────────────
 
-const VID: u16 = 0x04C5;
-const PID: u16 = 0x11A2;
-const EP_OUT: u8 = 0x02;
-const EP_IN: u8 = 0x81;
-const IFACE: u8 = 0;
-
-pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(100);
-const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
-const USB_TIMEOUT: Duration = Duration::from_millis(1000);
-const STATUS_TIMEOUT: Duration = Duration::from_millis(200);
-const MAX_POLL_FAILURES: u32 = 3;
+/// Poll interval for a local scanner; `[usb]` can change the daemon's.
+pub(crate) const POLL_INTERVAL: Duration = UsbConfig::LOCAL.poll_interval;
 
-// ── Fujitsu USB protocol ─────────────────────────────────────────────
+/// USB timing from `[usb]`, set once at startup.
+pub(crate) static USB: OnceLock<UsbConfig> = OnceLock::new();
 
-/// Wrap a SCSI CDB in the 31-byte Fujitsu USB command envelope.
-fn envelope(cdb: &[u8]) -> [u8; 31] {
-    debug_assert!(cdb.len() <= 12, "CDB exceeds 12-byte envelope capacity");
-    let mut buf = [0u8; 31];
-    buf[0] = 0x43;
-    buf[19..19 + cdb.len()].copy_from_slice(cdb);
-    buf
+pub(crate) fn usb_config() -> &'static UsbConfig {
+    static LOCAL: UsbConfig = UsbConfig::LOCAL;
+    USB.get().unwrap_or(&LOCAL)
 }
 
-/// GET_HW_STATUS CDB: opcode 0xC2, allocation length 12 (at CDB bytes 7-8).
-const GHS_CDB: [u8; 10] = [0xC2, 0, 0, 0, 0, 0, 0, 0, 0x0C, 0];
+/// `-c`, `--log-level` and `--device`, set once at startup so a reload
+/// can put them over the new config too.
+static GLOBAL: OnceLock<cli::Global> = OnceLock::new();
 
-// ── State types ──────────────────────────────────────────────────────
+/// The scanner model from `[device]`, set once at startup.
+pub(crate) static MODEL: OnceLock<Model> = OnceLock::new();
 
-/// Snapshot of scanner hardware state, decoded from GET_HW_STATUS.
+pub(crate) fn model() -> &'static Model {
+    MODEL.get().unwrap_or(&Model::S1500)
+}
+
+/// Events the daemon emits on its own, beside the scanner's [`Event`]s.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub(crate) struct State {
-    pub(crate) paper: bool,  // paper present in hopper
-    pub(crate) button: bool, // scan button physically held down
+enum DaemonEvent {
+    LidOpen,
+    Idle,
+    MaintenanceEntered,
+    MaintenanceExited,
+    PermissionDenied,
+    BatchStart,
+    BatchPage,
+    BatchEnd,
+    UsbDegraded,
+    UsbRecovered,
+    ScanStarted,
+    ScanFinished,
+    HandlerFailed,
 }
 
-impl State {
-    fn from_response(buf: &[u8]) -> Option<Self> {
-        if buf.len() < 5 {
-            debug!("short response: {} bytes (need 5)", buf.len());
-            return None;
+impl DaemonEvent {
+    /// The event's name as handlers and logs see it, e.g. `lid-open`.
+    const fn tag(self) -> &'static str {
+        match self {
+            Self::LidOpen => "lid-open",
+            Self::Idle => "idle",
+            Self::MaintenanceEntered => "maintenance-entered",
+            Self::MaintenanceExited => "maintenance-exited",
+            Self::PermissionDenied => "permission-denied",
+            Self::BatchStart => "batch-start",
+            Self::BatchPage => "batch-page",
+            Self::BatchEnd => "batch-end",
+            Self::UsbDegraded => "usb-degraded",
+            Self::UsbRecovered => "usb-recovered",
+            Self::ScanStarted => "scan-started",
+            Self::ScanFinished => "scan-finished",
+            Self::HandlerFailed => "handler-failed",
         }
-        Some(Self {
-            paper: buf[3] & 0x80 == 0,
-            // bit 5 (0x20) = button held; bit 0 (0x01) = button momentary/tap
-            button: buf[4] & 0x21 != 0,
-        })
     }
 }
 
-/// Events that the daemon can emit.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Event {
-    DeviceArrived,
-    DeviceLeft,
-    PaperIn,
-    PaperOut,
-    ButtonDown,
-    ButtonUp,
+/// Every event name that appears in the log: the raw events plus `scan`.
+pub(crate) const EVENT_NAMES: &[&str] = &[
+    "device-arrived",
+    "device-left",
+    "paper-in",
+    "paper-out",
+    "button-down",
+    "button-up",
+    "lid-open",
+    "idle",
+    "maintenance-entered",
+    "maintenance-exited",
+    "permission-denied",
+    "batch-start",
+    "batch-page",
+    "batch-end",
+    "usb-degraded",
+    "usb-recovered",
+    "scan",
+    "scan-started",
+    "scan-finished",
+    "handler-failed",
+];
+
+/// Log an event line at the level configured for its type (default `info`),
+/// and publish the event to any other listeners. `args` is the event name
+/// followed by its arguments, as the handler gets them.
+fn log_event(mode: &Mode, seq: u64, args: &[&str], line: std::fmt::Arguments) {
+    log_event_line(mode, seq, args, None, line);
+    publish(seq, args, None);
 }
 
-impl Event {
-    const fn tag(self) -> &'static str {
-        match self {
-            Self::DeviceArrived => "device-arrived",
-            Self::DeviceLeft => "device-left",
-            Self::PaperIn => "paper-in",
-            Self::PaperOut => "paper-out",
-            Self::ButtonDown => "button-down",
-            Self::ButtonUp => "button-up",
+/// The logging half of [`log_event`]. JSON log lines also get the event's
+/// fields; `gesture` is the one that completed, for `scan`.
+fn log_event_line(
+    mode: &Mode,
+    seq: u64,
+    args: &[&str],
+    gesture: Option<Gesture>,
+    line: std::fmt::Arguments,
+) {
+    let name = args.first().copied().unwrap_or_default();
+    let level = match mode {
+        Mode::ConfigMode(c) => c.event_log_level(name),
+        _ => LevelFilter::Info,
+    };
+    let Some(level) = level.to_level() else {
+        return;
+    };
+    if logging::json() {
+        let fields = event_fields(seq, args, gesture).finish();
+        log!(level, event = fields.as_str(); "{line}");
+    } else {
+        log!(level, "{line}");
+    }
+}
+
+/// Pass an event to the event log and the optional listeners besides the
+/// log and handler; `gesture` is the one that completed, for `scan`.
+fn publish(seq: u64, args: &[&str], gesture: Option<Gesture>) {
+    #[cfg(feature = "dbus")]
+    {
+        dbus::event(seq, args);
+        if let (Some(g @ (Gesture::Presses(n) | Gesture::Hold(n))), Some(profile)) =
+            (gesture, args.get(1))
+        {
+            dbus::gesture(profile, n, matches!(g, Gesture::Hold(_)));
         }
     }
+    let json = event_json(seq, args, gesture, logging::timestamp().as_deref());
+    eventlog::event(&json);
+    #[cfg(feature = "mqtt")]
+    mqtt::event(&json);
+    #[cfg(feature = "webhook")]
+    webhook::event(seq, &json);
+}
+
+/// An event as one line of JSON for the handler's stdin, the event log and
+/// the MQTT and webhook listeners: its [`event_fields`], the time and this host.
+fn event_json(
+    seq: u64,
+    args: &[&str],
+    gesture: Option<Gesture>,
+    timestamp: Option<&str>,
+) -> String {
+    let object = event_fields(seq, args, gesture).opt_str("timestamp", timestamp);
+    host::tag(object).finish()
+}
+
+/// An event's own JSON fields: the handler's arguments, and for `scan`
+/// the profile and the gesture that chose it.
+fn event_fields(seq: u64, args: &[&str], gesture: Option<Gesture>) -> json::Object {
+    let (name, rest) = args.split_first().unwrap_or((&"", &[]));
+    let mut object = json::Object::new()
+        .num("seq", seq)
+        .str("event", name)
+        .raw("args", &json::array(rest.iter().map(|a| json::string(a))));
+    if let Some(g @ (Gesture::Presses(n) | Gesture::Hold(n))) = gesture {
+        object = object
+            .opt_str("profile", rest.first().copied())
+            .num("press_count", n)
+            .bool("held", matches!(g, Gesture::Hold(_)));
+    }
+    object
 }
 
-/// Compare two states and yield the transition events between them.
-fn transitions(prev: State, curr: State) -> impl Iterator<Item = Event> {
-    [
-        (!prev.paper && curr.paper).then_some(Event::PaperIn),
-        (prev.paper && !curr.paper).then_some(Event::PaperOut),
-        (!prev.button && curr.button).then_some(Event::ButtonDown),
-        (prev.button && !curr.button).then_some(Event::ButtonUp),
-    ]
-    .into_iter()
-    .flatten()
+/// Pass a raw scanner transition, numbered `seq`, to the optional
+/// listeners, including button presses that config mode turns into
+/// gestures instead of events.
+#[cfg_attr(not(any(feature = "dbus", feature = "mqtt")), allow(unused_variables))]
+fn publish_transition(ev: Event, seq: u64) {
+    #[cfg(feature = "dbus")]
+    dbus::transition(ev, seq);
+    #[cfg(feature = "mqtt")]
+    mqtt::transition(ev, seq);
+}
+
+/// Sequence number of the most recently emitted event (0 = none yet).
+static EVENT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Assign the next event sequence number.
+///
+/// Numbers start at 1 when the daemon starts and increase by one for every
+/// event it emits, so consumers can spot gaps.
+fn next_seq() -> u64 {
+    EVENT_SEQ.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Sequence number of the most recently emitted event.
+fn current_seq() -> u64 {
+    EVENT_SEQ.load(Ordering::Relaxed)
+}
+
+/// Render a lid-open session length as e.g. `42m07s` or `1h05m00s`.
+fn format_session(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{h}h{m:02}m{s:02}s")
+    } else {
+        format!("{m}m{s:02}s")
+    }
 }
 
 // ── Gesture state machine ────────────────────────────────────────────
@@ -146,30 +303,109 @@ fn transitions(prev: State, curr: State) -> impl Iterator<Item = Event> {
 ///
 /// ```text
 /// Idle
-///   └─ button-down ──→ Pressed(count=1)
+///   └─ button-down ──→ Pressed(count=1, t)
 ///
-/// Pressed(n)
-///   └─ button-up ────→ Released(n, timestamp)
+/// Pressed(n, t)
+///   ├─ button-up ────→ Released(n, timestamp)
+///   ├─ button-up ────→ Held(n, d)         # held d ≥ long_press_ms (only if a
+///   │                                      # hold gesture is mapped)
+///   └─ timeout ──────→ emit scan(hold 1) → Idle  # n = 1 held past the
+///                                          # longest [long_press_profiles] tier
 ///
 /// Released(n, t)
-///   ├─ button-down ──→ Pressed(n+1)       # another press within window
+///   ├─ button-down ──→ Pressed(n+1, t)    # another press within window
 ///   └─ timeout ──────→ emit scan(n) → Idle # window expired, fire gesture
+///
+/// Held(n, d) ────────→ emit scan(hold n) → Idle  # no window to wait for
 /// ```
 #[derive(Debug)]
-enum GestureState {
+pub(crate) enum GestureState {
     Idle,
-    Pressed(u32),
+    Pressed(u32, Instant),
     Released(u32, Instant),
+    /// `n` presses, the last held for the duration.
+    Held(u32, Duration),
+}
+
+impl GestureState {
+    /// The gesture this state stands for once [`check_gesture_timeout`] has
+    /// fired on it; a press still down by then is a hold.
+    pub(crate) fn completed(&self) -> Option<Gesture> {
+        match *self {
+            Self::Released(n, _) => Some(Gesture::Presses(n)),
+            Self::Pressed(n, _) | Self::Held(n, _) => Some(Gesture::Hold(n)),
+            Self::Idle => None,
+        }
+    }
 }
 
 // ── USB communication ────────────────────────────────────────────────
 
+/// The scanner's USB device, if it is attached (the one named by `[usb]`
+/// `serial`, if set).
+pub(crate) fn find_device(ctx: &rusb::Context) -> rusb::Result<rusb::Device<rusb::Context>> {
+    device::find_device(ctx, model(), usb_config().serial.as_deref())
+}
+
 /// Open the scanner, returning a claimed device handle.
-pub(crate) fn try_open(ctx: &rusb::Context) -> Option<rusb::DeviceHandle<rusb::Context>> {
-    let handle = ctx.open_device_with_vid_pid(VID, PID)?;
-    let _ = handle.set_auto_detach_kernel_driver(true);
-    handle.claim_interface(IFACE).ok()?;
-    Some(handle)
+pub(crate) fn open_device(
+    ctx: &rusb::Context,
+) -> Result<rusb::DeviceHandle<rusb::Context>, UsbError> {
+    Ok(device::open_device(
+        ctx,
+        model(),
+        usb_config().serial.as_deref(),
+    )?)
+}
+
+/// Warn if more than one scanner is attached, since without `[usb]` `serial`
+/// the daemon just takes the first.
+fn warn_if_several(ctx: &rusb::Context) {
+    let devices = device::find_devices(ctx, model()).unwrap_or_default();
+    if devices.len() < 2 {
+        return;
+    }
+    let serials: Vec<String> = devices
+        .iter()
+        .map(|d| device::serial_number(d).unwrap_or_else(|_| "?".into()))
+        .collect();
+    warn!(
+        "usb: {} scanners attached (serials {}), using the first — run one s1500d per \
+         scanner, each with [usb] serial set",
+        devices.len(),
+        serials.join(", ")
+    );
+}
+
+/// What to tell the user when the scanner is present but its device node
+/// isn't accessible.
+pub(crate) fn access_denied_help(ctx: &rusb::Context) -> String {
+    let node = find_device(ctx)
+        .ok()
+        .and_then(|d| device::device_node(&d))
+        .unwrap_or_else(|| "the device node".into());
+    let (vid, pid) = (model().vid, model().pid);
+    if cfg!(target_os = "macos") {
+        format!(
+            "cannot claim the scanner ({vid:04x}:{pid:04x}): another program has it — \
+             quit Image Capture, ScanSnap Home or ScanSnap Manager (and anything else \
+             using the scanner), then close and reopen the lid"
+        )
+    } else if cfg!(target_os = "freebsd") {
+        format!(
+            "permission denied opening {node} ({vid:04x}:{pid:04x}) — add a devd rule \
+             giving your group access to the scanner's ugen node (see INSTALL.md), \
+             run `service devd restart`, then close and reopen the lid (or run \
+             s1500d as root)"
+        )
+    } else {
+        format!(
+            "permission denied opening {node} ({vid:04x}:{pid:04x}) — install \
+             contrib/99-scansnap.rules into /etc/udev/rules.d/, run \
+             `udevadm control --reload-rules && udevadm trigger`, then close and \
+             reopen the lid (or run s1500d as root)"
+        )
+    }
 }
 
 /// Open the scanner with a USB reset to clear stale protocol state.
@@ -177,20 +413,35 @@ pub(crate) fn try_open(ctx: &rusb::Context) -> Option<rusb::DeviceHandle<rusb::C
 /// Used in the outer reconnect loop to ensure a clean connection after a
 /// previous s1500d process may have left the device in a bad state (e.g.,
 /// after `systemctl restart`).
-fn try_open_with_reset(ctx: &rusb::Context) -> Option<rusb::DeviceHandle<rusb::Context>> {
-    let handle = try_open(ctx)?;
+fn try_open_with_reset(ctx: &rusb::Context) -> Result<rusb::DeviceHandle<rusb::Context>, UsbError> {
+    let handle = open_device(ctx)?;
     info!("usb: resetting device for clean state");
-    if handle.reset().is_err() {
-        warn!("usb: reset failed, proceeding with existing handle");
-        return Some(handle);
+    match handle.reset() {
+        Ok(()) => {}
+        // The reset made it re-enumerate (as it tends to on macOS); the old
+        // handle is dead, so re-open it like after a successful reset.
+        Err(rusb::Error::NotFound) => debug!("usb: device re-enumerated on reset"),
+        Err(_) => {
+            warn!("usb: reset failed, proceeding with existing handle");
+            return Ok(handle);
+        }
     }
     // Drop stale handle, wait for device to re-enumerate, then re-open fresh.
     drop(handle);
     thread::sleep(Duration::from_millis(200));
-    try_open(ctx)
+    open_device(ctx)
+}
+
+/// Clear a stalled endpoint so the next poll can get through; polls still
+/// failing after that go on to [`try_reset_device`].
+fn clear_stall(handle: &rusb::DeviceHandle<rusb::Context>) {
+    match device::clear_halts(handle) {
+        Ok(()) => info!("usb: endpoint stalled, cleared the halt"),
+        Err(e) => debug!("usb: cannot clear the halt: {e}"),
+    }
 }
 
-/// Attempt to recover from consecutive poll failures by resetting the device.
+/// Attempt to recover from failing polls by resetting the device.
 ///
 /// Takes ownership of the stale handle (preventing accidental reuse), resets,
 /// drops, re-opens, and verifies responsiveness with a test poll.
@@ -203,42 +454,60 @@ fn try_reset_device(
     drop(handle);
     thread::sleep(Duration::from_millis(200));
 
-    let new_handle = try_open(ctx)?;
+    let new_handle = match open_device(ctx) {
+        Ok(h) => h,
+        Err(e) => {
+            debug!("usb: cannot reopen after reset: {e}");
+            return None;
+        }
+    };
     // Verify the device is actually responsive.
-    if poll_status(&new_handle).is_some() {
-        info!("usb: device reset successful, resuming");
-        Some(new_handle)
-    } else {
-        warn!("usb: device unresponsive after reset");
-        None
+    match poll_status(&new_handle) {
+        Ok(_) => {
+            info!("usb: device reset successful, resuming");
+            Some(new_handle)
+        }
+        Err(e) => {
+            warn!("usb: device unresponsive after reset: {e}");
+            None
+        }
     }
 }
 
-/// Send GET_HW_STATUS and decode the response.
-pub(crate) fn poll_status(handle: &rusb::DeviceHandle<rusb::Context>) -> Option<State> {
-    let cmd = envelope(&GHS_CDB);
-
-    // Phase 1: command
-    handle.write_bulk(EP_OUT, &cmd, USB_TIMEOUT).ok()?;
+/// The last GET_HW_STATUS response that decoded, for state dumps.
+static LAST_RAW: Mutex<Option<device::Response>> = Mutex::new(None);
 
-    // Phase 2: data (12 bytes of hardware status)
-    let mut buf = [0u8; 64];
-    let n = handle.read_bulk(EP_IN, &mut buf, USB_TIMEOUT).ok()?;
+/// Ask the scanner who it is (INQUIRY); `None` if it won't say.
+pub(crate) fn identify(handle: &rusb::DeviceHandle<rusb::Context>) -> Option<s1500d::Inquiry> {
+    device::inquiry(handle, usb_config().timeout)
+        .map_err(|e| debug!("usb: INQUIRY failed: {e}"))
+        .ok()
+}
 
-    // Phase 3: drain the status envelope (0x53...)
-    let mut discard = [0u8; 64];
-    let _ = handle.read_bulk(EP_IN, &mut discard, STATUS_TIMEOUT);
+/// Send GET_HW_STATUS and decode the response.
+pub(crate) fn poll_status(handle: &rusb::DeviceHandle<rusb::Context>) -> Result<State, UsbError> {
+    read_status(handle).map(|(state, _)| state)
+}
 
+/// Like [`poll_status`], but also returns the raw response.
+pub(crate) fn read_status(
+    handle: &rusb::DeviceHandle<rusb::Context>,
+) -> Result<(State, device::Response), UsbError> {
+    let response = device::get_hw_status(handle, usb_config().timeout)?;
     debug!(
         "raw: {}",
-        buf[..n]
+        response
+            .bytes()
             .iter()
             .map(|b| format!("{b:02x}"))
             .collect::<Vec<_>>()
             .join(" ")
     );
-
-    State::from_response(&buf[..n])
+    let state = model()
+        .decode(response.bytes())
+        .ok_or(UsbError::BadResponse)?;
+    *LAST_RAW.lock().unwrap_or_else(|e| e.into_inner()) = Some(response);
+    Ok((state, response))
 }
 
 /// Release the USB handle so another process (scanimage) can claim the device.
@@ -248,30 +517,556 @@ fn release_usb(handle: rusb::DeviceHandle<rusb::Context>) {
     debug!("usb: released for handler");
 }
 
-/// Release USB, run handler, reclaim device, and re-read baseline state.
-/// Returns the new handle + fresh state, or None if the device is gone.
-fn run_handler_with_usb(
+/// Work that needs the USB interface released while it runs.
+pub(crate) enum Job {
+    Handler(Pending),
+    /// A built-in scan of the named profile, for event `seq`.
+    Scan(String, u64),
+}
+
+impl Job {
+    /// Whether this is a scan, handled by the daemon or the handler.
+    fn is_scan(&self) -> bool {
+        match self {
+            Self::Handler(pending) => is_scan(&pending.args),
+            Self::Scan(..) => true,
+        }
+    }
+
+    /// The event as the handler would get it, for suppression and logging.
+    fn describe(&self) -> String {
+        match self {
+            Self::Handler(pending) => pending.args.join(" "),
+            Self::Scan(profile, _) => format!("scan {profile}"),
+        }
+    }
+}
+
+/// Whether a handler run's arguments are for a `scan` gesture.
+pub(crate) fn is_scan(args: &[String]) -> bool {
+    args.first().is_some_and(|a| a == "scan")
+}
+
+/// Until when sensor changes are ignored after a scan job, per
+/// `post_scan_cooldown_ms`.
+pub(crate) fn scan_cooldown(mode: &Mode) -> Option<Instant> {
+    match mode {
+        Mode::ConfigMode(c) => c.post_scan_cooldown().map(|d| Instant::now() + d),
+        _ => None,
+    }
+}
+
+/// Whether `state` is a change to ignore because a scan's cooldown is
+/// still running; `cooldown` is cleared once it has run out.
+pub(crate) fn cooling_down(cooldown: &mut Option<Instant>, prev: State, state: State) -> bool {
+    if cooldown.is_some_and(|until| Instant::now() >= until) {
+        debug!("post-scan cooldown over");
+        *cooldown = None;
+    }
+    let cooling = cooldown.is_some();
+    if cooling && prev != state {
+        debug!(
+            "post-scan cooldown: ignoring paper={} button={}",
+            state.paper, state.button
+        );
+    }
+    cooling
+}
+
+/// How long to sleep before the next poll. In config mode a pending
+/// gesture polls every 20ms to hit its timeout promptly; otherwise it's
+/// `poll_interval` while anything is going on (a gesture, the button down,
+/// a change within `slow_after`) and `slow_poll_interval`, if set, after.
+pub(crate) fn next_poll(
+    usb: &UsbConfig,
+    mode: &Mode,
+    gesture: &GestureState,
+    button: bool,
+    last_activity: Instant,
+) -> Duration {
+    match (mode, gesture) {
+        (Mode::ConfigMode(_), GestureState::Released(_, _)) => Duration::from_millis(20),
+        (_, GestureState::Idle) if !button && last_activity.elapsed() >= usb.slow_after => {
+            usb.slow_poll_interval.unwrap_or(usb.poll_interval)
+        }
+        _ => usb.poll_interval,
+    }
+}
+
+/// Release USB, run handler or scan, reclaim device, and re-read baseline
+/// state. Returns the new handle + fresh state, or why it couldn't.
+fn run_with_usb(
     handle: rusb::DeviceHandle<rusb::Context>,
     ctx: &rusb::Context,
-    script: &str,
-    args: &[&str],
-) -> Option<(rusb::DeviceHandle<rusb::Context>, State)> {
+    mode: &Mode,
+    dispatcher: &mut Dispatcher,
+    job: &Job,
+) -> Result<(rusb::DeviceHandle<rusb::Context>, State), UsbError> {
+    let device = handle.device();
     release_usb(handle);
-    run_handler(script, args);
-    let h = try_open(ctx)?;
+    match job {
+        Job::Handler(pending) => dispatcher.dispatch_pending(pending),
+        Job::Scan(profile, _) => run_scan(mode, dispatcher, profile, &device),
+    }
+    let h = open_device(ctx)?;
     let state = poll_status(&h)?;
-    Some((h, state))
+    Ok((h, state))
+}
+
+/// Scan `name` with scanimage while USB is released, announcing it to
+/// the handler with `scan-started` and `scan-finished`.
+fn run_scan(
+    mode: &Mode,
+    dispatcher: &mut Dispatcher,
+    name: &str,
+    device: &rusb::Device<rusb::Context>,
+) {
+    let Mode::ConfigMode(config) = mode else {
+        return;
+    };
+    let Some(profile) = config.scan_profiles.get(name) else {
+        return;
+    };
+    let path = scan::output_dir(name, profile);
+    let dir = path.to_string_lossy();
+    let tag = DaemonEvent::ScanStarted.tag();
+    let seq = next_seq();
+    let args = [tag, name, &dir];
+    log_event(
+        mode,
+        seq,
+        &args,
+        format_args!("#{seq} {tag} {name} → {dir}"),
+    );
+    dispatcher.dispatch(&config.handler, &args, seq);
+
+    notify::status(&format!("scanning {name}"));
+    let outcome = {
+        let _keepalive = notify::Keepalive::start();
+        scan::run(
+            scan::SCANIMAGE,
+            profile,
+            &scan::device_name(device),
+            &path,
+            |page| {
+                info!("scan {name}: page {page}");
+                notify::status(&format!("scanning {name}: page {page}"));
+            },
+        )
+    };
+    notify::status(&format!(
+        "scanner present (bus {:03} device {:03})",
+        device.bus_number(),
+        device.address()
+    ));
+
+    let tag = DaemonEvent::ScanFinished.tag();
+    let pages = outcome.pages.to_string();
+    let seq = next_seq();
+    let args = [tag, name, &pages, &dir];
+    let result = if outcome.ok { "" } else { ", failed" };
+    log_event(
+        mode,
+        seq,
+        &args,
+        format_args!("#{seq} {tag} {name} ({pages} pages{result})"),
+    );
+    dispatcher.dispatch(&config.handler, &args, seq);
 }
 
 // ── Event dispatch ───────────────────────────────────────────────────
 
-/// Run the handler script with the given arguments, synchronously.
-fn run_handler(script: &str, args: &[&str]) {
-    debug!("exec: {script} {}", args.join(" "));
-    match ShellCommand::new(script).args(args).status() {
-        Ok(s) if s.success() => debug!("handler ok"),
-        Ok(s) => warn!("handler exited: {s}"),
-        Err(e) => error!("handler failed: {e}"),
+/// Runs handler commands on behalf of the event loop.
+///
+/// With a journal configured, each invocation is recorded on disk before it
+/// runs and cleared only once the handler succeeds, so work interrupted by a
+/// crash or power loss is replayed on the next start.
+#[derive(Default)]
+pub(crate) struct Dispatcher {
+    journal: Option<Journal>,
+    options: handler::Options,
+    /// While paused, events are still logged but no handler runs.
+    paused: bool,
+    /// In maintenance mode every event is ignored.
+    maintenance: bool,
+    /// `maintenance-entered`/`-exited` notifications not yet handed to the
+    /// handler: sequence number and arguments.
+    notices: Vec<(u64, Vec<String>)>,
+    /// Events a handler asked not to be dispatched, and until when.
+    snoozed: HashMap<String, Instant>,
+    /// Handler runs so far, and how many of them failed.
+    runs: u64,
+    failures: u64,
+    /// Daily window in which handlers don't run (`[quiet_hours]`).
+    quiet: Option<TimeWindow>,
+    /// Set by the quiet-hours override gesture until the lid closes.
+    quiet_lifted: bool,
+    /// Whether paper is in the feeder as of the last poll, for handlers;
+    /// `None` while the scanner is absent.
+    paper: Option<bool>,
+    /// How long the lid was open, for the `device-left` run.
+    lid_open_secs: Option<u64>,
+    /// Failed runs to announce as `handler-failed` (`on_handler_failure =
+    /// "event"`): the event's sequence number, name, and why it failed.
+    failed: Vec<(u64, String, handler::Failure)>,
+    /// Leave the scanner to other tools (`s1500ctl inhibit`) until
+    /// `uninhibit`.
+    inhibited: bool,
+    /// `inhibit` requests, answered once the scanner has been released.
+    inhibit_replies: Vec<control::Request>,
+    /// Handler arguments for profiles that set them, by profile name.
+    profile_args: HashMap<String, ArgsTemplate>,
+    /// Events never dispatched (`ignore_events`).
+    ignored: Vec<String>,
+    /// Drops handler runs over `[rate_limit]`.
+    gate: RateGate,
+}
+
+fn handler_options(config: &Config) -> handler::Options {
+    handler::Options {
+        nice: config.handler_nice,
+        ionice: config.handler_ionice,
+        cgroup: config.handler_cgroup.clone(),
+        audit_log: config.audit_log.as_ref().map(Into::into),
+        ssh: config.handler_ssh.clone(),
+        // Filled in when the scanner arrives.
+        serial: None,
+        identity: None,
+        json: config.handler_json,
+        timeout: config.handler_timeout_s.map(Duration::from_secs),
+        on_failure: config.on_handler_failure,
+        retries: config.handler_retries,
+        run_as: config.handler_user.clone(),
+        clear_env: config.handler_clear_env,
+        path: config.handler_path.clone(),
+        cwd: config.handler_cwd.as_ref().map(Into::into),
+    }
+}
+
+/// What a handler run is told about event `seq` besides its arguments.
+fn handler_context(
+    options: &handler::Options,
+    seq: u64,
+    args: &[&str],
+    gesture: Option<Gesture>,
+    timestamp: Option<String>,
+    paper: Option<bool>,
+    lid_open_secs: Option<u64>,
+) -> handler::Context {
+    handler::Context {
+        press_count: gesture.map(|(Gesture::Presses(n) | Gesture::Hold(n))| n),
+        paper,
+        lid_open_secs,
+        json: options
+            .json
+            .then(|| event_json(seq, args, gesture, timestamp.as_deref())),
+        timestamp,
+    }
+}
+
+impl Dispatcher {
+    /// Build the dispatcher for `mode`, replaying any journaled invocations
+    /// left unfinished by a previous run.
+    fn new(mode: &Mode) -> Self {
+        let config = match mode {
+            Mode::ConfigMode(c) => c,
+            _ => return Self::default(),
+        };
+        let options = handler_options(config);
+        let mut dispatcher = Self {
+            journal: None,
+            options,
+            quiet: config.quiet_hours.as_ref().map(|q| q.window),
+            profile_args: config.profile_args.clone(),
+            ignored: config.ignore_events.clone(),
+            gate: RateGate::new(config.rate_limit),
+            ..Self::default()
+        };
+        let Some(path) = &config.journal else {
+            return dispatcher;
+        };
+        let (journal, unfinished) = Journal::open(path.as_ref()).unwrap_or_else(|e| {
+            error!("journal: cannot open {path}: {e}");
+            std::process::exit(error::EXIT_IO);
+        });
+        dispatcher.journal = Some(journal);
+        if !unfinished.is_empty() {
+            info!(
+                "journal: replaying {} unfinished handler run(s)",
+                unfinished.len()
+            );
+        }
+        for entry in unfinished {
+            dispatcher.replay(&entry);
+        }
+        dispatcher
+    }
+
+    /// Run a journaled invocation left unfinished by a previous run, as
+    /// event `entry.seq` of that run, unless it would be suppressed or
+    /// rate-limited now. Either way the old entry is done with: a replay
+    /// that fails again is journaled afresh.
+    fn replay(&mut self, entry: &journal::Entry) {
+        let args: Vec<&str> = entry.args.iter().map(String::as_str).collect();
+        if !self.gated(&entry.command, &args, entry.seq) {
+            self.run_handler(&entry.command, &args, entry.seq, None, None);
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.complete(entry.id) {
+                error!("journal: cannot mark entry {} complete: {e}", entry.id);
+            }
+        }
+    }
+
+    /// Pick up handler settings and quiet hours from a reloaded config.
+    fn reconfigure(&mut self, config: &Config) {
+        let serial = self.options.serial.take();
+        let identity = self.options.identity.take();
+        self.options = handler::Options {
+            serial,
+            identity,
+            ..handler_options(config)
+        };
+        self.quiet = config.quiet_hours.as_ref().map(|q| q.window);
+        self.profile_args.clone_from(&config.profile_args);
+        self.ignored.clone_from(&config.ignore_events);
+        self.gate.set_limits(config.rate_limit);
+    }
+
+    /// Whether the handler run for event `seq` would go over
+    /// `[rate_limit]`, logging and counting it as dropped if so. A run let
+    /// through counts against the limit from now on.
+    fn rate_limited(&mut self, seq: u64, args: &[&str]) -> bool {
+        let now = Instant::now();
+        match self.gate.admit(args, now) {
+            Ok(()) => {
+                self.gate.record(args, now);
+                false
+            }
+            Err(reason) => {
+                info!(
+                    "rate limit: dropping #{seq} {} ({reason}; {} dropped so far)",
+                    args.join(" "),
+                    self.gate.dropped()
+                );
+                true
+            }
+        }
+    }
+
+    /// Whether a queued job is dropped rather than run: suppressed, or over
+    /// the rate limit. Checked once per job, before the scanner is
+    /// released for it.
+    fn drops(&mut self, job: &Job) -> bool {
+        match job {
+            Job::Handler(pending) => self.drops_pending(pending),
+            Job::Scan(profile, seq) => {
+                self.suppressed(*seq, &job.describe())
+                    || self.rate_limited(*seq, &["scan", profile])
+            }
+        }
+    }
+
+    /// [`drops`](Self::drops) for a queued handler run.
+    fn drops_pending(&mut self, job: &Pending) -> bool {
+        let args: Vec<&str> = job.args.iter().map(String::as_str).collect();
+        self.gated(&job.command, &args, job.seq)
+    }
+
+    /// Whether running `command` for event `seq` is suppressed or over the
+    /// rate limit. Without a handler (webhook only) there is nothing to
+    /// limit.
+    fn gated(&mut self, command: &[String], args: &[&str], seq: u64) -> bool {
+        self.suppressed(seq, &args.join(" "))
+            || (!command.is_empty() && self.rate_limited(seq, args))
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused {
+            if paused {
+                info!("dispatch paused — events are logged but no handler runs");
+            } else {
+                info!("dispatch resumed");
+            }
+        }
+        self.paused = paused;
+    }
+
+    /// Whether dispatch is paused, in maintenance, in quiet hours, or the
+    /// event (the first word of `what`) is ignored or snoozed, logging the
+    /// suppressed invocation if so.
+    fn suppressed(&self, seq: u64, what: &str) -> bool {
+        let event = what.split(' ').next().unwrap_or_default();
+        if self.ignored.iter().any(|e| e == event) {
+            debug!("ignore_events: not dispatching #{seq} {what}");
+            return true;
+        }
+        let snoozed = self
+            .snoozed
+            .get(event)
+            .is_some_and(|until| Instant::now() < *until);
+        let quiet = self.quiet_at(schedule::local_minute_of_day());
+        // Maintenance doesn't swallow the notices announcing it.
+        let maintenance = self.maintenance
+            && event != DaemonEvent::MaintenanceEntered.tag()
+            && event != DaemonEvent::MaintenanceExited.tag();
+        if maintenance {
+            info!("maintenance: ignoring #{seq} {what}");
+        } else if self.paused {
+            info!("paused: not dispatching #{seq} {what}");
+        } else if quiet {
+            info!("quiet hours: not dispatching #{seq} {what}");
+        } else if snoozed {
+            info!("snoozed: not dispatching #{seq} {what}");
+        }
+        self.paused || maintenance || quiet || snoozed
+    }
+
+    /// Whether quiet hours hold at `minute` past local midnight and haven't
+    /// been lifted by the override gesture.
+    fn quiet_at(&self, minute: u32) -> bool {
+        !self.quiet_lifted && self.quiet.is_some_and(|w| w.contains(minute))
+    }
+
+    /// Lift quiet hours until the lid closes (the `[quiet_hours]` override
+    /// gesture).
+    fn lift_quiet(&mut self) {
+        if self.quiet_at(schedule::local_minute_of_day()) {
+            info!("quiet hours lifted until the lid closes");
+            self.quiet_lifted = true;
+        } else {
+            info!("quiet-hours override — not in quiet hours, nothing to lift");
+        }
+    }
+
+    /// Apply what a handler asked for on its control pipe.
+    fn apply_requests(&mut self, requests: Vec<handler::Request>) {
+        let now = Instant::now();
+        self.snoozed.retain(|_, until| now < *until);
+        for request in requests {
+            match request {
+                handler::Request::Snooze(event, duration) => {
+                    let Some(until) = now.checked_add(duration) else {
+                        warn!(
+                            "snooze: ignoring {event} for {}s, too far ahead",
+                            duration.as_secs()
+                        );
+                        continue;
+                    };
+                    info!(
+                        "snooze: not dispatching {event} for {}s (handler request)",
+                        duration.as_secs()
+                    );
+                    self.snoozed.insert(event, until);
+                }
+            }
+        }
+    }
+
+    /// Run a queued handler job that [`drops`](Self::drops) let through.
+    fn dispatch_pending(&mut self, job: &Pending) {
+        let expanded = self.profile_args(job);
+        let args = expanded.as_ref().unwrap_or(&job.args);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let (gesture, timestamp) = (job.gesture, job.timestamp.clone());
+        self.run_handler(&job.command, &args, job.seq, gesture, timestamp);
+    }
+
+    /// The handler arguments a gesture's `scan <profile>` runs with, if
+    /// its profile sets them.
+    fn profile_args(&self, job: &Pending) -> Option<Vec<String>> {
+        let gesture = job.gesture?;
+        let [event, profile] = job.args.as_slice() else {
+            return None;
+        };
+        let template = self.profile_args.get(profile).filter(|_| event == "scan")?;
+        let (Gesture::Presses(press_count) | Gesture::Hold(press_count)) = gesture;
+        Some(template.expand(profile, Some(press_count), self.options.serial.as_deref()))
+    }
+
+    /// Run the handler for event `seq` now, unless it is suppressed or over
+    /// the rate limit.
+    fn dispatch(&mut self, command: &[String], args: &[&str], seq: u64) {
+        if !self.gated(command, args, seq) {
+            self.run_handler(command, args, seq, None, logging::timestamp());
+        }
+    }
+
+    fn run_handler(
+        &mut self,
+        command: &[String],
+        args: &[&str],
+        seq: u64,
+        gesture: Option<Gesture>,
+        timestamp: Option<String>,
+    ) {
+        // Without a handler (webhook only) there is nothing to run.
+        if command.is_empty() {
+            return;
+        }
+        let command: Vec<&str> = command.iter().map(String::as_str).collect();
+        let entry = self.journal.as_mut().and_then(|j| {
+            j.begin(&command, args, seq)
+                .map_err(|e| error!("journal: cannot record handler run: {e}"))
+                .ok()
+        });
+        let context = handler_context(
+            &self.options,
+            seq,
+            args,
+            gesture,
+            timestamp,
+            self.paper,
+            self.lid_open_secs,
+        );
+        let retries = match self.options.on_failure {
+            handler::OnFailure::Retry => self.options.retries,
+            _ => 0,
+        };
+        let mut failure = None;
+        let mut attempts = 0;
+        for attempt in 0..=retries {
+            if attempt > 0 {
+                warn!("retrying #{seq} {} ({attempt}/{retries})", args.join(" "));
+                thread::sleep(handler::RETRY_DELAY);
+            }
+            let outcome = {
+                let _keepalive = notify::Keepalive::start();
+                handler::run(&command, args, seq, &context, &self.options)
+            };
+            failure = outcome.failure;
+            attempts += 1;
+            self.runs += 1;
+            self.failures += u64::from(!outcome.ok());
+            self.apply_requests(outcome.requests);
+            if failure.is_none() {
+                break;
+            }
+        }
+        eventlog::handler(
+            seq,
+            args,
+            attempts,
+            failure,
+            logging::timestamp().as_deref(),
+        );
+        let event = args.first().copied().unwrap_or_default();
+        if let Some(failure) = failure {
+            // A failing handler-failed handler isn't announced again.
+            if self.options.on_failure == handler::OnFailure::Event
+                && event != DaemonEvent::HandlerFailed.tag()
+            {
+                self.failed.push((seq, event.to_string(), failure));
+            }
+        }
+        let ok = failure.is_none();
+        if let (Some(journal), Some(id)) = (self.journal.as_mut(), entry) {
+            if !ok {
+                warn!("journal: handler failed, entry {id} will be replayed on next start");
+            } else if let Err(e) = journal.complete(id) {
+                error!("journal: cannot mark entry {id} complete: {e}");
+            }
+        }
     }
 }
 
@@ -279,233 +1074,1437 @@ fn run_handler(script: &str, args: &[&str]) {
 
 /// What mode the daemon is running in.
 #[allow(clippy::enum_variant_names)]
-enum Mode {
+pub(crate) enum Mode {
     /// Log events only, no handler.
     LogOnly,
     /// Legacy: fire handler with raw event names (no gesture detection).
     Legacy(String),
     /// Config: gesture detection on button, handler with profile dispatch.
-    ConfigMode(Config),
+    ConfigMode(Box<Config>),
 }
 
-// ── Main loop ────────────────────────────────────────────────────────
+// ── Batch sessions ───────────────────────────────────────────────────
+
+/// Pages fed between two batch gestures, grouped under one ID.
+#[derive(Debug)]
+pub(crate) struct Batch {
+    id: String,
+    pages: u32,
+    last_activity: Instant,
+}
+
+/// Open a batch, returning the handler run that announces it.
+fn open_batch(batch: &mut Option<Batch>, mode: &Mode) -> Option<Action> {
+    let Mode::ConfigMode(config) = mode else {
+        return None;
+    };
+    let id = handler::new_job_id();
+    let tag = DaemonEvent::BatchStart.tag();
+    let seq = next_seq();
+    log_event(mode, seq, &[tag, &id], format_args!("#{seq} {tag} {id}"));
+    *batch = Some(Batch {
+        id: id.clone(),
+        pages: 0,
+        last_activity: Instant::now(),
+    });
+    Some(Action::RunHandler(
+        config.handler.clone(),
+        vec![tag.into(), id],
+        seq,
+    ))
+}
 
-fn print_usage() {
-    eprintln!(
-        "s1500d — event daemon for the Fujitsu ScanSnap S1500\n\
-         \n\
-         Usage:\n\
-         \x20 s1500d                   Monitor and log events\n\
-         \x20 s1500d HANDLER           Run HANDLER on each raw event\n\
-         \x20 s1500d -c CONFIG.toml    Gesture detection + profile dispatch\n\
-         \x20 s1500d --doctor          Interactive hardware verification\n\
-         \x20 s1500d --version         Show version\n\
-         \x20 s1500d --help            Show this message\n\
-         \n\
-         Handler mode (s1500d HANDLER) — handler receives the event name as $1:\n\
-         \x20 device-arrived   Scanner lid opened (USB device appeared)\n\
-         \x20 device-left      Scanner lid closed (USB device removed)\n\
-         \x20 paper-in         Paper inserted into feeder\n\
-         \x20 paper-out        Paper removed from feeder\n\
-         \x20 button-down      Scan button pressed\n\
-         \x20 button-up        Scan button released\n\
-         \n\
-         Config mode (s1500d -c CONFIG.toml) — handler receives:\n\
-         \x20 scan <profile>   Gesture completed (press count mapped to profile)\n\
-         \x20 paper-in         Paper inserted (no second arg)\n\
-         \x20 paper-out        Paper removed (no second arg)\n\
-         \x20 device-arrived   Scanner appeared (no second arg)\n\
-         \x20 device-left      Scanner removed (no second arg)\n\
-         \n\
-         Set log_level = \"debug\" in config.toml for verbose output\n\
-         (or RUST_LOG=debug to override)."
+/// Close the open batch, if any, returning the handler run that announces
+/// it. `reason` (gesture, idle, lid closed) is only logged.
+fn close_batch(batch: &mut Option<Batch>, reason: &str, mode: &Mode) -> Option<Action> {
+    let Mode::ConfigMode(config) = mode else {
+        return None;
+    };
+    let b = batch.take()?;
+    let tag = DaemonEvent::BatchEnd.tag();
+    let pages = b.pages.to_string();
+    let seq = next_seq();
+    log_event(
+        mode,
+        seq,
+        &[tag, &b.id, &pages],
+        format_args!("#{seq} {tag} {} ({pages} pages, {reason})", b.id),
     );
+    Some(Action::RunHandler(
+        config.handler.clone(),
+        vec![tag.into(), b.id, pages],
+        seq,
+    ))
+}
+
+/// Whether an open batch has gone `idle_seconds` without activity.
+fn batch_idle(batch: &Batch, mode: &Mode) -> bool {
+    match mode {
+        Mode::ConfigMode(c) => c
+            .batch
+            .as_ref()
+            .is_some_and(|cfg| batch.last_activity.elapsed() >= cfg.idle),
+        _ => false,
+    }
 }
 
-/// What action the event loop should take after processing transitions.
+/// What the event loop should do when a timer (gesture window, lid reminder)
+/// fires.
 #[derive(Debug)]
 enum Action {
     /// No handler to run — just continue polling.
     Continue,
-    /// Run handler with USB release/reclaim. Args: (script, args).
-    RunHandler(String, Vec<String>),
+    /// Queue a handler run with USB release/reclaim. Args: (command, args,
+    /// the event's seq).
+    RunHandler(Vec<String>, Vec<String>, u64),
+    /// Scan a `[profiles.NAME]` profile with USB released, for event `seq`.
+    Scan(String, u64),
+    /// Enter or leave maintenance mode (the `maintenance_presses` gesture).
+    ToggleMaintenance,
+    /// Open a batch, or close the open one (the `[batch]` gesture).
+    ToggleBatch,
+    /// Let handlers run despite quiet hours (the `[quiet_hours]` override).
+    LiftQuiet,
 }
 
-fn run(mode: Mode) -> ! {
-    let ctx = rusb::Context::new().expect("failed to create USB context");
+// ── Main loop ────────────────────────────────────────────────────────
+
+fn run(mut mode: Mode, config_path: Option<&str>, mut recorder: Option<Recorder>) -> ! {
+    let ctx = rusb::Context::new().unwrap_or_else(|e| {
+        error!("usb: cannot initialize libusb: {e}");
+        std::process::exit(error::EXIT_USB);
+    });
+    let usb = usb_config();
+    let watcher = hotplug::Watcher::new(&ctx);
+    if watcher.is_some() {
+        debug!("usb: hotplug notifications enabled");
+    } else {
+        info!(
+            "usb: no hotplug support, checking for the scanner every {}s",
+            usb.reconnect_interval.as_secs()
+        );
+    }
     let mut was_present = false;
     let mut prev: Option<State> = None;
+    // Carrying on from `state_file`: whether the scanner was there when the
+    // last run stopped (so its arrival isn't announced again), and what the
+    // first poll is compared with; both are used up by the first session.
+    let saved = load_saved(&mode);
+    let mut persisted = saved;
+    let mut resumed = saved.is_some_and(|s| s.present);
+    let mut baseline = start_baseline(&mode, saved);
     let mut gesture = GestureState::Idle;
+    // Sensor changes that haven't lasted long enough to count yet.
+    let mut debouncer = Debouncer::default();
+    // The `[watch]` bits as of the last poll; `None` until the first.
+    let mut watched: Option<u64> = None;
+    // Lid session: when the device appeared, and how many reminders have fired.
+    let mut opened_at: Option<Instant> = None;
+    let mut reminders_sent: u64 = 0;
+    // Last button or paper activity, and whether `idle` has fired since.
+    let mut last_activity = Instant::now();
+    let mut idle_sent = false;
+    // Why the scanner last failed to open, so each cause is reported once.
+    let mut open_error: Option<UsbError> = None;
+    let mut several_warned = false;
+    // Whether the scanner was let go for an inhibit, so lifting it is logged.
+    let mut inhibit_released = false;
+    // Until when the sensors are left to settle after a scan.
+    let mut cooldown: Option<Instant> = None;
+    let mut batch: Option<Batch> = None;
+    let started = Instant::now();
+    let (mut polls, mut failed_polls) = (0u64, 0u64);
+    let mut health = match &mode {
+        Mode::ConfigMode(c) => Health::new(c.usb_health.max_errors, c.usb_health.window),
+        _ => {
+            let defaults = config::UsbHealthConfig::default();
+            Health::new(defaults.max_errors, defaults.window)
+        }
+    };
+    let mut dispatcher = Dispatcher::new(&mode);
+    let mut queue = match &mode {
+        Mode::ConfigMode(c) => DispatchQueue::new(c.backpressure, c.queue_size),
+        _ => DispatchQueue::new(Backpressure::default(), queue::DEFAULT_CAPACITY),
+    };
+    let control = match &mode {
+        Mode::ConfigMode(c) => c.control_socket.as_ref().map(|path| {
+            ControlSocket::bind(path.as_ref()).unwrap_or_else(|e| {
+                error!("control: cannot listen on {path}: {e}");
+                std::process::exit(error::EXIT_IO);
+            })
+        }),
+        _ => None,
+    };
+    if let Mode::ConfigMode(c) = &mode {
+        if let Err(e) = eventlog::start(c.event_log.as_ref()) {
+            error!("event_log: {e}");
+            std::process::exit(error::EXIT_IO);
+        }
+    }
+    #[cfg(feature = "dbus")]
+    if let Mode::ConfigMode(c) = &mode {
+        if let Some(config) = &c.dbus {
+            dbus::start(config);
+        }
+    }
+    #[cfg(feature = "mqtt")]
+    if let Mode::ConfigMode(c) = &mode {
+        if let Some(config) = &c.mqtt {
+            mqtt::start(config);
+        }
+    }
+    #[cfg(feature = "webhook")]
+    if let Mode::ConfigMode(c) = &mode {
+        if let Some(url) = &c.webhook_url {
+            webhook::start(
+                url,
+                &c.webhook_headers,
+                c.webhook_retries,
+                c.webhook_backlog,
+                c.webhook_spool.as_deref(),
+            );
+        }
+    }
+    signals::install(libc::SIGUSR1);
+    signals::install(libc::SIGUSR2);
+    signals::install(libc::SIGQUIT);
+    signals::install(libc::SIGHUP);
+    notify::ready("waiting for the scanner");
 
     loop {
         // ── Phase 1: wait for device ─────────────────────────────
         let mut handle = loop {
-            match try_open_with_reset(&ctx) {
-                Some(h) => break h,
+            // While inhibited the scanner belongs to whoever asked for it.
+            let opened = match inhibited(&mode, &dispatcher) {
+                Some(_) => None,
                 None => {
-                    if was_present {
-                        info!("{}", Event::DeviceLeft.tag());
-                        emit_handler(&mode, &[Event::DeviceLeft.tag()]);
+                    if inhibit_released {
+                        info!("inhibit lifted, reclaiming the scanner");
+                        inhibit_released = false;
+                    }
+                    Some(try_open_with_reset(&ctx))
+                }
+            };
+            match opened {
+                None => notify::watchdog(),
+                Some(Ok(h)) => {
+                    if open_error == Some(UsbError::AccessDenied) {
+                        info!("usb: permission granted, device opened");
+                    }
+                    open_error = None;
+                    break h;
+                }
+                Some(Err(e)) => {
+                    notify::watchdog();
+                    if was_present || std::mem::take(&mut resumed) {
+                        dispatcher.paper = None;
+                        // The device is gone, so there is no USB to release —
+                        // run anything still queued before announcing it.
+                        while let Some(job) = queue.pop() {
+                            if !dispatcher.drops_pending(&job) {
+                                dispatcher.dispatch_pending(&job);
+                            }
+                        }
+                        if let Some(Action::RunHandler(command, args, seq)) =
+                            close_batch(&mut batch, "lid closed", &mode)
+                        {
+                            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                            dispatcher.dispatch(&command, &args, seq);
+                        }
+                        let secs = opened_at.take().map_or(0, |t| t.elapsed().as_secs());
+                        let seq = next_seq();
+                        publish_transition(Event::DeviceLeft, seq);
+                        let tag = Event::DeviceLeft.tag();
+                        log_event(
+                            &mode,
+                            seq,
+                            &[tag],
+                            format_args!("#{seq} {tag} (lid open {})", format_session(secs)),
+                        );
+                        // Handlers have always got `device-left` alone; the
+                        // session length goes in the environment.
+                        dispatcher.lid_open_secs = Some(secs);
+                        emit_handler(&mode, &mut dispatcher, &[tag], seq);
+                        dispatcher.lid_open_secs = None;
+                        dispatcher.quiet_lifted = false;
+                        notify::status("waiting for the scanner");
                         was_present = false;
                         prev = None;
+                        baseline = None;
+                        persist(&mode, &mut persisted, Saved::ABSENT);
+                        debouncer = Debouncer::default();
+                        watched = None;
                         gesture = GestureState::Idle;
                     }
-                    thread::sleep(RECONNECT_INTERVAL);
+                    // Report each cause once, then keep retrying quietly —
+                    // fixing the udev rule needs no restart.
+                    if open_error != Some(e) {
+                        match e {
+                            UsbError::NotFound => {}
+                            UsbError::AccessDenied => {
+                                notify::status(
+                                    "scanner attached but not accessible — check the udev rule",
+                                );
+                                error!("usb: {}", access_denied_help(&ctx));
+                                let tag = DaemonEvent::PermissionDenied.tag();
+                                let seq = next_seq();
+                                log_event(&mode, seq, &[tag], format_args!("#{seq} {tag}"));
+                                emit_handler(&mode, &mut dispatcher, &[tag], seq);
+                            }
+                            e => warn!("usb: cannot open the scanner ({e}), retrying"),
+                        }
+                    }
+                    open_error = Some(e);
                 }
             }
+            if signals::take(libc::SIGHUP) {
+                reload(&mut mode, config_path, &mut dispatcher);
+            }
+            let snapshot = Snapshot {
+                started,
+                handle: None,
+                opened_at,
+                last_activity,
+                prev,
+                gesture: &gesture,
+                batch: batch.as_ref(),
+                queue: &queue,
+                polls,
+                failed_polls,
+                health: &health,
+            };
+            service_control(control.as_ref(), &mode, &mut dispatcher, &snapshot);
+            report_notices(&mode, &mut dispatcher, None);
+            // Nothing is held here, so an inhibit takes effect at once.
+            reply_inhibited(&mut dispatcher);
+            #[cfg(feature = "tui")]
+            tui::update(&snapshot, &mode, &dispatcher);
+            hotplug::wait(watcher.as_ref(), &ctx, usb.reconnect_interval);
         };
 
         if !was_present {
-            info!("{}", Event::DeviceArrived.tag());
-            emit_handler(&mode, &[Event::DeviceArrived.tag()]);
+            dispatcher.options.serial = device::serial_number(&handle.device()).ok();
+            select_unit(&mut mode, &mut dispatcher);
+            dispatcher.options.identity = identify(&handle);
+            if let Some(identity) = &dispatcher.options.identity {
+                let serial = dispatcher.options.serial.as_deref().unwrap_or("unknown");
+                info!("usb: {identity}, serial {serial}");
+            }
+            if usb.serial.is_none() && !several_warned {
+                warn_if_several(&ctx);
+                several_warned = true;
+            }
+            notify::status(&format!(
+                "scanner present (bus {:03} device {:03})",
+                handle.device().bus_number(),
+                handle.device().address()
+            ));
+            if std::mem::take(&mut resumed) {
+                info!("scanner was already present before the restart, not announcing it");
+            } else {
+                let seq = next_seq();
+                publish_transition(Event::DeviceArrived, seq);
+                let tag = Event::DeviceArrived.tag();
+                log_event(&mode, seq, &[tag], format_args!("#{seq} {tag}"));
+                emit_handler(&mode, &mut dispatcher, &[tag], seq);
+            }
             was_present = true;
+            opened_at = Some(Instant::now());
+            reminders_sent = 0;
+            last_activity = Instant::now();
+            idle_sent = false;
         }
 
         // ── Phase 2: poll status while device is alive ───────────
         let mut poll_failures: u32 = 0;
         let mut has_reset = false;
         'poll: loop {
+            notify::watchdog();
+            if signals::take(libc::SIGHUP) {
+                reload(&mut mode, config_path, &mut dispatcher);
+                // The watches may have changed; take a new baseline.
+                watched = None;
+            }
+            let snapshot = Snapshot {
+                started,
+                handle: Some(&handle),
+                opened_at,
+                last_activity,
+                prev,
+                gesture: &gesture,
+                batch: batch.as_ref(),
+                queue: &queue,
+                polls,
+                failed_polls,
+                health: &health,
+            };
+            service_control(control.as_ref(), &mode, &mut dispatcher, &snapshot);
+            #[cfg(feature = "tui")]
+            tui::update(&snapshot, &mode, &dispatcher);
+
+            if let Some(by) = inhibited(&mode, &dispatcher) {
+                info!("inhibited ({by}): released the scanner, not polling until lifted");
+                notify::status("inhibited — scanner released");
+                release_usb(handle);
+                reply_inhibited(&mut dispatcher);
+                inhibit_released = true;
+                // Whatever happens meanwhile is the other tool's doing; take
+                // a fresh baseline afterwards instead of reporting it.
+                prev = None;
+                debouncer = Debouncer::default();
+                watched = None;
+                gesture = GestureState::Idle;
+                break 'poll;
+            }
+
             // Check gesture timeout before polling
-            let gesture_action = check_gesture_timeout(&gesture, &mode);
+            let mut scan = None;
+            let paper = prev.is_some_and(|s| s.paper);
+            let gesture_action = check_gesture_timeout(&gesture, &mode, paper);
             if let Some(action) = gesture_action {
+                let completed = gesture.completed();
                 gesture = GestureState::Idle;
-                match action {
-                    Action::Continue => {}
-                    Action::RunHandler(script, args) => {
-                        let arg_refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-                        match run_handler_with_usb(handle, &ctx, &script, &arg_refs) {
-                            Some((h, fresh)) => {
-                                handle = h;
-                                prev = Some(fresh);
-                            }
-                            None => break 'poll,
-                        }
-                    }
-                }
+                scan = take_action(
+                    action,
+                    completed,
+                    &mode,
+                    &mut dispatcher,
+                    &mut batch,
+                    &mut queue,
+                );
             }
 
-            let Some(state) = poll_status(&handle) else {
-                poll_failures += 1;
-                if poll_failures < MAX_POLL_FAILURES {
-                    debug!("poll failed ({poll_failures}/{MAX_POLL_FAILURES}), retrying");
-                    thread::sleep(POLL_INTERVAL);
-                    continue 'poll;
+            if let Some(change) = health.check(Instant::now()) {
+                report_health(change, &health, &mode, &mut queue);
+            }
+            report_handler_failures(&mode, &mut dispatcher, &mut queue);
+            report_notices(&mode, &mut dispatcher, Some(&mut queue));
+
+            if batch.as_ref().is_some_and(|b| batch_idle(b, &mode)) {
+                if let Some(Action::RunHandler(command, args, seq)) =
+                    close_batch(&mut batch, "idle", &mode)
+                {
+                    queue.push(command, args, seq);
                 }
-                if !has_reset {
-                    has_reset = true;
-                    if let Some(new_handle) = try_reset_device(handle, &ctx) {
-                        handle = new_handle;
-                        poll_failures = 0;
-                        continue 'poll;
-                    }
+            }
+
+            if !idle_sent {
+                if let Some(Action::RunHandler(command, args, seq)) =
+                    check_idle(last_activity, &mode)
+                {
+                    idle_sent = true;
+                    queue.push(command, args, seq);
+                }
+            }
+
+            let reminder = opened_at.and_then(|t| check_lid_reminder(t, reminders_sent, &mode));
+            if let Some(Action::RunHandler(command, args, seq)) = reminder {
+                reminders_sent += 1;
+                queue.push(command, args, seq);
+            }
+
+            // Run a gesture's scan or at most one queued handler per cycle;
+            // the reclaim re-reads the baseline so changes made meanwhile
+            // (the feeder emptying) don't fire.
+            if let Some(job) = scan
+                .or_else(|| queue.pop().map(Job::Handler))
+                .filter(|job| !dispatcher.drops(job))
+            {
+                dispatcher.paper = prev.map(|s| s.paper);
+                match run_with_usb(handle, &ctx, &mode, &mut dispatcher, &job) {
+                    Ok((h, fresh)) => {
+                        handle = h;
+                        prev = Some(fresh);
+                        debouncer = Debouncer::default();
+                        watched = None;
+                        if job.is_scan() {
+                            cooldown = scan_cooldown(&mode);
+                        }
+                    }
+                    // Gone (lid closed meanwhile) doesn't count against
+                    // health; still attached but not reclaimable does.
+                    Err(UsbError::NotFound | UsbError::Gone) => break 'poll,
+                    Err(e) => {
+                        if e == UsbError::AccessDenied {
+                            error!(
+                                "usb: cannot reclaim the scanner: {}",
+                                access_denied_help(&ctx)
+                            );
+                        } else {
+                            warn!("usb: cannot reclaim the scanner: {e}");
+                        }
+                        let change = health.record(Fault::Reclaim, Instant::now());
+                        if let Some(change) = change {
+                            report_health(change, &health, &mode, &mut queue);
+                        }
+                        break 'poll;
+                    }
+                }
+            }
+
+            polls += 1;
+            let (mut state, response) = match read_status(&handle) {
+                Ok(read) => read,
+                Err(UsbError::Gone) => {
+                    debug!("poll: device gone");
+                    break;
+                }
+                Err(UsbError::Busy) => {
+                    // The scanner answered, so the session is fine; ask
+                    // again next cycle without counting towards a reset.
+                    failed_polls += 1;
+                    debug!("poll: scanner busy, retrying");
+                    thread::sleep(usb.poll_interval);
+                    continue 'poll;
+                }
+                Err(e) => {
+                    failed_polls += 1;
+                    poll_failures += 1;
+                    if let Some(change) = health.record(Fault::from_usb(e), Instant::now()) {
+                        report_health(change, &health, &mode, &mut queue);
+                        // Errors spread over time never add up to
+                        // max_poll_failures in a row; try a reset now.
+                        poll_failures = poll_failures.max(usb.max_poll_failures);
+                    }
+                    if poll_failures < usb.max_poll_failures {
+                        if e == UsbError::Stalled {
+                            clear_stall(&handle);
+                        }
+                        debug!(
+                            "poll failed ({poll_failures}/{}), retrying",
+                            usb.max_poll_failures
+                        );
+                        thread::sleep(usb.poll_interval);
+                        continue 'poll;
+                    }
+                    if !has_reset {
+                        has_reset = true;
+                        if let Some(new_handle) = try_reset_device(handle, &ctx) {
+                            handle = new_handle;
+                            poll_failures = 0;
+                            continue 'poll;
+                        }
+                    }
+                    // Gone, or still there but unresponsive: either way the
+                    // reconnect loop re-enumerates it.
+                    debug!("poll failed, assuming device left");
+                    break;
                 }
-                debug!("poll failed, assuming device left");
-                break;
             };
+            // A later run of failures (the next suspend) gets its own reset.
             poll_failures = 0;
+            has_reset = false;
+
+            if let Some(Err(e)) = recorder
+                .as_mut()
+                .map(|r| r.record(SystemTime::now(), &response))
+            {
+                error!("record: cannot write, recording stopped: {e}");
+                recorder = None;
+            }
+
+            let bits = watch::read(watches(&mode), response.bytes());
+            if let Some(before) = watched.filter(|&before| before != bits) {
+                report_watches(before, bits, &mode, &mut queue);
+            }
+            watched = Some(bits);
 
             match prev {
                 None => {
                     info!("initial: paper={} button={}", state.paper, state.button);
+                    // Only paper can be caught up with; a press can't.
+                    let base = baseline.take().map(|b| State {
+                        button: state.button,
+                        ..b
+                    });
+                    if let Some(base) = base.filter(|&b| b != state) {
+                        info!("paper changed since the last run, catching up");
+                        last_activity = Instant::now();
+                        process_transitions(
+                            base,
+                            state,
+                            &mode,
+                            &mut gesture,
+                            &mut batch,
+                            &mut queue,
+                        );
+                    }
                 }
                 Some(p) => {
-                    // Determine what action to take based on transitions.
-                    // We process events to decide on a single action, then execute it.
-                    let action = process_transitions(p, state, &mode, &mut gesture);
-
-                    match action {
-                        Action::Continue => {
-                            // No handler ran. prev = Some(state) at the bottom
-                            // of the loop updates the baseline naturally.
-                            // Do NOT re-read here — it would swallow the ButtonUp
-                            // transition from momentary 0x01 taps.
-                        }
-                        Action::RunHandler(script, args) => {
-                            let arg_refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-                            match run_handler_with_usb(handle, &ctx, &script, &arg_refs) {
-                                Some((h, fresh)) => {
-                                    handle = h;
-                                    prev = Some(fresh);
-                                    thread::sleep(POLL_INTERVAL);
-                                    continue 'poll;
-                                }
-                                None => break 'poll,
-                            }
+                    state = debouncer.filter(p, state, settle(&mode), Instant::now());
+                    // A scanner still busy with the last scan can flap its
+                    // sensors; that settles into the baseline unreported.
+                    let cooling = cooling_down(&mut cooldown, p, state);
+                    if p != state && !cooling {
+                        if idle_sent {
+                            info!("activity after idle, resuming");
+                            idle_sent = false;
                         }
+                        last_activity = Instant::now();
+                    }
+                    // Button events feed the gesture machine; everything that
+                    // needs the handler is queued and run at the top of the
+                    // next cycle. prev = Some(state) below updates the
+                    // baseline. Do NOT re-read here — it would swallow the
+                    // ButtonUp transition from momentary 0x01 taps.
+                    if !cooling {
+                        process_transitions(p, state, &mode, &mut gesture, &mut batch, &mut queue);
                     }
                 }
             }
 
             prev = Some(state);
-
-            // In config mode with a pending gesture, poll faster to hit timeout promptly
-            let sleep = match (&mode, &gesture) {
-                (Mode::ConfigMode(_), GestureState::Released(_, _)) => Duration::from_millis(20),
-                _ => POLL_INTERVAL,
+            let saved = Saved {
+                present: true,
+                state: prev,
             };
-            thread::sleep(sleep);
+            persist(&mode, &mut persisted, saved);
+            if !queue.is_empty() {
+                continue 'poll;
+            }
+
+            let sleep = next_poll(usb, &mode, &gesture, state.button, last_activity);
+            // Wakes early if the lid is closed, so the next poll sees the
+            // device gone.
+            hotplug::wait(watcher.as_ref(), &ctx, sleep);
+        }
+    }
+}
+
+/// The state `state_file` saved when the last run stopped, if any.
+fn load_saved(mode: &Mode) -> Option<Saved> {
+    let Mode::ConfigMode(config) = mode else {
+        return None;
+    };
+    let path = config.state_file.as_deref()?;
+    match statefile::load(path.as_ref()) {
+        Ok(saved) => saved,
+        Err(e) => {
+            warn!("state_file: cannot read {path}: {e} — starting without it");
+            None
+        }
+    }
+}
+
+/// What the first poll is compared with: the saved sensors, or with
+/// nothing saved and `on_start = "emit-current"`, an empty feeder. `None`
+/// takes the scanner as it is found.
+fn start_baseline(mode: &Mode, saved: Option<Saved>) -> Option<State> {
+    match (mode, saved) {
+        (_, Some(saved)) => saved.state,
+        (Mode::ConfigMode(c), None) if c.on_start == OnStart::EmitCurrent => Some(State {
+            paper: false,
+            button: false,
+        }),
+        _ => None,
+    }
+}
+
+/// Write `saved` to `state_file` if it differs from what the file holds
+/// (`persisted`).
+fn persist(mode: &Mode, persisted: &mut Option<Saved>, saved: Saved) {
+    let Mode::ConfigMode(config) = mode else {
+        return;
+    };
+    let Some(path) = config.state_file.as_deref() else {
+        return;
+    };
+    if *persisted == Some(saved) {
+        return;
+    }
+    // Not retried until the next change, so a broken path isn't hammered.
+    *persisted = Some(saved);
+    if let Err(e) = statefile::save(path.as_ref(), saved) {
+        error!("state_file: cannot write {path}: {e}");
+    }
+}
+
+/// Re-read the config file on SIGHUP and swap it in, so profiles can be
+/// edited without a restart (and the device-arrived cycle that comes with
+/// one). A config that fails to load is logged and the old one kept.
+fn reload(mode: &mut Mode, config_path: Option<&str>, dispatcher: &mut Dispatcher) {
+    info!("SIGHUP received");
+    let (Mode::ConfigMode(running), Some(path)) = (mode, config_path) else {
+        warn!("reload: no config file (-c) to reload");
+        return;
+    };
+    let mut config = match config::reload_config(path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("reload: {e} — keeping the running config");
+            return;
+        }
+    };
+    if let Some(global) = GLOBAL.get() {
+        global.apply(&mut config);
+    }
+    config.select_unit(dispatcher.options.serial.as_deref());
+    for problem in config.problems() {
+        warn!("reload: {problem}");
+    }
+    for warning in config.lint() {
+        warn!("reload: {warning}");
+    }
+    let (restart, applied): (Vec<&str>, Vec<&str>) = running
+        .changes(&config)
+        .into_iter()
+        .partition(|key| config::RESTART_KEYS.contains(key));
+    config.keep_startup_settings(running);
+    if config.event_log != running.event_log {
+        if let Err(e) = eventlog::start(config.event_log.as_ref()) {
+            error!("reload: event_log: {e} — keeping the old one");
+            config.event_log.clone_from(&running.event_log);
+        }
+    }
+    dispatcher.reconfigure(&config);
+    **running = config;
+    if applied.is_empty() {
+        info!("reload: {path} loaded, no settings changed");
+    } else {
+        info!("reload: {path} loaded, changed {}", applied.join(", "));
+    }
+    if !restart.is_empty() {
+        warn!(
+            "reload: {} changed — takes effect on restart",
+            restart.join(", ")
+        );
+    }
+}
+
+/// Switch to the `[device."SERIAL"]` settings of the unit that just
+/// arrived, or back to the top-level ones if it has none.
+fn select_unit(mode: &mut Mode, dispatcher: &mut Dispatcher) {
+    let Mode::ConfigMode(config) = mode else {
+        return;
+    };
+    if !config.select_unit(dispatcher.options.serial.as_deref()) {
+        return;
+    }
+    match &config.unit {
+        Some(serial) => info!("config: using the [device.{serial:?}] settings"),
+        None => info!("config: no [device] section for this unit, using the top-level settings"),
+    }
+    dispatcher.reconfigure(config);
+}
+
+/// Handle pause/resume and maintenance requests from SIGUSR1 and the
+/// control socket.
+fn service_control(
+    control: Option<&ControlSocket>,
+    mode: &Mode,
+    dispatcher: &mut Dispatcher,
+    snapshot: &Snapshot,
+) {
+    if signals::take(libc::SIGUSR1) {
+        info!("SIGUSR1 received");
+        dispatcher.set_paused(!dispatcher.paused);
+    }
+    if signals::take(libc::SIGUSR2) {
+        info!("SIGUSR2 received");
+        set_debug_logging(!logging::verbose());
+    }
+    if signals::take(libc::SIGQUIT) {
+        info!("SIGQUIT received");
+        dump_state(&state_json(snapshot, mode, dispatcher), mode);
+    }
+    #[cfg(feature = "dbus")]
+    dbus::service(|| dbus_status(snapshot, mode, dispatcher));
+    for req in control.map(ControlSocket::requests).unwrap_or_default() {
+        let reply = match req.command {
+            Ok(Command::Pause) => {
+                dispatcher.set_paused(true);
+                format!("ok {}", dispatch_status(dispatcher))
+            }
+            Ok(Command::Resume) => {
+                dispatcher.set_paused(false);
+                format!("ok {}", dispatch_status(dispatcher))
+            }
+            Ok(Command::Maintenance(on)) => {
+                set_maintenance(on, "command", mode, dispatcher);
+                format!("ok {}", dispatch_status(dispatcher))
+            }
+            Ok(Command::Status) => {
+                let usb = if snapshot.health.is_degraded() {
+                    ", usb degraded"
+                } else {
+                    ""
+                };
+                let quiet = if dispatcher.quiet_at(schedule::local_minute_of_day()) {
+                    ", quiet hours"
+                } else {
+                    ""
+                };
+                let inhibit = if inhibited(mode, dispatcher).is_some() {
+                    ", inhibited"
+                } else {
+                    ""
+                };
+                format!("ok {}{inhibit}{quiet}{usb}", dispatch_status(dispatcher))
+            }
+            Ok(Command::Inhibit(on)) => {
+                dispatcher.inhibited = on;
+                if on {
+                    // Answered once the event loop has let go of the scanner.
+                    dispatcher.inhibit_replies.push(req);
+                    continue;
+                }
+                match inhibited(mode, dispatcher) {
+                    Some(by) => format!("ok still inhibited ({by})"),
+                    None => "ok uninhibited".into(),
+                }
+            }
+            Ok(Command::Debug(on)) => {
+                set_debug_logging(on);
+                format!("ok debug {}", if on { "on" } else { "off" })
+            }
+            Ok(Command::Dump) => {
+                let json = state_json(snapshot, mode, dispatcher);
+                dump_state(&json, mode);
+                format!("ok {json}")
+            }
+            Err(ref e) => format!("error: {e}"),
+        };
+        req.reply(&reply);
+    }
+}
+
+// ── State dumps ──────────────────────────────────────────────────────
+
+/// The event loop's own state, borrowed for a dump.
+struct Snapshot<'a> {
+    started: Instant,
+    /// The open device, if the scanner is present and claimed.
+    handle: Option<&'a rusb::DeviceHandle<rusb::Context>>,
+    opened_at: Option<Instant>,
+    last_activity: Instant,
+    prev: Option<State>,
+    gesture: &'a GestureState,
+    batch: Option<&'a Batch>,
+    queue: &'a DispatchQueue,
+    polls: u64,
+    failed_polls: u64,
+    health: &'a Health,
+}
+
+/// Everything the daemon knows about itself, as one line of JSON.
+fn state_json(snap: &Snapshot, mode: &Mode, dispatcher: &Dispatcher) -> String {
+    let (mode_name, config) = match mode {
+        Mode::LogOnly => ("log-only", String::from("null")),
+        Mode::Legacy(_) => ("legacy", String::from("null")),
+        Mode::ConfigMode(c) => (
+            "config",
+            json::Object::new()
+                .str("digest", &format!("{:016x}", c.digest))
+                .str("handler", &c.handler.join(" "))
+                .opt_str("ssh", c.handler_ssh.as_deref())
+                .bool("usb_remote", c.usb.remote)
+                .str("model", c.device.name)
+                .finish(),
+        ),
+    };
+    let identity = dispatcher.options.identity.as_ref();
+    let device = match snap.handle {
+        Some(h) => json::Object::new()
+            .bool("present", true)
+            .num("bus", h.device().bus_number())
+            .num("address", h.device().address())
+            .opt_str("serial", dispatcher.options.serial.as_deref())
+            .opt_str("product", identity.map(|i| i.product.as_str()))
+            .opt_str("firmware", identity.map(|i| i.revision.as_str())),
+        None => json::Object::new().bool("present", false),
+    }
+    .raw(
+        "lid_open_secs",
+        &snap
+            .opened_at
+            .map_or("null".into(), |t| t.elapsed().as_secs().to_string()),
+    )
+    .num("idle_secs", snap.last_activity.elapsed().as_secs())
+    .finish();
+    let raw = LAST_RAW.lock().unwrap_or_else(|e| e.into_inner()).map(|r| {
+        let hex: Vec<String> = r.bytes().iter().map(|b| format!("{b:02x}")).collect();
+        hex.join(" ")
+    });
+    let status = match snap.prev {
+        Some(s) => json::Object::new()
+            .bool("paper", s.paper)
+            .bool("button", s.button),
+        None => json::Object::new(),
+    }
+    .opt_str("raw", raw.as_deref())
+    .finish();
+    let (gesture, presses) = match *snap.gesture {
+        GestureState::Idle => ("idle", 0),
+        GestureState::Pressed(n, _) => ("pressed", n),
+        GestureState::Released(n, _) => ("released", n),
+        GestureState::Held(n, _) => ("held", n),
+    };
+    let gesture = json::Object::new()
+        .str("state", gesture)
+        .num("presses", presses)
+        .finish();
+    let batch = snap.batch.map_or("null".into(), |b| {
+        json::Object::new()
+            .str("id", &b.id)
+            .num("pages", b.pages)
+            .finish()
+    });
+    let queue = json::array(snap.queue.iter().map(|p| {
+        json::Object::new()
+            .num("seq", p.seq)
+            .raw("args", &json::array(p.args.iter().map(|a| json::string(a))))
+            .finish()
+    }));
+    let now = Instant::now();
+    let snoozed = dispatcher
+        .snoozed
+        .iter()
+        .filter(|(_, &until)| until > now)
+        .fold(json::Object::new(), |o, (event, until)| {
+            o.num(event, (*until - now).as_secs())
+        })
+        .finish();
+    let quiet_hours = dispatcher.quiet.map_or("null".into(), |w| {
+        json::Object::new()
+            .str("window", &w.to_string())
+            .bool(
+                "active",
+                dispatcher.quiet_at(schedule::local_minute_of_day()),
+            )
+            .bool("lifted", dispatcher.quiet_lifted)
+            .finish()
+    });
+    let usb_health = snap
+        .health
+        .totals()
+        .fold(
+            json::Object::new()
+                .bool("degraded", snap.health.is_degraded())
+                .num("recent_errors", snap.health.recent())
+                .num("window_secs", snap.health.window().as_secs()),
+            |o, (kind, n)| o.num(kind, n),
+        )
+        .finish();
+    let counters = json::Object::new()
+        .num("events", current_seq())
+        .num("polls", snap.polls)
+        .num("failed_polls", snap.failed_polls)
+        .num("handler_runs", dispatcher.runs)
+        .num("handler_failures", dispatcher.failures)
+        .num("handler_rate_limited", dispatcher.gate.dropped())
+        .finish();
+    host::tag(json::Object::new())
+        .num("pid", std::process::id())
+        .num("uptime_secs", snap.started.elapsed().as_secs())
+        .str("mode", mode_name)
+        .raw("config", &config)
+        .raw("device", &device)
+        .raw("status", &status)
+        .raw("gesture", &gesture)
+        .raw("batch", &batch)
+        .str("dispatch", dispatch_status(dispatcher))
+        .opt_str("inhibited", inhibited(mode, dispatcher))
+        .raw("quiet_hours", &quiet_hours)
+        .raw("queue", &queue)
+        .raw("snoozed", &snoozed)
+        .raw("usb_health", &usb_health)
+        .raw("counters", &counters)
+        .bool("debug", logging::verbose())
+        .finish()
+}
+
+/// The reply to the D-Bus `GetStatus` method: the parts of the state dump a
+/// desktop tool is likely to want.
+#[cfg(feature = "dbus")]
+fn dbus_status(
+    snap: &Snapshot,
+    mode: &Mode,
+    dispatcher: &Dispatcher,
+) -> Vec<(&'static str, dbus::Value)> {
+    use dbus::Value;
+    let mut status = vec![
+        ("version", Value::Str(env!("CARGO_PKG_VERSION").into())),
+        ("present", Value::Bool(snap.handle.is_some())),
+        ("dispatch", Value::Str(dispatch_status(dispatcher).into())),
+        ("usb_degraded", Value::Bool(snap.health.is_degraded())),
+        ("seq", Value::U64(current_seq())),
+    ];
+    if let Some(t) = snap.opened_at {
+        status.push(("lid_open_secs", Value::U64(t.elapsed().as_secs())));
+    }
+    if let Some(s) = snap.prev {
+        status.push(("paper", Value::Bool(s.paper)));
+        status.push(("button", Value::Bool(s.button)));
+    }
+    if let Some(serial) = &dispatcher.options.serial {
+        status.push(("serial", Value::Str(serial.clone())));
+    }
+    if let Mode::ConfigMode(c) = mode {
+        status.push(("model", Value::Str(c.device.name.into())));
+    }
+    if let Some(b) = snap.batch {
+        status.push(("batch", Value::Str(b.id.clone())));
+        status.push(("batch_pages", Value::U64(u64::from(b.pages))));
+    }
+    status
+}
+
+/// Write a state dump to `state_dump`, or to the log if that isn't set.
+fn dump_state(json: &str, mode: &Mode) {
+    let path = match mode {
+        Mode::ConfigMode(c) => c.state_dump.as_deref(),
+        _ => None,
+    };
+    match path {
+        Some(path) => match std::fs::write(path, format!("{json}\n")) {
+            Ok(()) => info!("state dumped to {path}"),
+            Err(e) => {
+                error!("cannot write state dump to {path}: {e}");
+                info!("state: {json}");
+            }
+        },
+        None => info!("state: {json}"),
+    }
+}
+
+/// Announce a change in USB health in the log and to the handler.
+fn report_health(change: Change, health: &Health, mode: &Mode, queue: &mut DispatchQueue) {
+    let seq = next_seq();
+    let window = health.window().as_secs();
+    let args = match change {
+        Change::Degraded => {
+            let tag = DaemonEvent::UsbDegraded.tag();
+            let errors = health.recent().to_string();
+            log_event(
+                mode,
+                seq,
+                &[tag, &errors],
+                format_args!(
+                    "#{seq} {tag} ({} errors in {window}s: {})",
+                    health.recent(),
+                    health.summary()
+                ),
+            );
+            warn!(
+                "usb: connection degraded, resetting the device — if this keeps \
+                 happening, stop the daemon and run `s1500d doctor`"
+            );
+            vec![tag.into(), errors]
+        }
+        Change::Recovered => {
+            let tag = DaemonEvent::UsbRecovered.tag();
+            log_event(
+                mode,
+                seq,
+                &[tag],
+                format_args!("#{seq} {tag} (no errors in {window}s)"),
+            );
+            vec![tag.into()]
+        }
+    };
+    match mode {
+        Mode::ConfigMode(c) => queue.push(c.handler.clone(), args, seq),
+        Mode::Legacy(script) => queue.push(vec![script.clone()], args, seq),
+        Mode::LogOnly => {}
+    }
+}
+
+/// The status bits `[watch]` names; only a config sets any.
+pub(crate) fn watches(mode: &Mode) -> &[config::Watch] {
+    match mode {
+        Mode::ConfigMode(c) => &c.watches,
+        _ => &[],
+    }
+}
+
+/// Announce `[watch]` bits that changed between two polls in the log and
+/// to the handler.
+pub(crate) fn report_watches(prev: u64, curr: u64, mode: &Mode, queue: &mut DispatchQueue) {
+    let Mode::ConfigMode(c) = mode else {
+        return;
+    };
+    for (w, on) in watch::changes(&c.watches, prev, curr) {
+        let event = w.event(on);
+        let seq = next_seq();
+        log_event(
+            mode,
+            seq,
+            &[&event],
+            format_args!("#{seq} {event} (byte {} bit {})", w.byte, w.bit),
+        );
+        queue.push(c.handler.clone(), vec![event], seq);
+    }
+}
+
+/// Announce failed handler runs (`on_handler_failure = "event"`) in the log
+/// and to the handler.
+pub(crate) fn report_handler_failures(
+    mode: &Mode,
+    dispatcher: &mut Dispatcher,
+    queue: &mut DispatchQueue,
+) {
+    for (failed_seq, event, failure) in std::mem::take(&mut dispatcher.failed) {
+        let tag = DaemonEvent::HandlerFailed.tag();
+        let reason = failure.to_string();
+        let seq = next_seq();
+        log_event(
+            mode,
+            seq,
+            &[tag, &event, &reason],
+            format_args!("#{seq} {tag} {event} {reason} (handler for #{failed_seq})"),
+        );
+        if let Mode::ConfigMode(c) = mode {
+            queue.push(c.handler.clone(), vec![tag.into(), event, reason], seq);
+        }
+    }
+}
+
+/// Switch runtime debug logging, announcing the change at a level that is
+/// visible either way.
+fn set_debug_logging(on: bool) {
+    if on {
+        logging::set_verbose(true);
+        info!("debug logging on");
+    } else {
+        info!("debug logging off — back to configured level");
+        logging::set_verbose(false);
+    }
+}
+
+/// Why the daemon is leaving the scanner alone, if it is: `s1500ctl
+/// inhibit`, or the `inhibit_file` existing.
+fn inhibited(mode: &Mode, dispatcher: &Dispatcher) -> Option<&'static str> {
+    let file = match mode {
+        Mode::ConfigMode(c) => c.inhibit_file.as_deref(),
+        _ => None,
+    };
+    if dispatcher.inhibited {
+        Some("s1500ctl inhibit")
+    } else if file.is_some_and(|f| std::path::Path::new(f).exists()) {
+        Some("inhibit_file")
+    } else {
+        None
+    }
+}
+
+/// Answer the `inhibit` requests waiting for the scanner to be released.
+fn reply_inhibited(dispatcher: &mut Dispatcher) {
+    for req in dispatcher.inhibit_replies.drain(..) {
+        req.reply("ok inhibited");
+    }
+}
+
+/// One-word dispatch state for `s1500ctl status`.
+fn dispatch_status(dispatcher: &Dispatcher) -> &'static str {
+    match (dispatcher.maintenance, dispatcher.paused) {
+        (true, true) => "maintenance, paused",
+        (true, false) => "maintenance",
+        (false, true) => "paused",
+        (false, false) => "running",
+    }
+}
+
+/// Enter or leave maintenance mode, notifying the handler with
+/// `maintenance-entered <source>` / `maintenance-exited <source>`.
+///
+/// The notification waits in the dispatcher until [`report_notices`] hands
+/// it on, so it runs with the scanner released like any other handler run.
+fn set_maintenance(on: bool, source: &str, mode: &Mode, dispatcher: &mut Dispatcher) {
+    if on == dispatcher.maintenance {
+        return;
+    }
+    let ev = if on {
+        DaemonEvent::MaintenanceEntered
+    } else {
+        DaemonEvent::MaintenanceExited
+    };
+    let seq = next_seq();
+    log_event(
+        mode,
+        seq,
+        &[ev.tag(), source],
+        format_args!("#{seq} {} ({source})", ev.tag()),
+    );
+    dispatcher
+        .notices
+        .push((seq, vec![ev.tag().into(), source.into()]));
+    dispatcher.maintenance = on;
+}
+
+/// Hand pending maintenance notifications to the handler: through `queue`
+/// while the scanner is held, so it is released for them, or at once when
+/// nothing is held (`None`).
+pub(crate) fn report_notices(
+    mode: &Mode,
+    dispatcher: &mut Dispatcher,
+    mut queue: Option<&mut DispatchQueue>,
+) {
+    for (seq, args) in std::mem::take(&mut dispatcher.notices) {
+        let command = match mode {
+            Mode::LogOnly => continue,
+            Mode::Legacy(script) => vec![script.clone()],
+            Mode::ConfigMode(config) => config.handler.clone(),
+        };
+        match queue.as_deref_mut() {
+            Some(queue) => queue.push(command, args, seq),
+            None => {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                dispatcher.dispatch(&command, &args, seq);
+            }
         }
     }
 }
 
 /// Check if a gesture timeout has expired and return the action to take.
-fn check_gesture_timeout(gesture: &GestureState, mode: &Mode) -> Option<Action> {
+/// `paper` is whether the feeder holds paper; without it a gesture mapped
+/// in `[profiles_no_paper]` picks that profile instead.
+pub(crate) fn check_gesture_timeout(
+    gesture: &GestureState,
+    mode: &Mode,
+    paper: bool,
+) -> Option<Action> {
     let config = match mode {
         Mode::ConfigMode(c) => c,
         _ => return None,
     };
-    let (count, ts) = match gesture {
-        GestureState::Released(count, ts) => (*count, *ts),
+    let (gesture, held) = match *gesture {
+        GestureState::Released(n, ts) if ts.elapsed() >= config.gesture_timeout() => {
+            (Gesture::Presses(n), None)
+        }
+        GestureState::Held(n, held) => (Gesture::Hold(n), Some(held)),
+        // Held past the longest tier: no use waiting for the release.
+        GestureState::Pressed(1, since)
+            if config.longest_hold().is_some_and(|d| since.elapsed() >= d) =>
+        {
+            (Gesture::Hold(1), Some(since.elapsed()))
+        }
         _ => return None,
     };
-    if ts.elapsed() < config.gesture_timeout() {
-        return None;
+    if let Gesture::Presses(count) = gesture {
+        if config.maintenance_presses == Some(count) {
+            debug!("gesture: {count}x press toggles maintenance");
+            return Some(Action::ToggleMaintenance);
+        }
+    }
+    if config.batch.as_ref().is_some_and(|b| b.gesture == gesture) {
+        debug!("gesture: {gesture} toggles batch");
+        return Some(Action::ToggleBatch);
+    }
+    if config
+        .quiet_hours
+        .as_ref()
+        .is_some_and(|q| q.override_gesture == Some(gesture))
+    {
+        debug!("gesture: {gesture} lifts quiet hours");
+        return Some(Action::LiftQuiet);
     }
 
-    if let Some(profile) = config.profiles.get(&count) {
-        info!("scan {} ({}x press)", profile, count);
+    // A single-press hold picks from [long_press_profiles] by how long it was.
+    let tier = held.filter(|_| gesture == Gesture::Hold(1) && config.longest_hold().is_some());
+    // With the feeder empty, [profiles_no_paper] comes first.
+    let no_paper = if paper {
+        None
+    } else {
+        config.profiles_no_paper.get(&gesture)
+    };
+    let profile = match tier {
+        Some(held) => config.hold_profile(held),
+        None => no_paper.or_else(|| config.profile_for(gesture, schedule::local_minute_of_day())),
+    };
+    if let Some(profile) = profile {
+        let seq = next_seq();
+        let args = ["scan", profile.as_str()];
+        let how = match tier {
+            Some(held) => format!("{gesture}, {:.1}s", held.as_secs_f64()),
+            None => gesture.to_string(),
+        };
+        log_event_line(
+            mode,
+            seq,
+            &args,
+            Some(gesture),
+            format_args!("#{seq} scan {profile} ({how})"),
+        );
+        publish(seq, &args, Some(gesture));
+        if config.scan_profiles.contains_key(profile) {
+            return Some(Action::Scan(profile.clone(), seq));
+        }
         Some(Action::RunHandler(
             config.handler.clone(),
             vec!["scan".into(), profile.clone()],
+            seq,
         ))
     } else {
-        info!("{}x press — no profile mapped, ignoring", count);
+        info!("{gesture} — no profile mapped, ignoring");
         Some(Action::Continue)
     }
 }
 
-/// Process state transitions and return what action to take.
+/// Carry out the action a completed gesture calls for, returning the scan
+/// to run if it is one.
+pub(crate) fn take_action(
+    action: Action,
+    completed: Option<Gesture>,
+    mode: &Mode,
+    dispatcher: &mut Dispatcher,
+    batch: &mut Option<Batch>,
+    queue: &mut DispatchQueue,
+) -> Option<Job> {
+    match action {
+        Action::RunHandler(command, args, seq) => queue.push_gesture(command, args, seq, completed),
+        Action::Scan(profile, seq) => return Some(Job::Scan(profile, seq)),
+        Action::ToggleMaintenance => {
+            let on = !dispatcher.maintenance;
+            set_maintenance(on, "gesture", mode, dispatcher);
+        }
+        Action::ToggleBatch => {
+            let announce = match batch {
+                None => open_batch(batch, mode),
+                Some(_) => close_batch(batch, "gesture", mode),
+            };
+            if let Some(Action::RunHandler(command, args, seq)) = announce {
+                queue.push(command, args, seq);
+            }
+        }
+        Action::LiftQuiet => dispatcher.lift_quiet(),
+        Action::Continue => {}
+    }
+    None
+}
+
+/// Check whether the lid has been open long enough for the next reminder.
+///
+/// Reminders repeat every `lid_reminder_minutes` for as long as the device
+/// stays present; `sent` is how many have already fired this session.
+fn check_lid_reminder(opened_at: Instant, sent: u64, mode: &Mode) -> Option<Action> {
+    let config = match mode {
+        Mode::ConfigMode(c) => c,
+        _ => return None,
+    };
+    let due = config.lid_reminder()? * u32::try_from(sent + 1).ok()?;
+    if opened_at.elapsed() < due {
+        return None;
+    }
+
+    let minutes = (due.as_secs() / 60).to_string();
+    let tag = DaemonEvent::LidOpen.tag();
+    let seq = next_seq();
+    log_event(
+        mode,
+        seq,
+        &[tag, &minutes],
+        format_args!("#{seq} {tag} ({minutes}m)"),
+    );
+    Some(Action::RunHandler(
+        config.handler.clone(),
+        vec![tag.into(), minutes],
+        seq,
+    ))
+}
+
+/// How long paper and button changes must last to count; only a config
+/// sets any.
+pub(crate) fn settle(mode: &Mode) -> Settle {
+    match mode {
+        Mode::ConfigMode(c) => c.settle(),
+        _ => Settle::default(),
+    }
+}
+
+/// Check whether the scanner has sat untouched for `idle_minutes`.
+///
+/// Fires once per idle spell; the caller re-arms it on the next button or
+/// paper activity. The S1500 has no known power-save command, so this only
+/// tells the handler, which can prompt someone to close the lid.
+fn check_idle(last_activity: Instant, mode: &Mode) -> Option<Action> {
+    let config = match mode {
+        Mode::ConfigMode(c) => c,
+        _ => return None,
+    };
+    let idle = config.idle()?;
+    if last_activity.elapsed() < idle {
+        return None;
+    }
+
+    let minutes = (idle.as_secs() / 60).to_string();
+    let tag = DaemonEvent::Idle.tag();
+    let seq = next_seq();
+    log_event(
+        mode,
+        seq,
+        &[tag, &minutes],
+        format_args!("#{seq} {tag} ({minutes}m without activity)"),
+    );
+    Some(Action::RunHandler(
+        config.handler.clone(),
+        vec![tag.into(), minutes],
+        seq,
+    ))
+}
+
+/// Process state transitions, queueing any handler runs they call for.
 ///
 /// For config mode, button events update the gesture state machine (no handler yet).
-/// For legacy mode, the first event triggers handler dispatch.
-/// For log-only, events are logged and Action::Continue is returned.
-fn process_transitions(
+/// For legacy mode, every event is queued for handler dispatch.
+/// For log-only, events are only logged.
+pub(crate) fn process_transitions(
     prev: State,
     curr: State,
     mode: &Mode,
     gesture: &mut GestureState,
-) -> Action {
+    batch: &mut Option<Batch>,
+    queue: &mut DispatchQueue,
+) {
     for ev in transitions(prev, curr) {
+        // The event a transition turns into, if any, keeps its number.
+        let seq = next_seq();
+        publish_transition(ev, seq);
         match mode {
             Mode::ConfigMode(ref config) => {
+                // In a batch, each sheet loaded is a page of the batch.
+                if let Some(b) = batch.as_mut() {
+                    b.last_activity = Instant::now();
+                    if ev == Event::PaperIn {
+                        b.pages += 1;
+                        let tag = DaemonEvent::BatchPage.tag();
+                        let page = b.pages.to_string();
+                        log_event(
+                            mode,
+                            seq,
+                            &[tag, &b.id, &page],
+                            format_args!("#{seq} {tag} {} (page {page})", b.id),
+                        );
+                        let args = vec![tag.into(), b.id.clone(), page];
+                        queue.push(config.handler.clone(), args, seq);
+                        continue;
+                    }
+                }
                 match ev {
                     Event::ButtonDown => {
+                        debug!("#{seq} {}", ev.tag());
                         *gesture = match *gesture {
-                            GestureState::Idle => {
+                            GestureState::Idle | GestureState::Held(..) => {
                                 debug!("gesture: press 1");
-                                GestureState::Pressed(1)
+                                GestureState::Pressed(1, Instant::now())
                             }
                             GestureState::Released(n, _) => {
                                 debug!("gesture: press {}", n + 1);
-                                GestureState::Pressed(n + 1)
+                                GestureState::Pressed(n + 1, Instant::now())
                             }
                             // Shouldn't happen (double down without up)
-                            GestureState::Pressed(n) => GestureState::Pressed(n),
+                            GestureState::Pressed(n, t) => GestureState::Pressed(n, t),
                         };
                     }
                     Event::ButtonUp => {
+                        debug!("#{seq} {}", ev.tag());
                         *gesture = match *gesture {
-                            GestureState::Pressed(n) => {
+                            GestureState::Pressed(n, t)
+                                if config.long_press().is_some_and(|d| t.elapsed() >= d) =>
+                            {
+                                debug!("gesture: release {n} after hold");
+                                GestureState::Held(n, t.elapsed())
+                            }
+                            GestureState::Pressed(n, _) => {
                                 debug!("gesture: release {n}, waiting...");
                                 GestureState::Released(n, Instant::now())
                             }
@@ -514,306 +2513,462 @@ fn process_transitions(
                     }
                     // Non-button events: fire handler immediately
                     _ => {
-                        info!("{}", ev.tag());
-                        return Action::RunHandler(config.handler.clone(), vec![ev.tag().into()]);
+                        log_event(mode, seq, &[ev.tag()], format_args!("#{seq} {}", ev.tag()));
+                        queue.push(config.handler.clone(), vec![ev.tag().into()], seq);
                     }
                 }
             }
             Mode::Legacy(ref script) => {
-                info!("{}", ev.tag());
-                return Action::RunHandler(script.clone(), vec![ev.tag().into()]);
+                log_event(mode, seq, &[ev.tag()], format_args!("#{seq} {}", ev.tag()));
+                queue.push(vec![script.clone()], vec![ev.tag().into()], seq);
             }
             Mode::LogOnly => {
-                info!("{}", ev.tag());
+                log_event(mode, seq, &[ev.tag()], format_args!("#{seq} {}", ev.tag()));
             }
         }
     }
-    Action::Continue
 }
 
-/// Run the handler for lifecycle events (device-arrived/left) that don't need USB release.
-fn emit_handler(mode: &Mode, args: &[&str]) {
+/// Run the handler at once for lifecycle events, without releasing USB:
+/// `device-left` and `permission-denied` come while nothing is held, and
+/// `device-arrived` runs before the first poll with the scanner claimed.
+fn emit_handler(mode: &Mode, dispatcher: &mut Dispatcher, args: &[&str], seq: u64) {
     match mode {
         Mode::LogOnly => {}
-        Mode::Legacy(script) => run_handler(script, args),
-        Mode::ConfigMode(config) => run_handler(&config.handler, args),
+        Mode::Legacy(script) => dispatcher.dispatch(std::slice::from_ref(script), args, seq),
+        Mode::ConfigMode(config) => dispatcher.dispatch(&config.handler, args, seq),
     }
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-
-    // Handle --help/--version/--doctor before logger init (they don't need it).
-    match args.get(1).map(String::as_str) {
-        Some("--help" | "-h") => {
-            print_usage();
-            std::process::exit(0);
-        }
-        Some("--version" | "-V") => {
-            println!("s1500d {}", env!("CARGO_PKG_VERSION"));
-            std::process::exit(0);
-        }
-        Some("--doctor") => {
-            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-                .format_timestamp_secs()
-                .init();
-            doctor();
-            return;
-        }
-        _ => {}
+    let cli = cli::Cli::parse();
+    let global = GLOBAL.get_or_init(|| cli.global.clone());
+
+    // Every subcommand finds the scanner by the config's `[device]` and
+    // `[usb]`, or `--device` alone.
+    let config = global.config.as_deref().map(load_config);
+    if let Some(config) = &config {
+        let _ = USB.set(config.usb.clone());
+    }
+    if let Some(model) = config.as_ref().map(|c| c.device).or(global.device) {
+        let _ = MODEL.set(model);
     }
 
-    // In config mode, load config first so log_level can feed the logger.
-    let config = if args.get(1).map(String::as_str) == Some("-c") {
-        let config_path = args.get(2).unwrap_or_else(|| {
-            eprintln!("s1500d: -c requires a config file path");
-            std::process::exit(1);
-        });
-        Some(load_config(config_path))
-    } else {
-        None
+    // The one-shot subcommands log plainly, and only warnings unless asked.
+    let level = global.log_level.as_deref();
+    let run = match cli.command {
+        None => cli.run,
+        Some(cli::Command::Run(run)) => run,
+        Some(cli::Command::Doctor(opts)) => {
+            init_plain_logging(global, "info");
+            doctor(&opts, global.config.as_deref());
+            return;
+        }
+        Some(cli::Command::Status(opts)) => {
+            init_plain_logging(global, "warn");
+            status::status(&opts)
+        }
+        Some(cli::Command::Wait(opts)) => {
+            init_plain_logging(global, "warn");
+            wait::wait(
+                &opts,
+                config.as_ref().map_or_else(Settle::default, Config::settle),
+            )
+        }
+        Some(cli::Command::Bench(opts)) => {
+            init_plain_logging(global, "warn");
+            bench::bench(&opts);
+            return;
+        }
+        Some(cli::Command::CheckConfig { strict }) => {
+            let (Some(path), Some(config)) = (global.config.as_deref(), &config) else {
+                cli::Cli::command()
+                    .error(
+                        clap::error::ErrorKind::MissingRequiredArgument,
+                        "check-config needs -c CONFIG.toml",
+                    )
+                    .exit()
+            };
+            // Loading has already printed the findings, or exited.
+            let problems = config.problems().len();
+            let warnings = config.lint().len();
+            if problems > 0 || (strict && warnings > 0) {
+                eprintln!("{path}: {problems} problem(s), {warnings} warning(s)");
+                std::process::exit(error::EXIT_CONFIG);
+            }
+            println!("{path}: ok");
+            return;
+        }
+        Some(cli::Command::AnalyzeCapture { file }) => {
+            match capture::analyze_capture(&file) {
+                Ok(report) => print!("{report}"),
+                Err(e) => {
+                    eprintln!("s1500d: {e}");
+                    std::process::exit(error::EXIT_FAILURE);
+                }
+            }
+            return;
+        }
     };
 
-    // RUST_LOG from environment wins; otherwise use config or default to "info".
-    let log_filter = std::env::var("RUST_LOG")
-        .unwrap_or_else(|_| config.as_ref().map_or("info", |c| &c.log_level).to_string());
+    if let Some(h) = run.handler.as_deref().filter(|h| !cli::handler_exists(h)) {
+        cli::Cli::command()
+            .error(
+                clap::error::ErrorKind::InvalidSubcommand,
+                format!("no subcommand or handler named '{h}'"),
+            )
+            .exit()
+    }
+    if run.tui && !cfg!(feature = "tui") {
+        eprintln!("s1500d: --tui needs a build with the tui feature (cargo build --features tui)");
+        std::process::exit(error::EXIT_USAGE);
+    }
 
-    env_logger::Builder::new()
-        .parse_filters(&log_filter)
-        .format_timestamp_secs()
-        .init();
+    // `--simulate [SCRIPT]`, or `--replay FILE` of a `--record` file, in
+    // place of the scanner.
+    let script = run.simulate.as_deref().map(|source| {
+        let text = match source {
+            "-" => std::io::read_to_string(std::io::stdin()),
+            path => std::fs::read_to_string(path),
+        };
+        let name = if source == "-" { "stdin" } else { source };
+        text.map_err(|e| format!("cannot read {name}: {e}"))
+            .and_then(|text| simulate::parse(&text).map_err(|e| format!("{name}: {e}")))
+            .unwrap_or_else(|e| {
+                eprintln!("s1500d: --simulate: {e}");
+                std::process::exit(error::EXIT_FAILURE);
+            })
+    });
+    let script = script.or_else(|| {
+        let path = run.replay.as_deref()?;
+        let steps = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {path}: {e}"))
+            .and_then(|text| record::steps(&text).map_err(|e| format!("{path}: {e}")));
+        Some(steps.unwrap_or_else(|e| {
+            eprintln!("s1500d: --replay: {e}");
+            std::process::exit(error::EXIT_FAILURE);
+        }))
+    });
+    let recorder = run.record.as_deref().map(|path| {
+        Recorder::open(path).unwrap_or_else(|e| {
+            eprintln!("s1500d: --record: cannot open {path}: {e}");
+            std::process::exit(error::EXIT_IO);
+        })
+    });
+
+    // --log-level wins, then RUST_LOG, then the config (which --log-level
+    // has already been put over), then "info".
+    let log_filter = match level {
+        Some(level) => level.to_string(),
+        None => std::env::var("RUST_LOG")
+            .unwrap_or_else(|_| config.as_ref().map_or("info", |c| &c.log_level).to_string()),
+    };
 
-    match args.get(1).map(String::as_str) {
-        Some("-c") => {
-            let config = config.unwrap();
-            let config_path = args.get(2).unwrap();
+    let timestamps = config
+        .as_ref()
+        .map(|c| c.log_timestamps.clone())
+        .unwrap_or_default();
+    // Start the dashboard first, so the logger sees its output pipe.
+    #[cfg(feature = "tui")]
+    if run.tui {
+        if let Err(e) = tui::start() {
+            eprintln!("s1500d: cannot start dashboard: {e}");
+            std::process::exit(error::EXIT_IO);
+        }
+    }
+    let format = match &config {
+        Some(c) => c.log_format,
+        None if global.log_json => logging::LogFormat::Json,
+        None => logging::LogFormat::Text,
+    };
+    logging::init(&log_filter, timestamps, format);
+    notify::init();
+
+    match (config, run.handler) {
+        (Some(config), _) => {
+            let config_path = global.config.as_deref().unwrap();
+            let host = config
+                .handler_ssh
+                .as_ref()
+                .map_or(String::new(), |h| format!(" (on {h} via ssh)"));
+            let handler = match config.handler.join(" ") {
+                h if h.is_empty() => "none".to_string(),
+                h => h,
+            };
             info!(
-                "s1500d starting — config: {config_path}, handler: {}, profiles: {:?}",
-                config.handler, config.profiles
+                "s1500d starting — config: {config_path}, handler: {handler}{host}, profiles: {:?}",
+                config.profiles
+            );
+            if config.device != Model::S1500 {
+                info!(
+                    "device: {} ({:04x}:{:04x})",
+                    config.device.name, config.device.vid, config.device.pid
+                );
+            }
+            if config.usb.remote {
+                info!(
+                    "usb: remote transport — {}ms timeouts, polling every {}ms",
+                    config.usb.timeout.as_millis(),
+                    config.usb.poll_interval.as_millis()
+                );
+            }
+            start(
+                Mode::ConfigMode(Box::new(config)),
+                Some(config_path),
+                script,
+                recorder,
             );
-            run(Mode::ConfigMode(config));
         }
-        Some(h) => {
+        (None, Some(h)) => {
             info!("s1500d starting — handler: {h} (legacy mode)");
-            run(Mode::Legacy(h.to_string()));
+            start(Mode::Legacy(h), None, script, recorder);
         }
-        None => {
+        (None, None) => {
             info!("s1500d starting — no handler (log only)");
-            run(Mode::LogOnly);
+            start(Mode::LogOnly, None, script, recorder);
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
-
-    // ── State::from_response ─────────────────────────────────────
-
-    #[test]
-    fn state_idle_scanner() {
-        // byte 3 = 0x80 (hopper empty), byte 4 = 0x00 (button not pressed)
-        let buf = [0, 0, 0, 0x80, 0x00, 0, 0, 0, 0, 0, 0, 0];
-        let s = State::from_response(&buf).unwrap();
-        assert!(!s.paper);
-        assert!(!s.button);
-    }
-
-    #[test]
-    fn state_paper_present() {
-        // byte 3 = 0x00 (bit 7 clear = paper present)
-        let buf = [0, 0, 0, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0];
-        let s = State::from_response(&buf).unwrap();
-        assert!(s.paper);
-        assert!(!s.button);
-    }
-
-    #[test]
-    fn state_button_held() {
-        // byte 4 = 0x20 (bit 5 = button held)
-        let buf = [0, 0, 0, 0x80, 0x20, 0, 0, 0, 0, 0, 0, 0];
-        let s = State::from_response(&buf).unwrap();
-        assert!(!s.paper);
-        assert!(s.button);
-    }
-
-    #[test]
-    fn state_button_momentary_tap() {
-        // byte 4 = 0x01 (bit 0 = momentary tap)
-        let buf = [0, 0, 0, 0x80, 0x01, 0, 0, 0, 0, 0, 0, 0];
-        let s = State::from_response(&buf).unwrap();
-        assert!(s.button);
-    }
-
-    #[test]
-    fn state_button_both_bits() {
-        // byte 4 = 0x21 (both button bits set)
-        let buf = [0, 0, 0, 0x80, 0x21, 0, 0, 0, 0, 0, 0, 0];
-        let s = State::from_response(&buf).unwrap();
-        assert!(s.button);
-    }
-
-    #[test]
-    fn state_paper_and_button() {
-        // byte 3 = 0x00 (paper present), byte 4 = 0x20 (button held)
-        let buf = [0, 0, 0, 0x00, 0x20, 0, 0, 0, 0, 0, 0, 0];
-        let s = State::from_response(&buf).unwrap();
-        assert!(s.paper);
-        assert!(s.button);
-    }
-
-    #[test]
-    fn state_short_buffer() {
-        assert!(State::from_response(&[0, 0]).is_none());
-    }
-
-    #[test]
-    fn state_empty_buffer() {
-        assert!(State::from_response(&[]).is_none());
-    }
-
-    #[test]
-    fn state_other_bits_ignored() {
-        // byte 3 has non-0x80 bits set but bit 7 is set → no paper
-        let buf = [0, 0, 0, 0xFF, 0x00, 0, 0, 0, 0, 0, 0, 0];
-        let s = State::from_response(&buf).unwrap();
-        assert!(!s.paper);
-
-        // byte 4 has bits set but not 0x20 or 0x01 → no button
-        let buf = [0, 0, 0, 0x80, 0xDE, 0, 0, 0, 0, 0, 0, 0];
-        let s = State::from_response(&buf).unwrap();
-        assert!(!s.button);
-    }
-
-    // ── envelope ─────────────────────────────────────────────────
-
-    #[test]
-    fn envelope_wraps_cdb() {
-        let cdb = [0xC2, 0, 0, 0, 0, 0, 0, 0, 0x0C, 0];
-        let env = envelope(&cdb);
-        assert_eq!(env[0], 0x43);
-        assert_eq!(&env[1..19], &[0u8; 18]);
-        assert_eq!(&env[19..29], &cdb);
-        assert_eq!(&env[29..31], &[0, 0]);
+/// Log to stderr for the subcommands other than `run`: `--log-level`,
+/// else `RUST_LOG`, else `default`; as JSON with `--log-json`.
+fn init_plain_logging(global: &cli::Global, default: &str) {
+    let mut builder = match global.log_level.as_deref() {
+        Some(level) => {
+            let mut builder = env_logger::Builder::new();
+            builder.parse_filters(level);
+            builder
+        }
+        None => {
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default))
+        }
+    };
+    if global.log_json {
+        builder.format(|buf, record| {
+            use std::io::Write;
+            let line = logging::json_line(logging::timestamp().as_deref(), record);
+            writeln!(buf, "{line}")
+        });
+    } else {
+        builder.format_timestamp_secs();
     }
+    builder.init();
+}
 
-    #[test]
-    fn envelope_short_cdb() {
-        let cdb = [0xAA];
-        let env = envelope(&cdb);
-        assert_eq!(env[0], 0x43);
-        assert_eq!(env[19], 0xAA);
-        assert_eq!(&env[20..31], &[0u8; 11]);
+/// Load a config file, with `--device` and `--log-level` put over it.
+pub(crate) fn load_config(path: &str) -> Config {
+    let mut config = config::load_config(path);
+    if let Some(global) = GLOBAL.get() {
+        global.apply(&mut config);
     }
+    config
+}
 
-    // ── transitions ──────────────────────────────────────────────
-
-    #[test]
-    fn transitions_no_change() {
-        let s = State {
-            paper: false,
-            button: false,
-        };
-        let events: Vec<_> = transitions(s, s).collect();
-        assert!(events.is_empty());
-    }
+/// Run the daemon, or with `--simulate` or `--replay`, play the script in
+/// place of the scanner and exit.
+fn start(
+    mode: Mode,
+    config_path: Option<&str>,
+    script: Option<Vec<simulate::Step>>,
+    recorder: Option<Recorder>,
+) -> ! {
+    let Some(steps) = script else {
+        run(mode, config_path, recorder)
+    };
+    info!("simulate: {} step(s), no scanner", steps.len());
+    simulate::run(&mode, &steps);
+    std::process::exit(0);
+}
 
-    #[test]
-    fn transitions_paper_in() {
-        let prev = State {
-            paper: false,
-            button: false,
-        };
-        let curr = State {
-            paper: true,
-            button: false,
-        };
-        let events: Vec<_> = transitions(prev, curr).collect();
-        assert_eq!(events, vec![Event::PaperIn]);
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[test]
-    fn transitions_paper_out() {
-        let prev = State {
-            paper: true,
-            button: false,
-        };
-        let curr = State {
-            paper: false,
-            button: false,
-        };
-        let events: Vec<_> = transitions(prev, curr).collect();
-        assert_eq!(events, vec![Event::PaperOut]);
+    fn event_json_fields() {
+        let json = event_json(3, &["paper-in"], None, None);
+        assert!(json.starts_with(r#"{"seq":3,"event":"paper-in","args":[],"#));
+        assert!(!json.contains("press_count") && json.contains(r#""timestamp":null"#));
+        assert!(json.contains(&format!(r#""version":"{}""#, env!("CARGO_PKG_VERSION"))));
+
+        let json = event_json(
+            4,
+            &["scan", "legal"],
+            Some(Gesture::Hold(2)),
+            Some("2026-03-14T09:26:53Z"),
+        );
+        assert!(json.starts_with(
+            r#"{"seq":4,"event":"scan","args":["legal"],"profile":"legal","press_count":2,"held":true,"timestamp":"2026-03-14T09:26:53Z","#
+        ));
+        assert!(json.contains(r#""host":"#) && json.contains(r#""instance":"#));
     }
 
     #[test]
-    fn transitions_button_down() {
-        let prev = State {
-            paper: false,
-            button: false,
-        };
-        let curr = State {
-            paper: false,
-            button: true,
+    fn handler_context_for_a_gesture() {
+        let options = handler::Options {
+            json: true,
+            ..handler::Options::default()
         };
-        let events: Vec<_> = transitions(prev, curr).collect();
-        assert_eq!(events, vec![Event::ButtonDown]);
+        let args = ["scan", "legal"];
+        let at = Some("2026-03-14T09:26:53Z".to_string());
+        let context = handler_context(
+            &options,
+            5,
+            &args,
+            Some(Gesture::Presses(2)),
+            at,
+            Some(false),
+            None,
+        );
+        assert_eq!(context.press_count, Some(2));
+        assert_eq!(context.paper, Some(false));
+        assert_eq!(context.timestamp.as_deref(), Some("2026-03-14T09:26:53Z"));
+        assert!(context.json.unwrap().contains(r#""press_count":2"#));
+
+        let context = handler_context(
+            &handler::Options::default(),
+            6,
+            &["idle", "20"],
+            None,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(context, handler::Context::default());
     }
 
     #[test]
-    fn transitions_button_up() {
-        let prev = State {
-            paper: false,
-            button: true,
-        };
-        let curr = State {
-            paper: false,
-            button: false,
-        };
-        let events: Vec<_> = transitions(prev, curr).collect();
-        assert_eq!(events, vec![Event::ButtonUp]);
+    fn event_names_cover_all_events() {
+        let scanner = [
+            Event::DeviceArrived,
+            Event::DeviceLeft,
+            Event::PaperIn,
+            Event::PaperOut,
+            Event::ButtonDown,
+            Event::ButtonUp,
+        ];
+        let daemon = [
+            DaemonEvent::LidOpen,
+            DaemonEvent::Idle,
+            DaemonEvent::MaintenanceEntered,
+            DaemonEvent::MaintenanceExited,
+            DaemonEvent::PermissionDenied,
+            DaemonEvent::BatchStart,
+            DaemonEvent::BatchPage,
+            DaemonEvent::BatchEnd,
+            DaemonEvent::UsbDegraded,
+            DaemonEvent::UsbRecovered,
+            DaemonEvent::ScanStarted,
+            DaemonEvent::ScanFinished,
+            DaemonEvent::HandlerFailed,
+        ];
+        let tags = scanner.map(Event::tag).into_iter();
+        for tag in tags.chain(daemon.map(DaemonEvent::tag)) {
+            assert!(EVENT_NAMES.contains(&tag), "{tag} missing");
+        }
     }
 
     #[test]
-    fn transitions_simultaneous() {
-        let prev = State {
-            paper: false,
-            button: false,
-        };
-        let curr = State {
-            paper: true,
-            button: true,
-        };
-        let events: Vec<_> = transitions(prev, curr).collect();
-        assert_eq!(events, vec![Event::PaperIn, Event::ButtonDown]);
+    fn session_formatting() {
+        assert_eq!(format_session(0), "0m00s");
+        assert_eq!(format_session(427), "7m07s");
+        assert_eq!(format_session(3900), "1h05m00s");
     }
 
-    // ── event tags ───────────────────────────────────────────────
+    // ── event sequence ───────────────────────────────────────────
 
     #[test]
-    fn event_tags() {
-        assert_eq!(Event::DeviceArrived.tag(), "device-arrived");
-        assert_eq!(Event::DeviceLeft.tag(), "device-left");
-        assert_eq!(Event::PaperIn.tag(), "paper-in");
-        assert_eq!(Event::PaperOut.tag(), "paper-out");
-        assert_eq!(Event::ButtonDown.tag(), "button-down");
-        assert_eq!(Event::ButtonUp.tag(), "button-up");
+    fn event_seq_increases() {
+        let a = next_seq();
+        let b = next_seq();
+        assert!(a >= 1);
+        assert!(b > a);
+        assert!(EVENT_SEQ.load(Ordering::Relaxed) >= b);
     }
 
     // ── process_transitions ──────────────────────────────────────
 
     fn test_config() -> Config {
         Config {
-            handler: "/bin/test-handler.sh".into(),
+            handler: vec!["/bin/test-handler.sh".into()],
+            handler_ssh: None,
             gesture_timeout_ms: 600,
+            long_press_ms: 1000,
+            paper_debounce_ms: 0,
+            post_scan_cooldown_ms: 0,
+            button_debounce_ms: 0,
             log_level: "info".into(),
-            profiles: HashMap::from([(1, "standard".into()), (2, "legal".into())]),
+            log_timestamps: Default::default(),
+            log_format: Default::default(),
+            event_log_levels: HashMap::new(),
+            ignore_events: Vec::new(),
+            lid_reminder_minutes: Some(30),
+            idle_minutes: None,
+            journal: None,
+            on_start: Default::default(),
+            state_file: None,
+            audit_log: None,
+            event_log: None,
+            control_socket: None,
+            inhibit_file: None,
+            maintenance_presses: Some(5),
+            batch: None,
+            quiet_hours: None,
+            dbus: None,
+            mqtt: None,
+            webhook_url: None,
+            webhook_headers: Vec::new(),
+            webhook_retries: 5,
+            webhook_spool: None,
+            webhook_backlog: 1000,
+            device: Model::S1500,
+            units: HashMap::new(),
+            unit: None,
+            unit_defaults: None,
+            usb: UsbConfig::LOCAL,
+            usb_health: Default::default(),
+            rate_limit: Default::default(),
+            backpressure: Backpressure::Queue,
+            queue_size: queue::DEFAULT_CAPACITY,
+            handler_json: false,
+            handler_timeout_s: None,
+            handler_retries: 3,
+            on_handler_failure: handler::OnFailure::Ignore,
+            handler_nice: None,
+            handler_ionice: None,
+            handler_user: None,
+            handler_clear_env: false,
+            handler_path: None,
+            handler_cwd: None,
+            handler_cgroup: None,
+            profiles: HashMap::from([
+                (Gesture::Presses(1), "standard".into()),
+                (Gesture::Presses(2), "legal".into()),
+            ]),
+            profiles_no_paper: HashMap::new(),
+            long_press_profiles: Default::default(),
+            scan_profiles: HashMap::new(),
+            profile_args: HashMap::new(),
+            schedule: Vec::new(),
+            watches: Vec::new(),
+            state_dump: None,
+            digest: 0xfeed,
         }
     }
 
+    fn test_queue() -> DispatchQueue {
+        DispatchQueue::new(Backpressure::Queue, queue::DEFAULT_CAPACITY)
+    }
+
+    /// Drain the queue into (command, args) pairs.
+    fn drain(queue: &mut DispatchQueue) -> Vec<(Vec<String>, Vec<String>)> {
+        std::iter::from_fn(|| queue.pop())
+            .map(|p| (p.command, p.args))
+            .collect()
+    }
+
     #[test]
-    fn process_log_only_returns_continue() {
+    fn process_log_only_queues_nothing() {
         let prev = State {
             paper: false,
             button: false,
@@ -823,8 +2978,16 @@ mod tests {
             button: false,
         };
         let mut gesture = GestureState::Idle;
-        let action = process_transitions(prev, curr, &Mode::LogOnly, &mut gesture);
-        assert!(matches!(action, Action::Continue));
+        let mut queue = test_queue();
+        process_transitions(
+            prev,
+            curr,
+            &Mode::LogOnly,
+            &mut gesture,
+            &mut None,
+            &mut queue,
+        );
+        assert!(queue.is_empty());
     }
 
     #[test]
@@ -838,15 +3001,34 @@ mod tests {
             button: false,
         };
         let mut gesture = GestureState::Idle;
+        let mut queue = test_queue();
         let mode = Mode::Legacy("/bin/handler.sh".into());
-        let action = process_transitions(prev, curr, &mode, &mut gesture);
-        match action {
-            Action::RunHandler(script, args) => {
-                assert_eq!(script, "/bin/handler.sh");
-                assert_eq!(args, vec!["paper-in"]);
-            }
-            Action::Continue => panic!("expected RunHandler"),
-        }
+        process_transitions(prev, curr, &mode, &mut gesture, &mut None, &mut queue);
+        let jobs = drain(&mut queue);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].0, ["/bin/handler.sh"]);
+        assert_eq!(jobs[0].1, vec!["paper-in"]);
+    }
+
+    #[test]
+    fn process_legacy_queues_simultaneous_events() {
+        let prev = State {
+            paper: false,
+            button: false,
+        };
+        let curr = State {
+            paper: true,
+            button: true,
+        };
+        let mut gesture = GestureState::Idle;
+        let mut queue = test_queue();
+        let mode = Mode::Legacy("/bin/handler.sh".into());
+        process_transitions(prev, curr, &mode, &mut gesture, &mut None, &mut queue);
+        let tags: Vec<_> = drain(&mut queue)
+            .into_iter()
+            .map(|(_, a)| a[0].clone())
+            .collect();
+        assert_eq!(tags, vec!["paper-in", "button-down"]);
     }
 
     #[test]
@@ -860,10 +3042,11 @@ mod tests {
             button: true,
         };
         let mut gesture = GestureState::Idle;
-        let mode = Mode::ConfigMode(test_config());
-        let action = process_transitions(prev, curr, &mode, &mut gesture);
-        assert!(matches!(action, Action::Continue));
-        assert!(matches!(gesture, GestureState::Pressed(1)));
+        let mut queue = test_queue();
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        process_transitions(prev, curr, &mode, &mut gesture, &mut None, &mut queue);
+        assert!(queue.is_empty());
+        assert!(matches!(gesture, GestureState::Pressed(1, _)));
     }
 
     #[test]
@@ -876,17 +3059,104 @@ mod tests {
             paper: false,
             button: false,
         };
-        let mut gesture = GestureState::Pressed(1);
-        let mode = Mode::ConfigMode(test_config());
-        let action = process_transitions(prev, curr, &mode, &mut gesture);
-        assert!(matches!(action, Action::Continue));
+        let mut gesture = GestureState::Pressed(1, Instant::now());
+        let mut queue = test_queue();
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        process_transitions(prev, curr, &mode, &mut gesture, &mut None, &mut queue);
+        assert!(queue.is_empty());
+        assert!(matches!(gesture, GestureState::Released(1, _)));
+    }
+
+    #[test]
+    fn process_config_long_press_is_held() {
+        let prev = State {
+            paper: false,
+            button: true,
+        };
+        let curr = State {
+            paper: false,
+            button: false,
+        };
+        let since = Instant::now() - Duration::from_secs(2);
+        let mut config = test_config();
+        let mut queue = test_queue();
+
+        // Without a hold gesture mapped, a long press is an ordinary press.
+        let mut gesture = GestureState::Pressed(1, since);
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        process_transitions(prev, curr, &mode, &mut gesture, &mut None, &mut queue);
         assert!(matches!(gesture, GestureState::Released(1, _)));
+
+        config.profiles.insert(Gesture::Hold(1), "photo".into());
+        let mode = Mode::ConfigMode(Box::new(config));
+        let mut gesture = GestureState::Pressed(1, since);
+        process_transitions(prev, curr, &mode, &mut gesture, &mut None, &mut queue);
+        assert!(matches!(gesture, GestureState::Held(1, _)));
+
+        // A hold completes the gesture without waiting for the window.
+        match check_gesture_timeout(&gesture, &mode, true) {
+            Some(Action::RunHandler(_, args, _)) => assert_eq!(args, vec!["scan", "photo"]),
+            other => panic!("expected RunHandler, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn gesture_without_paper_uses_no_paper_profiles() {
+        let mut config = test_config();
+        config
+            .profiles_no_paper
+            .insert(Gesture::Presses(1), "clean".into());
+        let mode = Mode::ConfigMode(Box::new(config));
+        let scan = |presses: u32, paper: bool| {
+            let gesture = GestureState::Released(presses, Instant::now() - Duration::from_secs(1));
+            match check_gesture_timeout(&gesture, &mode, paper) {
+                Some(Action::RunHandler(_, args, _)) => args[1].clone(),
+                other => panic!("expected RunHandler, got {other:?}"),
+            }
+        };
+        assert_eq!(scan(1, true), "standard");
+        assert_eq!(scan(1, false), "clean");
+        // Unmapped without paper: the usual profile.
+        assert_eq!(scan(2, false), "legal");
+    }
+
+    #[test]
+    fn long_press_tiers_pick_by_duration() {
+        let mut config = test_config();
+        config.long_press_profiles = [(1000, "photo".into()), (3000, "archive".into())].into();
+        let mode = Mode::ConfigMode(Box::new(config));
+        let scan = |gesture: GestureState| match check_gesture_timeout(&gesture, &mode, true) {
+            Some(Action::RunHandler(_, args, _)) => Some(args[1].clone()),
+            _ => None,
+        };
+        let ago = |secs: u64| Instant::now() - Duration::from_secs(secs);
+
+        assert_eq!(
+            scan(GestureState::Held(1, Duration::from_millis(1500))).as_deref(),
+            Some("photo")
+        );
+        assert_eq!(
+            scan(GestureState::Held(1, Duration::from_secs(5))).as_deref(),
+            Some("archive")
+        );
+        // Past the longest tier the hold fires while the button is still down.
+        assert_eq!(
+            scan(GestureState::Pressed(1, ago(4))).as_deref(),
+            Some("archive")
+        );
+        assert!(check_gesture_timeout(&GestureState::Pressed(1, ago(2)), &mode, true).is_none());
+        assert!(check_gesture_timeout(&GestureState::Pressed(2, ago(4)), &mode, true).is_none());
+        assert_eq!(
+            GestureState::Pressed(1, ago(4)).completed(),
+            Some(Gesture::Hold(1))
+        );
     }
 
     #[test]
     fn process_config_double_press() {
         let mut gesture = GestureState::Released(1, Instant::now());
-        let mode = Mode::ConfigMode(test_config());
+        let mut queue = test_queue();
+        let mode = Mode::ConfigMode(Box::new(test_config()));
 
         // Second button down
         let prev = State {
@@ -897,9 +3167,9 @@ mod tests {
             paper: false,
             button: true,
         };
-        let action = process_transitions(prev, curr, &mode, &mut gesture);
-        assert!(matches!(action, Action::Continue));
-        assert!(matches!(gesture, GestureState::Pressed(2)));
+        process_transitions(prev, curr, &mode, &mut gesture, &mut None, &mut queue);
+        assert!(queue.is_empty());
+        assert!(matches!(gesture, GestureState::Pressed(2, _)));
     }
 
     #[test]
@@ -913,26 +3183,43 @@ mod tests {
             button: false,
         };
         let mut gesture = GestureState::Idle;
-        let mode = Mode::ConfigMode(test_config());
-        let action = process_transitions(prev, curr, &mode, &mut gesture);
-        match action {
-            Action::RunHandler(script, args) => {
-                assert_eq!(script, "/bin/test-handler.sh");
-                assert_eq!(args, vec!["paper-in"]);
-            }
-            Action::Continue => panic!("expected RunHandler for paper-in"),
-        }
+        let mut queue = test_queue();
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        process_transitions(prev, curr, &mode, &mut gesture, &mut None, &mut queue);
+        let jobs = drain(&mut queue);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].0, ["/bin/test-handler.sh"]);
+        assert_eq!(jobs[0].1, vec!["paper-in"]);
+    }
+
+    #[test]
+    fn process_config_paper_does_not_swallow_press() {
+        let prev = State {
+            paper: false,
+            button: false,
+        };
+        let curr = State {
+            paper: true,
+            button: true,
+        };
+        let mut gesture = GestureState::Idle;
+        let mut queue = test_queue();
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        process_transitions(prev, curr, &mode, &mut gesture, &mut None, &mut queue);
+        assert_eq!(drain(&mut queue).len(), 1);
+        assert!(matches!(gesture, GestureState::Pressed(1, _)));
     }
 
     #[test]
-    fn process_no_change_returns_continue() {
+    fn process_no_change_queues_nothing() {
         let s = State {
             paper: false,
             button: false,
         };
         let mut gesture = GestureState::Idle;
-        let action = process_transitions(s, s, &Mode::LogOnly, &mut gesture);
-        assert!(matches!(action, Action::Continue));
+        let mut queue = test_queue();
+        process_transitions(s, s, &Mode::LogOnly, &mut gesture, &mut None, &mut queue);
+        assert!(queue.is_empty());
     }
 
     // ── check_gesture_timeout ────────────────────────────────────
@@ -941,45 +3228,72 @@ mod tests {
     fn gesture_timeout_not_config_mode() {
         let gesture = GestureState::Released(1, Instant::now());
         let mode = Mode::LogOnly;
-        assert!(check_gesture_timeout(&gesture, &mode).is_none());
+        assert!(check_gesture_timeout(&gesture, &mode, true).is_none());
     }
 
     #[test]
     fn gesture_timeout_not_released() {
-        let gesture = GestureState::Pressed(1);
-        let mode = Mode::ConfigMode(test_config());
-        assert!(check_gesture_timeout(&gesture, &mode).is_none());
+        let gesture = GestureState::Pressed(1, Instant::now());
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        assert!(check_gesture_timeout(&gesture, &mode, true).is_none());
     }
 
     #[test]
     fn gesture_timeout_not_expired() {
         let gesture = GestureState::Released(1, Instant::now());
-        let mode = Mode::ConfigMode(test_config());
-        assert!(check_gesture_timeout(&gesture, &mode).is_none());
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        assert!(check_gesture_timeout(&gesture, &mode, true).is_none());
     }
 
     #[test]
     fn gesture_timeout_expired_mapped() {
         // Use a timestamp far enough in the past
         let gesture = GestureState::Released(1, Instant::now() - Duration::from_secs(1));
-        let mode = Mode::ConfigMode(test_config());
-        let action = check_gesture_timeout(&gesture, &mode);
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        let action = check_gesture_timeout(&gesture, &mode, true);
         match action {
-            Some(Action::RunHandler(script, args)) => {
-                assert_eq!(script, "/bin/test-handler.sh");
+            Some(Action::RunHandler(command, args, _)) => {
+                assert_eq!(command, ["/bin/test-handler.sh"]);
                 assert_eq!(args, vec!["scan", "standard"]);
             }
             other => panic!("expected RunHandler, got {other:?}"),
         }
     }
 
+    #[test]
+    fn gesture_timeout_scan_profile_scans() {
+        let mut config = test_config();
+        config.scan_profiles.insert(
+            "legal".into(),
+            config::ScanProfile {
+                output_dir: "/srv/scans".into(),
+                resolution: 300,
+                mode: "Color".into(),
+                source: "ADF Duplex".into(),
+                format: "tiff".into(),
+            },
+        );
+        let mode = Mode::ConfigMode(Box::new(config));
+        let gesture = GestureState::Released(2, Instant::now() - Duration::from_secs(1));
+        match check_gesture_timeout(&gesture, &mode, true) {
+            Some(Action::Scan(profile, _)) => assert_eq!(profile, "legal"),
+            other => panic!("expected Scan, got {other:?}"),
+        }
+        // Profiles without scan settings still go to the handler.
+        let gesture = GestureState::Released(1, Instant::now() - Duration::from_secs(1));
+        assert!(matches!(
+            check_gesture_timeout(&gesture, &mode, true),
+            Some(Action::RunHandler(..))
+        ));
+    }
+
     #[test]
     fn gesture_timeout_expired_double_press() {
         let gesture = GestureState::Released(2, Instant::now() - Duration::from_secs(1));
-        let mode = Mode::ConfigMode(test_config());
-        let action = check_gesture_timeout(&gesture, &mode);
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        let action = check_gesture_timeout(&gesture, &mode, true);
         match action {
-            Some(Action::RunHandler(_, args)) => {
+            Some(Action::RunHandler(_, args, _)) => {
                 assert_eq!(args, vec!["scan", "legal"]);
             }
             other => panic!("expected RunHandler for double press, got {other:?}"),
@@ -987,10 +3301,682 @@ mod tests {
     }
 
     #[test]
-    fn gesture_timeout_expired_unmapped() {
+    fn gesture_timeout_maintenance_presses() {
         let gesture = GestureState::Released(5, Instant::now() - Duration::from_secs(1));
-        let mode = Mode::ConfigMode(test_config());
-        let action = check_gesture_timeout(&gesture, &mode);
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        let action = check_gesture_timeout(&gesture, &mode, true);
+        assert!(matches!(action, Some(Action::ToggleMaintenance)));
+    }
+
+    #[test]
+    fn batch_gesture_opens_and_closes_a_batch() {
+        let mut config = test_config();
+        config.batch = Some(config::BatchConfig {
+            gesture: Gesture::Presses(3),
+            idle: Duration::from_secs(300),
+        });
+        let mode = Mode::ConfigMode(Box::new(config));
+        let gesture = GestureState::Released(3, Instant::now() - Duration::from_secs(1));
+        assert!(matches!(
+            check_gesture_timeout(&gesture, &mode, true),
+            Some(Action::ToggleBatch)
+        ));
+
+        let mut batch = None;
+        let id = match open_batch(&mut batch, &mode) {
+            Some(Action::RunHandler(_, args, _)) => {
+                assert_eq!(args[0], "batch-start");
+                args[1].clone()
+            }
+            other => panic!("expected RunHandler, got {other:?}"),
+        };
+
+        // Each sheet loaded is a page; paper-out is dispatched as usual.
+        let mut queue = test_queue();
+        let empty = State {
+            paper: false,
+            button: false,
+        };
+        let loaded = State {
+            paper: true,
+            button: false,
+        };
+        let mut gesture = GestureState::Idle;
+        for _ in 0..2 {
+            process_transitions(empty, loaded, &mode, &mut gesture, &mut batch, &mut queue);
+            process_transitions(loaded, empty, &mode, &mut gesture, &mut batch, &mut queue);
+        }
+        let args: Vec<_> = drain(&mut queue).into_iter().map(|(_, a)| a).collect();
+        assert_eq!(
+            args,
+            vec![
+                vec!["batch-page".to_string(), id.clone(), "1".into()],
+                vec!["paper-out".into()],
+                vec!["batch-page".into(), id.clone(), "2".into()],
+                vec!["paper-out".into()],
+            ]
+        );
+        assert!(!batch_idle(batch.as_ref().unwrap(), &mode));
+
+        match close_batch(&mut batch, "gesture", &mode) {
+            Some(Action::RunHandler(_, args, _)) => assert_eq!(args, vec!["batch-end", &id, "2"]),
+            other => panic!("expected RunHandler, got {other:?}"),
+        }
+        assert!(batch.is_none());
+        assert!(close_batch(&mut batch, "idle", &mode).is_none());
+    }
+
+    #[test]
+    fn batch_closes_when_idle() {
+        let mut config = test_config();
+        config.batch = Some(config::BatchConfig {
+            gesture: Gesture::Presses(3),
+            idle: Duration::from_secs(60),
+        });
+        let mode = Mode::ConfigMode(Box::new(config));
+        let batch = Batch {
+            id: "x".into(),
+            pages: 1,
+            last_activity: Instant::now() - Duration::from_secs(61),
+        };
+        assert!(batch_idle(&batch, &mode));
+    }
+
+    #[test]
+    fn maintenance_suppresses_dispatch() {
+        let mut dispatcher = Dispatcher::default();
+        assert!(!dispatcher.suppressed(1, "paper-in"));
+        set_maintenance(true, "command", &Mode::LogOnly, &mut dispatcher);
+        assert!(dispatcher.suppressed(2, "paper-in"));
+        assert_eq!(dispatch_status(&dispatcher), "maintenance");
+        dispatcher.set_paused(true);
+        assert_eq!(dispatch_status(&dispatcher), "maintenance, paused");
+        set_maintenance(false, "command", &Mode::LogOnly, &mut dispatcher);
+        assert_eq!(dispatch_status(&dispatcher), "paused");
+        dispatcher.set_paused(false);
+        assert!(!dispatcher.suppressed(3, "paper-in"));
+    }
+
+    #[test]
+    fn journal_replay_is_gated_and_keeps_its_seq() {
+        let dir = std::env::temp_dir().join(format!("s1500d-{}-replay", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let (path, out) = (dir.join("journal"), dir.join("out"));
+        let script = format!("echo \"$1 $S1500D_SEQ\" >> {}", out.display());
+        {
+            let (mut journal, _) = Journal::open(&path).unwrap();
+            let command = ["/bin/sh", "-c", &script, "handler"];
+            journal.begin(&command, &["paper-in"], 7).unwrap();
+            journal.begin(&command, &["paper-out"], 8).unwrap();
+        }
+        let mut config = test_config();
+        config.journal = Some(path.to_string_lossy().into());
+        config.ignore_events = vec!["paper-out".into()];
+        let _dispatcher = Dispatcher::new(&Mode::ConfigMode(Box::new(config)));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "paper-in 7\n");
+        // One ran and the other is ignored now; neither is left over.
+        assert!(Journal::open(&path).unwrap().1.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rate_limit_is_checked_once_per_job() {
+        let mut config = test_config();
+        config.rate_limit.max_per_minute = Some(1);
+        let mut dispatcher = Dispatcher::new(&Mode::ConfigMode(Box::new(config)));
+        let pending = |seq| Pending {
+            command: vec!["true".into()],
+            args: vec!["paper-in".into()],
+            seq,
+            gesture: None,
+            timestamp: None,
+        };
+        assert!(!dispatcher.drops(&Job::Handler(pending(1))));
+        assert!(dispatcher.drops(&Job::Handler(pending(2))));
+        assert_eq!(dispatcher.gate.dropped(), 1);
+        // Built-in scans are limited per profile like any other event.
+        assert!(!dispatcher.drops(&Job::Scan("legal".into(), 3)));
+        assert!(dispatcher.drops(&Job::Scan("legal".into(), 4)));
+        assert_eq!(dispatcher.gate.dropped(), 2);
+    }
+
+    #[test]
+    fn maintenance_notices_wait_for_the_scanner_release() {
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        let mut dispatcher = Dispatcher::default();
+        let mut queue = test_queue();
+        set_maintenance(true, "gesture", &mode, &mut dispatcher);
+        report_notices(&mode, &mut dispatcher, Some(&mut queue));
+        let job = queue.pop().unwrap();
+        assert_eq!(job.args, ["maintenance-entered", "gesture"]);
+        // Queued behind the switch, but not swallowed by it.
+        assert!(!dispatcher.drops(&Job::Handler(job)));
+        assert!(dispatcher.notices.is_empty());
+    }
+
+    #[test]
+    fn snooze_suppresses_only_that_event() {
+        let mut dispatcher = Dispatcher::default();
+        dispatcher.apply_requests(vec![handler::Request::Snooze(
+            "paper-out".into(),
+            Duration::from_secs(30),
+        )]);
+        assert!(dispatcher.suppressed(1, "paper-out"));
+        assert!(!dispatcher.suppressed(2, "paper-in"));
+        assert!(!dispatcher.suppressed(3, "scan paper-out"));
+
+        dispatcher.apply_requests(vec![handler::Request::Snooze(
+            "paper-out".into(),
+            Duration::ZERO,
+        )]);
+        assert!(!dispatcher.suppressed(4, "paper-out"));
+    }
+
+    #[test]
+    fn ignore_events_are_never_dispatched() {
+        let toml = "handler = \"/bin/h.sh\"\nignore_events = [\"paper-in\", \"paper-out\"]";
+        let config = config::parse_config(toml).unwrap();
+        let mut dispatcher = Dispatcher::new(&Mode::ConfigMode(Box::new(config)));
+        assert!(dispatcher.suppressed(1, "paper-in"));
+        assert!(dispatcher.suppressed(2, "paper-out"));
+        assert!(!dispatcher.suppressed(3, "scan legal"));
+
+        dispatcher.reconfigure(&test_config());
+        assert!(!dispatcher.suppressed(4, "paper-in"));
+    }
+
+    #[test]
+    fn gesture_timeout_expired_unmapped() {
+        let gesture = GestureState::Released(4, Instant::now() - Duration::from_secs(1));
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        let action = check_gesture_timeout(&gesture, &mode, true);
         assert!(matches!(action, Some(Action::Continue)));
     }
+
+    // ── check_lid_reminder ───────────────────────────────────────
+
+    #[test]
+    fn lid_reminder_not_due() {
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        assert!(check_lid_reminder(Instant::now(), 0, &mode).is_none());
+    }
+
+    #[test]
+    fn lid_reminder_due_and_repeats() {
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        let opened = Instant::now() - Duration::from_secs(65 * 60);
+        match check_lid_reminder(opened, 1, &mode) {
+            Some(Action::RunHandler(_, args, _)) => assert_eq!(args, vec!["lid-open", "60"]),
+            other => panic!("expected RunHandler, got {other:?}"),
+        }
+        // Third reminder (90m) is not due yet.
+        assert!(check_lid_reminder(opened, 2, &mode).is_none());
+    }
+
+    #[test]
+    fn lid_reminder_disabled_outside_config_mode() {
+        let opened = Instant::now() - Duration::from_secs(3600);
+        assert!(check_lid_reminder(opened, 0, &Mode::LogOnly).is_none());
+    }
+
+    // ── state dumps ──────────────────────────────────────────────
+
+    fn test_snapshot<'a>(
+        gesture: &'a GestureState,
+        queue: &'a DispatchQueue,
+        health: &'a Health,
+    ) -> Snapshot<'a> {
+        Snapshot {
+            started: Instant::now(),
+            handle: None,
+            opened_at: None,
+            last_activity: Instant::now(),
+            prev: None,
+            gesture,
+            batch: None,
+            queue,
+            polls: 0,
+            failed_polls: 0,
+            health,
+        }
+    }
+
+    #[test]
+    fn quiet_hours_suppress_until_lifted() {
+        let mut dispatcher = Dispatcher {
+            quiet: Some(TimeWindow::parse("22:00", "07:00").unwrap()),
+            ..Dispatcher::default()
+        };
+        assert!(dispatcher.quiet_at(2 * 60));
+        assert!(!dispatcher.quiet_at(12 * 60));
+        dispatcher.quiet_lifted = true;
+        assert!(!dispatcher.quiet_at(2 * 60));
+        assert!(!Dispatcher::default().quiet_at(2 * 60));
+    }
+
+    #[test]
+    fn quiet_hours_override_gesture() {
+        let mut config = test_config();
+        config.quiet_hours = Some(config::QuietHours {
+            window: TimeWindow::parse("22:00", "07:00").unwrap(),
+            override_gesture: Some(Gesture::Hold(1)),
+        });
+        let mode = Mode::ConfigMode(Box::new(config));
+        assert!(matches!(
+            check_gesture_timeout(&GestureState::Held(1, Duration::from_secs(2)), &mode, true),
+            Some(Action::LiftQuiet)
+        ));
+        // Mapped gestures still resolve to their profile.
+        let gesture = GestureState::Released(1, Instant::now() - Duration::from_secs(1));
+        assert!(matches!(
+            check_gesture_timeout(&gesture, &mode, true),
+            Some(Action::RunHandler(..))
+        ));
+    }
+
+    #[test]
+    fn failed_handler_retries_or_announces() {
+        let fail = ["/bin/false".to_string()];
+        let mut dispatcher = Dispatcher::default();
+        dispatcher.options.on_failure = handler::OnFailure::Retry;
+        dispatcher.options.retries = 1;
+        dispatcher.dispatch(&fail, &["paper-in"], 1);
+        assert_eq!((dispatcher.runs, dispatcher.failures), (2, 2));
+        assert!(dispatcher.failed.is_empty());
+
+        dispatcher.options.on_failure = handler::OnFailure::Event;
+        dispatcher.dispatch(&fail, &["scan", "legal"], 2);
+        assert_eq!(dispatcher.runs, 3);
+        // A failed handler-failed run isn't announced in turn.
+        dispatcher.dispatch(&fail, &["handler-failed", "scan", "exit-1"], 3);
+        assert_eq!(
+            dispatcher.failed,
+            [(2, "scan".to_string(), handler::Failure::Exit(1))]
+        );
+
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        let mut queue = test_queue();
+        report_handler_failures(&mode, &mut dispatcher, &mut queue);
+        assert!(dispatcher.failed.is_empty());
+        let job = queue.pop().unwrap();
+        assert_eq!(job.args, ["handler-failed", "scan", "exit-1"]);
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn watched_bits_reach_the_handler() {
+        let mut config = test_config();
+        config.watches = vec![config::Watch {
+            name: "cover".into(),
+            byte: 5,
+            bit: 2,
+            invert: false,
+        }];
+        let mode = Mode::ConfigMode(Box::new(config));
+        let mut queue = test_queue();
+        let mut response = [0u8; 12];
+        let before = watch::read(watches(&mode), &response);
+        response[5] = 0x04;
+        let after = watch::read(watches(&mode), &response);
+        report_watches(before, after, &mode, &mut queue);
+        report_watches(after, before, &mode, &mut queue);
+        assert_eq!(queue.pop().unwrap().args, ["cover-on"]);
+        assert_eq!(queue.pop().unwrap().args, ["cover-off"]);
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn state_dump_json() {
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        let mut queue = test_queue();
+        queue.push(vec!["/h".into()], vec!["scan".into(), "le\"gal".into()], 7);
+        let gesture = GestureState::Released(2, Instant::now());
+        let batch = Batch {
+            id: "b1".into(),
+            pages: 3,
+            last_activity: Instant::now(),
+        };
+        let mut dispatcher = Dispatcher {
+            paused: true,
+            inhibited: true,
+            runs: 4,
+            failures: 1,
+            ..Dispatcher::default()
+        };
+        dispatcher
+            .snoozed
+            .insert("paper-out".into(), Instant::now() + Duration::from_secs(30));
+        let mut health = Health::new(2, Duration::from_secs(60));
+        health.record(Fault::Timeout, Instant::now());
+        health.record(Fault::Pipe, Instant::now());
+        let snapshot = Snapshot {
+            prev: Some(State {
+                paper: true,
+                button: false,
+            }),
+            batch: Some(&batch),
+            polls: 10,
+            failed_polls: 2,
+            ..test_snapshot(&gesture, &queue, &health)
+        };
+        let json = state_json(&snapshot, &mode, &dispatcher);
+        for part in [
+            r#""mode":"config","config":{"digest":"000000000000feed","handler":"/bin/test-handler.sh","ssh":null,"usb_remote":false,"model":"s1500"}"#,
+            r#""device":{"present":false,"lid_open_secs":null,"idle_secs":0}"#,
+            r#""status":{"paper":true,"button":false,"#,
+            r#""gesture":{"state":"released","presses":2}"#,
+            r#""batch":{"id":"b1","pages":3}"#,
+            r#""dispatch":"paused","inhibited":"s1500ctl inhibit""#,
+            r#""queue":[{"seq":7,"args":["scan","le\"gal"]}]"#,
+            r#""snoozed":{"paper-out":29}"#,
+            r#""polls":10,"failed_polls":2,"handler_runs":4,"handler_failures":1,"handler_rate_limited":0"#,
+            r#""usb_health":{"degraded":true,"recent_errors":2,"window_secs":60,"timeout":1,"pipe":1,"other":0,"reclaim":0}"#,
+        ] {
+            assert!(json.contains(part), "{part} not in {json}");
+        }
+        assert!(!json.contains('\n'));
+    }
+
+    #[test]
+    fn post_scan_cooldown_ignores_changes_until_over() {
+        let before = State {
+            paper: true,
+            button: false,
+        };
+        let after = State {
+            paper: false,
+            ..before
+        };
+        let mut config = test_config();
+        assert!(scan_cooldown(&Mode::ConfigMode(Box::new(test_config()))).is_none());
+        config.post_scan_cooldown_ms = 60_000;
+        let mut cooldown = scan_cooldown(&Mode::ConfigMode(Box::new(config)));
+        assert!(cooling_down(&mut cooldown, before, after));
+        assert!(cooldown.is_some());
+
+        let mut cooldown = Some(Instant::now());
+        assert!(!cooling_down(&mut cooldown, before, after));
+        assert!(cooldown.is_none());
+        assert!(is_scan(&["scan".into(), "legal".into()]));
+        assert!(!is_scan(&["paper-in".into()]));
+    }
+
+    #[test]
+    fn profile_args_expand_for_gesture_scans() {
+        let toml = "handler = \"/bin/h.sh\"\n[profiles]\n\
+                    3 = { name = \"legal\", args = [\"{event}\", \"{profile}\", \"--count={press_count}\", \"{serial}\"] }";
+        let config = config::parse_config(toml).unwrap();
+        let mut dispatcher = Dispatcher::new(&Mode::ConfigMode(Box::new(config)));
+        dispatcher.options.serial = Some("A1B2C3".into());
+        let job = |args: &[&str], gesture| Pending {
+            command: vec!["/bin/h.sh".into()],
+            args: args.iter().map(|&a| a.into()).collect(),
+            seq: 1,
+            gesture,
+            timestamp: None,
+        };
+        assert_eq!(
+            dispatcher.profile_args(&job(&["scan", "legal"], Some(Gesture::Presses(3)))),
+            Some(vec![
+                "scan".into(),
+                "legal".into(),
+                "--count=3".into(),
+                "A1B2C3".into()
+            ])
+        );
+        // Only a gesture's scan of a profile that sets args is rewritten.
+        assert_eq!(
+            dispatcher.profile_args(&job(&["scan", "legal"], None)),
+            None
+        );
+        let other = job(&["scan", "standard"], Some(Gesture::Presses(1)));
+        assert_eq!(dispatcher.profile_args(&other), None);
+    }
+
+    #[test]
+    fn start_baseline_from_saved_state_or_on_start() {
+        let loaded = State {
+            paper: true,
+            button: false,
+        };
+        let saved = Saved {
+            present: true,
+            state: Some(loaded),
+        };
+        let quiet = Mode::ConfigMode(Box::new(test_config()));
+        assert_eq!(start_baseline(&quiet, None), None);
+        assert_eq!(start_baseline(&quiet, Some(saved)), Some(loaded));
+        assert_eq!(start_baseline(&quiet, Some(Saved::ABSENT)), None);
+
+        let mut config = test_config();
+        config.on_start = OnStart::EmitCurrent;
+        let emit = Mode::ConfigMode(Box::new(config));
+        let empty = State {
+            paper: false,
+            button: false,
+        };
+        assert_eq!(start_baseline(&emit, None), Some(empty));
+        // A saved state wins over on_start.
+        assert_eq!(start_baseline(&emit, Some(saved)), Some(loaded));
+        assert_eq!(start_baseline(&Mode::LogOnly, None), None);
+    }
+
+    #[test]
+    fn persist_writes_only_changes() {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-persist", std::process::id()));
+        let mut config = test_config();
+        config.state_file = Some(path.to_string_lossy().into_owned());
+        let mode = Mode::ConfigMode(Box::new(config));
+        let mut persisted = None;
+        persist(&mode, &mut persisted, Saved::ABSENT);
+        assert_eq!(persisted, Some(Saved::ABSENT));
+        assert_eq!(load_saved(&mode), Some(Saved::ABSENT));
+
+        // Unchanged, so the file isn't touched.
+        std::fs::remove_file(&path).unwrap();
+        persist(&mode, &mut persisted, Saved::ABSENT);
+        assert!(!path.exists());
+        assert_eq!(load_saved(&mode), None);
+    }
+
+    #[test]
+    fn polls_slowly_only_when_quiet() {
+        let usb = UsbConfig {
+            slow_poll_interval: Some(Duration::from_millis(500)),
+            ..UsbConfig::LOCAL
+        };
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        let quiet = Instant::now() - Duration::from_secs(60);
+        let slow = Duration::from_millis(500);
+        let fast = usb.poll_interval;
+        assert_eq!(
+            next_poll(&usb, &mode, &GestureState::Idle, false, quiet),
+            slow
+        );
+        assert_eq!(
+            next_poll(&usb, &mode, &GestureState::Idle, true, quiet),
+            fast
+        );
+        let now = Instant::now();
+        assert_eq!(
+            next_poll(&usb, &mode, &GestureState::Idle, false, now),
+            fast
+        );
+        let pressed = GestureState::Pressed(1, now);
+        assert_eq!(next_poll(&usb, &mode, &pressed, false, quiet), fast);
+        let released = GestureState::Released(1, now);
+        let pending = Duration::from_millis(20);
+        assert_eq!(next_poll(&usb, &mode, &released, false, quiet), pending);
+        // Without slow_poll_interval nothing changes.
+        let idle = next_poll(&UsbConfig::LOCAL, &mode, &GestureState::Idle, false, quiet);
+        assert_eq!(idle, fast);
+    }
+
+    #[test]
+    fn inhibit_by_command_or_file() {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-inhibit", std::process::id()));
+        let mut config = test_config();
+        config.inhibit_file = Some(path.to_string_lossy().into_owned());
+        let mode = Mode::ConfigMode(Box::new(config));
+        let mut dispatcher = Dispatcher::default();
+        assert_eq!(inhibited(&mode, &dispatcher), None);
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(inhibited(&mode, &dispatcher), Some("inhibit_file"));
+        std::fs::remove_file(&path).unwrap();
+        dispatcher.inhibited = true;
+        assert_eq!(inhibited(&mode, &dispatcher), Some("s1500ctl inhibit"));
+        assert_eq!(inhibited(&Mode::LogOnly, &Dispatcher::default()), None);
+    }
+
+    #[test]
+    fn state_dump_written_to_file() {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-state.json", std::process::id()));
+        let mut config = test_config();
+        config.state_dump = Some(path.to_string_lossy().into_owned());
+        dump_state("{}", &Mode::ConfigMode(Box::new(config)));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reload_swaps_the_config() {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-reload.toml", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let mut mode = Mode::ConfigMode(Box::new(test_config()));
+        let mut dispatcher = Dispatcher::new(&mode);
+        dispatcher.options.serial = Some("A1B2C3".into());
+
+        let toml = "handler = \"/bin/h.sh\"\nhandler_nice = 10\nqueue_size = 2\n\
+                    [profiles]\n1 = \"legal\"\n";
+        std::fs::write(&path, toml).unwrap();
+        reload(&mut mode, Some(path_str), &mut dispatcher);
+        let Mode::ConfigMode(c) = &mode else {
+            unreachable!()
+        };
+        assert_eq!(c.profiles[&Gesture::Presses(1)], "legal");
+        // queue_size needs a restart.
+        assert_eq!(c.queue_size, test_config().queue_size);
+        assert_eq!(dispatcher.options.nice, Some(10));
+        assert_eq!(dispatcher.options.serial.as_deref(), Some("A1B2C3"));
+
+        // A broken config leaves the running one in place.
+        std::fs::write(&path, "handler = ").unwrap();
+        reload(&mut mode, Some(path_str), &mut dispatcher);
+        let Mode::ConfigMode(c) = &mode else {
+            unreachable!()
+        };
+        assert_eq!(c.profiles[&Gesture::Presses(1)], "legal");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn health_changes_reach_the_handler() {
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        let mut queue = test_queue();
+        let mut health = Health::new(1, Duration::from_secs(60));
+        let change = health.record(Fault::Timeout, Instant::now()).unwrap();
+        report_health(change, &health, &mode, &mut queue);
+        report_health(Change::Recovered, &health, &mode, &mut queue);
+        let args: Vec<_> = drain(&mut queue).into_iter().map(|(_, a)| a).collect();
+        assert_eq!(args, vec![vec!["usb-degraded", "1"], vec!["usb-recovered"]]);
+    }
+
+    // ── check_idle ───────────────────────────────────────────────
+
+    #[test]
+    fn idle_fires_after_quiet_period() {
+        let mut config = test_config();
+        config.idle_minutes = Some(20);
+        let mode = Mode::ConfigMode(Box::new(config));
+        assert!(check_idle(Instant::now(), &mode).is_none());
+        let quiet_since = Instant::now() - Duration::from_secs(21 * 60);
+        match check_idle(quiet_since, &mode) {
+            Some(Action::RunHandler(_, args, _)) => assert_eq!(args, vec!["idle", "20"]),
+            other => panic!("expected RunHandler, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn idle_disabled_by_default() {
+        let quiet_since = Instant::now() - Duration::from_secs(24 * 3600);
+        let mode = Mode::ConfigMode(Box::new(test_config()));
+        assert!(check_idle(quiet_since, &mode).is_none());
+        assert!(check_idle(quiet_since, &Mode::LogOnly).is_none());
+    }
+
+    // ── steady-state allocation ──────────────────────────────────
+
+    /// Counts heap allocations made by the current thread, so tests running
+    /// in parallel don't disturb each other's counts.
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    // SAFETY: defers to the system allocator; the counter is a plain Cell.
+    unsafe impl std::alloc::GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout);
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    #[test]
+    fn idle_poll_cycle_does_not_allocate() {
+        let mut config = test_config();
+        config.state_file = Some("/nonexistent/s1500d-state".into());
+        let mode = Mode::ConfigMode(Box::new(config));
+        let mut dispatcher = Dispatcher::default();
+        let mut queue = test_queue();
+        let mut gesture = GestureState::Released(1, Instant::now());
+        let opened = Instant::now();
+        let response = [0, 0, 0, 0x80, 0x80, 0, 0, 0, 0, 0, 0, 0];
+        let mut health = Health::new(10, Duration::from_secs(300));
+        let mut debouncer = Debouncer::default();
+        let mut cooldown = None;
+        let state = State::from_response(&response).unwrap();
+        let mut persisted = Some(Saved {
+            present: true,
+            state: Some(state),
+        });
+
+        let before = ALLOCATIONS.with(std::cell::Cell::get);
+        for _ in 0..1000 {
+            // What the event loop does per idle cycle, minus the USB I/O
+            // and the hotplug wait (libusb's own memory isn't counted).
+            notify::watchdog();
+            let snapshot = test_snapshot(&gesture, &queue, &health);
+            service_control(None, &mode, &mut dispatcher, &snapshot);
+            assert!(inhibited(&mode, &dispatcher).is_none());
+            assert!(check_gesture_timeout(&gesture, &mode, true).is_none());
+            assert!(check_lid_reminder(opened, 0, &mode).is_none());
+            assert!(queue.pop().is_none());
+            assert!(health.check(Instant::now()).is_none());
+            report_handler_failures(&mode, &mut dispatcher, &mut queue);
+            report_notices(&mode, &mut dispatcher, Some(&mut queue));
+            assert!(check_idle(Instant::now(), &mode).is_none());
+            assert_eq!(watch::read(watches(&mode), &response), 0);
+            let _ = s1500d::protocol::envelope(&s1500d::protocol::GHS_CDB);
+            let raw = State::from_response(&response).unwrap();
+            let state = debouncer.filter(state, raw, settle(&mode), Instant::now());
+            assert!(!cooling_down(&mut cooldown, state, state));
+            process_transitions(state, state, &mode, &mut gesture, &mut None, &mut queue);
+            let saved = Saved {
+                present: true,
+                state: Some(state),
+            };
+            persist(&mode, &mut persisted, saved);
+            let _ = next_poll(usb_config(), &mode, &gesture, state.button, opened);
+        }
+        assert_eq!(ALLOCATIONS.with(std::cell::Cell::get), before);
+    }
 }
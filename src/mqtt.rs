@@ -0,0 +1,681 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use s1500d::Event;
+
+use crate::config::MqttConfig;
+use crate::spool::Spool;
+use crate::{host, json};
+
+/// Seconds the broker may go without hearing from us before it drops the
+/// connection and publishes the `offline` will.
+const KEEPALIVE: u16 = 60;
+/// How long to wait for the broker while connecting, and for each reply.
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Time between attempts to reach the broker while it is unavailable.
+const RETRY: Duration = Duration::from_secs(30);
+
+// Control packet types, with the fixed flags where they are required.
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+
+/// Feeds the publisher thread, once [`start`] has been called.
+static UPDATES: Mutex<Option<Sender<Update>>> = Mutex::new(None);
+
+enum Update {
+    /// An event, already encoded as JSON.
+    Event(String),
+    /// A sensor's new state and the seq of the transition that set it.
+    State(Sensor, bool, u64),
+}
+
+/// The scanner state published as retained `{"state":"ON","seq":12}`
+/// topics, each a Home Assistant binary sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sensor {
+    Lid,
+    Paper,
+    Button,
+}
+
+impl Sensor {
+    const ALL: [Self; 3] = [Self::Lid, Self::Paper, Self::Button];
+
+    fn key(self) -> &'static str {
+        match self {
+            Self::Lid => "lid",
+            Self::Paper => "paper",
+            Self::Button => "button",
+        }
+    }
+
+    /// Name, device class and icon for Home Assistant.
+    fn discovery(self) -> (&'static str, Option<&'static str>, Option<&'static str>) {
+        match self {
+            Self::Lid => ("Lid", Some("opening"), None),
+            Self::Paper => ("Paper", None, Some("mdi:file-document-outline")),
+            Self::Button => ("Button", None, Some("mdi:gesture-tap-button")),
+        }
+    }
+}
+
+/// Start publishing to the broker from a background thread, which
+/// connects, reconnects and keeps the connection alive on its own — the
+/// event loop may be blocked on a handler for minutes.
+pub fn start(config: &MqttConfig) {
+    let (tx, rx) = mpsc::channel();
+    let publisher = Publisher::new(config.clone());
+    let spawned = thread::Builder::new()
+        .name("mqtt".into())
+        .spawn(move || publisher.run(&rx));
+    match spawned {
+        Ok(_) => *UPDATES.lock().unwrap_or_else(|e| e.into_inner()) = Some(tx),
+        Err(e) => warn!("mqtt: cannot start the publisher: {e}"),
+    }
+}
+
+fn send(update: Update) {
+    if let Some(tx) = UPDATES.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        let _ = tx.send(update);
+    }
+}
+
+/// Publish an event (see `event_json`) to `<prefix>/<node>/event`.
+pub fn event(json: &str) {
+    send(Update::Event(json.to_string()));
+}
+
+/// Update the lid, paper and button topics for a raw scanner transition,
+/// numbered `seq`.
+pub fn transition(ev: Event, seq: u64) {
+    let (sensor, on) = match ev {
+        Event::DeviceArrived => (Sensor::Lid, true),
+        Event::DeviceLeft => {
+            // Nothing is fed or pressed on a closed scanner.
+            send(Update::State(Sensor::Paper, false, seq));
+            send(Update::State(Sensor::Button, false, seq));
+            (Sensor::Lid, false)
+        }
+        Event::PaperIn => (Sensor::Paper, true),
+        Event::PaperOut => (Sensor::Paper, false),
+        Event::ButtonDown => (Sensor::Button, true),
+        Event::ButtonUp => (Sensor::Button, false),
+        _ => return,
+    };
+    send(Update::State(sensor, on, seq));
+}
+
+/// The topics for this scanner.
+struct Topics {
+    node: String,
+    base: String,
+}
+
+impl Topics {
+    fn new(config: &MqttConfig) -> Self {
+        let node = config.node_id.clone().unwrap_or_else(host::instance_id);
+        let base = format!("{}/{node}", config.topic_prefix);
+        Self { node, base }
+    }
+
+    fn availability(&self) -> String {
+        format!("{}/availability", self.base)
+    }
+
+    fn state(&self, sensor: Sensor) -> String {
+        format!("{}/{}", self.base, sensor.key())
+    }
+
+    fn event(&self) -> String {
+        format!("{}/event", self.base)
+    }
+}
+
+/// Home Assistant discovery configs, as (topic, payload): a binary sensor
+/// per [`Sensor`] and a sensor showing the last event.
+fn discovery(prefix: &str, topics: &Topics) -> Vec<(String, String)> {
+    let node = &topics.node;
+    let model = format!("ScanSnap {}", crate::model().name.to_uppercase());
+    let device = json::Object::new()
+        .raw(
+            "identifiers",
+            &json::array([json::string(&format!("s1500d_{node}"))]),
+        )
+        .str("name", &model)
+        .str("manufacturer", "Fujitsu")
+        .str("model", &model)
+        .str("sw_version", env!("CARGO_PKG_VERSION"))
+        .finish();
+    let entity = |name: &str, key: &str| {
+        json::Object::new()
+            .str("name", name)
+            .str("unique_id", &format!("s1500d_{node}_{key}"))
+            .str("availability_topic", &topics.availability())
+            .raw("device", &device)
+    };
+    let mut configs: Vec<(String, String)> = Sensor::ALL
+        .iter()
+        .map(|&sensor| {
+            let (name, class, icon) = sensor.discovery();
+            let topic = topics.state(sensor);
+            let mut payload = entity(name, sensor.key())
+                .str("state_topic", &topic)
+                .str("value_template", "{{ value_json.state }}")
+                .str("json_attributes_topic", &topic);
+            if let Some(class) = class {
+                payload = payload.str("device_class", class);
+            }
+            if let Some(icon) = icon {
+                payload = payload.str("icon", icon);
+            }
+            let topic = format!("{prefix}/binary_sensor/{node}/{}/config", sensor.key());
+            (topic, payload.finish())
+        })
+        .collect();
+    let last_event = entity("Last event", "event")
+        .str("state_topic", &topics.event())
+        .str("value_template", "{{ value_json.event }}")
+        .str("json_attributes_topic", &topics.event())
+        .str("icon", "mdi:scanner")
+        .finish();
+    configs.push((format!("{prefix}/sensor/{node}/event/config"), last_event));
+    configs
+}
+
+/// Owns the broker connection on the publisher thread.
+struct Publisher {
+    config: MqttConfig,
+    topics: Topics,
+    client: Option<Client>,
+    /// When to try the broker again after failing to reach it.
+    retry_at: Option<Instant>,
+    /// Last known state of each [`Sensor`] and its seq, republished on
+    /// every connect.
+    state: [(bool, u64); 3],
+    /// Events not yet acknowledged by the broker, oldest first.
+    backlog: VecDeque<String>,
+    /// Events dropped from a full backlog since the last report.
+    dropped: u64,
+    /// The backlog on disk (`spool`).
+    spool: Spool,
+}
+
+impl Publisher {
+    fn new(config: MqttConfig) -> Self {
+        let (spool, backlog) = Spool::open(config.spool.as_deref(), "mqtt");
+        Self {
+            topics: Topics::new(&config),
+            config,
+            client: None,
+            retry_at: None,
+            state: [(false, 0); 3],
+            backlog,
+            dropped: 0,
+            spool,
+        }
+    }
+
+    fn run(mut self, updates: &Receiver<Update>) {
+        loop {
+            self.connect();
+            self.flush();
+            let wait = match (&self.client, self.retry_at) {
+                (Some(_), _) => Duration::from_secs(u64::from(KEEPALIVE) / 2),
+                (None, Some(t)) => t.saturating_duration_since(Instant::now()),
+                (None, None) => RETRY,
+            };
+            match updates.recv_timeout(wait) {
+                Ok(Update::Event(json)) => self.queue(json),
+                Ok(Update::State(sensor, on, seq)) => {
+                    self.state[sensor as usize] = (on, seq);
+                    let topic = self.topics.state(sensor);
+                    let payload = state_json(on, seq);
+                    self.with(|c| c.publish(&topic, payload.as_bytes(), true));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.with(Client::ping);
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    /// Connect if disconnected and it's time, then announce the scanner:
+    /// discovery configs, availability and the current state.
+    fn connect(&mut self) {
+        if self.client.is_some() || self.retry_at.is_some_and(|t| Instant::now() < t) {
+            return;
+        }
+        let client_id = format!("s1500d-{}", self.topics.node);
+        match Client::connect(&self.config, &client_id, &self.topics.availability()) {
+            Ok(client) => {
+                info!(
+                    "mqtt: connected to {}:{}, publishing to {}",
+                    self.config.host, self.config.port, self.topics.base
+                );
+                self.client = Some(client);
+                self.retry_at = None;
+            }
+            Err(e) => {
+                warn!(
+                    "mqtt: cannot connect to {}:{}: {e} — retrying in {}s",
+                    self.config.host,
+                    self.config.port,
+                    RETRY.as_secs()
+                );
+                self.retry_at = Some(Instant::now() + RETRY);
+                return;
+            }
+        }
+        let mut announce = Vec::new();
+        if self.config.discovery {
+            announce = discovery(&self.config.discovery_prefix, &self.topics);
+        }
+        announce.push((self.topics.availability(), "online".into()));
+        for sensor in Sensor::ALL {
+            let (on, seq) = self.state[sensor as usize];
+            announce.push((self.topics.state(sensor), state_json(on, seq)));
+        }
+        for (topic, payload) in announce {
+            if self
+                .with(|c| c.publish(&topic, payload.as_bytes(), true))
+                .is_none()
+            {
+                return;
+            }
+        }
+        if self.dropped > 0 {
+            warn!(
+                "mqtt: {} events were dropped while the broker was unreachable",
+                std::mem::take(&mut self.dropped)
+            );
+        }
+    }
+
+    fn queue(&mut self, json: String) {
+        if self.backlog.len() >= self.config.backlog {
+            self.backlog.pop_front();
+            self.dropped += 1;
+        }
+        self.backlog.push_back(json);
+        self.save_spool();
+    }
+
+    /// Mirror the backlog to the spool.
+    fn save_spool(&self) {
+        if let Err(e) = self.spool.save(&self.backlog) {
+            warn!("mqtt: cannot update the spool: {e}");
+        }
+    }
+
+    /// Publish held events in order, each kept until the broker has it.
+    fn flush(&mut self) {
+        let topic = self.topics.event();
+        while let Some(json) = self.backlog.front() {
+            let json = json.clone();
+            if self
+                .with(|c| c.publish(&topic, json.as_bytes(), true))
+                .is_none()
+            {
+                if !self.backlog.is_empty() {
+                    debug!("mqtt: holding {} events", self.backlog.len());
+                }
+                return;
+            }
+            self.backlog.pop_front();
+            self.save_spool();
+        }
+    }
+
+    /// Run `f` on the connection, dropping it (to be reopened later) if it
+    /// fails.
+    fn with<T>(&mut self, f: impl FnOnce(&mut Client) -> io::Result<T>) -> Option<T> {
+        let result = f(self.client.as_mut()?);
+        result
+            .map_err(|e| {
+                warn!("mqtt: connection lost: {e}");
+                self.client = None;
+                self.retry_at = Some(Instant::now() + RETRY);
+            })
+            .ok()
+    }
+}
+
+/// A sensor topic's payload: `ON` or `OFF`, and the seq of the transition
+/// that set it (0 before any).
+fn state_json(on: bool, seq: u64) -> String {
+    let state = if on { "ON" } else { "OFF" };
+    json::Object::new()
+        .str("state", state)
+        .num("seq", seq)
+        .finish()
+}
+
+/// A minimal MQTT 3.1.1 client: publishing at QoS 1, nothing else.
+struct Client {
+    stream: TcpStream,
+    /// The last packet identifier used.
+    packet_id: u16,
+}
+
+impl Client {
+    /// Connect with a clean session and a retained `offline` will on
+    /// `will_topic`.
+    fn connect(config: &MqttConfig, client_id: &str, will_topic: &str) -> io::Result<Self> {
+        let mut stream = connect_tcp(&config.host, config.port)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let packet = connect_packet(
+            client_id,
+            config.username.as_deref(),
+            config.password.as_deref(),
+            will_topic,
+            b"offline",
+        );
+        stream.write_all(&packet)?;
+        match read_packet(&mut stream)? {
+            (CONNACK, body) if body.get(1) == Some(&0) => Ok(Self {
+                stream,
+                packet_id: 0,
+            }),
+            (CONNACK, body) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                connack_error(body.get(1).copied().unwrap_or(0xff)),
+            )),
+            (kind, _) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected CONNACK, got packet type {:#04x}", kind),
+            )),
+        }
+    }
+
+    /// Publish at QoS 1 and wait for the broker to acknowledge it.
+    fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+        // Packet identifiers must be non-zero.
+        self.packet_id = self.packet_id.wrapping_add(1).max(1);
+        let id = self.packet_id;
+        self.stream
+            .write_all(&publish_packet(topic, payload, retain, id))?;
+        loop {
+            let (kind, body) = read_packet(&mut self.stream)?;
+            if kind == PUBACK && body == id.to_be_bytes() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn ping(&mut self) -> io::Result<()> {
+        self.stream.write_all(&[PINGREQ, 0])?;
+        while read_packet(&mut self.stream)?.0 != PINGRESP {}
+        Ok(())
+    }
+}
+
+/// Connect to the first address of `host` that answers.
+fn connect_tcp(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+fn connack_error(code: u8) -> String {
+    match code {
+        1 => "broker refused the protocol version".into(),
+        2 => "broker rejected the client id".into(),
+        3 => "broker unavailable".into(),
+        4 => "bad username or password".into(),
+        5 => "not authorised".into(),
+        _ => format!("connection refused (code {code})"),
+    }
+}
+
+fn connect_packet(
+    client_id: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+    will_topic: &str,
+    will: &[u8],
+) -> Vec<u8> {
+    // Clean session, will at QoS 1, retained.
+    let mut flags = 0x02 | 0x04 | 0x08 | 0x20;
+    if username.is_some() {
+        flags |= 0x80;
+    }
+    if password.is_some() {
+        flags |= 0x40;
+    }
+    let mut body = Vec::new();
+    put_bytes(&mut body, b"MQTT");
+    body.push(4); // protocol level: 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&KEEPALIVE.to_be_bytes());
+    put_bytes(&mut body, client_id.as_bytes());
+    put_bytes(&mut body, will_topic.as_bytes());
+    put_bytes(&mut body, will);
+    for field in [username, password].into_iter().flatten() {
+        put_bytes(&mut body, field.as_bytes());
+    }
+    packet(CONNECT, &body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8], retain: bool, id: u16) -> Vec<u8> {
+    // QoS 1.
+    let mut first = PUBLISH | 0x02;
+    if retain {
+        first |= 0x01;
+    }
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 4);
+    put_bytes(&mut body, topic.as_bytes());
+    body.extend_from_slice(&id.to_be_bytes());
+    body.extend_from_slice(payload);
+    packet(first, &body)
+}
+
+/// A length-prefixed string or binary field.
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    let len = u16::try_from(bytes.len()).unwrap_or(u16::MAX);
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(&bytes[..usize::from(len)]);
+}
+
+/// The fixed header — type, flags and remaining length — then `body`.
+fn packet(first: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![first];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+    buf.extend_from_slice(body);
+    buf
+}
+
+/// Read one packet: its first byte (type and flags) and the rest after the
+/// length.
+fn read_packet(r: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+    r.read_exact(&mut byte)?;
+    let first = byte[0];
+    let mut len = 0usize;
+    for i in 0..4 {
+        r.read_exact(&mut byte)?;
+        len |= usize::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            let mut body = vec![0u8; len];
+            r.read_exact(&mut body)?;
+            return Ok((first, body));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "malformed remaining length",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn config(port: u16) -> MqttConfig {
+        MqttConfig {
+            host: "127.0.0.1".into(),
+            port,
+            topic_prefix: "s1500d".into(),
+            username: Some("u".into()),
+            password: Some("p".into()),
+            node_id: Some("office".into()),
+            discovery: true,
+            discovery_prefix: "homeassistant".into(),
+            spool: None,
+            backlog: 10,
+        }
+    }
+
+    #[test]
+    fn remaining_lengths() {
+        for len in [0, 1, 127, 128, 16383, 16384, 2_097_151] {
+            let encoded = packet(PINGRESP, &vec![7; len]);
+            let (kind, body) = read_packet(&mut encoded.as_slice()).unwrap();
+            assert_eq!((kind, body.len()), (PINGRESP, len));
+        }
+        assert_eq!(packet(PUBLISH, &[0; 128])[..3], [PUBLISH, 0x80, 0x01]);
+        assert!(read_packet(&mut [PUBACK, 0x80, 0x80, 0x80, 0x80].as_slice()).is_err());
+    }
+
+    #[test]
+    fn connect_packet_layout() {
+        let packet = connect_packet("c", Some("u"), Some("p"), "t/availability", b"offline");
+        let (kind, body) = read_packet(&mut packet.as_slice()).unwrap();
+        assert_eq!(kind, CONNECT);
+        assert_eq!(body[..8], [0, 4, b'M', b'Q', b'T', b'T', 4, 0xee]);
+        assert_eq!(body[8..10], 60u16.to_be_bytes());
+        assert!(body.ends_with(b"offline\0\x01u\0\x01p"));
+
+        let packet = connect_packet("c", None, None, "t", b"offline");
+        assert_eq!(read_packet(&mut packet.as_slice()).unwrap().1[7], 0x2e);
+    }
+
+    /// Accept one client, acknowledge everything it publishes until it
+    /// disconnects, and return what was published as (topic, payload,
+    /// retained).
+    fn broker(listener: TcpListener) -> Vec<(String, String, bool)> {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let (kind, _) = read_packet(&mut stream).unwrap();
+        assert_eq!(kind, CONNECT);
+        stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+        let mut published = Vec::new();
+        while let Ok((first, body)) = read_packet(&mut stream) {
+            match first & 0xf0 {
+                PUBLISH => {
+                    let len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+                    let topic = String::from_utf8(body[2..2 + len].to_vec()).unwrap();
+                    let id = &body[2 + len..4 + len];
+                    let payload = String::from_utf8(body[4 + len..].to_vec()).unwrap();
+                    stream.write_all(&[PUBACK, 2, id[0], id[1]]).unwrap();
+                    published.push((topic, payload, first & 0x01 != 0));
+                }
+                PINGREQ => stream.write_all(&[PINGRESP, 0]).unwrap(),
+                other => panic!("unexpected packet {other:#x}"),
+            }
+        }
+        published
+    }
+
+    #[test]
+    fn announces_state_and_publishes_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = thread::spawn(move || broker(listener));
+
+        let mut publisher = Publisher::new(config(port));
+        publisher.state[Sensor::Lid as usize] = (true, 3);
+        publisher.queue(r#"{"seq":1,"event":"paper-in"}"#.into());
+        publisher.connect();
+        publisher.flush();
+        assert!(publisher.client.is_some());
+        assert!(publisher.backlog.is_empty());
+        publisher.with(Client::ping).unwrap();
+        drop(publisher);
+
+        let published = broker.join().unwrap();
+        assert!(published.iter().all(|(_, _, retained)| *retained));
+        let topics: Vec<&str> = published.iter().map(|(t, _, _)| t.as_str()).collect();
+        assert_eq!(
+            topics,
+            [
+                "homeassistant/binary_sensor/office/lid/config",
+                "homeassistant/binary_sensor/office/paper/config",
+                "homeassistant/binary_sensor/office/button/config",
+                "homeassistant/sensor/office/event/config",
+                "s1500d/office/availability",
+                "s1500d/office/lid",
+                "s1500d/office/paper",
+                "s1500d/office/button",
+                "s1500d/office/event",
+            ]
+        );
+        let payloads: Vec<&str> = published[4..].iter().map(|(_, p, _)| p.as_str()).collect();
+        assert_eq!(
+            payloads,
+            [
+                "online",
+                r#"{"state":"ON","seq":3}"#,
+                r#"{"state":"OFF","seq":0}"#,
+                r#"{"state":"OFF","seq":0}"#,
+                r#"{"seq":1,"event":"paper-in"}"#
+            ]
+        );
+        let lid = &published[0].1;
+        assert!(lid.contains(r#""unique_id":"s1500d_office_lid""#));
+        assert!(lid.contains(r#""state_topic":"s1500d/office/lid""#));
+        assert!(lid.contains(r#""value_template":"{{ value_json.state }}""#));
+        assert!(lid.contains(r#""device_class":"opening""#));
+        assert!(lid.contains(r#""identifiers":["s1500d_office"]"#));
+    }
+
+    #[test]
+    fn events_wait_for_the_broker() {
+        // Nothing listens on this port once the listener is dropped.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut publisher = Publisher::new(config(port));
+        publisher.connect();
+        assert!(publisher.client.is_none());
+        assert!(publisher.retry_at.is_some());
+        for seq in 0..12 {
+            publisher.queue(seq.to_string());
+        }
+        publisher.flush();
+        assert_eq!(publisher.backlog.len(), 10);
+        assert_eq!(publisher.backlog.front().map(String::as_str), Some("2"));
+        assert_eq!(publisher.dropped, 2);
+    }
+}
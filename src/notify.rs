@@ -0,0 +1,194 @@
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+/// The service manager's notification socket, if systemd started us as a
+/// `Type=notify` unit; set once by [`init`].
+static NOTIFIER: OnceLock<Option<Notifier>> = OnceLock::new();
+
+/// Sends sd_notify(3) messages: readiness, status text and watchdog pings.
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    /// How often to ping: half of `WatchdogSec=`, or `None` without one.
+    watchdog: Option<Duration>,
+    last_ping: Mutex<Option<Instant>>,
+}
+
+impl Notifier {
+    /// A notifier for the socket at `path` (`@name` for the abstract
+    /// namespace).
+    pub fn new(path: &OsStr, watchdog: Option<Duration>) -> io::Result<Self> {
+        let addr = match path.as_bytes().strip_prefix(b"@") {
+            Some(name) => abstract_addr(name)?,
+            None => SocketAddr::from_pathname(path)?,
+        };
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            addr,
+            watchdog,
+            last_ping: Mutex::new(None),
+        })
+    }
+
+    pub fn send(&self, message: &str) -> io::Result<()> {
+        self.socket
+            .send_to_addr(message.as_bytes(), &self.addr)
+            .map(drop)
+    }
+
+    /// Ping the watchdog, unless the last ping was recent enough.
+    pub fn ping(&self) {
+        let Some(interval) = self.watchdog else {
+            return;
+        };
+        let mut last = self.last_ping.lock().unwrap_or_else(|e| e.into_inner());
+        if last.is_some_and(|t| t.elapsed() < interval) {
+            return;
+        }
+        *last = Some(Instant::now());
+        if let Err(e) = self.send("WATCHDOG=1") {
+            debug!("systemd: watchdog ping failed: {e}");
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &[u8]) -> io::Result<SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    SocketAddr::from_abstract_name(name)
+}
+
+/// Only Linux has the abstract namespace (or systemd).
+#[cfg(not(target_os = "linux"))]
+fn abstract_addr(_name: &[u8]) -> io::Result<SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract socket addresses are Linux-only",
+    ))
+}
+
+/// Pick up `NOTIFY_SOCKET` and `WATCHDOG_USEC` from the environment, and
+/// remove them so handlers don't inherit them.
+pub fn init() {
+    let path = std::env::var_os("NOTIFY_SOCKET");
+    let usec = std::env::var("WATCHDOG_USEC").ok();
+    // The watchdog is meant for us, not a process we were exec'd from.
+    let for_us =
+        std::env::var("WATCHDOG_PID").map_or(true, |pid| pid.parse() == Ok(std::process::id()));
+    for var in ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"] {
+        std::env::remove_var(var);
+    }
+    let watchdog = usec
+        .and_then(|u| u.parse::<u64>().ok())
+        .filter(|&u| u > 0 && for_us)
+        .map(|u| Duration::from_micros(u / 2));
+    let notifier = path.and_then(|p| {
+        Notifier::new(&p, watchdog)
+            .map_err(|e| warn!("systemd: cannot use NOTIFY_SOCKET {p:?}: {e}"))
+            .ok()
+    });
+    let _ = NOTIFIER.set(notifier);
+}
+
+fn notifier() -> Option<&'static Notifier> {
+    NOTIFIER.get().and_then(Option::as_ref)
+}
+
+fn send(message: &str) {
+    if let Some(n) = notifier() {
+        if let Err(e) = n.send(message) {
+            debug!("systemd: notify {message:?} failed: {e}");
+        }
+    }
+}
+
+/// Tell systemd start-up is finished.
+pub fn ready(status: &str) {
+    send(&format!("READY=1\nSTATUS={status}"));
+}
+
+/// Set the one-line status shown by `systemctl status`.
+pub fn status(status: &str) {
+    send(&format!("STATUS={status}"));
+}
+
+/// Ping the watchdog; call from every turn of the event loop.
+pub fn watchdog() {
+    if let Some(n) = notifier() {
+        n.ping();
+    }
+}
+
+/// Keeps the watchdog fed from another thread while the event loop is
+/// blocked waiting for a handler, which can legitimately take minutes.
+pub struct Keepalive {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Keepalive {
+    pub fn start() -> Self {
+        let Some(interval) = notifier().and_then(|n| n.watchdog) else {
+            return Self {
+                stop: None,
+                thread: None,
+            };
+        };
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || loop {
+            watchdog();
+            if stopped.recv_timeout(interval) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_reach_the_socket() {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-notify", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier::new(path.as_os_str(), Some(Duration::from_secs(60))).unwrap();
+
+        notifier.send("READY=1").unwrap();
+        notifier.ping();
+        // Too soon after the last one: not sent.
+        notifier.ping();
+        notifier.send("STATUS=x").unwrap();
+
+        let mut buf = [0u8; 64];
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let n = listener.recv(&mut buf).unwrap();
+            received.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+        }
+        assert_eq!(received, ["READY=1", "WATCHDOG=1", "STATUS=x"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,595 @@
+//! The S1500's USB protocol: command envelopes, GET_HW_STATUS decoding and
+//! the events derived from status changes.
+//!
+//! The scanner uses vendor-specific USB (class FF:FF:FF) with two bulk
+//! endpoints. SCSI commands are wrapped in a 31-byte envelope:
+//!
+//! ```text
+//! byte 0:     0x43  (Fujitsu USB_COMMAND_CODE)
+//! bytes 1-18: 0x00  (padding)
+//! bytes 19+:  SCSI CDB (up to 12 bytes)
+//! ```
+//!
+//! The protocol is 3-phase: command → data → status (0x53 envelope). The
+//! status envelope is 13 bytes with the SCSI status at byte 9; on CHECK
+//! CONDITION, REQUEST SENSE (0x03) says what went wrong.
+//!
+//! GET_HW_STATUS (SCSI 0xC2) returns 12 bytes:
+//! - byte\[3\] bit 7: hopper empty (inverted — 1 = empty, 0 = paper present)
+//! - byte\[4\] bit 5: scan button physically held
+//!
+//! Door state is not reported in GET_HW_STATUS because opening/closing the
+//! ADF lid powers the scanner on/off, which is a USB connect/disconnect event.
+
+use log::debug;
+
+/// The S1500's USB vendor and product IDs.
+pub const VID: u16 = 0x04C5;
+pub const PID: u16 = 0x11A2;
+pub const EP_OUT: u8 = 0x02;
+pub const EP_IN: u8 = 0x81;
+pub const IFACE: u8 = 0;
+
+/// Wrap a SCSI CDB in the 31-byte Fujitsu USB command envelope.
+pub fn envelope(cdb: &[u8]) -> [u8; 31] {
+    debug_assert!(cdb.len() <= 12, "CDB exceeds 12-byte envelope capacity");
+    let mut buf = [0u8; 31];
+    buf[0] = 0x43;
+    buf[19..19 + cdb.len()].copy_from_slice(cdb);
+    buf
+}
+
+/// GET_HW_STATUS CDB: opcode 0xC2, allocation length 12 (at CDB bytes 7-8).
+pub const GHS_CDB: [u8; 10] = [0xC2, 0, 0, 0, 0, 0, 0, 0, 0x0C, 0];
+
+/// INQUIRY CDB: opcode 0x12, allocation length 96.
+pub const INQUIRY_CDB: [u8; 6] = [0x12, 0, 0, 0, 0x60, 0];
+
+/// Who the scanner says it is, from standard INQUIRY data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inquiry {
+    /// `FUJITSU`.
+    pub vendor: String,
+    /// `ScanSnap S1500`.
+    pub product: String,
+    /// Firmware revision, e.g. `0M00`.
+    pub revision: String,
+}
+
+impl Inquiry {
+    /// Decode the vendor (bytes 8-15), product (16-31) and revision (32-35)
+    /// fields; `None` if the data is too short.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let field = |range: std::ops::Range<usize>| -> Option<String> {
+            let text = String::from_utf8_lossy(buf.get(range)?);
+            Some(
+                text.trim_matches(|c: char| c == ' ' || c == '\0')
+                    .to_string(),
+            )
+        };
+        Some(Self {
+            vendor: field(8..16)?,
+            product: field(16..32)?,
+            revision: field(32..36)?,
+        })
+    }
+}
+
+impl std::fmt::Display for Inquiry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}, firmware {}",
+            self.vendor, self.product, self.revision
+        )
+    }
+}
+
+/// REQUEST SENSE CDB: opcode 0x03, allocation length 18.
+pub const REQUEST_SENSE_CDB: [u8; 6] = [0x03, 0, 0, 0, 18, 0];
+
+/// Length of the status envelope that ends every command.
+pub const STATUS_LEN: usize = 13;
+
+/// SCSI status bytes.
+pub const STATUS_GOOD: u8 = 0x00;
+pub const STATUS_CHECK_CONDITION: u8 = 0x02;
+pub const STATUS_BUSY: u8 = 0x08;
+
+/// The SCSI status byte of a status envelope; `None` if `buf` isn't one.
+pub fn scsi_status(buf: &[u8]) -> Option<u8> {
+    (buf.len() == STATUS_LEN && buf[0] == 0x53).then(|| buf[9])
+}
+
+/// Sense data from REQUEST SENSE: why a command ended in CHECK CONDITION.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sense {
+    pub key: u8,
+    /// Additional sense code and qualifier.
+    pub asc: u8,
+    pub ascq: u8,
+}
+
+impl Sense {
+    /// Decode fixed-format sense data; `None` if it is short or another
+    /// format.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 14 || !matches!(buf[0] & 0x7f, 0x70 | 0x71) {
+            return None;
+        }
+        Some(Self {
+            key: buf[2] & 0x0f,
+            asc: buf[12],
+            ascq: buf[13],
+        })
+    }
+
+    /// NOT READY or UNIT ATTENTION: the scanner is busy or just reset, and
+    /// the next command will likely do.
+    pub fn is_transient(self) -> bool {
+        matches!(self.key, 0x2 | 0x6)
+    }
+
+    fn key_name(self) -> &'static str {
+        match self.key {
+            0x0 => "no sense",
+            0x1 => "recovered error",
+            0x2 => "not ready",
+            0x3 => "medium error",
+            0x4 => "hardware error",
+            0x5 => "illegal request",
+            0x6 => "unit attention",
+            0xb => "aborted command",
+            _ => "other",
+        }
+    }
+}
+
+impl std::fmt::Display for Sense {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "sense key {:#x} ({}), ASC {:#04x}, ASCQ {:#04x}",
+            self.key,
+            self.key_name(),
+            self.asc,
+            self.ascq
+        )
+    }
+}
+
+/// Snapshot of scanner hardware state, decoded from GET_HW_STATUS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct State {
+    /// Paper is present in the hopper.
+    pub paper: bool,
+    /// The scan button is held down.
+    pub button: bool,
+}
+
+impl State {
+    /// Decode an S1500 GET_HW_STATUS response; `None` if it is too short.
+    /// See [`Model::decode`] for other models.
+    pub fn from_response(buf: &[u8]) -> Option<Self> {
+        Model::S1500.decode(buf)
+    }
+}
+
+/// A ScanSnap model: its USB IDs and where GET_HW_STATUS puts the hopper and
+/// button bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Model {
+    pub name: &'static str,
+    pub vid: u16,
+    pub pid: u16,
+    /// Byte and mask of the hopper-empty flag (set = no paper).
+    pub hopper_empty: (usize, u8),
+    /// Byte and mask of the button bits (any set = pressed).
+    pub button: (usize, u8),
+}
+
+impl Model {
+    /// Verified with a physical scanner: bit 5 (0x20) of byte 4 is set while
+    /// the button is held, bit 0 (0x01) for about one poll after a tap.
+    pub const S1500: Self = Self {
+        name: "s1500",
+        vid: VID,
+        pid: PID,
+        hopper_empty: (3, 0x80),
+        button: (4, 0x21),
+    };
+
+    /// Unverified: the SANE `fujitsu` header's layout (button = byte 4,
+    /// bit 0).
+    pub const IX500: Self = Self {
+        name: "ix500",
+        vid: VID,
+        pid: 0x132B,
+        hopper_empty: (3, 0x80),
+        button: (4, 0x01),
+    };
+
+    /// Unverified: the SANE `fujitsu` header's layout.
+    pub const S1300I: Self = Self {
+        name: "s1300i",
+        vid: VID,
+        pid: 0x128D,
+        hopper_empty: (3, 0x80),
+        button: (4, 0x01),
+    };
+
+    pub const ALL: [Self; 3] = [Self::S1500, Self::IX500, Self::S1300I];
+
+    /// Look a model up by name (case-insensitive).
+    pub fn by_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|m| m.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Decode a GET_HW_STATUS response; `None` if it is too short.
+    pub fn decode(&self, buf: &[u8]) -> Option<State> {
+        let need = self.hopper_empty.0.max(self.button.0) + 1;
+        if buf.len() < need {
+            debug!("short response: {} bytes (need {need})", buf.len());
+            return None;
+        }
+        Some(State {
+            paper: buf[self.hopper_empty.0] & self.hopper_empty.1 == 0,
+            button: buf[self.button.0] & self.button.1 != 0,
+        })
+    }
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self::S1500
+    }
+}
+
+/// A change in the scanner's state: the device appearing or going away, or
+/// a paper or button transition from [`transitions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    DeviceArrived,
+    DeviceLeft,
+    PaperIn,
+    PaperOut,
+    ButtonDown,
+    ButtonUp,
+}
+
+impl Event {
+    /// The event's name as handlers and logs see it, e.g. `paper-in`.
+    pub const fn tag(self) -> &'static str {
+        match self {
+            Self::DeviceArrived => "device-arrived",
+            Self::DeviceLeft => "device-left",
+            Self::PaperIn => "paper-in",
+            Self::PaperOut => "paper-out",
+            Self::ButtonDown => "button-down",
+            Self::ButtonUp => "button-up",
+        }
+    }
+}
+
+/// Compare two states and yield the transition events between them.
+pub fn transitions(prev: State, curr: State) -> impl Iterator<Item = Event> {
+    [
+        (!prev.paper && curr.paper).then_some(Event::PaperIn),
+        (prev.paper && !curr.paper).then_some(Event::PaperOut),
+        (!prev.button && curr.button).then_some(Event::ButtonDown),
+        (prev.button && !curr.button).then_some(Event::ButtonUp),
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// The events between two looks at the scanner, `None` while it is absent.
+/// Arriving only counts as [`Event::DeviceArrived`]: what the scanner reads
+/// then is the baseline for the next look.
+pub fn changes(prev: Option<State>, curr: Option<State>) -> Vec<Event> {
+    match (prev, curr) {
+        (None, Some(_)) => vec![Event::DeviceArrived],
+        (Some(_), None) => vec![Event::DeviceLeft],
+        (Some(prev), Some(curr)) => transitions(prev, curr).collect(),
+        (None, None) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ── State::from_response ─────────────────────────────────────
+
+    #[test]
+    fn state_idle_scanner() {
+        // byte 3 = 0x80 (hopper empty), byte 4 = 0x00 (button not pressed)
+        let buf = [0, 0, 0, 0x80, 0x00, 0, 0, 0, 0, 0, 0, 0];
+        let s = State::from_response(&buf).unwrap();
+        assert!(!s.paper);
+        assert!(!s.button);
+    }
+
+    #[test]
+    fn state_paper_present() {
+        // byte 3 = 0x00 (bit 7 clear = paper present)
+        let buf = [0, 0, 0, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0];
+        let s = State::from_response(&buf).unwrap();
+        assert!(s.paper);
+        assert!(!s.button);
+    }
+
+    #[test]
+    fn state_button_held() {
+        // byte 4 = 0x20 (bit 5 = button held)
+        let buf = [0, 0, 0, 0x80, 0x20, 0, 0, 0, 0, 0, 0, 0];
+        let s = State::from_response(&buf).unwrap();
+        assert!(!s.paper);
+        assert!(s.button);
+    }
+
+    #[test]
+    fn state_button_momentary_tap() {
+        // byte 4 = 0x01 (bit 0 = momentary tap)
+        let buf = [0, 0, 0, 0x80, 0x01, 0, 0, 0, 0, 0, 0, 0];
+        let s = State::from_response(&buf).unwrap();
+        assert!(s.button);
+    }
+
+    #[test]
+    fn state_button_both_bits() {
+        // byte 4 = 0x21 (both button bits set)
+        let buf = [0, 0, 0, 0x80, 0x21, 0, 0, 0, 0, 0, 0, 0];
+        let s = State::from_response(&buf).unwrap();
+        assert!(s.button);
+    }
+
+    #[test]
+    fn state_paper_and_button() {
+        // byte 3 = 0x00 (paper present), byte 4 = 0x20 (button held)
+        let buf = [0, 0, 0, 0x00, 0x20, 0, 0, 0, 0, 0, 0, 0];
+        let s = State::from_response(&buf).unwrap();
+        assert!(s.paper);
+        assert!(s.button);
+    }
+
+    #[test]
+    fn state_short_buffer() {
+        assert!(State::from_response(&[0, 0]).is_none());
+    }
+
+    #[test]
+    fn state_empty_buffer() {
+        assert!(State::from_response(&[]).is_none());
+    }
+
+    #[test]
+    fn state_other_bits_ignored() {
+        // byte 3 has non-0x80 bits set but bit 7 is set → no paper
+        let buf = [0, 0, 0, 0xFF, 0x00, 0, 0, 0, 0, 0, 0, 0];
+        let s = State::from_response(&buf).unwrap();
+        assert!(!s.paper);
+
+        // byte 4 has bits set but not 0x20 or 0x01 → no button
+        let buf = [0, 0, 0, 0x80, 0xDE, 0, 0, 0, 0, 0, 0, 0];
+        let s = State::from_response(&buf).unwrap();
+        assert!(!s.button);
+    }
+
+    #[test]
+    fn model_layouts() {
+        // A tap on the S1500 sets only bit 0; a hold sets bit 5, which the
+        // SANE layout ignores.
+        let held = [0, 0, 0, 0x00, 0x20, 0, 0, 0, 0, 0, 0, 0];
+        assert!(Model::S1500.decode(&held).unwrap().button);
+        let state = Model::IX500.decode(&held).unwrap();
+        assert!(state.paper);
+        assert!(!state.button);
+
+        assert_eq!(Model::by_name("iX500"), Some(Model::IX500));
+        assert_eq!(Model::by_name("s1300i").map(|m| m.pid), Some(0x128D));
+        assert_eq!(Model::by_name("fi-7160"), None);
+
+        let custom = Model {
+            button: (9, 0x01),
+            ..Model::S1500
+        };
+        assert!(custom.decode(&[0; 9]).is_none());
+        assert!(custom.decode(&[0; 10]).is_some());
+    }
+
+    // ── envelope ─────────────────────────────────────────────────
+
+    #[test]
+    fn envelope_wraps_cdb() {
+        let cdb = [0xC2, 0, 0, 0, 0, 0, 0, 0, 0x0C, 0];
+        let env = envelope(&cdb);
+        assert_eq!(env[0], 0x43);
+        assert_eq!(&env[1..19], &[0u8; 18]);
+        assert_eq!(&env[19..29], &cdb);
+        assert_eq!(&env[29..31], &[0, 0]);
+    }
+
+    #[test]
+    fn envelope_short_cdb() {
+        let cdb = [0xAA];
+        let env = envelope(&cdb);
+        assert_eq!(env[0], 0x43);
+        assert_eq!(env[19], 0xAA);
+        assert_eq!(&env[20..31], &[0u8; 11]);
+    }
+
+    // ── status and sense ─────────────────────────────────────────
+
+    #[test]
+    fn status_envelope() {
+        let mut env = [0u8; 13];
+        env[0] = 0x53;
+        assert_eq!(scsi_status(&env), Some(STATUS_GOOD));
+        env[9] = STATUS_CHECK_CONDITION;
+        assert_eq!(scsi_status(&env), Some(STATUS_CHECK_CONDITION));
+        // Twelve bytes is the GET_HW_STATUS data, not a status envelope.
+        assert_eq!(scsi_status(&env[..12]), None);
+        env[0] = 0x43;
+        assert_eq!(scsi_status(&env), None);
+    }
+
+    #[test]
+    fn sense_data() {
+        // NOT READY, "logical unit is in process of becoming ready".
+        let mut buf = [0u8; 18];
+        buf[0] = 0x70;
+        buf[2] = 0x02;
+        buf[12] = 0x04;
+        buf[13] = 0x01;
+        let sense = Sense::parse(&buf).unwrap();
+        assert_eq!(
+            sense,
+            Sense {
+                key: 2,
+                asc: 0x04,
+                ascq: 0x01
+            }
+        );
+        assert!(sense.is_transient());
+        assert_eq!(
+            sense.to_string(),
+            "sense key 0x2 (not ready), ASC 0x04, ASCQ 0x01"
+        );
+
+        buf[2] = 0x04;
+        assert!(!Sense::parse(&buf).unwrap().is_transient());
+        assert_eq!(Sense::parse(&buf[..13]), None);
+        buf[0] = 0x72;
+        assert_eq!(Sense::parse(&buf), None);
+    }
+
+    #[test]
+    fn inquiry_data() {
+        let mut buf = [b' '; 96];
+        buf[..8].copy_from_slice(&[0x06, 0, 0x02, 0x02, 91, 0, 0, 0x10]);
+        buf[8..15].copy_from_slice(b"FUJITSU");
+        buf[16..30].copy_from_slice(b"ScanSnap S1500");
+        buf[32..36].copy_from_slice(b"0M00");
+        let inquiry = Inquiry::parse(&buf).unwrap();
+        assert_eq!(inquiry.vendor, "FUJITSU");
+        assert_eq!(inquiry.product, "ScanSnap S1500");
+        assert_eq!(inquiry.revision, "0M00");
+        assert_eq!(inquiry.to_string(), "FUJITSU ScanSnap S1500, firmware 0M00");
+        assert_eq!(Inquiry::parse(&buf[..35]), None);
+    }
+
+    // ── transitions ──────────────────────────────────────────────
+
+    #[test]
+    fn transitions_no_change() {
+        let s = State {
+            paper: false,
+            button: false,
+        };
+        let events: Vec<_> = transitions(s, s).collect();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn transitions_paper_in() {
+        let prev = State {
+            paper: false,
+            button: false,
+        };
+        let curr = State {
+            paper: true,
+            button: false,
+        };
+        let events: Vec<_> = transitions(prev, curr).collect();
+        assert_eq!(events, vec![Event::PaperIn]);
+    }
+
+    #[test]
+    fn transitions_paper_out() {
+        let prev = State {
+            paper: true,
+            button: false,
+        };
+        let curr = State {
+            paper: false,
+            button: false,
+        };
+        let events: Vec<_> = transitions(prev, curr).collect();
+        assert_eq!(events, vec![Event::PaperOut]);
+    }
+
+    #[test]
+    fn transitions_button_down() {
+        let prev = State {
+            paper: false,
+            button: false,
+        };
+        let curr = State {
+            paper: false,
+            button: true,
+        };
+        let events: Vec<_> = transitions(prev, curr).collect();
+        assert_eq!(events, vec![Event::ButtonDown]);
+    }
+
+    #[test]
+    fn transitions_button_up() {
+        let prev = State {
+            paper: false,
+            button: true,
+        };
+        let curr = State {
+            paper: false,
+            button: false,
+        };
+        let events: Vec<_> = transitions(prev, curr).collect();
+        assert_eq!(events, vec![Event::ButtonUp]);
+    }
+
+    #[test]
+    fn transitions_simultaneous() {
+        let prev = State {
+            paper: false,
+            button: false,
+        };
+        let curr = State {
+            paper: true,
+            button: true,
+        };
+        let events: Vec<_> = transitions(prev, curr).collect();
+        assert_eq!(events, vec![Event::PaperIn, Event::ButtonDown]);
+    }
+
+    #[test]
+    fn changes_cover_presence() {
+        let empty = State {
+            paper: false,
+            button: false,
+        };
+        let loaded = State {
+            paper: true,
+            button: false,
+        };
+        assert_eq!(changes(None, Some(loaded)), [Event::DeviceArrived]);
+        assert_eq!(changes(Some(empty), None), [Event::DeviceLeft]);
+        assert_eq!(changes(Some(empty), Some(loaded)), [Event::PaperIn]);
+        assert!(changes(Some(empty), Some(empty)).is_empty());
+        assert!(changes(None, None).is_empty());
+    }
+
+    // ── event tags ───────────────────────────────────────────────
+
+    #[test]
+    fn event_tags() {
+        assert_eq!(Event::DeviceArrived.tag(), "device-arrived");
+        assert_eq!(Event::DeviceLeft.tag(), "device-left");
+        assert_eq!(Event::PaperIn.tag(), "paper-in");
+        assert_eq!(Event::PaperOut.tag(), "paper-out");
+        assert_eq!(Event::ButtonDown.tag(), "button-down");
+        assert_eq!(Event::ButtonUp.tag(), "button-up");
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::VecDeque;
+
+use log::{debug, warn};
+use serde::Deserialize;
+
+use crate::config::Gesture;
+use crate::logging;
+
+/// Default number of dispatches that may wait in the queue.
+pub const DEFAULT_CAPACITY: usize = 16;
+
+/// What to do when a handler dispatch arrives and the queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backpressure {
+    /// Keep dispatches in order; drop new ones once the queue is full.
+    #[default]
+    Queue,
+    /// Keep only the latest pending dispatch per event type.
+    Coalesce,
+    /// Make room for new dispatches by dropping the oldest pending one.
+    DropOldest,
+}
+
+/// A handler invocation waiting to be run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pending {
+    /// Handler program and its fixed arguments.
+    pub command: Vec<String>,
+    /// Event arguments (`scan legal`, `paper-in`, …).
+    pub args: Vec<String>,
+    /// Sequence number of the event that caused this dispatch.
+    pub seq: u64,
+    /// The gesture that chose the profile, for `scan`.
+    pub gesture: Option<Gesture>,
+    /// When the dispatch was queued, in the log's timestamp format.
+    pub timestamp: Option<String>,
+}
+
+impl Pending {
+    /// The event type, used as the coalescing key (`scan`, `paper-in`, …).
+    fn kind(&self) -> &str {
+        self.args.first().map_or("", String::as_str)
+    }
+}
+
+/// Bounded FIFO of handler dispatches, drained one per poll cycle.
+///
+/// Handlers run synchronously, so several events detected in the same poll
+/// (e.g. paper-in and a gesture resolving together) have to wait their turn.
+#[derive(Debug)]
+pub struct DispatchQueue {
+    policy: Backpressure,
+    capacity: usize,
+    items: VecDeque<Pending>,
+}
+
+impl DispatchQueue {
+    pub fn new(policy: Backpressure, capacity: usize) -> Self {
+        Self {
+            policy,
+            capacity: capacity.max(1),
+            items: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, command: Vec<String>, args: Vec<String>, seq: u64) {
+        self.push_gesture(command, args, seq, None);
+    }
+
+    /// [`push`](Self::push) a dispatch caused by `gesture`.
+    pub fn push_gesture(
+        &mut self,
+        command: Vec<String>,
+        args: Vec<String>,
+        seq: u64,
+        gesture: Option<Gesture>,
+    ) {
+        // No handler configured (webhook only): nothing to run.
+        if command.is_empty() {
+            return;
+        }
+        let item = Pending {
+            command,
+            args,
+            seq,
+            gesture,
+            timestamp: logging::timestamp(),
+        };
+        if self.policy == Backpressure::Coalesce {
+            if let Some(i) = self.items.iter().position(|p| p.kind() == item.kind()) {
+                debug!("dispatch: coalescing pending {}", item.kind());
+                self.items.remove(i);
+            }
+        }
+        if self.items.len() >= self.capacity {
+            match self.policy {
+                Backpressure::DropOldest => {
+                    if let Some(old) = self.items.pop_front() {
+                        warn!("dispatch: queue full, dropping oldest {}", old.kind());
+                    }
+                }
+                Backpressure::Queue | Backpressure::Coalesce => {
+                    warn!("dispatch: queue full, dropping {}", item.kind());
+                    return;
+                }
+            }
+        }
+        self.items.push_back(item);
+    }
+
+    pub fn pop(&mut self) -> Option<Pending> {
+        self.items.pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Waiting dispatches, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Pending> {
+        self.items.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(q: &mut DispatchQueue, args: &[&str]) {
+        q.push(
+            vec!["/bin/h.sh".into()],
+            args.iter().map(|a| a.to_string()).collect(),
+            0,
+        );
+    }
+
+    fn drain(q: &mut DispatchQueue) -> Vec<String> {
+        std::iter::from_fn(|| q.pop())
+            .map(|p| p.args.join(" "))
+            .collect()
+    }
+
+    #[test]
+    fn queue_keeps_order_and_drops_newest() {
+        let mut q = DispatchQueue::new(Backpressure::Queue, 2);
+        push(&mut q, &["paper-in"]);
+        push(&mut q, &["scan", "standard"]);
+        push(&mut q, &["paper-out"]);
+        assert_eq!(drain(&mut q), vec!["paper-in", "scan standard"]);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn drop_oldest_makes_room() {
+        let mut q = DispatchQueue::new(Backpressure::DropOldest, 2);
+        push(&mut q, &["paper-in"]);
+        push(&mut q, &["scan", "standard"]);
+        push(&mut q, &["paper-out"]);
+        assert_eq!(drain(&mut q), vec!["scan standard", "paper-out"]);
+    }
+
+    #[test]
+    fn coalesce_keeps_latest_per_type() {
+        let mut q = DispatchQueue::new(Backpressure::Coalesce, 8);
+        push(&mut q, &["paper-in"]);
+        push(&mut q, &["scan", "standard"]);
+        push(&mut q, &["paper-out"]);
+        push(&mut q, &["scan", "legal"]);
+        assert_eq!(drain(&mut q), vec!["paper-in", "paper-out", "scan legal"]);
+    }
+
+    #[test]
+    fn nothing_queued_without_a_handler() {
+        let mut q = DispatchQueue::new(Backpressure::Queue, 2);
+        q.push(Vec::new(), vec!["paper-in".into()], 1);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn policy_names_deserialize() {
+        #[derive(Deserialize)]
+        struct T {
+            p: Backpressure,
+        }
+        let t: T = toml::from_str(r#"p = "drop-oldest""#).unwrap();
+        assert_eq!(t.p, Backpressure::DropOldest);
+        assert!(toml::from_str::<T>(r#"p = "lifo""#).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimit;
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// Why a handler run was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The event already ran `max_per_minute` times in the last minute.
+    PerMinute(u32),
+    /// The same arguments ran less than `min_interval` ago.
+    Repeat(Duration),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PerMinute(max) => write!(f, "over {max} per minute"),
+            Self::Repeat(min) => write!(f, "repeated within {}ms", min.as_millis()),
+        }
+    }
+}
+
+/// Gate in front of handler runs for a flapping sensor: at most
+/// `max_per_minute` runs of each event name in any minute, and no run
+/// with the same arguments as the last one within `min_interval` of it.
+#[derive(Debug, Default)]
+pub struct RateGate {
+    limits: RateLimit,
+    /// When each event ran within the last minute, oldest first.
+    recent: HashMap<String, VecDeque<Instant>>,
+    /// The arguments of the last run, and when it started.
+    last: Option<(Vec<String>, Instant)>,
+    /// Runs dropped since startup.
+    dropped: u64,
+}
+
+impl RateGate {
+    pub fn new(limits: RateLimit) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Switch to reloaded limits, keeping what has run so far.
+    pub fn set_limits(&mut self, limits: RateLimit) {
+        self.limits = limits;
+    }
+
+    /// Whether a run of `args` may start at `now`; a refusal counts as a
+    /// dropped run. Admitted runs are only counted once [`record`]ed.
+    ///
+    /// [`record`]: Self::record
+    pub fn admit(&mut self, args: &[&str], now: Instant) -> Result<(), Reason> {
+        let event = args.first().copied().unwrap_or_default();
+        let repeat = self.limits.min_interval.filter(|&min| {
+            self.last.as_ref().is_some_and(|(last, at)| {
+                last.iter().map(String::as_str).eq(args.iter().copied())
+                    && now.saturating_duration_since(*at) < min
+            })
+        });
+        let reason = if let Some(min) = repeat {
+            Some(Reason::Repeat(min))
+        } else if let Some(max) = self.limits.max_per_minute {
+            let runs = self.recent.get_mut(event).map_or(0, |runs| {
+                while runs
+                    .front()
+                    .is_some_and(|&t| now.saturating_duration_since(t) >= MINUTE)
+                {
+                    runs.pop_front();
+                }
+                runs.len()
+            });
+            (runs >= max as usize).then_some(Reason::PerMinute(max))
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => {
+                self.dropped += 1;
+                Err(reason)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Count a run of `args` starting at `now`.
+    pub fn record(&mut self, args: &[&str], now: Instant) {
+        let event = args.first().copied().unwrap_or_default();
+        if self.limits.max_per_minute.is_some() {
+            self.recent
+                .entry(event.to_string())
+                .or_default()
+                .push_back(now);
+        }
+        self.last = Some((args.iter().map(|&a| a.to_string()).collect(), now));
+    }
+
+    /// Runs dropped since startup.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_per_minute: Option<u32>, min_interval_ms: Option<u64>) -> RateLimit {
+        RateLimit {
+            max_per_minute,
+            min_interval: min_interval_ms.map(Duration::from_millis),
+        }
+    }
+
+    fn run(gate: &mut RateGate, args: &[&str], now: Instant) -> Result<(), Reason> {
+        gate.admit(args, now)?;
+        gate.record(args, now);
+        Ok(())
+    }
+
+    #[test]
+    fn limits_runs_per_event_per_minute() {
+        let mut gate = RateGate::new(limits(Some(2), None));
+        let t = Instant::now();
+        assert!(run(&mut gate, &["paper-in"], t).is_ok());
+        assert!(run(&mut gate, &["paper-in"], t + Duration::from_secs(10)).is_ok());
+        let third = run(&mut gate, &["paper-in"], t + Duration::from_secs(20));
+        assert_eq!(third, Err(Reason::PerMinute(2)));
+        // Each event has its own budget.
+        assert!(run(&mut gate, &["paper-out"], t + Duration::from_secs(20)).is_ok());
+        // A minute after the first run, there is room again.
+        assert!(run(&mut gate, &["paper-in"], t + Duration::from_secs(60)).is_ok());
+        assert_eq!(gate.dropped(), 1);
+    }
+
+    #[test]
+    fn drops_identical_runs_within_the_interval() {
+        let mut gate = RateGate::new(limits(None, Some(2000)));
+        let t = Instant::now();
+        assert!(run(&mut gate, &["scan", "legal"], t).is_ok());
+        let again = run(&mut gate, &["scan", "legal"], t + Duration::from_secs(1));
+        assert_eq!(again, Err(Reason::Repeat(Duration::from_secs(2))));
+        // Different arguments, or the interval over, go through.
+        assert!(run(&mut gate, &["scan", "photo"], t + Duration::from_secs(1)).is_ok());
+        assert!(run(&mut gate, &["scan", "photo"], t + Duration::from_secs(3)).is_ok());
+        assert_eq!(gate.dropped(), 1);
+
+        let mut open = RateGate::default();
+        for _ in 0..100 {
+            assert!(run(&mut open, &["paper-in"], t).is_ok());
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use s1500d::device::Response;
+
+use crate::simulate::Step;
+
+/// Longest pause `--replay` keeps between two responses. Longer gaps —
+/// the lid closed, or the next session appended to the file — are cut to
+/// this, which is still past any gesture window.
+pub const MAX_GAP: Duration = Duration::from_secs(5);
+
+/// Appends each GET_HW_STATUS response to a `--record` file: one line per
+/// poll, the Unix time in milliseconds followed by the bytes in hex.
+#[derive(Debug)]
+pub struct Recorder {
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Open `path` for appending, marking where this session starts.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut out = BufWriter::new(file);
+        writeln!(
+            out,
+            "# s1500d {}: unix time (ms), GET_HW_STATUS bytes",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        out.flush()?;
+        Ok(Self { out })
+    }
+
+    /// Append one response, flushed so a crash loses nothing.
+    pub fn record(&mut self, at: SystemTime, response: &Response) -> io::Result<()> {
+        let ms = at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        write!(self.out, "{ms}")?;
+        for b in response.bytes() {
+            write!(self.out, " {b:02x}")?;
+        }
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
+
+/// Turn a recording into `--simulate` steps: each response, preceded by
+/// the time since the one before (at most [`MAX_GAP`]).
+pub fn steps(text: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    let mut last: Option<u64> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (ms, response) = parse_line(line).map_err(|e| format!("line {}: {e}", i + 1))?;
+        if let Some(last) = last {
+            let gap = Duration::from_millis(ms.saturating_sub(last));
+            steps.push(Step::Sleep(gap.min(MAX_GAP)));
+        }
+        last = Some(ms);
+        steps.push(Step::Status(response));
+    }
+    Ok(steps)
+}
+
+fn parse_line(line: &str) -> Result<(u64, Response), String> {
+    let mut words = line.split_whitespace();
+    let ms = words
+        .next()
+        .and_then(|w| w.parse().ok())
+        .ok_or("expected a time in milliseconds")?;
+    let bytes = words
+        .map(|w| u8::from_str_radix(w, 16).map_err(|_| format!("{w:?} is not a hex byte")))
+        .collect::<Result<Vec<u8>, String>>()?;
+    if bytes.len() > 12 {
+        return Err(format!("{} bytes; a response has at most 12", bytes.len()));
+    }
+    Ok((ms, Response::new(&bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_round_trips() {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-record", std::process::id()));
+        let path = path.to_str().unwrap();
+        let at = UNIX_EPOCH + Duration::from_millis(1_000);
+        let response = Response::new(&[0, 0, 0, 0x80, 0x01, 0, 0, 0, 0, 0, 0, 0]);
+        let mut recorder = Recorder::open(path).unwrap();
+        recorder.record(at, &response).unwrap();
+        recorder
+            .record(at + Duration::from_millis(100), &response)
+            .unwrap();
+        drop(recorder);
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert!(text.ends_with("1100 00 00 00 80 01 00 00 00 00 00 00 00\n"));
+        assert_eq!(
+            steps(&text),
+            Ok(vec![
+                Step::Status(response),
+                Step::Sleep(Duration::from_millis(100)),
+                Step::Status(response),
+            ])
+        );
+    }
+
+    #[test]
+    fn long_gaps_are_cut() {
+        let text = "1000 00\n# next session\n999000 00\n";
+        assert_eq!(steps(text).unwrap()[1], Step::Sleep(MAX_GAP));
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!(steps("1000 zz").unwrap_err().starts_with("line 1:"));
+        assert!(steps("soon 00").is_err());
+        assert!(steps(&format!("1000{}", " 00".repeat(13))).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use log::{debug, error, warn};
+
+use crate::config::ScanProfile;
+
+/// The program that does the scanning.
+pub const SCANIMAGE: &str = "scanimage";
+
+/// How a scan went.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Outcome {
+    /// Pages written to the scan's directory.
+    pub pages: u32,
+    /// Whether scanimage finished without an error.
+    pub ok: bool,
+}
+
+/// SANE's name for the scanner at this USB address.
+pub fn device_name(device: &rusb::Device<rusb::Context>) -> String {
+    format!(
+        "fujitsu:libusb:{:03}:{:03}",
+        device.bus_number(),
+        device.address()
+    )
+}
+
+/// A new directory for one scan of `name`: `output_dir/NAME-YYYYMMDD-HHMMSS`
+/// in local time.
+pub fn output_dir(name: &str, profile: &ScanProfile) -> PathBuf {
+    let now = jiff::Zoned::now().strftime("%Y%m%d-%H%M%S").to_string();
+    Path::new(&profile.output_dir).join(format!("{name}-{now}"))
+}
+
+/// scanimage's arguments to scan every sheet in the feeder into `dir`, one
+/// file per page.
+fn args(profile: &ScanProfile, device: &str, dir: &Path) -> Vec<String> {
+    vec![
+        format!("--device-name={device}"),
+        format!("--source={}", profile.source),
+        format!("--mode={}", profile.mode),
+        format!("--resolution={}", profile.resolution),
+        format!("--format={}", profile.format),
+        format!("--batch={}/page-%04d.{}", dir.display(), profile.format),
+    ]
+}
+
+/// The page number from scanimage's `Scanned page N. (scanner status = …)`.
+fn scanned_page(line: &str) -> Option<u32> {
+    let rest = line.strip_prefix("Scanned page ")?;
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..digits].parse().ok()
+}
+
+/// Run `program` (scanimage) to scan the feeder into `dir`, calling
+/// `progress` with each page number as it finishes. The device must be
+/// free, so the caller releases its USB interface first. A scan that
+/// produced no pages leaves no directory behind.
+pub fn run(
+    program: &str,
+    profile: &ScanProfile,
+    device: &str,
+    dir: &Path,
+    mut progress: impl FnMut(u32),
+) -> Outcome {
+    let failed = Outcome {
+        pages: 0,
+        ok: false,
+    };
+    if let Err(e) = fs::create_dir_all(dir) {
+        error!("scan: cannot create {}: {e}", dir.display());
+        return failed;
+    }
+    let args = args(profile, device, dir);
+    debug!("scan: {program} {}", args.join(" "));
+    let mut child = match Command::new(program)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            error!("scan: cannot run {program}: {e}");
+            let _ = fs::remove_dir(dir);
+            return failed;
+        }
+    };
+    // Keep the last message: it says why a scan stopped early.
+    let mut last = String::new();
+    if let Some(stderr) = child.stderr.take() {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            debug!("scan: {line}");
+            if let Some(page) = scanned_page(&line) {
+                progress(page);
+            }
+            if !line.trim().is_empty() {
+                last = line;
+            }
+        }
+    }
+    let status = match child.wait() {
+        Ok(status) => status,
+        Err(e) => {
+            error!("scan: cannot wait for {program}: {e}");
+            return failed;
+        }
+    };
+    let pages = fs::read_dir(dir).map_or(0, |entries| {
+        entries
+            .filter_map(Result::ok)
+            .filter(|e| e.file_name().to_string_lossy().starts_with("page-"))
+            .count() as u32
+    });
+    let ok = status.success() && pages > 0;
+    if !ok {
+        warn!("scan: {program} {status}: {last}");
+    }
+    if pages == 0 {
+        let _ = fs::remove_dir(dir);
+    }
+    Outcome { pages, ok }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn profile() -> ScanProfile {
+        ScanProfile {
+            output_dir: "/srv/scans".into(),
+            resolution: 300,
+            mode: "Color".into(),
+            source: "ADF Duplex".into(),
+            format: "png".into(),
+        }
+    }
+
+    #[test]
+    fn scanimage_arguments() {
+        let args = args(
+            &profile(),
+            "fujitsu:libusb:001:004",
+            Path::new("/srv/scans/a"),
+        );
+        assert_eq!(
+            args,
+            [
+                "--device-name=fujitsu:libusb:001:004",
+                "--source=ADF Duplex",
+                "--mode=Color",
+                "--resolution=300",
+                "--format=png",
+                "--batch=/srv/scans/a/page-%04d.png",
+            ]
+        );
+        let dir = output_dir("legal", &profile());
+        let leaf = dir.file_name().unwrap().to_str().unwrap();
+        assert!(dir.starts_with("/srv/scans"));
+        assert!(leaf.starts_with("legal-") && leaf.len() == "legal-20260314-092653".len());
+    }
+
+    #[test]
+    fn progress_lines() {
+        assert_eq!(
+            scanned_page("Scanned page 3. (scanner status = 5)"),
+            Some(3)
+        );
+        assert_eq!(scanned_page("Scanning page 3"), None);
+        assert_eq!(scanned_page("Batch terminated, 3 pages scanned"), None);
+    }
+
+    #[test]
+    fn counts_the_pages_written() {
+        let base = std::env::temp_dir().join(format!("s1500d-{}-scan", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(&base).unwrap();
+        // Stands in for scanimage: writes two pages where --batch says.
+        let fake = base.join("scanimage");
+        fs::write(
+            &fake,
+            "#!/bin/sh\n\
+             for a; do case $a in --batch=*) pattern=${a#--batch=};; esac; done\n\
+             for n in 1 2; do\n\
+               echo \"Scanning page $n\" >&2\n\
+               : > \"$(printf \"$pattern\" $n)\"\n\
+               echo \"Scanned page $n. (scanner status = 5)\" >&2\n\
+             done\n\
+             echo 'scanimage: sane_start: Document feeder out of documents' >&2\n",
+        )
+        .unwrap();
+        fs::set_permissions(&fake, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut seen = Vec::new();
+        let dir = base.join("out");
+        let outcome = run(fake.to_str().unwrap(), &profile(), "test", &dir, |p| {
+            seen.push(p)
+        });
+        assert_eq!(outcome, Outcome { pages: 2, ok: true });
+        assert_eq!(seen, [1, 2]);
+        assert!(dir.join("page-0002.png").exists());
+
+        // Nothing scanned: the empty directory is removed again.
+        let dir = base.join("empty");
+        let outcome = run("/bin/false", &profile(), "test", &dir, |_| {});
+        assert_eq!(
+            outcome,
+            Outcome {
+                pages: 0,
+                ok: false
+            }
+        );
+        assert!(!dir.exists());
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
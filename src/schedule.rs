@@ -0,0 +1,109 @@
+use std::fmt;
+
+/// A daily time window such as `09:00-17:00`, in minutes since local midnight.
+///
+/// Windows where `from` is later than `to` wrap past midnight
+/// (`22:00-07:00` covers the night). The end is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    from: u32,
+    to: u32,
+}
+
+impl TimeWindow {
+    pub fn parse(from: &str, to: &str) -> Result<Self, String> {
+        let window = Self {
+            from: parse_hhmm(from)?,
+            to: parse_hhmm(to)?,
+        };
+        if window.from == window.to {
+            return Err(format!("time window {window} is empty"));
+        }
+        Ok(window)
+    }
+
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.from < self.to {
+            (self.from..self.to).contains(&minute)
+        } else {
+            minute >= self.from || minute < self.to
+        }
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.from / 60,
+            self.from % 60,
+            self.to / 60,
+            self.to % 60
+        )
+    }
+}
+
+/// Parse `HH:MM` (24-hour) into minutes since midnight.
+fn parse_hhmm(s: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid time {s:?} (expected HH:MM)");
+    let (h, m) = s.split_once(':').ok_or_else(invalid)?;
+    let h: u32 = h.parse().map_err(|_| invalid())?;
+    let m: u32 = m.parse().map_err(|_| invalid())?;
+    if h > 23 || m > 59 {
+        return Err(invalid());
+    }
+    Ok(h * 60 + m)
+}
+
+/// Current local time of day, in minutes since midnight.
+///
+/// Uses the system time zone (`TZ` / `/etc/localtime`), falling back to
+/// UTC if the local time cannot be determined.
+pub fn local_minute_of_day() -> u32 {
+    let now = jiff::Zoned::now();
+    u32::from(now.hour().unsigned_abs()) * 60 + u32::from(now.minute().unsigned_abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_valid_window() {
+        let w = TimeWindow::parse("09:00", "17:30").unwrap();
+        assert_eq!(w.to_string(), "09:00-17:30");
+    }
+
+    #[test]
+    fn parse_invalid_times() {
+        assert!(TimeWindow::parse("9", "17:00").is_err());
+        assert!(TimeWindow::parse("24:00", "17:00").is_err());
+        assert!(TimeWindow::parse("09:60", "17:00").is_err());
+        assert!(TimeWindow::parse("09:00", "09:00").is_err());
+    }
+
+    #[test]
+    fn daytime_window() {
+        let w = TimeWindow::parse("09:00", "17:00").unwrap();
+        assert!(!w.contains(8 * 60 + 59));
+        assert!(w.contains(9 * 60));
+        assert!(w.contains(16 * 60 + 59));
+        assert!(!w.contains(17 * 60));
+    }
+
+    #[test]
+    fn window_wraps_midnight() {
+        let w = TimeWindow::parse("22:00", "07:00").unwrap();
+        assert!(w.contains(23 * 60));
+        assert!(w.contains(0));
+        assert!(w.contains(6 * 60 + 59));
+        assert!(!w.contains(7 * 60));
+        assert!(!w.contains(12 * 60));
+    }
+
+    #[test]
+    fn local_time_in_range() {
+        assert!(local_minute_of_day() < 24 * 60);
+    }
+}
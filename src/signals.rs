@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Highest signal number tracked (covers the standard signals).
+const MAX_SIGNAL: usize = 32;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CLEAR: AtomicBool = AtomicBool::new(false);
+
+/// Signals received but not yet handled by the event loop.
+static PENDING: [AtomicBool; MAX_SIGNAL] = [CLEAR; MAX_SIGNAL];
+
+extern "C" fn on_signal(sig: libc::c_int) {
+    // Only an atomic store: async-signal-safe.
+    if let Some(flag) = usize::try_from(sig).ok().and_then(|s| PENDING.get(s)) {
+        flag.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Catch `sig` and record it for [`take`] instead of the default action.
+///
+/// The event loop checks for recorded signals once per poll cycle, so all
+/// real work happens outside the signal handler.
+pub fn install(sig: libc::c_int) {
+    // SAFETY: on_signal only performs an atomic store.
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_signal;
+        libc::signal(sig, handler as libc::sighandler_t);
+    }
+}
+
+/// Whether `sig` has arrived since the last call, clearing it.
+pub fn take(sig: libc::c_int) -> bool {
+    usize::try_from(sig)
+        .ok()
+        .and_then(|s| PENDING.get(s))
+        .is_some_and(|flag| flag.swap(false, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_is_recorded_once() {
+        install(libc::SIGUSR1);
+        assert!(!take(libc::SIGUSR1));
+        // SAFETY: raise has no preconditions; SIGUSR1 is caught above.
+        unsafe {
+            libc::raise(libc::SIGUSR1);
+        }
+        assert!(take(libc::SIGUSR1));
+        assert!(!take(libc::SIGUSR1));
+    }
+}
@@ -0,0 +1,251 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use s1500d::device::Response;
+use s1500d::protocol::State;
+
+use crate::debounce::Debouncer;
+use crate::queue::{self, Backpressure, DispatchQueue};
+use crate::{
+    check_gesture_timeout, cooling_down, is_scan, model, process_transitions,
+    report_handler_failures, report_notices, report_watches, scan_cooldown, settle, take_action,
+    watch, watches, Batch, Dispatcher, GestureState, Job, Mode, POLL_INTERVAL,
+};
+
+/// One line of a `--simulate` script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// `sleep MS`: let time pass, as polls would.
+    Sleep(Duration),
+    /// `paper-in`, `paper-out`.
+    Paper(bool),
+    /// `button-down`, `button-up`.
+    Button(bool),
+    /// A raw GET_HW_STATUS response, from a `--replay` recording. Decoded,
+    /// debounced and checked for `[watch]` bits like a live poll.
+    Status(Response),
+}
+
+/// Parse a script: one step per line, `#` comments and blank lines
+/// ignored.
+pub fn parse(text: &str) -> Result<Vec<Step>, String> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            (!line.is_empty()).then(|| parse_step(line).map_err(|e| format!("line {}: {e}", i + 1)))
+        })
+        .collect()
+}
+
+fn parse_step(line: &str) -> Result<Step, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    Ok(match words.as_slice() {
+        ["sleep", ms] => Step::Sleep(Duration::from_millis(
+            ms.parse()
+                .map_err(|_| format!("sleep takes milliseconds, not {ms:?}"))?,
+        )),
+        ["paper-in"] => Step::Paper(true),
+        ["paper-out"] => Step::Paper(false),
+        ["button-down"] => Step::Button(true),
+        ["button-up"] => Step::Button(false),
+        _ => {
+            return Err(format!(
+                "unknown step {line:?} (expected sleep MS, paper-in, paper-out, \
+                 button-down or button-up)"
+            ))
+        }
+    })
+}
+
+/// What the poll loop keeps between cycles, minus the scanner.
+struct Simulation {
+    dispatcher: Dispatcher,
+    queue: DispatchQueue,
+    gesture: GestureState,
+    batch: Option<Batch>,
+    debouncer: Debouncer,
+    /// The `[watch]` bits as of the last [`Step::Status`].
+    watched: Option<u64>,
+    /// Until when sensor changes are ignored after a scan.
+    cooldown: Option<Instant>,
+}
+
+impl Simulation {
+    /// One poll cycle without the poll: complete a gesture whose window
+    /// has run out, and run at most one queued handler.
+    fn tick(&mut self, mode: &Mode, state: State) {
+        if let Some(action) = check_gesture_timeout(&self.gesture, mode, state.paper) {
+            let completed = self.gesture.completed();
+            self.gesture = GestureState::Idle;
+            let job = take_action(
+                action,
+                completed,
+                mode,
+                &mut self.dispatcher,
+                &mut self.batch,
+                &mut self.queue,
+            );
+            if let Some(Job::Scan(profile, _)) = job.filter(|job| !self.dispatcher.drops(job)) {
+                info!("simulate: not scanning {profile} (no scanner)");
+                self.cooldown = scan_cooldown(mode);
+            }
+        }
+        report_handler_failures(mode, &mut self.dispatcher, &mut self.queue);
+        report_notices(mode, &mut self.dispatcher, Some(&mut self.queue));
+        if let Some(job) = self.queue.pop() {
+            if !self.dispatcher.drops_pending(&job) {
+                self.dispatcher.dispatch_pending(&job);
+                if is_scan(&job.args) {
+                    self.cooldown = scan_cooldown(mode);
+                }
+            }
+        }
+    }
+
+    /// The state a response leaves the sensors in, after announcing any
+    /// `[watch]` bits it changed; `None` if it doesn't decode.
+    fn poll(&mut self, mode: &Mode, state: State, response: &Response) -> Option<State> {
+        let Some(raw) = model().decode(response.bytes()) else {
+            warn!(
+                "simulate: response too short to decode: {:02x?}",
+                response.bytes()
+            );
+            return None;
+        };
+        let bits = watch::read(watches(mode), response.bytes());
+        if let Some(before) = self.watched.filter(|&before| before != bits) {
+            report_watches(before, bits, mode, &mut self.queue);
+        }
+        self.watched = Some(bits);
+        Some(
+            self.debouncer
+                .filter(state, raw, settle(mode), Instant::now()),
+        )
+    }
+
+    /// Let `duration` pass a poll interval at a time.
+    fn wait(&mut self, mode: &Mode, state: State, duration: Duration) {
+        let until = Instant::now() + duration;
+        loop {
+            self.tick(mode, state);
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return;
+            }
+            thread::sleep(left.min(POLL_INTERVAL));
+        }
+    }
+}
+
+/// Play `steps` through the daemon's transition and gesture handling in
+/// place of the scanner, dispatching to the real handler. Starts with the
+/// feeder empty and the button up — or, for a replay, as the first
+/// response has them. Once the script ends, waits for a gesture still in
+/// progress and runs everything queued.
+pub fn run(mode: &Mode, steps: &[Step]) {
+    let queue = match mode {
+        Mode::ConfigMode(c) => DispatchQueue::new(c.backpressure, c.queue_size),
+        _ => DispatchQueue::new(Backpressure::default(), queue::DEFAULT_CAPACITY),
+    };
+    let mut sim = Simulation {
+        dispatcher: Dispatcher::new(mode),
+        queue,
+        gesture: GestureState::Idle,
+        batch: None,
+        debouncer: Debouncer::default(),
+        watched: None,
+        cooldown: None,
+    };
+    let mut state = State {
+        paper: false,
+        button: false,
+    };
+    for &step in steps {
+        let next = match step {
+            Step::Sleep(duration) => {
+                sim.wait(mode, state, duration);
+                continue;
+            }
+            Step::Paper(paper) => State { paper, ..state },
+            Step::Button(button) => State { button, ..state },
+            Step::Status(response) => {
+                // The first response is the baseline, as on arrival.
+                let first = sim.watched.is_none();
+                let Some(next) = sim.poll(mode, state, &response) else {
+                    continue;
+                };
+                if first {
+                    info!("initial: paper={} button={}", next.paper, next.button);
+                    state = next;
+                    continue;
+                }
+                next
+            }
+        };
+        if cooling_down(&mut sim.cooldown, state, next) {
+            state = next;
+            sim.tick(mode, state);
+            continue;
+        }
+        process_transitions(
+            state,
+            next,
+            mode,
+            &mut sim.gesture,
+            &mut sim.batch,
+            &mut sim.queue,
+        );
+        state = next;
+        sim.tick(mode, state);
+    }
+    // A released or held button always completes; one still down may not.
+    while matches!(
+        sim.gesture,
+        GestureState::Released(..) | GestureState::Held(..)
+    ) {
+        sim.wait(mode, state, POLL_INTERVAL);
+    }
+    while let Some(job) = sim.queue.pop() {
+        if !sim.dispatcher.drops_pending(&job) {
+            sim.dispatcher.dispatch_pending(&job);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_steps() {
+        let script = "\
+# double press
+button-down
+sleep 80   # held briefly
+button-up
+
+paper-in
+";
+        assert_eq!(
+            parse(script),
+            Ok(vec![
+                Step::Button(true),
+                Step::Sleep(Duration::from_millis(80)),
+                Step::Button(false),
+                Step::Paper(true),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_bad_steps() {
+        assert!(parse("button-down\npress\n")
+            .unwrap_err()
+            .starts_with("line 2:"));
+        assert!(parse("sleep").is_err());
+        assert!(parse("sleep 1s").is_err());
+        assert!(parse("paper-in now").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use log::{info, warn};
+
+/// Copy on disk of the events a network sink (MQTT, webhook) is holding,
+/// so a restart during an outage doesn't lose them.
+///
+/// The file holds one event per line, oldest first. Like the journal, it is
+/// rewritten atomically (and synced) whenever the held set changes, and
+/// read back when the sink starts.
+#[derive(Debug, Default)]
+pub struct Spool {
+    /// `None` keeps events in memory only.
+    path: Option<PathBuf>,
+}
+
+impl Spool {
+    /// Open the spool at `path` for `sink` (used in log messages), returning
+    /// the events a previous run left in it.
+    pub fn open(path: Option<&str>, sink: &str) -> (Self, VecDeque<String>) {
+        let Some(path) = path else {
+            return (Self::default(), VecDeque::new());
+        };
+        let held: VecDeque<String> = match fs::read_to_string(path) {
+            Ok(text) => text.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => {
+                warn!("{sink}: cannot read spool {path}: {e}");
+                VecDeque::new()
+            }
+        };
+        if !held.is_empty() {
+            info!("{sink}: {} spooled events to send", held.len());
+        }
+        let spool = Self {
+            path: Some(path.into()),
+        };
+        (spool, held)
+    }
+
+    /// Replace the file's contents with `held`, oldest first.
+    pub fn save<I>(&self, held: I) -> io::Result<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        for line in held {
+            file.write_all(line.as_ref().as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_events_survive_reopen() {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-spool", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let (spool, held) = Spool::open(Some(path), "test");
+        assert!(held.is_empty());
+        spool.save([r#"{"seq":1}"#, r#"{"seq":2}"#]).unwrap();
+        let (spool, held) = Spool::open(Some(path), "test");
+        assert_eq!(held, [r#"{"seq":1}"#, r#"{"seq":2}"#]);
+
+        spool.save(std::iter::empty::<&str>()).unwrap();
+        assert!(Spool::open(Some(path), "test").1.is_empty());
+        fs::remove_file(path).unwrap();
+
+        // Without a path nothing touches the disk.
+        let (spool, held) = Spool::open(None, "test");
+        assert!(held.is_empty());
+        spool.save(["x"]).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use s1500d::protocol::State;
+use serde::Deserialize;
+
+/// What the first poll after startup reports when there is no saved state
+/// to compare it with (`on_start`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnStart {
+    /// Take the scanner as it is as the baseline; nothing fires.
+    #[default]
+    Quiet,
+    /// Fire `paper-in` if paper is already loaded, as if it had just been.
+    EmitCurrent,
+}
+
+/// What the daemon last knew about the scanner, kept in `state_file` so a
+/// restart carries on from it instead of from nothing.
+///
+/// The file is one line, `present=1 paper=1 button=0`, or `present=0` while
+/// the scanner is away; it is rewritten atomically on every change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Saved {
+    /// Whether the scanner was attached.
+    pub present: bool,
+    /// Its sensors as of the last poll, while present.
+    pub state: Option<State>,
+}
+
+impl Saved {
+    pub const ABSENT: Self = Self {
+        present: false,
+        state: None,
+    };
+}
+
+/// Read the saved state; `None` if there is no file yet. A file that
+/// doesn't parse is an error, so it isn't silently taken as absent.
+pub fn load(path: &Path) -> io::Result<Option<Saved>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    parse(text.trim())
+        .map(Some)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unrecognised contents"))
+}
+
+/// Atomically replace the file with `saved`.
+pub fn save(path: &Path, saved: Saved) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(format!("{}\n", format(saved)).as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+fn format(saved: Saved) -> String {
+    match saved.state.filter(|_| saved.present) {
+        Some(s) => format!(
+            "present=1 paper={} button={}",
+            u8::from(s.paper),
+            u8::from(s.button)
+        ),
+        None => format!("present={}", u8::from(saved.present)),
+    }
+}
+
+fn parse(line: &str) -> Option<Saved> {
+    let mut present = None;
+    let (mut paper, mut button) = (None, None);
+    for field in line.split_whitespace() {
+        let (key, value) = field.split_once('=')?;
+        let value = match value {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        };
+        match key {
+            "present" => present = Some(value),
+            "paper" => paper = Some(value),
+            "button" => button = Some(value),
+            _ => return None,
+        }
+    }
+    let present = present?;
+    let state = match (paper, button) {
+        (Some(paper), Some(button)) if present => Some(State { paper, button }),
+        _ => None,
+    };
+    Some(Saved { present, state })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_the_file() {
+        let path = std::env::temp_dir().join(format!("s1500d-{}-state", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(load(&path).unwrap(), None);
+
+        let saved = Saved {
+            present: true,
+            state: Some(State {
+                paper: true,
+                button: false,
+            }),
+        };
+        save(&path, saved).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "present=1 paper=1 button=0\n"
+        );
+        assert_eq!(load(&path).unwrap(), Some(saved));
+
+        save(&path, Saved::ABSENT).unwrap();
+        assert_eq!(load(&path).unwrap(), Some(Saved::ABSENT));
+
+        fs::write(&path, "present=yes\n").unwrap();
+        assert!(load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn present_without_sensors() {
+        let saved = parse("present=1").unwrap();
+        assert!(saved.present && saved.state.is_none());
+        assert_eq!(parse("present=1 paper=1"), Some(saved));
+        assert_eq!(parse("paper=1 button=0"), None);
+    }
+}
@@ -0,0 +1,140 @@
+use clap::Args;
+use s1500d::protocol::State;
+use s1500d::Inquiry;
+
+use crate::error::{self, UsbError};
+use crate::json;
+use crate::{access_denied_help, identify, open_device, poll_status};
+
+/// What `status` was asked to do.
+#[derive(Debug, Default, PartialEq, Eq, Args)]
+pub struct Options {
+    /// Print one JSON object instead of text.
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// One reading of an attached scanner.
+#[derive(Debug)]
+struct Reading {
+    state: State,
+    serial: Option<String>,
+    identity: Option<Inquiry>,
+}
+
+/// `present` and, for an attached scanner, its sensors as one JSON object.
+/// The lid is open whenever the scanner is on the bus.
+fn report_json(reading: Option<&Reading>) -> String {
+    let Some(r) = reading else {
+        return json::Object::new()
+            .bool("present", false)
+            .str("lid", "closed")
+            .finish();
+    };
+    let identity = r.identity.as_ref();
+    json::Object::new()
+        .bool("present", true)
+        .str("lid", "open")
+        .bool("paper", r.state.paper)
+        .bool("button", r.state.button)
+        .opt_str("serial", r.serial.as_deref())
+        .opt_str("product", identity.map(|i| i.product.as_str()))
+        .opt_str("firmware", identity.map(|i| i.revision.as_str()))
+        .finish()
+}
+
+fn report_text(reading: Option<&Reading>) -> String {
+    let Some(r) = reading else {
+        return "scanner: not found (lid closed or unplugged)\n".into();
+    };
+    let serial = r.serial.as_deref().unwrap_or("unknown");
+    let scanner = match &r.identity {
+        Some(identity) => format!("{identity}, serial {serial}"),
+        None => format!("serial {serial}"),
+    };
+    format!(
+        "scanner: present ({scanner})\nlid:     open\npaper:   {}\nbutton:  {}\n",
+        if r.state.paper { "loaded" } else { "empty" },
+        if r.state.button { "down" } else { "up" }
+    )
+}
+
+/// Report why the scanner couldn't be opened or read and exit: the udev
+/// hint and [`error::EXIT_ACCESS`] for permissions, otherwise
+/// [`error::EXIT_FAILURE`]. `busy` says what to do instead when something
+/// else — the daemon — has it.
+pub fn fail(ctx: &rusb::Context, e: UsbError, busy: &str) -> ! {
+    match e {
+        UsbError::AccessDenied => {
+            eprintln!("s1500d: {}", access_denied_help(ctx));
+            std::process::exit(error::EXIT_ACCESS);
+        }
+        UsbError::Busy => eprintln!("s1500d: scanner busy — {busy}"),
+        e => eprintln!("s1500d: cannot read the scanner: {e}"),
+    }
+    std::process::exit(error::EXIT_FAILURE);
+}
+
+/// `s1500d status`: read the scanner once, print what it reports and
+/// exit — 0 if it is attached, [`error::EXIT_NOT_FOUND`] if not.
+pub fn status(opts: &Options) -> ! {
+    let ctx = rusb::Context::new().unwrap_or_else(|e| {
+        eprintln!("s1500d: cannot initialize libusb: {e}");
+        std::process::exit(error::EXIT_USB);
+    });
+    let busy = "if the daemon is running, use `s1500ctl status` \
+                (or run this from a handler, while the daemon has released it)";
+    let reading = match open_device(&ctx) {
+        Ok(handle) => Some(Reading {
+            state: poll_status(&handle).unwrap_or_else(|e| fail(&ctx, e, busy)),
+            serial: s1500d::device::serial_number(&handle.device()).ok(),
+            identity: identify(&handle),
+        }),
+        Err(UsbError::NotFound) => None,
+        Err(e) => fail(&ctx, e, busy),
+    };
+    if opts.json {
+        println!("{}", report_json(reading.as_ref()));
+    } else {
+        print!("{}", report_text(reading.as_ref()));
+    }
+    std::process::exit(match reading {
+        Some(_) => 0,
+        None => error::EXIT_NOT_FOUND,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading() -> Reading {
+        Reading {
+            state: State {
+                paper: true,
+                button: false,
+            },
+            serial: Some("A1B2C3".into()),
+            identity: Some(Inquiry {
+                vendor: "FUJITSU".into(),
+                product: "ScanSnap S1500".into(),
+                revision: "0M00".into(),
+            }),
+        }
+    }
+
+    #[test]
+    fn reports_present_scanner() {
+        let r = reading();
+        assert_eq!(
+            report_json(Some(&r)),
+            r#"{"present":true,"lid":"open","paper":true,"button":false,"serial":"A1B2C3","product":"ScanSnap S1500","firmware":"0M00"}"#
+        );
+        assert!(report_text(Some(&r)).contains("paper:   loaded\n"));
+    }
+
+    #[test]
+    fn reports_absent_scanner() {
+        assert_eq!(report_json(None), r#"{"present":false,"lid":"closed"}"#);
+    }
+}
@@ -0,0 +1,299 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, terminal};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Gauge, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use crate::{format_session, Dispatcher, GestureState, Mode, Snapshot};
+
+/// Lines of log and handler output kept for the dashboard.
+const LOG_LINES: usize = 200;
+
+/// Log and handler output, newest last.
+static OUTPUT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+thread_local! {
+    static DASHBOARD: RefCell<Option<Dashboard>> = const { RefCell::new(None) };
+}
+
+struct Dashboard {
+    terminal: Terminal<CrosstermBackend<File>>,
+    /// The original stderr, restored when the dashboard goes away.
+    stderr: OwnedFd,
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        let _ = restore_terminal(self.terminal.backend_mut());
+        // SAFETY: dup2 onto fd 2 of a descriptor we own.
+        unsafe {
+            libc::dup2(self.stderr.as_raw_fd(), libc::STDERR_FILENO);
+        }
+    }
+}
+
+fn restore_terminal(out: &mut impl Write) -> io::Result<()> {
+    terminal::disable_raw_mode()?;
+    execute!(out, terminal::LeaveAlternateScreen, cursor::Show)
+}
+
+/// Take over the terminal for the dashboard.
+///
+/// Everything written to stdout and stderr from here on — the log, and
+/// handler output, which would otherwise scribble over the screen — is
+/// redirected into a pipe and shown in the dashboard's output pane.
+pub fn start() -> io::Result<()> {
+    // SAFETY: dup has no preconditions; the results are owned below.
+    let (tty, stderr) = unsafe {
+        let tty = libc::dup(libc::STDOUT_FILENO);
+        let stderr = libc::dup(libc::STDERR_FILENO);
+        if tty < 0 || stderr < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        (File::from_raw_fd(tty), OwnedFd::from_raw_fd(stderr))
+    };
+    let (read, write) = crate::handler::pipe()?;
+    let read = File::from(read);
+    std::thread::spawn(move || {
+        for line in BufReader::new(read).lines().map_while(Result::ok) {
+            let mut output = OUTPUT.lock().unwrap_or_else(|e| e.into_inner());
+            if output.len() == LOG_LINES {
+                output.pop_front();
+            }
+            output.push_back(line);
+        }
+    });
+
+    let mut out = tty;
+    terminal::enable_raw_mode()?;
+    execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+    // SAFETY: dup2 of an open descriptor onto the standard ones.
+    unsafe {
+        libc::dup2(write.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(write.as_raw_fd(), libc::STDERR_FILENO);
+    }
+
+    // Put the terminal back before a panic message is printed.
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        stop();
+        previous(info);
+    }));
+
+    let terminal = Terminal::new(CrosstermBackend::new(out))?;
+    DASHBOARD.with(|d| *d.borrow_mut() = Some(Dashboard { terminal, stderr }));
+    Ok(())
+}
+
+/// Restore the terminal and stderr.
+pub fn stop() {
+    // try_with: may run from the panic hook while the dashboard is borrowed.
+    let _ = DASHBOARD.try_with(|d| d.try_borrow_mut().map(|mut d| d.take()));
+}
+
+/// Redraw the dashboard, if one is running, and exit if the user asked to
+/// quit (`q`, Esc or Ctrl-C — raw mode turns Ctrl-C into a key press).
+pub fn update(snap: &Snapshot, mode: &Mode, dispatcher: &Dispatcher) {
+    let quit = DASHBOARD.with(|d| {
+        let mut d = d.borrow_mut();
+        let Some(dashboard) = d.as_mut() else {
+            return false;
+        };
+        let _ = dashboard.terminal.draw(|f| draw(f, snap, mode, dispatcher));
+        quit_requested()
+    });
+    if quit {
+        stop();
+        std::process::exit(0);
+    }
+}
+
+fn quit_requested() -> bool {
+    while event::poll(Duration::ZERO).unwrap_or(false) {
+        if let Ok(TermEvent::Key(key)) = event::read() {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.kind == KeyEventKind::Press
+                && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc))
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn draw(f: &mut Frame, snap: &Snapshot, mode: &Mode, dispatcher: &Dispatcher) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(6),
+            Constraint::Length(3),
+            Constraint::Length(6),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .split(f.size());
+    let top = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(rows[0]);
+    let middle = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(rows[2]);
+
+    f.render_widget(
+        Paragraph::new(scanner_lines(snap)).block(titled("Scanner")),
+        top[0],
+    );
+    f.render_widget(Paragraph::new(usb_lines(snap)).block(titled("USB")), top[1]);
+    let (label, ratio) = gesture_progress(snap.gesture, mode);
+    f.render_widget(
+        Gauge::default()
+            .block(titled("Gesture"))
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .label(label)
+            .ratio(ratio),
+        rows[1],
+    );
+    f.render_widget(
+        Paragraph::new(dispatch_lines(snap, dispatcher)).block(titled("Handler")),
+        middle[0],
+    );
+    f.render_widget(
+        Paragraph::new(queue_lines(snap)).block(titled("Queue")),
+        middle[1],
+    );
+
+    let height = usize::from(rows[3].height.saturating_sub(2));
+    let lines: Vec<Line> = {
+        let output = OUTPUT.lock().unwrap_or_else(|e| e.into_inner());
+        let skip = output.len().saturating_sub(height);
+        output
+            .iter()
+            .skip(skip)
+            .map(|l| Line::raw(l.clone()))
+            .collect()
+    };
+    f.render_widget(Paragraph::new(lines).block(titled("Log")), rows[3]);
+    f.render_widget(Paragraph::new(" q: quit"), rows[4]);
+}
+
+fn titled(title: &str) -> Block<'_> {
+    Block::default().borders(Borders::ALL).title(title)
+}
+
+fn scanner_lines(snap: &Snapshot) -> Vec<Line<'static>> {
+    let device = match snap.handle {
+        Some(h) => format!(
+            "present (bus {:03} device {:03})",
+            h.device().bus_number(),
+            h.device().address()
+        ),
+        None => "waiting — open the ADF lid".into(),
+    };
+    let lid = snap
+        .opened_at
+        .map_or("closed".into(), |t| format_session(t.elapsed().as_secs()));
+    let (paper, button) = match snap.prev {
+        Some(s) => (
+            if s.paper { "loaded" } else { "empty" },
+            if s.button { "down" } else { "up" },
+        ),
+        None => ("?", "?"),
+    };
+    vec![
+        Line::raw(format!("device  {device}")),
+        Line::raw(format!("lid     {lid}")),
+        Line::raw(format!("paper   {paper}")),
+        Line::raw(format!("button  {button}")),
+    ]
+}
+
+fn usb_lines(snap: &Snapshot) -> Vec<Line<'static>> {
+    let health = if snap.health.is_degraded() {
+        "DEGRADED"
+    } else {
+        "ok"
+    };
+    let totals: Vec<String> = snap
+        .health
+        .totals()
+        .map(|(kind, n)| format!("{kind} {n}"))
+        .collect();
+    vec![
+        Line::raw(format!("health  {health}")),
+        Line::raw(format!(
+            "polls   {} ({} failed)",
+            snap.polls, snap.failed_polls
+        )),
+        Line::raw(format!("errors  {}", totals.join(", "))),
+        Line::raw(format!(
+            "recent  {} in {}s",
+            snap.health.recent(),
+            snap.health.window().as_secs()
+        )),
+    ]
+}
+
+fn dispatch_lines(snap: &Snapshot, dispatcher: &Dispatcher) -> Vec<Line<'static>> {
+    let batch = snap
+        .batch
+        .map_or("none".into(), |b| format!("{} ({} pages)", b.id, b.pages));
+    vec![
+        Line::raw(format!("dispatch  {}", crate::dispatch_status(dispatcher))),
+        Line::raw(format!(
+            "runs      {} ({} failed)",
+            dispatcher.runs, dispatcher.failures
+        )),
+        Line::raw(format!("batch     {batch}")),
+        Line::raw(format!("events    {}", crate::current_seq())),
+    ]
+}
+
+fn queue_lines(snap: &Snapshot) -> Vec<Line<'static>> {
+    let lines: Vec<Line> = snap
+        .queue
+        .iter()
+        .map(|p| Line::raw(format!("#{} {}", p.seq, p.args.join(" "))))
+        .collect();
+    if lines.is_empty() {
+        vec![Line::raw("(empty)")]
+    } else {
+        lines
+    }
+}
+
+/// Label and fill for the gesture gauge: how far through the gesture
+/// window (or towards a long press) the button is.
+fn gesture_progress(gesture: &GestureState, mode: &Mode) -> (String, f64) {
+    let Mode::ConfigMode(config) = mode else {
+        return ("gestures need a config file (-c)".into(), 0.0);
+    };
+    let progress =
+        |since: &Instant, of: Duration| (since.elapsed().as_secs_f64() / of.as_secs_f64()).min(1.0);
+    match gesture {
+        GestureState::Idle => ("idle".into(), 0.0),
+        GestureState::Pressed(n, since) => match config.long_press() {
+            Some(hold) => (format!("{n}x press — holding"), progress(since, hold)),
+            None => (format!("{n}x press"), 0.0),
+        },
+        GestureState::Released(n, since) => (
+            format!("{n}x press — waiting for more"),
+            progress(since, config.gesture_timeout()),
+        ),
+        GestureState::Held(n, _) => (format!("{n}x press, held"), 1.0),
+    }
+}
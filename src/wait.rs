@@ -0,0 +1,113 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use s1500d::protocol::{changes, Event, State};
+
+use crate::debounce::{Debouncer, Settle};
+use crate::error::{self, UsbError};
+use crate::{hotplug, open_device, read_status, status, usb_config};
+
+/// The events `wait` can watch for: the scanner's own, not the ones the
+/// daemon derives from its config (gestures, `idle`, batches).
+const EVENTS: [Event; 6] = [
+    Event::DeviceArrived,
+    Event::DeviceLeft,
+    Event::PaperIn,
+    Event::PaperOut,
+    Event::ButtonDown,
+    Event::ButtonUp,
+];
+
+/// What `wait` was asked to do.
+#[derive(Debug, Default, PartialEq, Eq, Args)]
+pub struct Options {
+    /// Exit on the first of these: device-arrived, device-left, paper-in,
+    /// paper-out, button-down, button-up.
+    #[arg(value_name = "EVENT", required = true, value_parser = parse_event)]
+    pub events: Vec<Event>,
+    /// Give up after SECS seconds.
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    pub timeout: Option<Duration>,
+}
+
+fn parse_event(name: &str) -> Result<Event, String> {
+    EVENTS.into_iter().find(|e| e.tag() == name).ok_or_else(|| {
+        "expected device-arrived, device-left, paper-in, paper-out, button-down or \
+             button-up"
+            .into()
+    })
+}
+
+fn parse_secs(secs: &str) -> Result<Duration, String> {
+    secs.parse()
+        .map(Duration::from_secs)
+        .map_err(|_| "expected whole seconds".into())
+}
+
+/// `s1500d wait EVENT...`: poll the scanner until one of the events
+/// happens, print its name and exit 0 — or exit [`error::EXIT_TIMEOUT`]
+/// once `--timeout` runs out. Events are changes from how the scanner is
+/// when it starts: `paper-in` with paper already loaded waits for it to be
+/// taken out and loaded again. `settle` is the config's debounce.
+pub fn wait(opts: &Options, settle: Settle) -> ! {
+    let ctx = rusb::Context::new().unwrap_or_else(|e| {
+        eprintln!("s1500d: cannot initialize libusb: {e}");
+        std::process::exit(error::EXIT_USB);
+    });
+    let watcher = hotplug::Watcher::new(&ctx);
+    let interval = usb_config().poll_interval;
+    let deadline = opts.timeout.map(|t| Instant::now() + t);
+    let mut handle = None;
+    let mut debouncer = Debouncer::default();
+    // What the last look found; `None` before the first.
+    let mut last: Option<Option<State>> = None;
+    loop {
+        let left = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        if left.is_some_and(|left| left.is_zero()) {
+            let secs = opts.timeout.unwrap_or_default().as_secs();
+            eprintln!("s1500d: wait: nothing after {secs}s");
+            std::process::exit(error::EXIT_TIMEOUT);
+        }
+        if handle.is_none() {
+            handle = match open_device(&ctx) {
+                Ok(h) => Some(h),
+                Err(UsbError::NotFound) => None,
+                Err(e) => status::fail(
+                    &ctx,
+                    e,
+                    "the daemon holds the scanner while it runs; use a handler instead",
+                ),
+            };
+        }
+        let now = match handle.as_ref().map(read_status) {
+            None => None,
+            Some(Ok((raw, _))) => Some(match last {
+                Some(Some(prev)) => debouncer.filter(prev, raw, settle, Instant::now()),
+                _ => raw,
+            }),
+            Some(Err(UsbError::NotFound | UsbError::Gone)) => {
+                handle = None;
+                debouncer = Debouncer::default();
+                None
+            }
+            // Busy or a failed transfer: ask again next time.
+            Some(Err(_)) => {
+                thread::sleep(interval);
+                continue;
+            }
+        };
+        if let Some(prev) = last {
+            if let Some(event) = changes(prev, now)
+                .into_iter()
+                .find(|e| opts.events.contains(e))
+            {
+                println!("{}", event.tag());
+                std::process::exit(0);
+            }
+        }
+        last = Some(now);
+        let sleep = left.map_or(interval, |left| left.min(interval));
+        hotplug::wait(watcher.as_ref(), &ctx, sleep);
+    }
+}
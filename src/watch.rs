@@ -0,0 +1,85 @@
+use crate::config::Watch;
+
+/// The most bits `[watch]` can name.
+pub const MAX_WATCHES: usize = 64;
+
+impl Watch {
+    /// Whether the bit is on in a GET_HW_STATUS response (after `invert`);
+    /// off if the response is too short to hold it.
+    pub fn is_on(&self, bytes: &[u8]) -> bool {
+        let set = bytes
+            .get(self.byte)
+            .is_some_and(|b| b & (1 << self.bit) != 0);
+        set != self.invert
+    }
+
+    /// The event for the bit turning on or off: `<name>-on`, `<name>-off`.
+    pub fn event(&self, on: bool) -> String {
+        format!("{}-{}", self.name, if on { "on" } else { "off" })
+    }
+}
+
+/// The watched bits of a response, bit `i` of the result for `watches[i]`.
+pub fn read(watches: &[Watch], bytes: &[u8]) -> u64 {
+    watches
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| w.is_on(bytes))
+        .fold(0, |bits, (i, _)| bits | 1 << i)
+}
+
+/// The watches whose bit differs between two [`read`]s, and whether each
+/// is now on.
+pub fn changes(watches: &[Watch], prev: u64, curr: u64) -> impl Iterator<Item = (&Watch, bool)> {
+    watches
+        .iter()
+        .enumerate()
+        .filter(move |(i, _)| (prev ^ curr) & 1 << i != 0)
+        .map(move |(i, w)| (w, curr & 1 << i != 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watches() -> Vec<Watch> {
+        vec![
+            Watch {
+                name: "cover-sensor".into(),
+                byte: 5,
+                bit: 2,
+                invert: false,
+            },
+            Watch {
+                name: "jam".into(),
+                byte: 3,
+                bit: 7,
+                invert: true,
+            },
+        ]
+    }
+
+    #[test]
+    fn reads_bits() {
+        let watches = watches();
+        let mut bytes = [0u8; 12];
+        // The inverted bit is on while clear.
+        assert_eq!(read(&watches, &bytes), 0b10);
+        bytes[5] = 0x04;
+        bytes[3] = 0x80;
+        assert_eq!(read(&watches, &bytes), 0b01);
+        // Too short: nothing is set, so only the inverted bit is on.
+        assert_eq!(read(&watches, &bytes[..4]), 0b00);
+        assert_eq!(read(&watches, &bytes[..2]), 0b10);
+    }
+
+    #[test]
+    fn changes_name_events() {
+        let watches = watches();
+        let events: Vec<String> = changes(&watches, 0b10, 0b01)
+            .map(|(w, on)| w.event(on))
+            .collect();
+        assert_eq!(events, ["cover-sensor-on", "jam-off"]);
+        assert_eq!(changes(&watches, 0b01, 0b01).count(), 0);
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+
+use crate::config::WebhookUrl;
+use crate::spool::Spool;
+
+/// How long to wait for the server to accept the connection, and for each
+/// read and write.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// First retry delay; it doubles after each failure up to [`MAX_BACKOFF`].
+const BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Feeds the sender thread, once [`start`] has been called.
+static EVENTS: Mutex<Option<Sender<(u64, String)>>> = Mutex::new(None);
+
+/// Start posting events to `url` from a background thread, so a slow or
+/// unreachable server never holds up the event loop. Up to `backlog` events
+/// are held for the server; they are also kept in `spool`, if set, and sent
+/// first.
+pub fn start(
+    url: &WebhookUrl,
+    headers: &[(String, String)],
+    retries: u32,
+    backlog: usize,
+    spool: Option<&str>,
+) {
+    let (tx, rx) = mpsc::channel();
+    let poster = Poster::new(url.clone(), headers.to_vec(), retries, backlog, spool);
+    let spawned = thread::Builder::new()
+        .name("webhook".into())
+        .spawn(move || poster.run(&rx));
+    match spawned {
+        Ok(_) => {
+            info!("webhook: posting events to {}", url.url);
+            *EVENTS.lock().unwrap_or_else(|e| e.into_inner()) = Some(tx);
+        }
+        Err(e) => warn!("webhook: cannot start the sender: {e}"),
+    }
+}
+
+/// Queue event `seq` (see `event_json`) to be posted.
+pub fn event(seq: u64, json: &str) {
+    if let Some(tx) = EVENTS.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        let _ = tx.send((seq, json.to_string()));
+    }
+}
+
+/// Why a post didn't get a response.
+#[derive(Debug)]
+enum Failure {
+    /// Couldn't connect: the event waits, however long that takes.
+    Unreachable(io::Error),
+    /// Connected, but no valid response: counts against the retries.
+    Failed(io::Error),
+}
+
+/// Posts held events in order on the sender thread.
+struct Poster {
+    url: WebhookUrl,
+    headers: Vec<(String, String)>,
+    retries: u32,
+    /// Events not yet delivered (or given up on), oldest first.
+    backlog: VecDeque<(u64, String)>,
+    /// How many events `backlog` may hold.
+    capacity: usize,
+    /// Failed attempts at the oldest event.
+    failures: u32,
+    /// Delay before the next attempt after a failure.
+    backoff: Duration,
+    unreachable: bool,
+    /// Events dropped from a full backlog since the last report.
+    dropped: u64,
+    /// The backlog on disk, as `seq \t json` lines.
+    spool: Spool,
+}
+
+impl Poster {
+    fn new(
+        url: WebhookUrl,
+        headers: Vec<(String, String)>,
+        retries: u32,
+        capacity: usize,
+        spool: Option<&str>,
+    ) -> Self {
+        let (spool, held) = Spool::open(spool, "webhook");
+        let backlog = held
+            .iter()
+            .filter_map(|line| {
+                let (seq, json) = line.split_once('\t')?;
+                Some((seq.parse().ok()?, json.to_string()))
+            })
+            .collect();
+        Self {
+            url,
+            headers,
+            retries,
+            backlog,
+            capacity,
+            failures: 0,
+            backoff: BACKOFF,
+            unreachable: false,
+            dropped: 0,
+            spool,
+        }
+    }
+
+    fn run(mut self, events: &Receiver<(u64, String)>) {
+        loop {
+            let wait = self.step();
+            let received = if self.backlog.is_empty() {
+                events.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                events.recv_timeout(wait)
+            };
+            match received {
+                Ok(event) => {
+                    self.queue(event);
+                    events.try_iter().for_each(|e| self.queue(e));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    fn queue(&mut self, event: (u64, String)) {
+        if self.backlog.len() >= self.capacity {
+            self.backlog.pop_front();
+            self.failures = 0;
+            self.dropped += 1;
+        }
+        self.backlog.push_back(event);
+        self.save_spool();
+    }
+
+    /// Mirror the backlog to the spool.
+    fn save_spool(&self) {
+        let lines = self
+            .backlog
+            .iter()
+            .map(|(seq, json)| format!("{seq}\t{json}"));
+        if let Err(e) = self.spool.save(lines) {
+            warn!("webhook: cannot update the spool: {e}");
+        }
+    }
+
+    /// Done with the oldest event, delivered or given up on.
+    fn pop(&mut self) {
+        self.backlog.pop_front();
+        self.save_spool();
+        self.failures = 0;
+        self.backoff = BACKOFF;
+    }
+
+    /// Try to post the oldest held event. Returns how long to wait before
+    /// the next attempt: zero once it's been dealt with.
+    fn step(&mut self) -> Duration {
+        let Some((seq, json)) = self.backlog.front() else {
+            return Duration::ZERO;
+        };
+        let seq = *seq;
+        match post(&self.url, &self.headers, json) {
+            Err(Failure::Unreachable(e)) => {
+                if !self.unreachable {
+                    warn!(
+                        "webhook: cannot reach {}: {e} — holding events",
+                        self.url.url
+                    );
+                    self.unreachable = true;
+                }
+                debug!("webhook: {} events held", self.backlog.len());
+                return self.next_backoff();
+            }
+            Ok(status) if (200..300).contains(&status) => {
+                debug!("webhook: event #{seq} delivered ({status})");
+            }
+            Ok(status @ (408 | 429 | 500..=599)) => {
+                return self.failed(seq, &format!("HTTP {status}"));
+            }
+            Ok(status) => error!("webhook: event #{seq} rejected with HTTP {status}, dropping it"),
+            Err(Failure::Failed(e)) => return self.failed(seq, &e.to_string()),
+        }
+        if std::mem::take(&mut self.unreachable) {
+            info!("webhook: {} reachable again", self.url.url);
+        }
+        if self.dropped > 0 {
+            warn!(
+                "webhook: {} events were dropped while {} was unreachable",
+                std::mem::take(&mut self.dropped),
+                self.url.url
+            );
+        }
+        self.pop();
+        Duration::ZERO
+    }
+
+    /// Count a failed attempt at event `seq`; drop it once it has used up
+    /// its retries.
+    fn failed(&mut self, seq: u64, why: &str) -> Duration {
+        self.unreachable = false;
+        self.failures += 1;
+        if self.failures > self.retries {
+            error!(
+                "webhook: giving up on event #{seq} after {} attempts: {why}",
+                self.failures
+            );
+            self.pop();
+            return Duration::ZERO;
+        }
+        let wait = self.next_backoff();
+        warn!(
+            "webhook: event #{seq} failed: {why} — retry {}/{} in {}s",
+            self.failures,
+            self.retries,
+            wait.as_secs()
+        );
+        wait
+    }
+
+    fn next_backoff(&mut self) -> Duration {
+        let wait = self.backoff;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        wait
+    }
+}
+
+/// POST `body` and return the response status.
+fn post(url: &WebhookUrl, headers: &[(String, String)], body: &str) -> Result<u16, Failure> {
+    let mut stream = connect_tcp(&url.host, url.port).map_err(Failure::Unreachable)?;
+    exchange(&mut stream, &request(url, headers, body)).map_err(Failure::Failed)
+}
+
+fn exchange(stream: &mut TcpStream, request: &[u8]) -> io::Result<u16> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.write_all(request)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    parse_status(&line).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("bad status line {:?}", line.trim_end()),
+        )
+    })
+}
+
+/// Connect to the first address of `host` that answers.
+fn connect_tcp(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+fn request(url: &WebhookUrl, headers: &[(String, String)], body: &str) -> Vec<u8> {
+    let mut head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: s1500d/{}\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path,
+        url.authority,
+        env!("CARGO_PKG_VERSION"),
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    let mut request = head.into_bytes();
+    request.extend_from_slice(body.as_bytes());
+    request
+}
+
+/// The status code from a line like `HTTP/1.1 204 No Content`.
+fn parse_status(line: &str) -> Option<u16> {
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn url(port: u16) -> WebhookUrl {
+        WebhookUrl {
+            url: format!("http://127.0.0.1:{port}/hook"),
+            authority: format!("127.0.0.1:{port}"),
+            host: "127.0.0.1".into(),
+            port,
+            path: "/hook".into(),
+        }
+    }
+
+    /// Answer one request per status in turn, returning the requests.
+    fn server(listener: TcpListener, statuses: Vec<u16>) -> Vec<String> {
+        statuses
+            .into_iter()
+            .map(|status| {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                // The request is small; read until the body has arrived.
+                while !String::from_utf8_lossy(&request).ends_with('}') {
+                    let n = stream.read(&mut buf).unwrap();
+                    assert!(n > 0, "request cut short");
+                    request.extend_from_slice(&buf[..n]);
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Whatever\r\nContent-Length: 0\r\n\r\n"
+                )
+                .unwrap();
+                String::from_utf8(request).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn request_layout() {
+        let headers = [("Authorization".to_string(), "Bearer x".to_string())];
+        let request = request(&url(8080), &headers, r#"{"seq":1}"#);
+        assert_eq!(
+            String::from_utf8(request).unwrap(),
+            format!(
+                "POST /hook HTTP/1.1\r\nHost: 127.0.0.1:8080\r\nUser-Agent: s1500d/{}\r\n\
+                 Content-Type: application/json\r\nContent-Length: 9\r\nConnection: close\r\n\
+                 Authorization: Bearer x\r\n\r\n{{\"seq\":1}}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(parse_status("HTTP/1.1 204 No Content\r\n"), Some(204));
+        assert_eq!(parse_status("HTTP/1.0 500\r\n"), Some(500));
+        assert_eq!(parse_status("SSH-2.0-OpenSSH\r\n"), None);
+        assert_eq!(parse_status(""), None);
+    }
+
+    #[test]
+    fn retries_then_delivers_in_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || server(listener, vec![503, 200, 400, 200]));
+
+        let mut poster = Poster::new(url(port), Vec::new(), 5, 10, None);
+        poster.queue((1, r#"{"seq":1}"#.into()));
+        poster.queue((2, r#"{"seq":2}"#.into()));
+        poster.queue((3, r#"{"seq":3}"#.into()));
+        // 503: kept, retried after the first backoff.
+        assert_eq!(poster.step(), BACKOFF);
+        assert_eq!((poster.backlog.len(), poster.failures), (3, 1));
+        assert_eq!(poster.step(), Duration::ZERO);
+        // 400 won't get better: dropped without retrying.
+        assert_eq!(poster.step(), Duration::ZERO);
+        assert_eq!(poster.step(), Duration::ZERO);
+        assert!(poster.backlog.is_empty());
+
+        let requests = server.join().unwrap();
+        let bodies: Vec<&str> = requests
+            .iter()
+            .map(|r| r.split("\r\n\r\n").nth(1).unwrap())
+            .collect();
+        assert_eq!(
+            bodies,
+            [
+                r#"{"seq":1}"#,
+                r#"{"seq":1}"#,
+                r#"{"seq":2}"#,
+                r#"{"seq":3}"#
+            ]
+        );
+        assert!(requests[0].starts_with("POST /hook HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn gives_up_after_the_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || server(listener, vec![500, 500]));
+
+        let mut poster = Poster::new(url(port), Vec::new(), 1, 10, None);
+        poster.queue((1, r#"{"seq":1}"#.into()));
+        assert_eq!(poster.step(), BACKOFF);
+        assert_eq!(poster.step(), Duration::ZERO);
+        assert!(poster.backlog.is_empty());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn held_events_outlast_a_restart() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let path =
+            std::env::temp_dir().join(format!("s1500d-{}-webhook-spool", std::process::id()));
+        let spool = path.to_str();
+        let _ = std::fs::remove_file(&path);
+        let mut poster = Poster::new(url(port), Vec::new(), 0, 10, spool);
+        poster.queue((1, r#"{"seq":1}"#.into()));
+        poster.queue((2, r#"{"seq":2}"#.into()));
+        assert_eq!(poster.step(), BACKOFF);
+        drop(poster);
+
+        let poster = Poster::new(url(port), Vec::new(), 0, 10, spool);
+        let held: Vec<_> = poster.backlog.iter().map(|(seq, _)| *seq).collect();
+        assert_eq!(held, [1, 2]);
+        assert_eq!(poster.backlog[1].1, r#"{"seq":2}"#);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unreachable_server_holds_events() {
+        // Nothing listens on this port once the listener is dropped.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut poster = Poster::new(url(port), Vec::new(), 0, 10, None);
+        poster.queue((1, "{}".into()));
+        assert_eq!(poster.step(), BACKOFF);
+        assert_eq!(poster.step(), BACKOFF * 2);
+        // Unreachable isn't a failed attempt, so the event is still held.
+        assert_eq!((poster.backlog.len(), poster.failures), (1, 0));
+        assert!(poster.unreachable);
+
+        for seq in 2..=11 {
+            poster.queue((seq, "{}".into()));
+        }
+        assert_eq!(poster.backlog.len(), 10);
+        assert_eq!(poster.backlog.front().map(|e| e.0), Some(2));
+        assert_eq!(poster.dropped, 1);
+    }
+}